@@ -2,12 +2,12 @@ extern crate alloc;
 use alloc::string::ToString;
 use alloc::format;
 use alloc::string::String;
-use alloc::vec::Vec;
 use noli::net::lookup_host;
 use noli::net::SocketAddr;
 use noli::net::TcpStream;
 use saba_core::error::Error;
 use saba_core::http::HttpResponse;
+use saba_core::http::HttpResponseReader;
 
 pub struct HttpClient {}
 
@@ -17,6 +17,18 @@ impl HttpClient {
     }
 
     pub fn get(&self, host: String, port: u16, path: String) -> Result<HttpResponse, Error> {
+        self.get_streaming(host, port, path, &mut |_, _| {})
+    }
+
+    /// レスポンスを受け取りながら、届いたボディをその都度 `on_body` に渡す
+    /// `on_body` にはボディを除いたレスポンスと、新しく届いたボディが渡される
+    pub fn get_streaming(
+        &self,
+        host: String,
+        port: u16,
+        path: String,
+        on_body: &mut dyn FnMut(&HttpResponse, &str),
+    ) -> Result<HttpResponse, Error> {
         let ips = match lookup_host(&host) {
             Ok(ips) => ips,
             Err(e) => {
@@ -60,7 +72,7 @@ impl HttpClient {
             }
         };
 
-        let mut received = Vec::new();
+        let mut reader = HttpResponseReader::new();
         loop {
             let mut buf = [0u8; 4096];
             let bytes_read = match stream.read(&mut buf) {
@@ -76,15 +88,14 @@ impl HttpClient {
                 break;
             }
 
-            received.extend_from_slice(&buf[..bytes_read]);
+            let body = reader.push(&buf[..bytes_read])?;
+            if let Some(head) = reader.head() {
+                if !body.is_empty() {
+                    on_body(head, &body);
+                }
+            }
         }
 
-        match core::str::from_utf8(&received) {
-            Ok(response) => HttpResponse::new(response.to_string()),
-            Err(e) => Err(Error::Network(format!(
-                "Invalid received response: {}",
-                e
-            ))),
-        }
+        reader.finish()
     }
 }
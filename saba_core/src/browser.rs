@@ -1,12 +1,23 @@
+use crate::error::Error;
+use crate::http::HttpResponse;
 use crate::renderer::page::Page;
 use alloc::rc::Rc;
+use alloc::string::String;
+use alloc::string::ToString;
 use alloc::vec::Vec;
 use core::cell::RefCell;
 
+/// URL を受け取り、ネットワークからリソースを取得する関数
+pub type FetchHandler = fn(String) -> Result<HttpResponse, Error>;
+
 #[derive(Debug, Clone)]
 pub struct Browser {
     active_page_index: usize,
     pages: Vec<Rc<RefCell<Page>>>,
+    // これまでに移動した URL。リンクの :visited に使う
+    history: Vec<String>,
+    // ページが外部のスタイルシートなどを取得するために使う
+    fetch_handler: Option<FetchHandler>,
 }
 
 impl Browser {
@@ -16,10 +27,14 @@ impl Browser {
         let browser = Rc::new(RefCell::new(Self {
             active_page_index: 0,
             pages: Vec::new(),
+            history: Vec::new(),
+            fetch_handler: None,
         }));
 
         page.set_browser(Rc::downgrade(&browser));
-        browser.borrow_mut().pages.push(Rc::new(RefCell::new(page)));
+        let page = Rc::new(RefCell::new(page));
+        page.borrow_mut().set_this(Rc::downgrade(&page));
+        browser.borrow_mut().pages.push(page);
 
         browser
     }
@@ -29,4 +44,25 @@ impl Browser {
     }
 
     pub fn next_page(&mut self) {}
+
+    /// 移動した URL を履歴に追加する
+    pub fn push_history(&mut self, url: String) {
+        self.history.push(url);
+    }
+
+    pub fn history(&self) -> Vec<String> {
+        self.history.clone()
+    }
+
+    pub fn set_fetch_handler(&mut self, fetch_handler: FetchHandler) {
+        self.fetch_handler = Some(fetch_handler);
+    }
+
+    /// ネットワークから `url` のリソースを取得する
+    pub fn fetch(&self, url: String) -> Result<HttpResponse, Error> {
+        match self.fetch_handler {
+            Some(fetch_handler) => fetch_handler(url),
+            None => Err(Error::Network("no fetch handler is set".to_string())),
+        }
+    }
 }
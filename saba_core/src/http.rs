@@ -83,6 +83,94 @@ impl HttpResponse {
     }
 }
 
+/// 少しずつ届く HTTP レスポンスを解釈する
+/// ヘッダーをすべて受け取ったあとは、届いたボディをその都度文字列として取り出せる
+#[derive(Debug, Clone, Default)]
+pub struct HttpResponseReader {
+    // まだ解釈していない受信データ。ヘッダーの途中や、UTF-8 の文字の途中で途切れたバイト列が残る
+    pending: Vec<u8>,
+    // ボディを除いたレスポンス。ヘッダーをすべて受け取るまでは None になる
+    head: Option<HttpResponse>,
+    body: String,
+}
+
+impl HttpResponseReader {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// ヘッダーをすべて受け取っている場合は、ボディを除いたレスポンスを返す
+    pub fn head(&self) -> Option<&HttpResponse> {
+        self.head.as_ref()
+    }
+
+    /// 受信した `bytes` を追加し、新しく読めるようになったボディを返す
+    pub fn push(&mut self, bytes: &[u8]) -> Result<String, Error> {
+        self.pending.extend_from_slice(bytes);
+
+        if self.head.is_none() {
+            let end = match header_end(&self.pending) {
+                Some(end) => end,
+                None => return Ok(String::new()),
+            };
+            let head = match core::str::from_utf8(&self.pending[..end]) {
+                Ok(head) => head.to_string(),
+                Err(e) => return Err(Error::Network(format!("Invalid received response: {}", e))),
+            };
+            self.head = Some(HttpResponse::new(head)?);
+            self.pending.drain(..end);
+        }
+
+        // 文字の途中で途切れている場合は、残りのバイトが届くまで待つ
+        let valid = match core::str::from_utf8(&self.pending) {
+            Ok(valid) => valid,
+            Err(e) if e.error_len().is_none() => {
+                core::str::from_utf8(&self.pending[..e.valid_up_to()]).unwrap_or_default()
+            }
+            Err(e) => return Err(Error::Network(format!("Invalid received response: {}", e))),
+        };
+        let chunk = valid.to_string();
+        self.pending.drain(..chunk.len());
+        self.body.push_str(&chunk);
+        Ok(chunk)
+    }
+
+    /// すべてのデータを受け取ったあとに呼ばれ、ボディを含むレスポンス全体を返す
+    pub fn finish(self) -> Result<HttpResponse, Error> {
+        match self.head {
+            Some(head) => {
+                if !self.pending.is_empty() {
+                    return Err(Error::Network(
+                        "Invalid received response: incomplete utf-8 sequence".to_string(),
+                    ));
+                }
+                Ok(HttpResponse {
+                    body: self.body,
+                    ..head
+                })
+            }
+            // 空行がない場合は、受け取ったデータ全体をレスポンスとして解釈する
+            None => match core::str::from_utf8(&self.pending) {
+                Ok(response) => HttpResponse::new(response.to_string()),
+                Err(e) => Err(Error::Network(format!("Invalid received response: {}", e))),
+            },
+        }
+    }
+}
+
+/// ヘッダーの終わりを示す空行の直後の位置を返す
+fn header_end(bytes: &[u8]) -> Option<usize> {
+    (0..bytes.len()).find_map(|i| {
+        if bytes[i..].starts_with(b"\n\n") {
+            Some(i + 2)
+        } else if bytes[i..].starts_with(b"\n\r\n") {
+            Some(i + 3)
+        } else {
+            None
+        }
+    })
+}
+
 #[derive(Debug, Clone)]
 pub struct Header {
     name: String,
@@ -148,4 +236,45 @@ mod tests {
         let res = HttpResponse::new(raw);
         assert!(res.is_err());
     }
+
+    #[test]
+    fn test_reader() {
+        let mut reader = HttpResponseReader::new();
+        assert_eq!(
+            Ok(String::new()),
+            reader.push(b"HTTP/1.1 200 OK\r\nContent-")
+        );
+        assert!(reader.head().is_none());
+        assert_eq!(
+            Ok("<p>".to_string()),
+            reader.push(b"Type: text/html\r\n\r\n<p>")
+        );
+        assert_eq!(Some(200), reader.head().map(|head| head.status_code()));
+
+        // UTF-8 の文字の途中で途切れた場合は、残りが届くまで待つ
+        let text = "あい".as_bytes();
+        assert_eq!(Ok(String::new()), reader.push(&text[..1]));
+        assert_eq!(Ok("あ".to_string()), reader.push(&text[1..4]));
+        assert_eq!(
+            Ok("い</p>".to_string()),
+            reader.push(&[&text[4..], b"</p>"].concat())
+        );
+
+        let res = reader.finish().expect("failed to parse http response");
+        assert_eq!(
+            res.header_value("Content-Type"),
+            Ok("text/html".to_string())
+        );
+        assert_eq!(res.body(), "<p>あい</p>".to_string());
+    }
+
+    #[test]
+    fn test_reader_invalid() {
+        let mut reader = HttpResponseReader::new();
+        assert!(reader.push(b"HTTP/1.1 200 OK\n\n\xff").is_err());
+
+        let mut reader = HttpResponseReader::new();
+        assert!(reader.push(b"HTTP/1.1 200 OK\n\n\xe3\x81").is_ok());
+        assert!(reader.finish().is_err());
+    }
 }
\ No newline at end of file
@@ -0,0 +1,181 @@
+//! カスケード。要素に適用される宣言を優先順位の順に並べ、プロパティごとに1つの値を決める
+//! https://www.w3.org/TR/css-cascade-4/#cascading
+
+use crate::renderer::css::cssom::ComponentValue;
+use crate::renderer::css::cssom::CssParser;
+use crate::renderer::css::cssom::Declaration;
+use crate::renderer::css::cssom::Origin;
+use crate::renderer::css::cssom::Selector;
+use crate::renderer::css::cssom::Specificity;
+use crate::renderer::css::cssom::StyleSheet;
+use crate::renderer::css::token::CssTokenizer;
+use crate::renderer::dom::api::matches_selector;
+use crate::renderer::dom::node::Dom;
+use crate::renderer::dom::node::NodeId;
+use alloc::collections::BTreeMap;
+use alloc::string::String;
+use alloc::vec::Vec;
+
+/// プロパティ名と、カスケードの結果そのプロパティに決まった値
+/// https://www.w3.org/TR/css-cascade-4/#cascaded
+pub type CascadedValues = BTreeMap<String, ComponentValue>;
+
+/// 宣言の優先順位。フィールドの順に比べ、大きいほうが優先される
+/// https://www.w3.org/TR/css-cascade-4/#cascade-sort
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
+struct Priority {
+    // 出どころと !important の組み合わせ
+    origin_and_importance: u8,
+    // style 属性の宣言かどうか
+    inline: bool,
+    specificity: Specificity,
+    // 宣言が現れた順番
+    order: usize,
+}
+
+/// 出どころと重要度による優先順位
+/// !important の宣言では、出どころの優先順位が逆になる
+fn origin_and_importance(origin: Origin, important: bool) -> u8 {
+    match (origin, important) {
+        (Origin::UserAgent, false) => 0,
+        (Origin::Author, false) => 1,
+        (Origin::Author, true) => 2,
+        (Origin::UserAgent, true) => 3,
+    }
+}
+
+/// `selector` のうち `node` に一致するものの詳細度を返す。一致しない場合は None を返す
+/// セレクタリストの場合は、一致したセレクタの中で最も高い詳細度になる
+fn matching_specificity(dom: &Dom, node: NodeId, selector: &Selector) -> Option<Specificity> {
+    match selector {
+        Selector::SelectorList(selectors) => selectors
+            .iter()
+            .filter(|s| matches_selector(dom, node, s))
+            .map(|s| s.specificity())
+            .max(),
+        _ if matches_selector(dom, node, selector) => Some(selector.specificity()),
+        _ => None,
+    }
+}
+
+/// `node` の style 属性に書かれた宣言を返す
+fn inline_declarations(dom: &Dom, node: NodeId) -> Vec<Declaration> {
+    let style = match dom.get(node).get_element() {
+        Some(element) => element.get_attribute("style"),
+        None => None,
+    };
+    match style {
+        Some(style) => CssParser::new(CssTokenizer::new(style)).parse_declaration_list(),
+        None => Vec::new(),
+    }
+}
+
+/// `sheets` のルールと style 属性から、`node` のプロパティごとの値を決める
+pub fn cascade(dom: &Dom, node: NodeId, sheets: &[&StyleSheet]) -> CascadedValues {
+    let mut declarations: Vec<(Priority, Declaration)> = Vec::new();
+
+    for sheet in sheets {
+        for rule in &sheet.rules {
+            let specificity = match matching_specificity(dom, node, &rule.selector) {
+                Some(specificity) => specificity,
+                None => continue,
+            };
+            for declaration in &rule.declarations {
+                let priority = Priority {
+                    origin_and_importance: origin_and_importance(
+                        sheet.origin,
+                        declaration.important,
+                    ),
+                    inline: false,
+                    specificity,
+                    order: declarations.len(),
+                };
+                declarations.push((priority, declaration.clone()));
+            }
+        }
+    }
+
+    // style 属性の宣言は文書の作者によるもので、同じ重要度のルールより優先される
+    for declaration in inline_declarations(dom, node) {
+        let priority = Priority {
+            origin_and_importance: origin_and_importance(Origin::Author, declaration.important),
+            inline: true,
+            specificity: Specificity::default(),
+            order: declarations.len(),
+        };
+        declarations.push((priority, declaration));
+    }
+
+    // 優先順位の低いものから順に書き込み、優先順位の高い値で上書きする
+    declarations.sort_by_key(|(priority, _)| *priority);
+    let mut values = CascadedValues::new();
+    for (_, declaration) in declarations {
+        values.insert(declaration.property, declaration.value);
+    }
+    values
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::renderer::css::token::CssToken;
+    use crate::renderer::dom::api::query_selector_all;
+    use crate::renderer::html::parser::HtmlParser;
+    use crate::renderer::html::token::HtmlTokenizer;
+    use alloc::string::ToString;
+
+    fn stylesheet(css: &str, origin: Origin) -> StyleSheet {
+        let mut sheet = CssParser::new(CssTokenizer::new(css.to_string())).parse_stylesheet();
+        sheet.set_origin(origin);
+        sheet
+    }
+
+    fn color(values: &CascadedValues) -> Option<ComponentValue> {
+        values.get("color").cloned()
+    }
+
+    fn ident(s: &str) -> Option<ComponentValue> {
+        Some(CssToken::Ident(s.to_string()))
+    }
+
+    #[test]
+    fn test_cascade() {
+        let html = r#"<html><head></head><body>
+            <p id="a" class="x">a</p>
+            <p class="x" style="color: teal">b</p>
+            <p class="x y" style="color: teal">c</p>
+            <h1>d</h1>
+        </body></html>"#;
+        let mut dom = Dom::new();
+        let document = HtmlParser::new(&mut dom, HtmlTokenizer::new(html.to_string()))
+            .construct_tree(&mut dom);
+        let ps = query_selector_all(&dom, document, "p").unwrap();
+        let h1 = query_selector_all(&dom, document, "h1").unwrap();
+
+        let ua = stylesheet(
+            "p { color: black; display: block; } h1 { color: gray !important; }",
+            Origin::UserAgent,
+        );
+        let author = stylesheet(
+            "#a { color: red; } p.x { color: green; } .x { color: blue; } \
+             .y { color: lime ! IMPORTANT; } h1 { color: navy !important; }",
+            Origin::Author,
+        );
+        let sheets = [&ua, &author];
+
+        // 詳細度の高い #a が、後に書かれた p.x や .x より優先される
+        let a = cascade(&dom, ps[0], &sheets);
+        assert_eq!(ident("red"), color(&a));
+        // 作者のスタイルシートに値がないプロパティは、ユーザーエージェントの値になる
+        assert_eq!(ident("block"), a.get("display").cloned());
+
+        // style 属性は、重要でない宣言の中で最も優先される
+        assert_eq!(ident("teal"), color(&cascade(&dom, ps[1], &sheets)));
+
+        // !important の宣言は、style 属性より優先される
+        assert_eq!(ident("lime"), color(&cascade(&dom, ps[2], &sheets)));
+
+        // ユーザーエージェントの !important の宣言は、作者の !important の宣言より優先される
+        assert_eq!(ident("gray"), color(&cascade(&dom, h1[0], &sheets)));
+    }
+}
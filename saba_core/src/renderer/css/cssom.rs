@@ -1,19 +1,79 @@
 use crate::alloc::string::ToString;
+use crate::error::Error;
+use crate::renderer::css::media::parse_media_query_list;
+use crate::renderer::css::media::MediaEnvironment;
+use crate::renderer::css::media::MediaQueryList;
 use crate::renderer::css::token::CssToken;
 use crate::renderer::css::token::CssTokenizer;
+use alloc::boxed::Box;
 use alloc::string::String;
 use alloc::vec::Vec;
 use core::iter::Peekable;
+use core::ops::Add;
+
+// @media のブロックと :not() の入れ子の深さの上限。これより深い入れ子は解釈しない
+const MAX_NESTING_DEPTH: usize = 32;
 
 #[derive(Debug, Clone, PartialEq)]
 pub struct StyleSheet {
     pub rules: Vec<QualifiedRule>,
+    pub origin: Origin,
+    // @import で読み込むスタイルシート。ルールより前に書かれたものだけが有効になる
+    pub imports: Vec<ImportRule>,
+    // 解析中に読み飛ばした不正なルールや宣言
+    pub diagnostics: Vec<CssDiagnostic>,
+}
+
+/// @import で読み込むスタイルシート
+/// https://www.w3.org/TR/css-cascade-4/#at-import
+#[derive(Debug, Clone, PartialEq)]
+pub struct ImportRule {
+    // 読み込むスタイルシートの URL。相対 URL は、@import を書いたスタイルシートの URL を基準にする
+    pub url: String,
+    // 読み込んだスタイルシートのルールを適用する条件
+    pub media: MediaQueryList,
+}
+
+/// 解析中に見つかった不正な CSS。該当するルールや宣言は読み飛ばし、次のルールや宣言から解析を続ける
+/// https://www.w3.org/TR/css-syntax-3/#error-handling
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CssDiagnostic {
+    /// セレクタを解釈できないため、ルール全体を捨てた
+    InvalidSelector,
+    /// 宣言を解釈できないため、捨てた。値はプロパティ名 (プロパティ名がない場合は空文字列)
+    InvalidDeclaration(String),
+    /// サポートしない @ から始まるルールを読み飛ばした。値は @ の後ろの名前
+    UnsupportedAtRule(String),
+    /// 必要なブロックがないなど、正しくない @ から始まるルールを捨てた。値は @ の後ろの名前
+    InvalidAtRule(String),
+    /// 宣言ブロックが始まる前に入力が終わったため、ルールを捨てた
+    UnexpectedEndOfInput,
+    /// ほかのルールの後ろに書かれた @import を無視した。値は読み込むはずだった URL
+    MisplacedImport(String),
+    /// @import や <link> で指定されたスタイルシートを取得できなかった。値は URL
+    FetchFailed(String),
+    /// 読み込み中のスタイルシートを再び読み込もうとしたため、@import を無視した。値は URL
+    CircularImport(String),
+    /// @media のブロックや :not() の入れ子が深すぎるため、ルールを捨てた
+    TooDeeplyNested,
+}
+
+/// スタイルシートの出どころ。カスケードで宣言の優先順位を決めるために使う
+/// https://www.w3.org/TR/css-cascade-4/#cascading-origins
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Origin {
+    // ブラウザが持つデフォルトのスタイルシート
+    UserAgent,
+    // 文書の <style> 要素と style 属性
+    Author,
 }
 
 #[derive(Debug, Clone, PartialEq)]
 pub struct QualifiedRule {
     pub selector: Selector,
     pub declarations: Vec<Declaration>,
+    // ルールを囲む @media の条件。すべての条件を満たす場合にのみ、ルールが適用される
+    pub media: Vec<MediaQueryList>,
 }
 
 impl QualifiedRule {
@@ -21,6 +81,7 @@ impl QualifiedRule {
         Self {
             selector: Selector::TypeSelector("".to_string()),
             declarations: Vec::new(),
+            media: Vec::new(),
         }
     }
 
@@ -38,13 +99,140 @@ pub enum Selector {
     TypeSelector(String),
     ClassSelector(String),
     IdSelector(String),
+    /// [href] や [type="text"] のように、属性の有無や値に一致する要素を表す
+    /// https://www.w3.org/TR/selectors-4/#attribute-selectors
+    AttributeSelector {
+        name: String,
+        operator: AttributeOperator,
+        value: String,
+    },
+    /// :first-child のような擬似クラス
+    PseudoClassSelector(PseudoClass),
+    /// すべての要素に一致する * セレクタ
+    UniversalSelector,
+    /// p.note#x のように、並べたセレクタすべてに一致する要素を表す
+    /// https://www.w3.org/TR/selectors-4/#compound
+    CompoundSelector(Vec<Selector>),
+    /// div > p のように、`right` に一致する要素のうち、`left` に一致する要素と
+    /// `combinator` の関係にあるものを表す
+    /// https://www.w3.org/TR/selectors-4/#complex
+    ComplexSelector {
+        left: Box<Selector>,
+        combinator: Combinator,
+        right: Box<Selector>,
+    },
+    /// h1, h2 のように、カンマで区切られたセレクタのいずれかに一致する要素を表す
+    SelectorList(Vec<Selector>),
     UnknownSelector,
 }
 
+/// セレクタの詳細度。(ID セレクタの数, クラス・属性・擬似クラスセレクタの数, タイプセレクタの数) を
+/// 辞書順に比べ、大きいほうが優先される
+/// https://www.w3.org/TR/selectors-4/#specificity-rules
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Specificity(pub u32, pub u32, pub u32);
+
+impl Add for Specificity {
+    type Output = Self;
+
+    fn add(self, other: Self) -> Self {
+        Self(self.0 + other.0, self.1 + other.1, self.2 + other.2)
+    }
+}
+
+impl Selector {
+    /// セレクタの詳細度を返す
+    /// セレクタリストの場合は、最も詳細度の高いセレクタの詳細度になる
+    pub fn specificity(&self) -> Specificity {
+        match self {
+            Selector::IdSelector(_) => Specificity(1, 0, 0),
+            Selector::ClassSelector(_) | Selector::AttributeSelector { .. } => Specificity(0, 1, 0),
+            // :not() の詳細度は、引数のセレクタの詳細度になる
+            Selector::PseudoClassSelector(PseudoClass::Not(selector)) => selector.specificity(),
+            Selector::PseudoClassSelector(_) => Specificity(0, 1, 0),
+            Selector::TypeSelector(_) => Specificity(0, 0, 1),
+            Selector::UniversalSelector | Selector::UnknownSelector => Specificity::default(),
+            Selector::CompoundSelector(selectors) => selectors
+                .iter()
+                .fold(Specificity::default(), |acc, s| acc + s.specificity()),
+            Selector::ComplexSelector { left, right, .. } => {
+                left.specificity() + right.specificity()
+            }
+            Selector::SelectorList(selectors) => selectors
+                .iter()
+                .map(|s| s.specificity())
+                .max()
+                .unwrap_or_default(),
+        }
+    }
+}
+
+/// https://www.w3.org/TR/selectors-4/#combinators
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Combinator {
+    // 空白文字。子孫の要素
+    Descendant,
+    // >。子の要素
+    Child,
+    // +。直後の兄弟要素
+    NextSibling,
+    // ~。後ろにある兄弟要素
+    SubsequentSibling,
+}
+
+/// 属性セレクタの値の比べ方
+/// https://www.w3.org/TR/selectors-4/#attribute-representation
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum AttributeOperator {
+    // [name]。属性があれば値は問わない
+    Exists,
+    // [name=value]
+    Equals,
+    // [name~=value]。空白文字で区切られた値のいずれかが等しい
+    Includes,
+    // [name|=value]。値が等しいか、value- で始まる
+    DashMatch,
+    // [name^=value]
+    Prefix,
+    // [name$=value]
+    Suffix,
+    // [name*=value]
+    Substring,
+}
+
+/// https://www.w3.org/TR/selectors-4/#structural-pseudos
+/// https://www.w3.org/TR/selectors-4/#useraction-pseudos
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PseudoClass {
+    FirstChild,
+    LastChild,
+    /// :nth-child(an+b)。兄弟要素の中で、ある 0 以上の整数 n について an+b 番目の要素
+    NthChild {
+        a: i32,
+        b: i32,
+    },
+    /// :not()。引数のセレクタリストに一致しない要素
+    Not(Box<Selector>),
+    Empty,
+    Root,
+    // マウスカーソルの下にある要素とその祖先
+    Hover,
+    // マウスのボタンが押されている要素とその祖先
+    Active,
+    // フォーカスされている要素
+    Focus,
+    // 移動したことのない URL へのリンク
+    Link,
+    // 移動したことのある URL へのリンク
+    Visited,
+}
+
 #[derive(Debug, Clone, PartialEq)]
 pub struct Declaration {
     pub property: String,
     pub value: ComponentValue,
+    // !important が付いているかどうか
+    pub important: bool,
 }
 
 impl Declaration {
@@ -52,6 +240,7 @@ impl Declaration {
         Self {
             property: String::new(),
             value: ComponentValue::Ident(String::new()),
+            important: false,
         }
     }
 
@@ -62,28 +251,64 @@ impl Declaration {
     pub fn set_value(&mut self, value: ComponentValue) {
         self.value = value;
     }
+
+    pub fn set_important(&mut self, important: bool) {
+        self.important = important;
+    }
 }
 
 pub type ComponentValue = CssToken;
 
 impl StyleSheet {
     pub fn new() -> Self {
-        Self { rules: Vec::new() }
+        Self {
+            rules: Vec::new(),
+            origin: Origin::Author,
+            imports: Vec::new(),
+            diagnostics: Vec::new(),
+        }
     }
 
     pub fn set_rules(&mut self, rules: Vec<QualifiedRule>) {
         self.rules = rules;
     }
+
+    pub fn set_origin(&mut self, origin: Origin) {
+        self.origin = origin;
+    }
+
+    /// `environment` で @media の条件を満たすルールだけを持つスタイルシートを返す
+    pub fn matching_media(&self, environment: &MediaEnvironment) -> StyleSheet {
+        let mut sheet = StyleSheet::new();
+        sheet.set_origin(self.origin);
+        sheet.set_rules(
+            self.rules
+                .iter()
+                .filter(|rule| rule.media.iter().all(|media| media.matches(environment)))
+                .cloned()
+                .collect(),
+        );
+        sheet
+    }
 }
 
 #[derive(Debug, Clone)]
 pub struct CssParser {
     t: Peekable<CssTokenizer>,
+    imports: Vec<ImportRule>,
+    diagnostics: Vec<CssDiagnostic>,
+    // 解釈中の @media のブロックと :not() の入れ子の深さ
+    depth: usize,
 }
 
 impl CssParser {
     pub fn new(t: CssTokenizer) -> Self {
-        Self { t: t.peekable() }
+        Self {
+            t: t.peekable(),
+            imports: Vec::new(),
+            diagnostics: Vec::new(),
+            depth: 0,
+        }
     }
 
     pub fn parse_stylesheet(&mut self) -> StyleSheet {
@@ -91,13 +316,50 @@ impl CssParser {
         let mut sheet = StyleSheet::new();
 
         // トークン列からルールのリストを作成し、StyleSheet のフィールドに設定
-        sheet.set_rules(self.consume_list_of_rules());
+        sheet.set_rules(self.consume_list_of_rules(true));
+        sheet.imports = core::mem::take(&mut self.imports);
+        sheet.diagnostics = core::mem::take(&mut self.diagnostics);
         sheet
     }
 
-    fn consume_list_of_rules(&mut self) -> Vec<QualifiedRule> {
+    /// これまでの解析で読み飛ばした不正なルールや宣言
+    pub fn diagnostics(&self) -> &[CssDiagnostic] {
+        &self.diagnostics
+    }
+
+    /// カンマで区切られたセレクタのリストを解釈する。querySelector などに渡される文字列に使う
+    /// https://www.w3.org/TR/selectors-4/#parse-selector
+    pub fn parse_selector_list(&mut self) -> Result<Vec<Selector>, Error> {
+        let selector = self.consume_selector();
+        if self.t.peek().is_some() {
+            return Err(Error::UnexpectedInput(
+                "unexpected token after a selector".to_string(),
+            ));
+        }
+
+        match selector {
+            Selector::UnknownSelector => Err(Error::UnexpectedInput(
+                "failed to parse a selector".to_string(),
+            )),
+            Selector::SelectorList(selectors) => Ok(selectors),
+            selector => Ok([selector].to_vec()),
+        }
+    }
+
+    /// style 属性の値のような、波括弧で囲まれていない宣言のリストを解釈する
+    /// https://www.w3.org/TR/css-syntax-3/#parse-a-list-of-declarations
+    pub fn parse_declaration_list(&mut self) -> Vec<Declaration> {
+        self.consume_list_of_declarations()
+    }
+
+    /// ルールのリストを解釈する。`top_level` が false の場合は、@media のブロックの中のルールとして
+    /// ブロックの終わり (}) までを解釈する
+    /// https://www.w3.org/TR/css-syntax-3/#consume-list-of-rules
+    fn consume_list_of_rules(&mut self, top_level: bool) -> Vec<QualifiedRule> {
         // 空のベクタを作成
         let mut rules = Vec::new();
+        // @import は、スタイルシートの最上位でほかのルールより前にのみ書ける
+        let mut import_allowed = top_level;
 
         loop {
             let token = match self.t.peek() {
@@ -106,84 +368,486 @@ impl CssParser {
             };
 
             match token {
+                // スタイルシートの最上位に現れる <!-- と --> は無視する
+                CssToken::Whitespace | CssToken::Cdo | CssToken::Cdc => {
+                    self.t.next();
+                    continue;
+                }
+                CssToken::CloseCurly if !top_level => {
+                    self.t.next();
+                    return rules;
+                }
                 // AtKeyword トークンが出てくた場合、ほかの CSS をインポートする
                 // @import 、メディアクエリを表す @media などのルールが始まることを表す
+                CssToken::AtKeyword(keyword) if keyword.eq_ignore_ascii_case("import") => {
+                    self.consume_import_rule(import_allowed);
+                    continue;
+                }
+                CssToken::AtKeyword(keyword) if keyword.eq_ignore_ascii_case("media") => {
+                    self.consume_media_rule(&mut rules);
+                }
                 CssToken::AtKeyword(_keyword) => {
-                    let _rule = self.consume_qualified_rule();
-                    // しかし、本書のブラウザでは @ から始まるルールはサポートしないので、無視する
+                    self.consume_at_rule();
                 }
                 _ => {
-                    // １つのるーる　を解釈し、ベクタに追加する
-                    let rule = self.consume_qualified_rule();
-                    match rule {
-                        Some(r) => rules.push(r),
-                        None => return rules,
+                    // １つのるーる　を解釈し、ベクタに追加する。不正なルールは捨てて、次のルールに進む
+                    if let Some(rule) = self.consume_qualified_rule() {
+                        rules.push(rule);
                     }
                 }
             }
+            import_allowed = false;
         }
     }
 
-    fn consume_qualified_rule(&mut self) -> Option<QualifiedRule> {
-        let mut rule = QualifiedRule::new();
+    /// @import のルールを解釈する。`allowed` が false の場合は、ほかのルールの後ろにあるので無視する
+    /// https://www.w3.org/TR/css-cascade-4/#at-import
+    fn consume_import_rule(&mut self, allowed: bool) {
+        let name = match self.t.next() {
+            Some(CssToken::AtKeyword(name)) => name,
+            _ => return,
+        };
 
+        // ; までのトークンが、URL とメディアクエリのリストになる
+        let mut prelude = Vec::new();
         loop {
-            let token = match self.t.peek() {
-                Some(t) => t,
-                None => return None,
-            };
+            match self.t.peek() {
+                None => break,
+                Some(CssToken::SemiColon) => {
+                    self.t.next();
+                    break;
+                }
+                // @import はブロックを持たない
+                Some(CssToken::OpenCurly) => {
+                    self.consume_component_value();
+                    self.diagnostics.push(CssDiagnostic::InvalidAtRule(name));
+                    return;
+                }
+                Some(_) => prelude.extend(self.consume_component_value()),
+            }
+        }
 
-            match token {
-                CssToken::OpenCurly => {
-                    assert_eq!(self.t.next(), Some(CssToken::OpenCurly));
-                    rule.set_declarations(self.consume_list_of_declarations());
-                    return Some(rule);
+        let mut prelude = prelude
+            .into_iter()
+            .skip_while(|token| *token == CssToken::Whitespace);
+        // url(a.css)、"a.css"、url("a.css") のいずれかの形式で URL を書く
+        let url = match prelude.next() {
+            Some(CssToken::Url(url)) | Some(CssToken::StringToken(url)) => url,
+            Some(CssToken::Function(function)) if function.eq_ignore_ascii_case("url") => {
+                let mut arguments = prelude
+                    .by_ref()
+                    .take_while(|token| *token != CssToken::CloseParenthesis)
+                    .filter(|token| *token != CssToken::Whitespace);
+                match (arguments.next(), arguments.next()) {
+                    (Some(CssToken::StringToken(url)), None) => url,
+                    _ => {
+                        self.diagnostics.push(CssDiagnostic::InvalidAtRule(name));
+                        return;
+                    }
                 }
-                _ => {
-                    rule.set_selector(self.consume_selector());
+            }
+            _ => {
+                self.diagnostics.push(CssDiagnostic::InvalidAtRule(name));
+                return;
+            }
+        };
+
+        if !allowed {
+            self.diagnostics.push(CssDiagnostic::MisplacedImport(url));
+            return;
+        }
+        let media = parse_media_query_list(&prelude.collect::<Vec<_>>());
+        self.imports.push(ImportRule { url, media });
+    }
+
+    /// @media のルールを解釈し、ブロックの中のルールに条件を付けて `rules` に追加する
+    /// 条件はレイアウトのたびに評価するので、表示領域の大きさが変わると適用されるルールも変わる
+    /// https://www.w3.org/TR/css-conditional-3/#at-media
+    fn consume_media_rule(&mut self, rules: &mut Vec<QualifiedRule>) {
+        let name = match self.t.next() {
+            Some(CssToken::AtKeyword(name)) => name,
+            _ => return,
+        };
+
+        // { までのトークンがメディアクエリのリストになる
+        let mut prelude = Vec::new();
+        loop {
+            match self.t.peek() {
+                None => {
+                    self.diagnostics.push(CssDiagnostic::UnexpectedEndOfInput);
+                    return;
                 }
+                Some(CssToken::SemiColon) => {
+                    self.t.next();
+                    self.diagnostics.push(CssDiagnostic::InvalidAtRule(name));
+                    return;
+                }
+                // 入れ子が深すぎる場合は、ブロックごと読み飛ばす
+                Some(CssToken::OpenCurly) if self.depth >= MAX_NESTING_DEPTH => {
+                    self.consume_component_value();
+                    self.diagnostics.push(CssDiagnostic::TooDeeplyNested);
+                    return;
+                }
+                Some(CssToken::OpenCurly) => {
+                    self.t.next();
+                    break;
+                }
+                Some(_) => prelude.extend(self.consume_component_value()),
             }
         }
+
+        let media = parse_media_query_list(&prelude);
+        self.depth += 1;
+        let nested_rules = self.consume_list_of_rules(false);
+        self.depth -= 1;
+        for mut rule in nested_rules {
+            // 外側の @media の条件が先に並ぶ
+            rule.media.insert(0, media.clone());
+            rules.push(rule);
+        }
     }
 
-    fn consume_selector(&mut self) -> Selector {
-        let token = match self.t.next() {
-            Some(t) => t,
-            None => panic!("should have a token but got None"),
+    /// @ から始まるルールを、; か {} ブロックの終わりまで読み飛ばす
+    /// 本書のブラウザでは @media 以外の @ から始まるルールはサポートしない
+    /// https://www.w3.org/TR/css-syntax-3/#consume-at-rule
+    fn consume_at_rule(&mut self) {
+        let name = match self.t.next() {
+            Some(CssToken::AtKeyword(name)) => name,
+            _ => return,
         };
 
-        match token {
-            CssToken::HashToken(value) => Selector::IdSelector(value[1..].to_string()),
-            CssToken::Delim(delim) => {
-                if delim == '.' {
-                    return Selector::ClassSelector(self.consume_ident());
+        loop {
+            match self.t.peek() {
+                None => break,
+                Some(CssToken::SemiColon) => {
+                    self.t.next();
+                    break;
+                }
+                Some(CssToken::OpenCurly) => {
+                    self.consume_component_value();
+                    break;
+                }
+                Some(_) => {
+                    self.consume_component_value();
                 }
-                panic!("Parse error: {:?} is an unexpected token.", token);
             }
-            CssToken::Ident(ident) => {
-                // a:hover のようなセレクタはタイプセレクタとして扱うため、もし
-                // コロン(:) が出てきた場合は宣言ブロックの開始直前までトークンを進める
-                if self.t.peek() == Some(&CssToken::Colon) {
-                    while self.t.peek() != Some(&CssToken::OpenCurly) {
-                        self.t.next();
-                    }
+        }
+        self.diagnostics
+            .push(CssDiagnostic::UnsupportedAtRule(name));
+    }
+
+    /// https://www.w3.org/TR/css-syntax-3/#consume-qualified-rule
+    fn consume_qualified_rule(&mut self) -> Option<QualifiedRule> {
+        let mut rule = QualifiedRule::new();
+        rule.set_selector(self.consume_selector());
+
+        if self.t.next() != Some(CssToken::OpenCurly) {
+            self.diagnostics.push(CssDiagnostic::UnexpectedEndOfInput);
+            return None;
+        }
+        rule.set_declarations(self.consume_list_of_declarations());
+
+        // セレクタが不正な場合は、宣言ブロックを読み終えてからルール全体を捨てる
+        if rule.selector == Selector::UnknownSelector {
+            self.diagnostics.push(CssDiagnostic::InvalidSelector);
+            return None;
+        }
+        Some(rule)
+    }
+
+    /// カンマで区切られたセレクタのリストを、宣言ブロックの開始 ({) か入力の最後まで解釈する
+    /// 解釈できないセレクタが含まれる場合は、リスト全体が UnknownSelector になる
+    /// https://www.w3.org/TR/selectors-4/#parse-selector
+    fn consume_selector(&mut self) -> Selector {
+        let mut selectors = Vec::new();
+
+        loop {
+            match self.consume_complex_selector() {
+                Some(selector) => selectors.push(selector),
+                None => {
+                    self.skip_selector();
+                    return Selector::UnknownSelector;
                 }
-                Selector::TypeSelector(ident.to_string())
             }
-            CssToken::AtKeyword(_keyword) => {
-                // @ から始まるルールを無視するために、宣言ブロックの開始直前までトークンを進める
-                while self.t.peek() != Some(&CssToken::OpenCurly) {
+
+            match self.t.peek() {
+                Some(CssToken::Delim(',')) => {
                     self.t.next();
                 }
-                Selector::UnknownSelector
+                None | Some(CssToken::OpenCurly) => break,
+                Some(_) => {
+                    self.skip_selector();
+                    return Selector::UnknownSelector;
+                }
             }
-            _ => {
+        }
+
+        if selectors.len() == 1 {
+            return selectors.remove(0);
+        }
+        Selector::SelectorList(selectors)
+    }
+
+    /// 宣言ブロックの開始直前までトークンを進める
+    fn skip_selector(&mut self) {
+        while self.t.peek().is_some() && self.t.peek() != Some(&CssToken::OpenCurly) {
+            self.t.next();
+        }
+    }
+
+    /// 空白文字を読み飛ばし、読み飛ばした場合は true を返す
+    fn skip_whitespace(&mut self) -> bool {
+        let mut skipped = false;
+        while self.t.peek() == Some(&CssToken::Whitespace) {
+            self.t.next();
+            skipped = true;
+        }
+        skipped
+    }
+
+    /// 結合子でつながった複合セレクタの並びを、左から順に ComplexSelector に入れ子にする
+    fn consume_complex_selector(&mut self) -> Option<Selector> {
+        self.skip_whitespace();
+        let mut selector = self.consume_compound_selector()?;
+
+        loop {
+            let whitespace = self.skip_whitespace();
+            let combinator = match self.t.peek() {
+                Some(CssToken::Delim('>')) => Combinator::Child,
+                Some(CssToken::Delim('+')) => Combinator::NextSibling,
+                Some(CssToken::Delim('~')) => Combinator::SubsequentSibling,
+                None
+                | Some(CssToken::Delim(','))
+                | Some(CssToken::OpenCurly)
+                | Some(CssToken::CloseParenthesis) => return Some(selector),
+                // 結合子を書かずに空白文字で区切った場合は、子孫結合子になる
+                Some(_) if whitespace => Combinator::Descendant,
+                Some(_) => return None,
+            };
+            if combinator != Combinator::Descendant {
                 self.t.next();
-                Selector::UnknownSelector
+                self.skip_whitespace();
+            }
+
+            selector = Selector::ComplexSelector {
+                left: Box::new(selector),
+                combinator,
+                right: Box::new(self.consume_compound_selector()?),
+            };
+        }
+    }
+
+    /// 空白文字を挟まずに並んだセレクタを解釈する。タイプセレクタと * は先頭にのみ書ける
+    fn consume_compound_selector(&mut self) -> Option<Selector> {
+        let mut selectors = Vec::new();
+
+        loop {
+            let selector = match self.t.peek() {
+                Some(CssToken::Ident(_)) if selectors.is_empty() => match self.t.next() {
+                    Some(CssToken::Ident(name)) => Selector::TypeSelector(name),
+                    _ => return None,
+                },
+                Some(CssToken::Delim('*')) if selectors.is_empty() => {
+                    self.t.next();
+                    Selector::UniversalSelector
+                }
+                Some(CssToken::HashToken(_)) => match self.t.next() {
+                    Some(CssToken::HashToken(value)) => {
+                        Selector::IdSelector(value[1..].to_string())
+                    }
+                    _ => return None,
+                },
+                Some(CssToken::Delim('.')) => {
+                    self.t.next();
+                    match self.t.next() {
+                        Some(CssToken::Ident(class_name)) => Selector::ClassSelector(class_name),
+                        _ => return None,
+                    }
+                }
+                Some(CssToken::OpenSquareBracket) => {
+                    self.t.next();
+                    self.consume_attribute_selector()?
+                }
+                Some(CssToken::Colon) => {
+                    self.t.next();
+                    Selector::PseudoClassSelector(self.consume_pseudo_class()?)
+                }
+                _ => break,
+            };
+            selectors.push(selector);
+        }
+
+        match selectors.len() {
+            0 => None,
+            1 => selectors.pop(),
+            _ => Some(Selector::CompoundSelector(selectors)),
+        }
+    }
+
+    /// [ の直後から ] までを属性セレクタとして解釈する
+    /// https://www.w3.org/TR/selectors-4/#attribute-selectors
+    fn consume_attribute_selector(&mut self) -> Option<Selector> {
+        self.skip_whitespace();
+        // HTML 文書の属性名は大文字と小文字を区別しない
+        let name = match self.t.next() {
+            Some(CssToken::Ident(name)) => name.to_ascii_lowercase(),
+            _ => return None,
+        };
+        self.skip_whitespace();
+
+        let operator = match self.t.next() {
+            Some(CssToken::CloseSquareBracket) => {
+                return Some(Selector::AttributeSelector {
+                    name,
+                    operator: AttributeOperator::Exists,
+                    value: String::new(),
+                })
             }
+            Some(CssToken::Delim('=')) => AttributeOperator::Equals,
+            Some(CssToken::Delim(c)) => {
+                let operator = match c {
+                    '~' => AttributeOperator::Includes,
+                    '|' => AttributeOperator::DashMatch,
+                    '^' => AttributeOperator::Prefix,
+                    '$' => AttributeOperator::Suffix,
+                    '*' => AttributeOperator::Substring,
+                    _ => return None,
+                };
+                if self.t.next() != Some(CssToken::Delim('=')) {
+                    return None;
+                }
+                operator
+            }
+            _ => return None,
+        };
+        self.skip_whitespace();
+
+        let value = match self.t.next() {
+            Some(CssToken::Ident(value)) | Some(CssToken::StringToken(value)) => value,
+            _ => return None,
+        };
+        self.skip_whitespace();
+        if self.t.next() != Some(CssToken::CloseSquareBracket) {
+            return None;
         }
+
+        Some(Selector::AttributeSelector {
+            name,
+            operator,
+            value,
+        })
     }
 
+    /// : の直後から擬似クラスを解釈する。サポートしない擬似クラスは解釈できないセレクタとして扱う
+    fn consume_pseudo_class(&mut self) -> Option<PseudoClass> {
+        // 引数をとる擬似クラスは、:nth-child( のように <function-token> になる
+        let (name, has_arguments) = match self.t.next() {
+            Some(CssToken::Ident(name)) => (name.to_ascii_lowercase(), false),
+            Some(CssToken::Function(name)) => (name.to_ascii_lowercase(), true),
+            _ => return None,
+        };
+
+        if !has_arguments {
+            return match name.as_str() {
+                "first-child" => Some(PseudoClass::FirstChild),
+                "last-child" => Some(PseudoClass::LastChild),
+                "empty" => Some(PseudoClass::Empty),
+                "root" => Some(PseudoClass::Root),
+                "hover" => Some(PseudoClass::Hover),
+                "active" => Some(PseudoClass::Active),
+                "focus" => Some(PseudoClass::Focus),
+                "link" => Some(PseudoClass::Link),
+                "visited" => Some(PseudoClass::Visited),
+                _ => None,
+            };
+        }
+
+        self.skip_whitespace();
+        let pseudo_class = match name.as_str() {
+            "nth-child" => {
+                let (a, b) = self.consume_an_plus_b()?;
+                PseudoClass::NthChild { a, b }
+            }
+            "not" => PseudoClass::Not(Box::new(self.consume_nested_selector_list()?)),
+            _ => return None,
+        };
+        self.skip_whitespace();
+        if self.t.next() != Some(CssToken::CloseParenthesis) {
+            return None;
+        }
+        Some(pseudo_class)
+    }
+
+    /// :not() の引数のような、括弧の中のセレクタリストを解釈する
+    /// 入れ子が深すぎる場合は、解釈できないセレクタとして扱う
+    fn consume_nested_selector_list(&mut self) -> Option<Selector> {
+        if self.depth >= MAX_NESTING_DEPTH {
+            self.diagnostics.push(CssDiagnostic::TooDeeplyNested);
+            return None;
+        }
+
+        self.depth += 1;
+        let mut selectors = Vec::new();
+        let result = loop {
+            match self.consume_complex_selector() {
+                Some(selector) => selectors.push(selector),
+                None => break None,
+            }
+            if self.t.peek() != Some(&CssToken::Delim(',')) {
+                break Some(());
+            }
+            self.t.next();
+        };
+        self.depth -= 1;
+        result?;
+
+        if selectors.len() == 1 {
+            return selectors.pop();
+        }
+        Some(Selector::SelectorList(selectors))
+    }
+
+    /// :nth-child() の引数の an+b を、閉じ括弧の直前まで解釈する
+    /// https://www.w3.org/TR/css-syntax-3/#anb-microsyntax
+    fn consume_an_plus_b(&mut self) -> Option<(i32, i32)> {
+        // 2n+1 は Dimension と Number のように分かれたトークンになるので、
+        // いったん文字列に戻してから解釈する
+        let mut s = String::new();
+        loop {
+            match self.t.peek() {
+                Some(CssToken::CloseParenthesis) | None => break,
+                Some(CssToken::Whitespace) => {}
+                Some(CssToken::Number(n)) => {
+                    // 整数でない場合は an+b として正しくない
+                    if *n != (*n as i32) as f64 {
+                        return None;
+                    }
+                    // +1 の符号はトークンに残らないので、2n の後ろでは + を補う
+                    if *n >= 0.0 && !s.is_empty() && !s.ends_with(['+', '-']) {
+                        s.push('+');
+                    }
+                    s.push_str(&(*n as i32).to_string());
+                }
+                Some(CssToken::Dimension(n, unit)) => {
+                    if *n != (*n as i32) as f64 {
+                        return None;
+                    }
+                    s.push_str(&(*n as i32).to_string());
+                    s.push_str(unit);
+                }
+                Some(CssToken::Ident(ident)) => s.push_str(ident),
+                Some(CssToken::Delim(c)) => s.push(*c),
+                Some(_) => return None,
+            }
+            self.t.next();
+        }
+
+        parse_an_plus_b(&s.to_ascii_lowercase())
+    }
+
+    /// 宣言のリストを、宣言ブロックの終わり (}) か入力の最後まで解釈する
+    /// 不正な宣言は捨てて、次の ; の後ろから解析を続ける
+    /// https://www.w3.org/TR/css-syntax-3/#consume-list-of-declarations
     fn consume_list_of_declarations(&mut self) -> Vec<Declaration> {
         let mut declarations = Vec::new();
 
@@ -195,73 +859,151 @@ impl CssParser {
 
             match token {
                 CssToken::CloseCurly => {
-                    assert_eq!(self.t.next(), Some(CssToken::CloseCurly));
+                    self.t.next();
                     return declarations;
                 }
-                CssToken::SemiColon => {
-                    assert_eq!(self.t.next(), Some(CssToken::SemiColon));
+                CssToken::Whitespace | CssToken::SemiColon => {
                     // 一つの宣言が終了。なにもしない。
-                }
-                CssToken::Ident(ref _ident) => {
-                    if let Some(declaration) = self.consume_declaration() {
-                        declarations.push(declaration);
-                    }
+                    self.t.next();
                 }
                 _ => {
-                    self.t.next();
+                    let tokens = self.consume_declaration_tokens();
+                    match parse_declaration(tokens) {
+                        Ok(declaration) => declarations.push(declaration),
+                        Err(diagnostic) => self.diagnostics.push(diagnostic),
+                    }
                 }
             }
         }
     }
 
-    fn consume_declaration(&mut self) -> Option<Declaration> {
-        if self.t.peek().is_none() {
-            return None;
+    /// 1つの宣言のトークンを、次の ; か } の直前まで読む
+    fn consume_declaration_tokens(&mut self) -> Vec<CssToken> {
+        let mut tokens = Vec::new();
+        while !matches!(
+            self.t.peek(),
+            None | Some(CssToken::SemiColon) | Some(CssToken::CloseCurly)
+        ) {
+            tokens.extend(self.consume_component_value());
         }
+        tokens
+    }
 
-        // Declaration 構造体を初期化する
-        let mut declaration = Declaration::new();
-        // Declaration 構造体のプロパティに識別子を設定する
-        declaration.set_property(self.consume_ident());
-        // もし次のトークンが転んでない場合、パースエラーなので、None を返す
-        match self.t.next() {
-            Some(token) => match token {
-                CssToken::Colon => {}
-                _ => return None,
-            },
-            None => return None,
+    /// 1つのコンポーネント値を読み、そのトークンを返す
+    /// 括弧や関数の場合は、対応する閉じ括弧 (入力の最後で閉じられていない場合は入力の最後) までを1つの値とする
+    /// https://www.w3.org/TR/css-syntax-3/#consume-component-value
+    fn consume_component_value(&mut self) -> Vec<CssToken> {
+        let mut tokens = Vec::new();
+        // 閉じられていない括弧に対応する閉じ括弧。入れ子が深くてもスタックを使い果たさないように、再帰は使わない
+        let mut closing = Vec::new();
+
+        for token in self.t.by_ref() {
+            match token {
+                CssToken::OpenCurly => closing.push(CssToken::CloseCurly),
+                CssToken::OpenSquareBracket => closing.push(CssToken::CloseSquareBracket),
+                CssToken::OpenParenthesis | CssToken::Function(_) => {
+                    closing.push(CssToken::CloseParenthesis)
+                }
+                ref t if closing.last() == Some(t) => {
+                    closing.pop();
+                }
+                _ => {}
+            }
+            tokens.push(token);
+            if closing.is_empty() {
+                break;
+            }
         }
+        tokens
+    }
+}
 
-        // Declaration 構造体の値にコンポーネント値を設定する
-        declaration.set_value(self.consume_component_value());
+/// 1つの宣言のトークンを Declaration に変換する
+/// 本書のブラウザでは、宣言の値として最初のコンポーネント値のみを使う
+/// https://www.w3.org/TR/css-syntax-3/#consume-declaration
+fn parse_declaration(tokens: Vec<CssToken>) -> Result<Declaration, CssDiagnostic> {
+    let mut tokens = tokens
+        .into_iter()
+        .filter(|token| *token != CssToken::Whitespace);
 
-        Some(declaration)
+    // 宣言はプロパティ名から始まる
+    let property = match tokens.next() {
+        Some(CssToken::Ident(property)) => property,
+        _ => return Err(CssDiagnostic::InvalidDeclaration(String::new())),
+    };
+    let invalid = CssDiagnostic::InvalidDeclaration(property.clone());
+
+    // もし次のトークンがコロンでない場合、パースエラーなので、宣言を捨てる
+    if tokens.next() != Some(CssToken::Colon) {
+        return Err(invalid);
     }
 
-    fn consume_ident(&mut self) -> String {
-        let token = match self.t.next() {
-            Some(t) => t,
-            None => panic!("should have a token but got None"),
-        };
+    let mut values: Vec<ComponentValue> = tokens.collect();
+    let mut declaration = Declaration::new();
+    declaration.set_property(property);
 
-        match token {
-            CssToken::Ident(ident) => ident.to_string(),
-            _ => {
-                panic!("Parse error: {:?} is an unexpected token.", token);
-            }
+    // 値の後ろに !important が続く場合は、重要な宣言になる
+    if let [.., CssToken::Delim('!'), CssToken::Ident(ident)] = values.as_slice() {
+        if ident.eq_ignore_ascii_case("important") {
+            values.truncate(values.len() - 2);
+            declaration.set_important(true);
         }
     }
 
-    fn consume_component_value(&mut self) -> ComponentValue {
-        self.t
-            .next()
-            .expect("should have a token in consume_component_value")
+    // 値がない宣言、!important 以外の ! を含む宣言、不正な文字列や URL を含む宣言は捨てる
+    if values.is_empty()
+        || values.iter().any(|value| {
+            matches!(
+                value,
+                CssToken::Delim('!') | CssToken::BadString | CssToken::BadUrl
+            )
+        })
+    {
+        return Err(invalid);
     }
+
+    declaration.set_value(values.remove(0));
+    Ok(declaration)
+}
+
+/// an+b の形式の文字列を (a, b) に変換する。odd と even のキーワードも受け付ける
+fn parse_an_plus_b(s: &str) -> Option<(i32, i32)> {
+    match s {
+        "odd" => return Some((2, 1)),
+        "even" => return Some((2, 0)),
+        _ => {}
+    }
+
+    let (a, b) = match s.find('n') {
+        Some(i) => {
+            let a = match &s[..i] {
+                "" | "+" => 1,
+                "-" => -1,
+                a => a.parse::<i32>().ok()?,
+            };
+            (a, &s[i + 1..])
+        }
+        None => (0, s),
+    };
+    let b = match b {
+        "" if a != 0 => 0,
+        b => {
+            // n の後ろの b には符号が必要
+            if a != 0 && !b.starts_with(['+', '-']) {
+                return None;
+            }
+            b.strip_prefix('+').unwrap_or(b).parse::<i32>().ok()?
+        }
+    };
+
+    Some((a, b))
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::renderer::css::media::ColorScheme;
+    use alloc::format;
     use alloc::vec;
 
     #[test]
@@ -374,4 +1116,379 @@ mod tests {
             i += 1;
         }
     }
+
+    #[test]
+    fn test_selector_list() {
+        let t = CssTokenizer::new("p, .note,#main".to_string());
+        let selectors = CssParser::new(t).parse_selector_list();
+        assert_eq!(
+            Ok(vec![
+                Selector::TypeSelector("p".to_string()),
+                Selector::ClassSelector("note".to_string()),
+                Selector::IdSelector("main".to_string()),
+            ]),
+            selectors
+        );
+
+        let t = CssTokenizer::new("".to_string());
+        assert!(CssParser::new(t).parse_selector_list().is_err());
+        let t = CssTokenizer::new("p,".to_string());
+        assert!(CssParser::new(t).parse_selector_list().is_err());
+        let t = CssTokenizer::new("*".to_string());
+        assert_eq!(
+            Ok(vec![Selector::UniversalSelector]),
+            CssParser::new(t).parse_selector_list()
+        );
+    }
+
+    #[test]
+    fn test_complex_selector() {
+        let style = "div > p.note#x, h1 + p ~ a, body * { color: red; }".to_string();
+        let t = CssTokenizer::new(style);
+        let cssom = CssParser::new(t).parse_stylesheet();
+
+        let complex =
+            |left: Selector, combinator: Combinator, right: Selector| Selector::ComplexSelector {
+                left: Box::new(left),
+                combinator,
+                right: Box::new(right),
+            };
+        let expected = Selector::SelectorList(vec![
+            complex(
+                Selector::TypeSelector("div".to_string()),
+                Combinator::Child,
+                Selector::CompoundSelector(vec![
+                    Selector::TypeSelector("p".to_string()),
+                    Selector::ClassSelector("note".to_string()),
+                    Selector::IdSelector("x".to_string()),
+                ]),
+            ),
+            complex(
+                complex(
+                    Selector::TypeSelector("h1".to_string()),
+                    Combinator::NextSibling,
+                    Selector::TypeSelector("p".to_string()),
+                ),
+                Combinator::SubsequentSibling,
+                Selector::TypeSelector("a".to_string()),
+            ),
+            complex(
+                Selector::TypeSelector("body".to_string()),
+                Combinator::Descendant,
+                Selector::UniversalSelector,
+            ),
+        ]);
+        assert_eq!(1, cssom.rules.len());
+        assert_eq!(expected, cssom.rules[0].selector);
+        assert_eq!(1, cssom.rules[0].declarations.len());
+
+        // 解釈できないセレクタを含むルールは捨てられる
+        let t = CssTokenizer::new("p > { color: red; } h1 { color: blue; }".to_string());
+        let cssom = CssParser::new(t).parse_stylesheet();
+        assert_eq!(1, cssom.rules.len());
+        assert_eq!(
+            Selector::TypeSelector("h1".to_string()),
+            cssom.rules[0].selector
+        );
+        assert_eq!(vec![CssDiagnostic::InvalidSelector], cssom.diagnostics);
+    }
+
+    #[test]
+    fn test_attribute_and_pseudo_class_selector() {
+        let parse = |selectors: &str| {
+            let t = CssTokenizer::new(selectors.to_string());
+            CssParser::new(t).parse_selector_list()
+        };
+        let attribute =
+            |name: &str, operator: AttributeOperator, value: &str| Selector::AttributeSelector {
+                name: name.to_string(),
+                operator,
+                value: value.to_string(),
+            };
+
+        assert_eq!(
+            Ok(vec![attribute("href", AttributeOperator::Exists, "")]),
+            parse("[HREF]")
+        );
+        assert_eq!(
+            Ok(vec![Selector::CompoundSelector(vec![
+                Selector::TypeSelector("input".to_string()),
+                attribute("type", AttributeOperator::Equals, "text"),
+                attribute("class", AttributeOperator::Includes, "a"),
+            ])]),
+            parse("input[type=\"text\"][ class ~= a ]")
+        );
+        assert_eq!(
+            Ok(vec![
+                attribute("lang", AttributeOperator::DashMatch, "en"),
+                attribute("href", AttributeOperator::Prefix, "https"),
+                attribute("href", AttributeOperator::Suffix, ".html"),
+                attribute("href", AttributeOperator::Substring, "example"),
+            ]),
+            parse("[lang|=en], [href^='https'], [href$=\".html\"], [href*=example]")
+        );
+
+        let nth = |a: i32, b: i32| Selector::PseudoClassSelector(PseudoClass::NthChild { a, b });
+        assert_eq!(
+            Ok(vec![
+                nth(2, 1),
+                nth(2, 0),
+                nth(3, -1),
+                nth(-1, 3),
+                nth(0, 5),
+                nth(1, 0)
+            ]),
+            parse(
+                ":nth-child(odd), :nth-child(EVEN), :nth-child(3n-1), \
+                 :nth-child(-n + 3), :nth-child(5), :nth-child(n)"
+            )
+        );
+        assert_eq!(
+            Ok(vec![Selector::CompoundSelector(vec![
+                Selector::TypeSelector("li".to_string()),
+                Selector::PseudoClassSelector(PseudoClass::FirstChild),
+                Selector::PseudoClassSelector(PseudoClass::Not(Box::new(Selector::SelectorList(
+                    vec![
+                        Selector::ClassSelector("a".to_string()),
+                        Selector::PseudoClassSelector(PseudoClass::Empty),
+                    ]
+                )))),
+            ])]),
+            parse("li:first-child:not(.a, :empty)")
+        );
+        assert_eq!(
+            Ok(vec![
+                Selector::PseudoClassSelector(PseudoClass::Root),
+                Selector::PseudoClassSelector(PseudoClass::LastChild),
+            ]),
+            parse(":root, :last-child")
+        );
+        assert_eq!(
+            Ok(vec![Selector::ComplexSelector {
+                left: Box::new(Selector::PseudoClassSelector(PseudoClass::Hover)),
+                combinator: Combinator::Child,
+                right: Box::new(Selector::CompoundSelector(vec![
+                    Selector::TypeSelector("a".to_string()),
+                    Selector::PseudoClassSelector(PseudoClass::Visited),
+                    Selector::PseudoClassSelector(PseudoClass::Focus),
+                    Selector::PseudoClassSelector(PseudoClass::Active),
+                ])),
+            }]),
+            parse(":HOVER > a:visited:focus:active")
+        );
+        assert_eq!(
+            Ok(vec![Selector::PseudoClassSelector(PseudoClass::Link)]),
+            parse(":link")
+        );
+
+        assert!(parse("[href").is_err());
+        assert!(parse("[href=]").is_err());
+        assert!(parse("[href!=a]").is_err());
+        assert!(parse(":nth-child(2n1)").is_err());
+        assert!(parse(":nth-child()").is_err());
+        assert!(parse(":not(p").is_err());
+        assert!(parse(":unknown").is_err());
+    }
+
+    #[test]
+    fn test_specificity() {
+        let specificity = |selectors: &str| {
+            let t = CssTokenizer::new(selectors.to_string());
+            let selectors = CssParser::new(t).parse_selector_list().unwrap();
+            Selector::SelectorList(selectors).specificity()
+        };
+
+        assert_eq!(Specificity(0, 0, 0), specificity("*"));
+        assert_eq!(Specificity(0, 0, 1), specificity("p"));
+        assert_eq!(Specificity(1, 2, 1), specificity("p#a.b:hover"));
+        assert_eq!(Specificity(0, 1, 2), specificity("body > p[lang]"));
+        assert_eq!(Specificity(1, 0, 1), specificity("p:not(#a, .b)"));
+        assert_eq!(Specificity(0, 1, 0), specificity("p, .b"));
+        assert!(specificity("#a") > specificity(".a.b.c.d"));
+    }
+
+    #[test]
+    fn test_important_and_declaration_list() {
+        let t = CssTokenizer::new("color: red !important; display :none;x".to_string());
+        let declarations = CssParser::new(t).parse_declaration_list();
+
+        assert_eq!(2, declarations.len());
+        assert_eq!("color", declarations[0].property);
+        assert!(declarations[0].important);
+        assert_eq!("display", declarations[1].property);
+        assert_eq!(CssToken::Ident("none".to_string()), declarations[1].value);
+        assert!(!declarations[1].important);
+    }
+
+    #[test]
+    fn test_error_recovery() {
+        let style = "p { color: red; } \
+                     p:unknown, h1 { color: blue; } \
+                     @font-face { font-family: x; } \
+                     h1 { color red; display: block; 12px; color: green !imp; \
+                          background-color: url(a b); margin-top: rgb(1, 2); } \
+                     h2 { color: navy;"
+            .to_string();
+        let sheet = CssParser::new(CssTokenizer::new(style)).parse_stylesheet();
+
+        // 不正なルールと宣言だけが捨てられ、その後ろのルールと宣言は解釈される
+        let selectors: Vec<Selector> = sheet.rules.iter().map(|r| r.selector.clone()).collect();
+        assert_eq!(
+            vec![
+                Selector::TypeSelector("p".to_string()),
+                Selector::TypeSelector("h1".to_string()),
+                Selector::TypeSelector("h2".to_string()),
+            ],
+            selectors
+        );
+        let properties: Vec<&str> = sheet.rules[1]
+            .declarations
+            .iter()
+            .map(|d| d.property.as_str())
+            .collect();
+        assert_eq!(vec!["display", "margin-top"], properties);
+        assert_eq!(
+            CssToken::Function("rgb".to_string()),
+            sheet.rules[1].declarations[1].value
+        );
+        assert_eq!(1, sheet.rules[2].declarations.len());
+
+        assert_eq!(
+            vec![
+                CssDiagnostic::InvalidSelector,
+                CssDiagnostic::UnsupportedAtRule("font-face".to_string()),
+                CssDiagnostic::InvalidDeclaration("color".to_string()),
+                CssDiagnostic::InvalidDeclaration("".to_string()),
+                CssDiagnostic::InvalidDeclaration("color".to_string()),
+                CssDiagnostic::InvalidDeclaration("background-color".to_string()),
+            ],
+            sheet.diagnostics
+        );
+    }
+
+    #[test]
+    fn test_never_panics() {
+        // 不完全な入力でもパニックせず、最後まで解釈を終える
+        for style in [
+            "{",
+            "}",
+            "p {",
+            "p",
+            ":",
+            "@media",
+            "@media {",
+            "p { color: }",
+            "p { : red }",
+            "[",
+            "a[href",
+            "p { color: rgb(1, 2; }",
+            "p { ((([[[{{{ }",
+            "} p { color: red }",
+            "p { color: red } }",
+            "p, { }",
+            "@import url(a.css)",
+            "<!-- p { } -->",
+        ] {
+            let mut parser = CssParser::new(CssTokenizer::new(style.to_string()));
+            let _ = parser.parse_stylesheet();
+            let mut parser = CssParser::new(CssTokenizer::new(style.to_string()));
+            let _ = parser.parse_declaration_list();
+        }
+    }
+
+    #[test]
+    fn test_too_deeply_nested() {
+        let depth = MAX_NESTING_DEPTH + 1;
+        let style = format!(
+            "{}p {{ color: red; }}{} h1 {{ color: blue; }}",
+            "@media screen { ".repeat(depth),
+            " }".repeat(depth)
+        );
+        let sheet = CssParser::new(CssTokenizer::new(style)).parse_stylesheet();
+        // 深すぎる @media のブロックは読み飛ばし、その後ろのルールから解釈を続ける
+        assert_eq!(1, sheet.rules.len());
+        assert_eq!(
+            Selector::TypeSelector("h1".to_string()),
+            sheet.rules[0].selector
+        );
+        assert_eq!(vec![CssDiagnostic::TooDeeplyNested], sheet.diagnostics);
+
+        let style = format!(
+            "p{}a{} {{ color: red; }} h1 {{ color: blue; }}",
+            ":not(".repeat(depth),
+            ")".repeat(depth)
+        );
+        let sheet = CssParser::new(CssTokenizer::new(style)).parse_stylesheet();
+        assert_eq!(1, sheet.rules.len());
+        assert_eq!(
+            vec![
+                CssDiagnostic::TooDeeplyNested,
+                CssDiagnostic::InvalidSelector
+            ],
+            sheet.diagnostics
+        );
+
+        // 上限までの入れ子は解釈できる
+        let style = format!(
+            "{}p {{ color: red; }}{}",
+            "@media screen { ".repeat(MAX_NESTING_DEPTH),
+            " }".repeat(MAX_NESTING_DEPTH)
+        );
+        let sheet = CssParser::new(CssTokenizer::new(style)).parse_stylesheet();
+        assert_eq!(MAX_NESTING_DEPTH, sheet.rules[0].media.len());
+    }
+
+    #[test]
+    fn test_media_rule() {
+        let style =
+            "@media screen and (min-width: 600px) { p { color: red; } @media print { h1 {} } } \
+                     @media screen; h2 { color: blue; }"
+                .to_string();
+        let sheet = CssParser::new(CssTokenizer::new(style)).parse_stylesheet();
+
+        // @media の中のルールは、外側から順に条件を持つ
+        let media: Vec<usize> = sheet.rules.iter().map(|r| r.media.len()).collect();
+        assert_eq!(vec![1, 2, 0], media);
+        assert_eq!(
+            Selector::TypeSelector("h2".to_string()),
+            sheet.rules[2].selector
+        );
+        assert_eq!(
+            vec![CssDiagnostic::InvalidAtRule("media".to_string())],
+            sheet.diagnostics
+        );
+
+        let wide = MediaEnvironment::new(800, 400, ColorScheme::Light);
+        let narrow = MediaEnvironment::new(400, 400, ColorScheme::Light);
+        assert_eq!(2, sheet.matching_media(&wide).rules.len());
+        assert_eq!(1, sheet.matching_media(&narrow).rules.len());
+    }
+
+    #[test]
+    fn test_import_rule() {
+        let style = "@import url(a.css); @import \"b.css\" screen and (min-width: 600px); \
+                     @import url( \"c.css\" ) print; @import; \
+                     p { color: red; } @import url(d.css);"
+            .to_string();
+        let sheet = CssParser::new(CssTokenizer::new(style)).parse_stylesheet();
+
+        let urls: Vec<&str> = sheet.imports.iter().map(|i| i.url.as_str()).collect();
+        assert_eq!(vec!["a.css", "b.css", "c.css"], urls);
+        let wide = MediaEnvironment::new(800, 400, ColorScheme::Light);
+        let narrow = MediaEnvironment::new(400, 400, ColorScheme::Light);
+        assert!(sheet.imports[0].media.matches(&narrow));
+        assert!(sheet.imports[1].media.matches(&wide));
+        assert!(!sheet.imports[1].media.matches(&narrow));
+        assert!(!sheet.imports[2].media.matches(&wide));
+
+        // ルールの後ろに書かれた @import は無視される
+        assert_eq!(1, sheet.rules.len());
+        assert_eq!(
+            vec![
+                CssDiagnostic::InvalidAtRule("import".to_string()),
+                CssDiagnostic::MisplacedImport("d.css".to_string()),
+            ],
+            sheet.diagnostics
+        );
+    }
 }
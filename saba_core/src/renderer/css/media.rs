@@ -0,0 +1,427 @@
+//! メディアクエリ。@media のルールを、表示領域の大きさやユーザーの設定に応じて適用する
+//! https://www.w3.org/TR/mediaqueries-4/
+
+use crate::constants::CONTENT_AREA_HEIGHT;
+use crate::constants::CONTENT_AREA_WIDTH;
+use crate::renderer::css::token::CssToken;
+use alloc::string::String;
+use alloc::vec::Vec;
+
+/// ユーザーが好む配色
+/// https://www.w3.org/TR/mediaqueries-5/#prefers-color-scheme
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ColorScheme {
+    Light,
+    Dark,
+}
+
+/// メディアクエリを評価する環境。表示領域の大きさ (px) とユーザーの設定を持つ
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct MediaEnvironment {
+    width: i64,
+    height: i64,
+    color_scheme: ColorScheme,
+}
+
+impl MediaEnvironment {
+    pub fn new(width: i64, height: i64, color_scheme: ColorScheme) -> Self {
+        Self {
+            width,
+            height,
+            color_scheme,
+        }
+    }
+
+    pub fn width(&self) -> i64 {
+        self.width
+    }
+
+    pub fn height(&self) -> i64 {
+        self.height
+    }
+
+    pub fn color_scheme(&self) -> ColorScheme {
+        self.color_scheme
+    }
+}
+
+impl Default for MediaEnvironment {
+    /// ブラウザのコンテンツエリアの大きさと、明るい配色
+    fn default() -> Self {
+        Self::new(CONTENT_AREA_WIDTH, CONTENT_AREA_HEIGHT, ColorScheme::Light)
+    }
+}
+
+/// カンマで区切られたメディアクエリのリスト。いずれかのクエリに一致すれば一致する
+/// 空のリストは、すべての環境に一致する
+/// https://www.w3.org/TR/mediaqueries-4/#mq-list
+#[derive(Debug, Clone, PartialEq)]
+pub struct MediaQueryList(pub Vec<MediaQuery>);
+
+impl MediaQueryList {
+    pub fn matches(&self, environment: &MediaEnvironment) -> bool {
+        self.0.is_empty() || self.0.iter().any(|query| query.matches(environment))
+    }
+}
+
+/// `not screen and (min-width: 600px)` のような1つのメディアクエリ
+/// https://www.w3.org/TR/mediaqueries-4/#media
+#[derive(Debug, Clone, PartialEq)]
+pub struct MediaQuery {
+    // not が付いている場合、メディアの種類と条件の評価結果を反転する
+    negated: bool,
+    media_type: MediaType,
+    // and で結ばれた条件。すべての条件を満たす場合に一致する
+    features: Vec<MediaFeature>,
+}
+
+impl MediaQuery {
+    /// 解釈できないメディアクエリは、どの環境にも一致しない `not all` として扱う
+    /// https://www.w3.org/TR/mediaqueries-4/#error-handling
+    fn not_all() -> Self {
+        Self {
+            negated: true,
+            media_type: MediaType::All,
+            features: Vec::new(),
+        }
+    }
+
+    pub fn matches(&self, environment: &MediaEnvironment) -> bool {
+        let matches = self.media_type.matches()
+            && self
+                .features
+                .iter()
+                .all(|feature| feature.matches(environment));
+        matches != self.negated
+    }
+}
+
+/// https://www.w3.org/TR/mediaqueries-4/#media-types
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MediaType {
+    All,
+    Screen,
+    Print,
+    /// サポートしないメディアの種類。どの環境にも一致しない
+    Unknown(String),
+}
+
+impl MediaType {
+    /// 本書のブラウザは画面に描画するので、all と screen にのみ一致する
+    fn matches(&self) -> bool {
+        matches!(self, MediaType::All | MediaType::Screen)
+    }
+}
+
+/// 括弧で囲まれた1つの条件
+/// https://www.w3.org/TR/mediaqueries-4/#mq-features
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum MediaFeature {
+    /// 表示領域の幅を値 (px) と比べる。(min-width: 600px) は (width >= 600px) と同じになる
+    Width(Comparison, i64),
+    /// 表示領域の高さを値 (px) と比べる
+    Height(Comparison, i64),
+    PrefersColorScheme(ColorScheme),
+}
+
+impl MediaFeature {
+    fn matches(&self, environment: &MediaEnvironment) -> bool {
+        match self {
+            MediaFeature::Width(comparison, value) => comparison.compare(environment.width, *value),
+            MediaFeature::Height(comparison, value) => {
+                comparison.compare(environment.height, *value)
+            }
+            MediaFeature::PrefersColorScheme(scheme) => environment.color_scheme == *scheme,
+        }
+    }
+}
+
+/// 範囲を表す条件の比較演算子
+/// https://www.w3.org/TR/mediaqueries-4/#mq-range-context
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Comparison {
+    Less,
+    LessOrEqual,
+    Equal,
+    GreaterOrEqual,
+    Greater,
+}
+
+impl Comparison {
+    fn compare(&self, actual: i64, value: i64) -> bool {
+        match self {
+            Comparison::Less => actual < value,
+            Comparison::LessOrEqual => actual <= value,
+            Comparison::Equal => actual == value,
+            Comparison::GreaterOrEqual => actual >= value,
+            Comparison::Greater => actual > value,
+        }
+    }
+
+    /// 値が左に書かれた場合 (600px <= width) の比較を、値が右に書かれた場合の比較にする
+    fn flip(&self) -> Self {
+        match self {
+            Comparison::Less => Comparison::Greater,
+            Comparison::LessOrEqual => Comparison::GreaterOrEqual,
+            Comparison::Equal => Comparison::Equal,
+            Comparison::GreaterOrEqual => Comparison::LessOrEqual,
+            Comparison::Greater => Comparison::Less,
+        }
+    }
+}
+
+/// @media の後ろに書かれたトークン列を、メディアクエリのリストとして解釈する
+/// https://www.w3.org/TR/mediaqueries-4/#typedef-media-query-list
+pub fn parse_media_query_list(tokens: &[CssToken]) -> MediaQueryList {
+    let tokens: Vec<&CssToken> = tokens
+        .iter()
+        .filter(|token| **token != CssToken::Whitespace)
+        .collect();
+    if tokens.is_empty() {
+        return MediaQueryList(Vec::new());
+    }
+
+    let queries = tokens
+        .split(|token| **token == CssToken::Delim(','))
+        .map(|query| parse_media_query(query).unwrap_or_else(MediaQuery::not_all))
+        .collect();
+    MediaQueryList(queries)
+}
+
+/// 空白文字を除いたトークン列を、1つのメディアクエリとして解釈する
+fn parse_media_query(tokens: &[&CssToken]) -> Option<MediaQuery> {
+    let mut query = MediaQuery {
+        negated: false,
+        media_type: MediaType::All,
+        features: Vec::new(),
+    };
+    let mut rest = tokens;
+
+    // [not | only]? <media-type> [and <条件>]? または <条件>
+    if let [CssToken::Ident(keyword), tail @ ..] = rest {
+        if keyword.eq_ignore_ascii_case("not") {
+            query.negated = true;
+            rest = tail;
+        } else if keyword.eq_ignore_ascii_case("only") {
+            // only は古いブラウザにクエリを無視させるためのもので、評価には影響しない
+            rest = tail;
+        }
+    }
+    match rest {
+        [CssToken::Ident(media_type), tail @ ..] => {
+            query.media_type = match media_type.to_ascii_lowercase().as_str() {
+                // 条件の前に書かれるべきキーワードは、メディアの種類として使えない
+                "not" | "only" | "and" | "or" => return None,
+                "all" => MediaType::All,
+                "screen" => MediaType::Screen,
+                "print" => MediaType::Print,
+                other => MediaType::Unknown(other.into()),
+            };
+            rest = tail;
+            if rest.is_empty() {
+                return Some(query);
+            }
+            match rest {
+                [CssToken::Ident(and), tail @ ..] if and.eq_ignore_ascii_case("and") => rest = tail,
+                _ => return None,
+            }
+        }
+        // only の後ろには、メディアの種類が必要
+        _ if tokens.len() != rest.len() && !query.negated => return None,
+        _ => {}
+    }
+
+    // (条件) and (条件) and ...
+    loop {
+        let (features, tail) = parse_media_feature(rest)?;
+        query.features.extend(features);
+        rest = match tail {
+            [] => return Some(query),
+            [CssToken::Ident(and), tail @ ..] if and.eq_ignore_ascii_case("and") => tail,
+            _ => return None,
+        };
+    }
+}
+
+/// 先頭の括弧で囲まれた条件を解釈し、条件と残りのトークン列を返す
+/// 600px <= width <= 800px のように2つの比較を含む条件は、2つの条件になる
+fn parse_media_feature<'a>(
+    tokens: &'a [&'a CssToken],
+) -> Option<(Vec<MediaFeature>, &'a [&'a CssToken])> {
+    let (inner, rest) = match tokens {
+        [CssToken::OpenParenthesis, tail @ ..] => {
+            let close = tail
+                .iter()
+                .position(|token| **token == CssToken::CloseParenthesis)?;
+            (&tail[..close], &tail[close + 1..])
+        }
+        _ => return None,
+    };
+
+    let features = match inner {
+        // (width) のような真偽値としての評価
+        [CssToken::Ident(name)] => match name.to_ascii_lowercase().as_str() {
+            "width" => Vec::from([MediaFeature::Width(Comparison::Greater, 0)]),
+            "height" => Vec::from([MediaFeature::Height(Comparison::Greater, 0)]),
+            _ => return None,
+        },
+        // (min-width: 600px) や (prefers-color-scheme: dark) のような、名前と値の組
+        [CssToken::Ident(name), CssToken::Colon, value] => {
+            Vec::from([parse_plain_feature(&name.to_ascii_lowercase(), value)?])
+        }
+        // (width >= 600px)、(600px <= width)、(600px <= width <= 800px) のような範囲
+        _ => parse_range_feature(inner)?,
+    };
+    Some((features, rest))
+}
+
+fn parse_plain_feature(name: &str, value: &CssToken) -> Option<MediaFeature> {
+    let feature = match name {
+        "width" => MediaFeature::Width(Comparison::Equal, length(value)?),
+        "min-width" => MediaFeature::Width(Comparison::GreaterOrEqual, length(value)?),
+        "max-width" => MediaFeature::Width(Comparison::LessOrEqual, length(value)?),
+        "height" => MediaFeature::Height(Comparison::Equal, length(value)?),
+        "min-height" => MediaFeature::Height(Comparison::GreaterOrEqual, length(value)?),
+        "max-height" => MediaFeature::Height(Comparison::LessOrEqual, length(value)?),
+        "prefers-color-scheme" => match value {
+            CssToken::Ident(scheme) if scheme.eq_ignore_ascii_case("light") => {
+                MediaFeature::PrefersColorScheme(ColorScheme::Light)
+            }
+            CssToken::Ident(scheme) if scheme.eq_ignore_ascii_case("dark") => {
+                MediaFeature::PrefersColorScheme(ColorScheme::Dark)
+            }
+            _ => return None,
+        },
+        _ => return None,
+    };
+    Some(feature)
+}
+
+/// 範囲の構文で書かれた条件を解釈する
+/// https://www.w3.org/TR/mediaqueries-4/#mq-range-context
+fn parse_range_feature(tokens: &[&CssToken]) -> Option<Vec<MediaFeature>> {
+    // 比較演算子で区切られた項に分ける。<= と >= は2つの <delim-token> になる
+    let mut terms: Vec<&CssToken> = Vec::new();
+    let mut comparisons: Vec<Comparison> = Vec::new();
+    let mut rest = tokens;
+    while let [term, tail @ ..] = rest {
+        terms.push(term);
+        let (comparison, tail) = match tail {
+            [] => break,
+            [CssToken::Delim('<'), CssToken::Delim('='), tail @ ..] => {
+                (Comparison::LessOrEqual, tail)
+            }
+            [CssToken::Delim('>'), CssToken::Delim('='), tail @ ..] => {
+                (Comparison::GreaterOrEqual, tail)
+            }
+            [CssToken::Delim('<'), tail @ ..] => (Comparison::Less, tail),
+            [CssToken::Delim('>'), tail @ ..] => (Comparison::Greater, tail),
+            [CssToken::Delim('='), tail @ ..] => (Comparison::Equal, tail),
+            _ => return None,
+        };
+        comparisons.push(comparison);
+        rest = tail;
+    }
+
+    match (terms.as_slice(), comparisons.as_slice()) {
+        // width >= 600px
+        ([CssToken::Ident(name), value], [comparison]) => {
+            Some(Vec::from([range_feature(name, *comparison, value)?]))
+        }
+        // 600px <= width
+        ([value, CssToken::Ident(name)], [comparison]) => {
+            Some(Vec::from([range_feature(name, comparison.flip(), value)?]))
+        }
+        // 600px <= width <= 800px。2つの比較は同じ向きでなければならない
+        ([low, CssToken::Ident(name), high], [first, second]) => {
+            let less = |c: &Comparison| matches!(c, Comparison::Less | Comparison::LessOrEqual);
+            let greater =
+                |c: &Comparison| matches!(c, Comparison::Greater | Comparison::GreaterOrEqual);
+            if !((less(first) && less(second)) || (greater(first) && greater(second))) {
+                return None;
+            }
+            Some(Vec::from([
+                range_feature(name, first.flip(), low)?,
+                range_feature(name, *second, high)?,
+            ]))
+        }
+        _ => None,
+    }
+}
+
+fn range_feature(name: &str, comparison: Comparison, value: &CssToken) -> Option<MediaFeature> {
+    match name.to_ascii_lowercase().as_str() {
+        "width" => Some(MediaFeature::Width(comparison, length(value)?)),
+        "height" => Some(MediaFeature::Height(comparison, length(value)?)),
+        _ => None,
+    }
+}
+
+/// 長さを px に変換する。単位は px のみサポートし、単位のない数値は 0 のみ受け付ける
+fn length(value: &CssToken) -> Option<i64> {
+    match value {
+        CssToken::Dimension(n, unit) if unit.eq_ignore_ascii_case("px") => Some(*n as i64),
+        CssToken::Number(n) if *n == 0.0 => Some(0),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::renderer::css::token::CssTokenizer;
+    use alloc::string::ToString;
+
+    fn matches(query: &str, environment: &MediaEnvironment) -> bool {
+        let tokens: Vec<CssToken> = CssTokenizer::new(query.to_string()).collect();
+        parse_media_query_list(&tokens).matches(environment)
+    }
+
+    #[test]
+    fn test_media_types_and_keywords() {
+        let env = MediaEnvironment::default();
+        assert!(matches("", &env));
+        assert!(matches("all", &env));
+        assert!(matches("only screen", &env));
+        assert!(!matches("print", &env));
+        assert!(matches("not print", &env));
+        assert!(!matches("tv", &env));
+        assert!(matches("print, screen", &env));
+        // 解釈できないクエリは not all になるが、ほかのクエリには影響しない
+        assert!(!matches("only", &env));
+        assert!(!matches("screen and", &env));
+        assert!(matches("screen (width), all", &env));
+    }
+
+    #[test]
+    fn test_width_and_height() {
+        let env = MediaEnvironment::new(600, 400, ColorScheme::Light);
+        assert!(matches("(min-width: 600px)", &env));
+        assert!(!matches("(max-width: 599px)", &env));
+        assert!(matches(
+            "screen and (min-width: 500px) and (max-height: 400px)",
+            &env
+        ));
+        assert!(matches("(width)", &env));
+        assert!(matches("(width: 600px)", &env));
+        assert!(matches("(width >= 600px)", &env));
+        assert!(!matches("(width > 600px)", &env));
+        assert!(matches("(500px < width)", &env));
+        assert!(matches("(500px < width <= 600px)", &env));
+        assert!(!matches("(300px <= height < 400px)", &env));
+        assert!(!matches("(500px < width > 300px)", &env));
+        assert!(matches("not screen and (max-width: 500px)", &env));
+        // 単位は px のみサポートする
+        assert!(!matches("(min-width: 10em)", &env));
+    }
+
+    #[test]
+    fn test_prefers_color_scheme() {
+        let light = MediaEnvironment::new(600, 400, ColorScheme::Light);
+        let dark = MediaEnvironment::new(600, 400, ColorScheme::Dark);
+        assert!(matches("(prefers-color-scheme: light)", &light));
+        assert!(!matches("(prefers-color-scheme: dark)", &light));
+        assert!(matches("(prefers-color-scheme: dark)", &dark));
+        assert!(matches("not all and (prefers-color-scheme: light)", &dark));
+    }
+}
@@ -1,19 +1,44 @@
+//! CSS のトークナイザ
+//! https://www.w3.org/TR/css-syntax-3/#tokenization
+
+use alloc::format;
 use alloc::string::String;
 use alloc::vec::Vec;
+
 #[derive(Debug, Clone, PartialEq)]
 pub enum CssToken {
+    /// #id や #ffffff のような <hash-token>。値は先頭の # を含む
     HashToken(String),
     Delim(char),
     Number(f64),
+    /// 12px のような、単位がついた数値
+    Dimension(f64, String),
+    Percentage(f64),
     Colon,
     SemiColon,
     OpenParenthesis,
     CloseParenthesis,
     OpenCurly,
     CloseCurly,
+    OpenSquareBracket,
+    CloseSquareBracket,
     Ident(String),
+    /// rgb( のような、関数名と開き括弧。引数は後に続くトークンになる
+    Function(String),
     StringToken(String),
+    /// 途中で改行された不正な文字列
+    BadString,
+    /// url(image.png) のような、引用符で囲まれていない URL
+    Url(String),
+    /// 不正な文字を含む url(...)
+    BadUrl,
     AtKeyword(String),
+    // 連続する空白文字。子孫結合子を区別するために使う
+    Whitespace,
+    /// <!--
+    Cdo,
+    /// -->
+    Cdc,
 }
 
 #[derive(Debug, Clone, PartialEq)]
@@ -26,156 +51,424 @@ impl CssTokenizer {
     pub fn new(input: String) -> Self {
         Self {
             pos: 0,
-            input: input.chars().collect(),
+            input: preprocess(&input),
+        }
+    }
+
+    /// 現在の位置から `offset` 文字先の文字を返す。入力の最後を超える場合は None を返す
+    fn peek_char(&self, offset: usize) -> Option<char> {
+        self.input.get(self.pos + offset).copied()
+    }
+
+    /// `offset` 文字先から、有効なエスケープ (\ と改行以外の文字) が始まるかどうか
+    /// https://www.w3.org/TR/css-syntax-3/#starts-with-a-valid-escape
+    fn starts_valid_escape(&self, offset: usize) -> bool {
+        self.peek_char(offset) == Some('\\')
+            && !matches!(self.peek_char(offset + 1), None | Some('\n'))
+    }
+
+    /// `offset` 文字先から、識別子が始まるかどうか
+    /// https://www.w3.org/TR/css-syntax-3/#would-start-an-identifier
+    fn starts_identifier(&self, offset: usize) -> bool {
+        match self.peek_char(offset) {
+            Some('-') => match self.peek_char(offset + 1) {
+                Some(c) if is_name_start(c) || c == '-' => true,
+                _ => self.starts_valid_escape(offset + 1),
+            },
+            Some('\\') => self.starts_valid_escape(offset),
+            Some(c) => is_name_start(c),
+            None => false,
+        }
+    }
+
+    /// 現在の位置から、数値が始まるかどうか
+    /// https://www.w3.org/TR/css-syntax-3/#starts-with-a-number
+    fn starts_number(&self) -> bool {
+        let is_digit = |offset| matches!(self.peek_char(offset), Some('0'..='9'));
+        match self.peek_char(0) {
+            Some('+') | Some('-') => is_digit(1) || (self.peek_char(1) == Some('.') && is_digit(2)),
+            Some('.') => is_digit(1),
+            Some('0'..='9') => true,
+            _ => false,
+        }
+    }
+
+    /// コメント (/* ... */) を読み飛ばす。閉じられていないコメントは入力の最後まで続く
+    /// https://www.w3.org/TR/css-syntax-3/#consume-comments
+    fn consume_comments(&mut self) {
+        while self.peek_char(0) == Some('/') && self.peek_char(1) == Some('*') {
+            self.pos += 2;
+            loop {
+                match self.peek_char(0) {
+                    None => return,
+                    Some('*') if self.peek_char(1) == Some('/') => {
+                        self.pos += 2;
+                        break;
+                    }
+                    Some(_) => self.pos += 1,
+                }
+            }
         }
     }
 
-    fn consume_string_token(&mut self) -> String {
+    /// \ の直後からエスケープされた文字を読み、その文字を返す
+    /// https://www.w3.org/TR/css-syntax-3/#consume-escaped-code-point
+    fn consume_escaped_char(&mut self) -> char {
+        match self.peek_char(0) {
+            // 入力の最後で終わったエスケープは U+FFFD になる
+            None => '\u{FFFD}',
+            Some(c) if c.is_ascii_hexdigit() => {
+                // 最大 6 桁の 16 進数は文字コードを表す。直後の空白文字 1 つは読み飛ばす
+                let mut hex = String::new();
+                while hex.len() < 6 {
+                    match self.peek_char(0) {
+                        Some(c) if c.is_ascii_hexdigit() => {
+                            hex.push(c);
+                            self.pos += 1;
+                        }
+                        _ => break,
+                    }
+                }
+                if self.peek_char(0).is_some_and(is_whitespace) {
+                    self.pos += 1;
+                }
+                // NULL、サロゲート、範囲外の文字コードは U+FFFD になる
+                u32::from_str_radix(&hex, 16)
+                    .ok()
+                    .filter(|code| *code != 0)
+                    .and_then(char::from_u32)
+                    .unwrap_or('\u{FFFD}')
+            }
+            Some(c) => {
+                self.pos += 1;
+                c
+            }
+        }
+    }
+
+    /// 識別子に使える文字とエスケープを、続く限り読む
+    /// https://www.w3.org/TR/css-syntax-3/#consume-name
+    fn consume_name(&mut self) -> String {
         let mut s = String::new();
 
         loop {
-            if self.pos >= self.input.len() {
-                return s;
+            match self.peek_char(0) {
+                Some(c) if is_name(c) => {
+                    s.push(c);
+                    self.pos += 1;
+                }
+                Some('\\') if self.starts_valid_escape(0) => {
+                    self.pos += 1;
+                    s.push(self.consume_escaped_char());
+                }
+                // 入力の最後に達した場合、識別子はそこで終わる
+                _ => return s,
             }
+        }
+    }
+
+    /// 開始の引用符の直後から、`ending` で閉じられるまでを文字列として読む
+    /// https://www.w3.org/TR/css-syntax-3/#consume-string-token
+    fn consume_string_token(&mut self, ending: char) -> CssToken {
+        let mut s = String::new();
+
+        loop {
+            match self.peek_char(0) {
+                // 閉じられていない文字列は、入力の最後までになる
+                None => return CssToken::StringToken(s),
+                Some(c) if c == ending => {
+                    self.pos += 1;
+                    return CssToken::StringToken(s);
+                }
+                // 改行は読まずに残し、不正な文字列とする
+                Some('\n') => return CssToken::BadString,
+                Some('\\') => match self.peek_char(1) {
+                    None => self.pos += 1,
+                    // \ と改行は、文字列を次の行に続ける
+                    Some('\n') => self.pos += 2,
+                    Some(_) => {
+                        self.pos += 1;
+                        s.push(self.consume_escaped_char());
+                    }
+                },
+                Some(c) => {
+                    s.push(c);
+                    self.pos += 1;
+                }
+            }
+        }
+    }
+
+    /// 符号、小数点、指数を含む数値を読む
+    /// https://www.w3.org/TR/css-syntax-3/#consume-number
+    fn consume_number(&mut self) -> f64 {
+        let mut repr = String::new();
+
+        if let Some(sign @ ('+' | '-')) = self.peek_char(0) {
+            repr.push(sign);
             self.pos += 1;
-            let c = self.input[self.pos];
-            match c {
-                '"' | '\'' => break,
+        }
+        self.consume_digits(&mut repr);
+        if self.peek_char(0) == Some('.') && matches!(self.peek_char(1), Some('0'..='9')) {
+            repr.push('.');
+            self.pos += 1;
+            self.consume_digits(&mut repr);
+        }
+        if let Some(e @ ('e' | 'E')) = self.peek_char(0) {
+            let sign = matches!(self.peek_char(1), Some('+') | Some('-'));
+            let digit_offset = if sign { 2 } else { 1 };
+            if matches!(self.peek_char(digit_offset), Some('0'..='9')) {
+                repr.push(e);
+                if sign {
+                    repr.extend(self.peek_char(1));
+                }
+                self.pos += digit_offset;
+                self.consume_digits(&mut repr);
+            }
+        }
+
+        repr.parse::<f64>().unwrap_or(0.0)
+    }
+
+    fn consume_digits(&mut self, repr: &mut String) {
+        while let Some(c @ '0'..='9') = self.peek_char(0) {
+            repr.push(c);
+            self.pos += 1;
+        }
+    }
+
+    /// 数値と、その後に続く単位または % を読む
+    /// https://www.w3.org/TR/css-syntax-3/#consume-numeric-token
+    fn consume_numeric_token(&mut self) -> CssToken {
+        let number = self.consume_number();
+
+        if self.starts_identifier(0) {
+            return CssToken::Dimension(number, self.consume_name());
+        }
+        if self.peek_char(0) == Some('%') {
+            self.pos += 1;
+            return CssToken::Percentage(number);
+        }
+        CssToken::Number(number)
+    }
+
+    /// 識別子、関数名、url(...) のいずれかを読む
+    /// https://www.w3.org/TR/css-syntax-3/#consume-ident-like-token
+    fn consume_ident_like_token(&mut self) -> CssToken {
+        let name = self.consume_name();
+
+        if self.peek_char(0) != Some('(') {
+            return CssToken::Ident(name);
+        }
+        self.pos += 1;
 
-                _ => s.push(c),
+        if name.eq_ignore_ascii_case("url") {
+            let mut offset = 0;
+            while self.peek_char(offset).is_some_and(is_whitespace) {
+                offset += 1;
+            }
+            // 引用符で囲まれた URL は、url という名前の関数と文字列として扱う
+            if !matches!(self.peek_char(offset), Some('"') | Some('\'')) {
+                self.pos += offset;
+                return self.consume_url_token();
             }
         }
-        s
+        CssToken::Function(name)
     }
 
-    fn consume_numeric_token(&mut self) -> f64 {
-        let mut num = 0f64;
-        let mut floating = false;
-        let mut floating_digit = 1f64;
+    /// url( の直後の空白を読み飛ばした位置から、閉じ括弧までを URL として読む
+    /// https://www.w3.org/TR/css-syntax-3/#consume-url-token
+    fn consume_url_token(&mut self) -> CssToken {
+        let mut url = String::new();
 
         loop {
-            if self.pos >= self.input.len() {
-                return num;
-            }
-            let c = self.input[self.pos];
-            match c {
-                '0'..='9' => {
-                    if floating {
-                        floating_digit *= 1f64 / 10f64;
-                        num += (c.to_digit(10).unwrap() as f64) * floating_digit
-                    } else {
-                        num = num * 10.0 + (c.to_digit(10).unwrap() as f64);
+            match self.peek_char(0) {
+                None => return CssToken::Url(url),
+                Some(')') => {
+                    self.pos += 1;
+                    return CssToken::Url(url);
+                }
+                Some(c) if is_whitespace(c) => {
+                    // URL の後ろの空白は、閉じ括弧の前でだけ許される
+                    while self.peek_char(0).is_some_and(is_whitespace) {
+                        self.pos += 1;
+                    }
+                    match self.peek_char(0) {
+                        None => return CssToken::Url(url),
+                        Some(')') => {
+                            self.pos += 1;
+                            return CssToken::Url(url);
+                        }
+                        Some(_) => return self.consume_bad_url_remnants(),
+                    }
+                }
+                Some('"') | Some('\'') | Some('(') => return self.consume_bad_url_remnants(),
+                Some(c) if is_non_printable(c) => return self.consume_bad_url_remnants(),
+                Some('\\') => {
+                    if !self.starts_valid_escape(0) {
+                        return self.consume_bad_url_remnants();
                     }
                     self.pos += 1;
+                    url.push(self.consume_escaped_char());
                 }
-                '.' => {
-                    floating = true;
+                Some(c) => {
+                    url.push(c);
                     self.pos += 1;
                 }
-                _ => break,
             }
         }
-        num
     }
 
-    fn consume_ident_token(&mut self) -> String {
-        let mut s = String::new();
-        s.push(self.input[self.pos]);
-
+    /// 不正な url(...) の残りを、閉じ括弧まで読み飛ばす
+    /// https://www.w3.org/TR/css-syntax-3/#consume-remnants-of-bad-url
+    fn consume_bad_url_remnants(&mut self) -> CssToken {
         loop {
-            self.pos += 1;
-            let c = self.input[self.pos];
-            match c {
-                'a'..='z' | 'A'..='Z' | '0'..='9' | '-' | '_' => {
-                    s.push(c);
+            match self.peek_char(0) {
+                None => break,
+                Some(')') => {
+                    self.pos += 1;
+                    break;
                 }
-                _ => break,
+                Some('\\') if self.starts_valid_escape(0) => {
+                    self.pos += 1;
+                    self.consume_escaped_char();
+                }
+                Some(_) => self.pos += 1,
             }
         }
-        s
+        CssToken::BadUrl
     }
 }
 
 impl Iterator for CssTokenizer {
     type Item = CssToken;
 
+    /// https://www.w3.org/TR/css-syntax-3/#consume-token
     fn next(&mut self) -> Option<Self::Item> {
-        loop {
-            if self.pos >= self.input.len() {
-                return None;
-            }
-            let c = self.input[self.pos];
-
-            let token = match c {
-                '(' => CssToken::OpenParenthesis,
-                ')' => CssToken::CloseParenthesis,
-                ',' => CssToken::Delim(','),
-                '.' => CssToken::Delim('.'),
-                ':' => CssToken::Colon,
-                ';' => CssToken::SemiColon,
-                '{' => CssToken::OpenCurly,
-                '}' => CssToken::CloseCurly,
-                ' ' | '\n' => {
+        self.consume_comments();
+        let c = self.peek_char(0)?;
+
+        let token = match c {
+            c if is_whitespace(c) => {
+                // 連続する空白文字は、１つの <whitespace-token> にまとめる
+                while self.peek_char(0).is_some_and(is_whitespace) {
                     self.pos += 1;
-                    continue;
                 }
-                '"' | '\'' => {
-                    let value = self.consume_string_token();
-                    CssToken::StringToken(value)
-                }
-                '0'..='9' => {
-                    let t = CssToken::Number(self.consume_numeric_token());
-                    self.pos -= 1;
-                    t
-                }
-                '#' => {
-                    // 常に #ID の形式の ID セレクタとして扱う。
-                    let value = self.consume_ident_token();
-                    self.pos -= 1;
-                    CssToken::HashToken(value)
-                }
-                '-' => {
-                    // 負の数は取り扱わないため、- は識別子の一つとして扱う。
-                    let t = CssToken::Ident(self.consume_ident_token());
-                    self.pos -= 1;
-                    t
+                CssToken::Whitespace
+            }
+            '"' | '\'' => {
+                self.pos += 1;
+                self.consume_string_token(c)
+            }
+            '#' => {
+                self.pos += 1;
+                if self.peek_char(0).is_some_and(is_name) || self.starts_valid_escape(0) {
+                    CssToken::HashToken(format!("#{}", self.consume_name()))
+                } else {
+                    CssToken::Delim('#')
                 }
-                '@' => {
-                    // 次の 3 文字が識別子として有効な文字の場合、<at-keyword-token> トークンを作成して返す。
-                    // それ以外の場合、<delim-token> を返す。
-                    if self.input[self.pos + 1].is_ascii_alphabetic()
-                        && self.input[self.pos + 2].is_alphanumeric()
-                        && self.input[self.pos + 3].is_alphanumeric()
-                    {
-                        // skip '@'
-                        self.pos += 1;
-                        let t = CssToken::AtKeyword(self.consume_ident_token());
-                        self.pos -= 1;
-                        t
-                    } else {
-                        CssToken::Delim('@')
-                    }
+            }
+            '+' | '.' if self.starts_number() => self.consume_numeric_token(),
+            '-' => {
+                if self.starts_number() {
+                    self.consume_numeric_token()
+                } else if self.peek_char(1) == Some('-') && self.peek_char(2) == Some('>') {
+                    self.pos += 3;
+                    CssToken::Cdc
+                } else if self.starts_identifier(0) {
+                    self.consume_ident_like_token()
+                } else {
+                    self.pos += 1;
+                    CssToken::Delim('-')
                 }
-                'a'..='z' | 'A'..='Z' | '_' => {
-                    let t = CssToken::Ident(self.consume_ident_token());
-                    self.pos -= 1;
-                    t
+            }
+            '<' if self.peek_char(1) == Some('!')
+                && self.peek_char(2) == Some('-')
+                && self.peek_char(3) == Some('-') =>
+            {
+                self.pos += 4;
+                CssToken::Cdo
+            }
+            '@' => {
+                self.pos += 1;
+                // 識別子が続く場合は <at-keyword-token>、それ以外は <delim-token> になる
+                if self.starts_identifier(0) {
+                    CssToken::AtKeyword(self.consume_name())
+                } else {
+                    CssToken::Delim('@')
                 }
-                _ => {
-                    unimplemented!("char {} is not supported yet", c);
+            }
+            '\\' if self.starts_valid_escape(0) => self.consume_ident_like_token(),
+            '0'..='9' => self.consume_numeric_token(),
+            c if is_name_start(c) => self.consume_ident_like_token(),
+            _ => {
+                self.pos += 1;
+                match c {
+                    '(' => CssToken::OpenParenthesis,
+                    ')' => CssToken::CloseParenthesis,
+                    ':' => CssToken::Colon,
+                    ';' => CssToken::SemiColon,
+                    '{' => CssToken::OpenCurly,
+                    '}' => CssToken::CloseCurly,
+                    '[' => CssToken::OpenSquareBracket,
+                    ']' => CssToken::CloseSquareBracket,
+                    // それ以外の文字 (, や . を含む) は、そのまま <delim-token> として返す
+                    _ => CssToken::Delim(c),
                 }
-            };
+            }
+        };
 
-            self.pos += 1;
-            return Some(token);
+        Some(token)
+    }
+}
+
+/// 改行を \n にそろえ、NULL を U+FFFD に置き換える
+/// https://www.w3.org/TR/css-syntax-3/#input-preprocessing
+fn preprocess(input: &str) -> Vec<char> {
+    let mut chars = Vec::new();
+    let mut iter = input.chars().peekable();
+    while let Some(c) = iter.next() {
+        match c {
+            '\r' => {
+                if iter.peek() == Some(&'\n') {
+                    iter.next();
+                }
+                chars.push('\n');
+            }
+            '\x0C' => chars.push('\n'),
+            '\0' => chars.push('\u{FFFD}'),
+            c => chars.push(c),
         }
     }
+    chars
+}
+
+fn is_whitespace(c: char) -> bool {
+    matches!(c, ' ' | '\t' | '\n')
+}
+
+/// 識別子の先頭に使える文字
+/// https://www.w3.org/TR/css-syntax-3/#ident-start-code-point
+fn is_name_start(c: char) -> bool {
+    c.is_ascii_alphabetic() || c == '_' || !c.is_ascii()
+}
+
+/// 識別子に使える文字
+/// https://www.w3.org/TR/css-syntax-3/#ident-code-point
+fn is_name(c: char) -> bool {
+    is_name_start(c) || c.is_ascii_digit() || c == '-'
+}
+
+/// https://www.w3.org/TR/css-syntax-3/#non-printable-code-point
+fn is_non_printable(c: char) -> bool {
+    matches!(c, '\0'..='\x08' | '\x0B' | '\x0E'..='\x1F' | '\x7F')
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
     use alloc::string::ToString;
+    use alloc::vec;
 
     #[test]
     fn test_empty() {
@@ -190,11 +483,15 @@ mod tests {
         let mut t = CssTokenizer::new(style);
         let expected = [
             CssToken::Ident("p".to_string()),
+            CssToken::Whitespace,
             CssToken::OpenCurly,
+            CssToken::Whitespace,
             CssToken::Ident("color".to_string()),
             CssToken::Colon,
+            CssToken::Whitespace,
             CssToken::Ident("red".to_string()),
             CssToken::SemiColon,
+            CssToken::Whitespace,
             CssToken::CloseCurly,
         ];
         for e in expected {
@@ -209,11 +506,15 @@ mod tests {
         let mut t = CssTokenizer::new(style);
         let expected = [
             CssToken::HashToken("#id".to_string()),
+            CssToken::Whitespace,
             CssToken::OpenCurly,
+            CssToken::Whitespace,
             CssToken::Ident("color".to_string()),
             CssToken::Colon,
+            CssToken::Whitespace,
             CssToken::Ident("red".to_string()),
             CssToken::SemiColon,
+            CssToken::Whitespace,
             CssToken::CloseCurly,
         ];
         for e in expected {
@@ -229,11 +530,15 @@ mod tests {
         let expected = [
             CssToken::Delim('.'),
             CssToken::Ident("class".to_string()),
+            CssToken::Whitespace,
             CssToken::OpenCurly,
+            CssToken::Whitespace,
             CssToken::Ident("color".to_string()),
             CssToken::Colon,
+            CssToken::Whitespace,
             CssToken::Ident("red".to_string()),
             CssToken::SemiColon,
+            CssToken::Whitespace,
             CssToken::CloseCurly,
         ];
         for e in expected {
@@ -248,22 +553,33 @@ mod tests {
         let mut t = CssTokenizer::new(style);
         let expected = [
             CssToken::Ident("p".to_string()),
+            CssToken::Whitespace,
             CssToken::OpenCurly,
+            CssToken::Whitespace,
             CssToken::Ident("content".to_string()),
             CssToken::Colon,
+            CssToken::Whitespace,
             CssToken::StringToken("Hey".to_string()),
             CssToken::SemiColon,
+            CssToken::Whitespace,
             CssToken::CloseCurly,
+            CssToken::Whitespace,
             CssToken::Ident("h1".to_string()),
+            CssToken::Whitespace,
             CssToken::OpenCurly,
+            CssToken::Whitespace,
             CssToken::Ident("font-size".to_string()),
             CssToken::Colon,
+            CssToken::Whitespace,
             CssToken::Number(40.0),
             CssToken::SemiColon,
+            CssToken::Whitespace,
             CssToken::Ident("color".to_string()),
             CssToken::Colon,
+            CssToken::Whitespace,
             CssToken::Ident("blue".to_string()),
             CssToken::SemiColon,
+            CssToken::Whitespace,
             CssToken::CloseCurly,
         ];
         for e in expected {
@@ -271,4 +587,121 @@ mod tests {
         }
         assert!(t.next().is_none());
     }
+
+    #[test]
+    fn test_ident_at_end_of_input() {
+        let style = "p".to_string();
+        let mut t = CssTokenizer::new(style);
+        assert_eq!(Some(CssToken::Ident("p".to_string())), t.next());
+        assert!(t.next().is_none());
+    }
+
+    fn tokens(style: &str) -> Vec<CssToken> {
+        CssTokenizer::new(style.to_string()).collect()
+    }
+
+    #[test]
+    fn test_comments() {
+        assert_eq!(
+            vec![
+                CssToken::Ident("a".to_string()),
+                CssToken::Ident("b".to_string()),
+                CssToken::Whitespace,
+            ],
+            tokens("/* x */a/**/b /* 閉じられていない")
+        );
+    }
+
+    #[test]
+    fn test_numeric_tokens() {
+        assert_eq!(
+            vec![
+                CssToken::Dimension(12.0, "px".to_string()),
+                CssToken::Whitespace,
+                CssToken::Percentage(50.0),
+                CssToken::Whitespace,
+                CssToken::Number(-150.0),
+                CssToken::Whitespace,
+                CssToken::Number(0.5),
+                CssToken::Whitespace,
+                CssToken::Number(3.0),
+                CssToken::Whitespace,
+                CssToken::Dimension(1.0, "e".to_string()),
+            ],
+            tokens("12px 50% -1.5e2 .5 +3 1e")
+        );
+    }
+
+    #[test]
+    fn test_functions_and_urls() {
+        assert_eq!(
+            vec![
+                CssToken::Function("rgb".to_string()),
+                CssToken::Number(1.0),
+                CssToken::Delim(','),
+                CssToken::Number(2.0),
+                CssToken::CloseParenthesis,
+                CssToken::Whitespace,
+                CssToken::Url("a.png".to_string()),
+                CssToken::Whitespace,
+                CssToken::Function("url".to_string()),
+                CssToken::StringToken("b.png".to_string()),
+                CssToken::CloseParenthesis,
+                CssToken::Whitespace,
+                CssToken::BadUrl,
+                CssToken::Ident("x".to_string()),
+            ],
+            tokens("rgb(1,2) url( a.png ) url(\"b.png\") url(a b)x")
+        );
+    }
+
+    #[test]
+    fn test_escapes_and_strings() {
+        assert_eq!(
+            vec![
+                CssToken::Delim('.'),
+                CssToken::Ident("10".to_string()),
+                CssToken::Whitespace,
+                CssToken::Ident("a:b".to_string()),
+                CssToken::Whitespace,
+                CssToken::StringToken("it's".to_string()),
+                CssToken::Whitespace,
+                CssToken::BadString,
+                CssToken::Whitespace,
+                CssToken::Ident("c".to_string()),
+            ],
+            tokens(".\\31 0 a\\:b 'it\\'s' 'abc\nc")
+        );
+    }
+
+    #[test]
+    fn test_at_keyword_and_cdo_cdc() {
+        assert_eq!(
+            vec![
+                CssToken::Cdo,
+                CssToken::AtKeyword("media".to_string()),
+                CssToken::Whitespace,
+                CssToken::Delim('@'),
+                CssToken::Whitespace,
+                CssToken::Delim('!'),
+                CssToken::Ident("important".to_string()),
+                CssToken::Whitespace,
+                CssToken::Ident("--x".to_string()),
+                CssToken::Whitespace,
+                CssToken::Cdc,
+            ],
+            tokens("<!--@media @ !important --x -->")
+        );
+    }
+
+    #[test]
+    fn test_never_panics() {
+        // 不完全な入力でもパニックせず、最後まで読み終える
+        for style in [
+            "@", "@-", "#", "\\", "'abc", "\"\\", "url(", "url(a", "url(a\\", "/*", "/", "1e+",
+            "-", "--", "<!-", "+.", "\0", "\r\n", "\\110000", "#\\", "u\\",
+        ] {
+            let _ = tokens(style);
+        }
+    }
 }
@@ -0,0 +1,37 @@
+//! ユーザーエージェントスタイルシート。要素のデフォルトのスタイルを CSS で定義する
+//! https://html.spec.whatwg.org/multipage/rendering.html#rendering
+
+use crate::renderer::css::cssom::CssParser;
+use crate::renderer::css::cssom::Origin;
+use crate::renderer::css::cssom::StyleSheet;
+use crate::renderer::css::token::CssTokenizer;
+use alloc::string::ToString;
+
+/// ユーザーエージェントスタイルシートの CSS
+const USER_AGENT_CSS: &str = include_str!("ua.css");
+
+/// ユーザーエージェントスタイルシートを解析し、出どころを UserAgent にして返す
+pub fn user_agent_stylesheet() -> StyleSheet {
+    let mut sheet =
+        CssParser::new(CssTokenizer::new(USER_AGENT_CSS.to_string())).parse_stylesheet();
+    sheet.set_origin(Origin::UserAgent);
+    sheet
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::renderer::css::cssom::Selector;
+
+    #[test]
+    fn test_user_agent_stylesheet() {
+        let sheet = user_agent_stylesheet();
+        assert_eq!(Origin::UserAgent, sheet.origin);
+        assert!(!sheet.rules.is_empty());
+        // すべてのルールのセレクタが解析できている
+        for rule in &sheet.rules {
+            assert!(!matches!(rule.selector, Selector::UnknownSelector));
+            assert!(!rule.declarations.is_empty());
+        }
+    }
+}
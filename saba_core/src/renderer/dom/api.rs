@@ -1,90 +1,1008 @@
+use crate::error::Error;
+use crate::renderer::css::cssom::AttributeOperator;
+use crate::renderer::css::cssom::Combinator;
+use crate::renderer::css::cssom::CssParser;
+use crate::renderer::css::cssom::PseudoClass;
+use crate::renderer::css::cssom::Selector;
+use crate::renderer::css::token::CssTokenizer;
+use crate::renderer::dom::mutation::is_observed;
+use crate::renderer::dom::mutation::queue_attribute_mutation;
+use crate::renderer::dom::mutation::queue_character_data_mutation;
+use crate::renderer::dom::mutation::queue_child_list_mutation;
+use crate::renderer::dom::mutation::MutationRecordType;
+use crate::renderer::dom::node::Dom;
 use crate::renderer::dom::node::Element;
 use crate::renderer::dom::node::ElementKind;
-use crate::renderer::dom::node::Node;
+use crate::renderer::dom::node::ElementState;
+use crate::renderer::dom::node::NodeId;
 use crate::renderer::dom::node::NodeKind;
+use crate::renderer::dom::node::WindowId;
+use crate::renderer::dom::traversal::descendants;
+use crate::renderer::dom::traversal::preorder;
+use crate::renderer::html::attribute::Attribute;
+use crate::renderer::page::Page;
+use alloc::format;
 use alloc::rc::Rc;
 use alloc::string::String;
 use alloc::string::ToString;
 use alloc::vec::Vec;
 use core::cell::RefCell;
 
+/// `node` とその子孫のうち、文書順で最初の `element_kind` の要素を返す
 pub fn get_target_element_node(
-    node: Option<Rc<RefCell<Node>>>,
+    dom: &Dom,
+    node: Option<NodeId>,
     element_kind: ElementKind,
-) -> Option<Rc<RefCell<Node>>> {
+) -> Option<NodeId> {
+    preorder(dom, node?).find(|n| dom.get(*n).element_kind() == Some(element_kind))
+}
+
+/// `node` 以下にある `element_kind` の要素を、文書順にすべて返す
+pub fn get_target_element_nodes(
+    dom: &Dom,
+    node: Option<NodeId>,
+    element_kind: ElementKind,
+) -> Vec<NodeId> {
     match node {
-        Some(n) => {
-            if n.borrow().kind()
-                == NodeKind::Element(Element::new(&element_kind.to_string(), Vec::new()))
-            {
-                return Some(n.clone());
-            }
-            let result1 = get_target_element_node(n.borrow().first_child(), element_kind);
-            let result2 = get_target_element_node(n.borrow().next_sibling(), element_kind);
-            if result1.is_none() && result2.is_none() {
-                return None;
+        Some(n) => preorder(dom, n)
+            .filter(|n| dom.get(*n).element_kind() == Some(element_kind))
+            .collect(),
+        None => Vec::new(),
+    }
+}
+
+/// ノードの子孫にあるテキストをすべてつなげて返す (textContent)
+pub fn get_text_content(dom: &Dom, node: NodeId) -> String {
+    let mut content = String::new();
+    for n in preorder(dom, node) {
+        if let NodeKind::Text(ref s) = dom.get(n).kind {
+            content.push_str(s);
+        }
+    }
+    content
+}
+
+/// `node` を `parent` の最後の子ノードとして追加する
+pub(crate) fn append_node(dom: &mut Dom, parent: NodeId, node: NodeId) {
+    insert_node(dom, parent, node, None);
+}
+
+/// `node` を `parent` の子ノード `reference` の直前に追加する
+/// `reference` が None の場合は最後の子ノードとして追加する
+pub(crate) fn insert_node(dom: &mut Dom, parent: NodeId, node: NodeId, reference: Option<NodeId>) {
+    let previous = match reference {
+        Some(r) => dom.get(r).previous_sibling(),
+        None => dom.get(parent).last_child(),
+    };
+
+    match previous {
+        Some(p) => {
+            dom.get_mut(p).set_next_sibling(Some(node));
+            dom.get_mut(node).set_previous_sibling(Some(p));
+        }
+        None => dom.get_mut(parent).set_first_child(Some(node)),
+    }
+
+    match reference {
+        Some(r) => {
+            dom.get_mut(r).set_previous_sibling(Some(node));
+            dom.get_mut(node).set_next_sibling(Some(r));
+        }
+        None => dom.get_mut(parent).set_last_child(Some(node)),
+    }
+
+    dom.get_mut(node).set_parent(Some(parent));
+
+    // 別の文書から移されたノードは、挿入先の文書に属するようになる
+    if let Some(document) = node_document(dom, parent) {
+        adopt_node(dom, node, document);
+    }
+
+    queue_child_list_mutation(
+        dom,
+        parent,
+        [node].to_vec(),
+        Vec::new(),
+        previous,
+        reference,
+    );
+}
+
+/// `node` とその子孫が `document` に属するようにする
+/// https://dom.spec.whatwg.org/#concept-node-adopt
+fn adopt_node(dom: &mut Dom, node: NodeId, document: NodeId) {
+    if dom.get(node).owner_document() == Some(document) {
+        return;
+    }
+
+    let nodes: Vec<NodeId> = preorder(dom, node).collect();
+    for n in nodes {
+        dom.get_mut(n).set_owner_document(Some(document));
+    }
+}
+
+/// ノードが属する文書を返す。文書ノードの場合は自分自身を返す
+/// https://dom.spec.whatwg.org/#concept-node-document
+pub fn node_document(dom: &Dom, node: NodeId) -> Option<NodeId> {
+    if dom.get(node).kind == NodeKind::Document {
+        return Some(node);
+    }
+    dom.get(node).owner_document()
+}
+
+/// ノードが属する文書を表示しているウィンドウを返す
+pub fn node_window(dom: &Dom, node: NodeId) -> Option<WindowId> {
+    dom.get(node_document(dom, node)?).window()
+}
+
+/// ノードが属する文書を表示しているページを返す
+pub fn node_page(dom: &Dom, node: NodeId) -> Option<Rc<RefCell<Page>>> {
+    dom.window(node_window(dom, node)?).page().upgrade()
+}
+
+/// `node` を親ノードと兄弟ノードから切り離す
+pub(crate) fn detach_node(dom: &mut Dom, node: NodeId) {
+    let parent = dom.get(node).parent();
+    let previous = dom.get(node).previous_sibling();
+    let next = dom.get(node).next_sibling();
+
+    match previous {
+        Some(p) => dom.get_mut(p).set_next_sibling(next),
+        None => {
+            if let Some(parent) = parent {
+                dom.get_mut(parent).set_first_child(next);
             }
-            if result1.is_none() {
-                return result2;
+        }
+    }
+
+    match next {
+        Some(n) => dom.get_mut(n).set_previous_sibling(previous),
+        None => {
+            if let Some(parent) = parent {
+                dom.get_mut(parent).set_last_child(previous);
             }
-            result1
         }
-        None => None,
     }
+
+    {
+        let n = dom.get_mut(node);
+        n.set_parent(None);
+        n.set_previous_sibling(None);
+        n.set_next_sibling(None);
+    }
+
+    if let Some(parent) = parent {
+        queue_child_list_mutation(dom, parent, Vec::new(), [node].to_vec(), previous, next);
+    }
+}
+
+/// `ancestor` が `node` 自身、または `node` の祖先かどうか
+fn is_inclusive_ancestor(dom: &Dom, ancestor: NodeId, node: NodeId) -> bool {
+    let mut current = Some(node);
+    while let Some(n) = current {
+        if n == ancestor {
+            return true;
+        }
+        current = dom.get(n).parent();
+    }
+    false
+}
+
+/// `parent` が `child` の親ノードかどうか
+fn is_parent_of(dom: &Dom, parent: NodeId, child: NodeId) -> bool {
+    dom.get(child).parent() == Some(parent)
+}
+
+/// `node` を `parent` の子ノードとして挿入できるかを確かめる
+/// https://dom.spec.whatwg.org/#concept-node-ensure-pre-insertion-validity
+fn ensure_pre_insertion_validity(
+    dom: &Dom,
+    parent: NodeId,
+    node: NodeId,
+    child: Option<NodeId>,
+) -> Result<(), Error> {
+    match (&dom.get(parent).kind, &dom.get(node).kind) {
+        (NodeKind::Text(_), _) => {
+            return Err(Error::UnexpectedInput(
+                "a text node cannot have children".to_string(),
+            ))
+        }
+        (_, NodeKind::Document) => {
+            return Err(Error::UnexpectedInput(
+                "a document cannot be inserted".to_string(),
+            ))
+        }
+        (NodeKind::Document, NodeKind::Text(_)) => {
+            return Err(Error::UnexpectedInput(
+                "a text node cannot be a child of a document".to_string(),
+            ))
+        }
+        _ => {}
+    }
+
+    // ノードを自分自身や子孫の下に挿入すると、ツリーが循環してしまう
+    if is_inclusive_ancestor(dom, node, parent) {
+        return Err(Error::UnexpectedInput(format!(
+            "{:?} is an ancestor of the parent",
+            dom.get(node).kind()
+        )));
+    }
+
+    if let Some(c) = child {
+        if !is_parent_of(dom, parent, c) {
+            return Err(Error::UnexpectedInput(format!(
+                "{:?} is not a child of the parent",
+                dom.get(c).kind()
+            )));
+        }
+    }
+
+    Ok(())
+}
+
+/// `node` を `parent` の最後の子ノードとして追加し、`node` を返す
+/// `node` がすでにツリーの中にある場合は、元の位置から移動する
+/// https://dom.spec.whatwg.org/#dom-node-appendchild
+pub fn append_child(dom: &mut Dom, parent: NodeId, node: NodeId) -> Result<NodeId, Error> {
+    insert_before(dom, parent, node, None)
+}
+
+/// `node` を `parent` の子ノード `child` の直前に挿入し、`node` を返す
+/// `child` が None の場合は最後の子ノードとして追加する
+/// https://dom.spec.whatwg.org/#dom-node-insertbefore
+pub fn insert_before(
+    dom: &mut Dom,
+    parent: NodeId,
+    node: NodeId,
+    child: Option<NodeId>,
+) -> Result<NodeId, Error> {
+    ensure_pre_insertion_validity(dom, parent, node, child)?;
+
+    // 自分自身の直前に挿入する場合は、元の次の兄弟ノードの直前に挿入することになる
+    let reference = match child {
+        Some(c) if c == node => dom.get(node).next_sibling(),
+        c => c,
+    };
+
+    detach_node(dom, node);
+    insert_node(dom, parent, node, reference);
+    Ok(node)
+}
+
+/// `child` を `parent` の子ノードから取り除き、`child` を返す
+/// https://dom.spec.whatwg.org/#dom-node-removechild
+pub fn remove_child(dom: &mut Dom, parent: NodeId, child: NodeId) -> Result<NodeId, Error> {
+    if !is_parent_of(dom, parent, child) {
+        return Err(Error::UnexpectedInput(format!(
+            "{:?} is not a child of the parent",
+            dom.get(child).kind()
+        )));
+    }
+
+    detach_node(dom, child);
+    Ok(child)
+}
+
+/// `parent` の子ノード `child` を `node` で置き換え、`child` を返す
+/// https://dom.spec.whatwg.org/#dom-node-replacechild
+pub fn replace_child(
+    dom: &mut Dom,
+    parent: NodeId,
+    node: NodeId,
+    child: NodeId,
+) -> Result<NodeId, Error> {
+    ensure_pre_insertion_validity(dom, parent, node, Some(child))?;
+
+    if node == child {
+        return Ok(child);
+    }
+
+    // `node` が `child` の次の兄弟ノードの場合、`node` を切り離したあとの位置を参照する
+    let mut reference = dom.get(child).next_sibling();
+    if reference == Some(node) {
+        reference = dom.get(node).next_sibling();
+    }
+
+    detach_node(dom, child);
+    detach_node(dom, node);
+    insert_node(dom, parent, node, reference);
+    Ok(child)
+}
+
+/// ノードを複製する。`deep` が true の場合は子孫のノードもすべて複製する
+/// 複製されたノードは親ノードを持たない
+/// https://dom.spec.whatwg.org/#dom-node-clonenode
+pub fn clone_node(dom: &mut Dom, node: NodeId, deep: bool) -> NodeId {
+    let copy = dom.create_node(dom.get(node).kind());
+    // 複製されたノードは、元のノードと同じ文書に属する
+    let document = dom.get(node).owner_document();
+    dom.get_mut(copy).set_owner_document(document);
+
+    if deep {
+        let mut child = dom.get(node).first_child();
+        while let Some(c) = child {
+            let c_copy = clone_node(dom, c, true);
+            append_node(dom, copy, c_copy);
+            child = dom.get(c).next_sibling();
+        }
+    }
+
+    copy
 }
 
-pub fn get_style_content(root: Rc<RefCell<Node>>) -> String {
-    let style_node = match get_target_element_node(Some(root), ElementKind::Style) {
-        Some(node) => node,
-        None => return "".to_string(),
+/// ノードの子ノードをすべて取り除き、`text` を持つテキストノードに置き換える (textContent への代入)
+/// テキストノードの場合は、その文字列を `text` に置き換える
+pub fn set_text_content(dom: &mut Dom, node: NodeId, text: String) {
+    if matches!(dom.get(node).kind, NodeKind::Text(_)) {
+        replace_data(dom, node, |data| *data = text);
+        return;
+    }
+
+    let mut child = dom.get(node).first_child();
+    while let Some(c) = child {
+        child = dom.get(c).next_sibling();
+        detach_node(dom, c);
+    }
+
+    if !text.is_empty() {
+        let text = dom.create_node(NodeKind::Text(text));
+        append_node(dom, node, text);
+    }
+}
+
+/// テキストノードの文字列を `f` で書き換える
+/// https://dom.spec.whatwg.org/#concept-cd-replace
+pub(crate) fn replace_data(dom: &mut Dom, node: NodeId, f: impl FnOnce(&mut String)) {
+    // 変更前の文字列は、変更が監視されている場合のみ複製する
+    let old_value = match dom.get(node).kind {
+        NodeKind::Text(ref data) if is_observed(dom, node, MutationRecordType::CharacterData) => {
+            Some(data.clone())
+        }
+        NodeKind::Text(_) => None,
+        _ => return,
     };
-    let text_node = match style_node.borrow().first_child() {
-        Some(node) => node,
-        None => return "".to_string(),
+
+    if let NodeKind::Text(ref mut data) = dom.get_mut(node).kind {
+        f(data);
+    }
+
+    if let Some(old_value) = old_value {
+        queue_character_data_mutation(dom, node, old_value);
+    }
+}
+
+/// 要素の属性 `name` の値を設定する
+/// https://dom.spec.whatwg.org/#dom-element-setattribute
+pub fn set_attribute(dom: &mut Dom, node: NodeId, name: &str, value: &str) {
+    let old_value = match dom.get(node).get_element() {
+        Some(e) => e.get_attribute(&name.to_ascii_lowercase()),
+        None => return,
     };
-    let content = match &text_node.borrow().kind() {
-        NodeKind::Text(ref s) => s.clone(),
-        _ => return "".to_string(),
+
+    if let Some(e) = dom.get_mut(node).get_element_mut() {
+        e.set_attribute(name, value);
+    }
+    queue_attribute_mutation(dom, node, &name.to_ascii_lowercase(), old_value);
+}
+
+/// 要素を `f` で変更し、値が変わった属性の変更を記録する
+/// classList.add のように、複数の属性をまとめて変更する操作に使う
+pub fn modify_element<R>(
+    dom: &mut Dom,
+    node: NodeId,
+    f: impl FnOnce(&mut Element) -> R,
+) -> Option<R> {
+    let before = dom.get(node).get_element()?.attributes();
+    let result = f(dom.get_mut(node).get_element_mut()?);
+    let after = dom.get(node).get_element()?.attributes();
+
+    let value_of = |attributes: &Vec<Attribute>, name: &str| {
+        attributes
+            .iter()
+            .find(|a| a.name() == name)
+            .map(|a| a.value())
     };
-    content
+    let mut names: Vec<String> = before.iter().map(|a| a.name()).collect();
+    for attr in &after {
+        if !names.contains(&attr.name()) {
+            names.push(attr.name());
+        }
+    }
+    for name in names {
+        let old_value = value_of(&before, &name);
+        if old_value != value_of(&after, &name) {
+            queue_attribute_mutation(dom, node, &name, old_value);
+        }
+    }
+
+    Some(result)
 }
 
-pub fn get_element_by_id(
-    node: Option<Rc<RefCell<Node>>>,
-    id_name: &String,
-) -> Option<Rc<RefCell<Node>>> {
-    match node {
-        Some(n) => {
-            if let NodeKind::Element(e) = n.borrow().kind() {
-                for attr in &e.attributes() {
-                    if attr.name() == "id" && attr.value() == *id_name {
-                        return Some(n.clone());
+/// 文書のタイトルを返す。最初の <title> 要素の中身から、前後の空白を取り除き、連続する空白を１つにまとめたもの
+/// https://html.spec.whatwg.org/multipage/dom.html#document.title
+pub fn get_document_title(dom: &Dom, document: NodeId) -> String {
+    let title = match get_target_element_node(dom, Some(document), ElementKind::Title) {
+        Some(t) => t,
+        None => return String::new(),
+    };
+
+    get_text_content(dom, title)
+        .split_ascii_whitespace()
+        .collect::<Vec<&str>>()
+        .join(" ")
+}
+
+/// 文書のタイトルを変更する。<title> 要素がなければ <head> 要素の中に作成する
+pub fn set_document_title(dom: &mut Dom, document: NodeId, title: String) {
+    let element = match get_target_element_node(dom, Some(document), ElementKind::Title) {
+        Some(t) => t,
+        None => {
+            let head = match get_target_element_node(dom, Some(document), ElementKind::Head) {
+                Some(h) => h,
+                None => return,
+            };
+            let element = dom.create_node(NodeKind::Element(Element::new("title", Vec::new())));
+            append_node(dom, head, element);
+            element
+        }
+    };
+
+    set_text_content(dom, element, title);
+}
+
+/// すべての <style> 要素の中身を文書順に返す
+pub fn get_style_contents(dom: &Dom, root: NodeId) -> Vec<String> {
+    get_target_element_nodes(dom, Some(root), ElementKind::Style)
+        .into_iter()
+        .map(|n| get_text_content(dom, n))
+        .collect()
+}
+
+/// 文書のスタイルシートの出どころ
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum StyleSource {
+    /// <style> 要素の中身
+    Inline(String),
+    /// <link rel="stylesheet"> の href 属性の値
+    External(String),
+}
+
+/// <style> 要素と <link rel="stylesheet" href> 要素で指定されたスタイルシートを、文書順に返す
+/// https://html.spec.whatwg.org/multipage/links.html#link-type-stylesheet
+pub fn get_style_sources(dom: &Dom, root: NodeId) -> Vec<StyleSource> {
+    preorder(dom, root)
+        .filter_map(|n| {
+            let element = dom.get(n).get_element()?;
+            match element.kind() {
+                ElementKind::Style => Some(StyleSource::Inline(get_text_content(dom, n))),
+                ElementKind::Link => {
+                    // rel 属性は空白文字で区切られたキーワードのリストで、大文字と小文字を区別しない
+                    let rel = element.get_attribute("rel")?;
+                    if !rel
+                        .split_ascii_whitespace()
+                        .any(|r| r.eq_ignore_ascii_case("stylesheet"))
+                    {
+                        return None;
+                    }
+                    match element.get_attribute("href") {
+                        Some(href) if !href.is_empty() => Some(StyleSource::External(href)),
+                        _ => None,
                     }
                 }
+                _ => None,
             }
-            let result1 = get_element_by_id(n.borrow().first_child(), id_name);
-            let result2 = get_element_by_id(n.borrow().next_sibling(), id_name);
-            if result1.is_none() {
-                return result2;
+        })
+        .collect()
+}
+
+/// ノードがセレクタに一致するかどうか
+/// 結合子を含むセレクタは、右側の複合セレクタから順に、祖先や兄弟の要素をたどって確かめる
+/// https://www.w3.org/TR/selectors-4/#match-a-selector-against-an-element
+pub fn matches_selector(dom: &Dom, node: NodeId, selector: &Selector) -> bool {
+    let element = match dom.get(node).get_element() {
+        Some(e) => e,
+        None => return false,
+    };
+
+    match selector {
+        Selector::TypeSelector(type_name) => {
+            element.kind().to_string() == type_name.to_ascii_lowercase()
+        }
+        Selector::ClassSelector(class_name) => element.contains_class(class_name),
+        Selector::IdSelector(id_name) => element.get_attribute("id") == Some(id_name.clone()),
+        Selector::AttributeSelector {
+            name,
+            operator,
+            value,
+        } => match element.get_attribute(name) {
+            Some(attr) => matches_attribute(&attr, *operator, value),
+            None => false,
+        },
+        Selector::PseudoClassSelector(pseudo_class) => {
+            matches_pseudo_class(dom, node, pseudo_class)
+        }
+        Selector::UniversalSelector => true,
+        Selector::CompoundSelector(selectors) => {
+            selectors.iter().all(|s| matches_selector(dom, node, s))
+        }
+        Selector::ComplexSelector {
+            left,
+            combinator,
+            right,
+        } => {
+            if !matches_selector(dom, node, right) {
+                return false;
+            }
+            match combinator {
+                Combinator::Descendant => {
+                    let mut ancestor = dom.get(node).parent();
+                    while let Some(a) = ancestor {
+                        if matches_selector(dom, a, left) {
+                            return true;
+                        }
+                        ancestor = dom.get(a).parent();
+                    }
+                    false
+                }
+                Combinator::Child => match dom.get(node).parent() {
+                    Some(parent) => matches_selector(dom, parent, left),
+                    None => false,
+                },
+                Combinator::NextSibling => match previous_element_sibling(dom, node) {
+                    Some(sibling) => matches_selector(dom, sibling, left),
+                    None => false,
+                },
+                Combinator::SubsequentSibling => {
+                    let mut sibling = previous_element_sibling(dom, node);
+                    while let Some(s) = sibling {
+                        if matches_selector(dom, s, left) {
+                            return true;
+                        }
+                        sibling = previous_element_sibling(dom, s);
+                    }
+                    false
+                }
             }
-            result1
         }
-        None => None,
+        Selector::SelectorList(selectors) => {
+            selectors.iter().any(|s| matches_selector(dom, node, s))
+        }
+        Selector::UnknownSelector => false,
     }
 }
 
-pub fn get_js_content(root: Rc<RefCell<Node>>) -> String {
-    let js_node = match get_target_element_node(Some(root), ElementKind::Script) {
-        Some(node) => node,
-        None => return "".to_string(),
-    };
-    let text_node = match js_node.borrow().first_child() {
-        Some(node) => node,
-        None => return "".to_string(),
-    };
-    let content = match &text_node.borrow().kind() {
-        NodeKind::Text(ref s) => s.clone(),
-        _ => return "".to_string(),
+/// 属性の値 `attr` が、属性セレクタの `operator` と `value` に一致するかどうか
+/// https://www.w3.org/TR/selectors-4/#attribute-representation
+fn matches_attribute(attr: &str, operator: AttributeOperator, value: &str) -> bool {
+    match operator {
+        AttributeOperator::Exists => true,
+        AttributeOperator::Equals => attr == value,
+        AttributeOperator::Includes => {
+            !value.is_empty()
+                && !value.contains(char::is_whitespace)
+                && attr.split_ascii_whitespace().any(|v| v == value)
+        }
+        AttributeOperator::DashMatch => {
+            attr == value || (attr.starts_with(value) && attr[value.len()..].starts_with('-'))
+        }
+        // 値が空の場合は、どの要素にも一致しない
+        AttributeOperator::Prefix => !value.is_empty() && attr.starts_with(value),
+        AttributeOperator::Suffix => !value.is_empty() && attr.ends_with(value),
+        AttributeOperator::Substring => !value.is_empty() && attr.contains(value),
+    }
+}
+
+/// 動的な擬似クラスは、文書を表示しているウィンドウが持つ状態と比べる
+/// https://www.w3.org/TR/selectors-4/#structural-pseudos
+/// https://www.w3.org/TR/selectors-4/#useraction-pseudos
+fn matches_pseudo_class(dom: &Dom, node: NodeId, pseudo_class: &PseudoClass) -> bool {
+    match pseudo_class {
+        PseudoClass::FirstChild => previous_element_sibling(dom, node).is_none(),
+        PseudoClass::LastChild => next_element_sibling(dom, node).is_none(),
+        PseudoClass::NthChild { a, b } => {
+            let mut index = 1;
+            let mut sibling = previous_element_sibling(dom, node);
+            while let Some(s) = sibling {
+                index += 1;
+                sibling = previous_element_sibling(dom, s);
+            }
+            // index = a * n + b を満たす 0 以上の整数 n があるかどうか
+            // a と b は i32 の範囲の任意の値をとるので、オーバーフローしないように i64 で計算する
+            let diff = index - i64::from(*b);
+            match i64::from(*a) {
+                0 => diff == 0,
+                a => diff % a == 0 && diff / a >= 0,
+            }
+        }
+        PseudoClass::Not(selector) => !matches_selector(dom, node, selector),
+        // 要素とテキストの子を持たない要素。空文字列のテキストノードは無視する
+        PseudoClass::Empty => {
+            let mut child = dom.get(node).first_child();
+            while let Some(c) = child {
+                match dom.get(c).kind {
+                    NodeKind::Text(ref s) if s.is_empty() => {}
+                    _ => return false,
+                }
+                child = dom.get(c).next_sibling();
+            }
+            true
+        }
+        PseudoClass::Root => match dom.get(node).parent() {
+            Some(parent) => dom.get(parent).kind == NodeKind::Document,
+            None => false,
+        },
+        // 子孫の要素が :hover や :active の状態であれば、祖先の要素も一致する
+        PseudoClass::Hover => has_state_in_subtree(dom, node, ElementState::Hover),
+        PseudoClass::Active => has_state_in_subtree(dom, node, ElementState::Active),
+        PseudoClass::Focus => match node_window(dom, node) {
+            Some(window) => {
+                dom.window(window).element_with_state(ElementState::Focus) == Some(node)
+            }
+            None => false,
+        },
+        PseudoClass::Link => match link_destination(dom, node) {
+            Some(url) => !is_visited(dom, node, &url),
+            None => false,
+        },
+        PseudoClass::Visited => match link_destination(dom, node) {
+            Some(url) => is_visited(dom, node, &url),
+            None => false,
+        },
+    }
+}
+
+/// `node` かその子孫が `state` の状態にあるかどうか
+fn has_state_in_subtree(dom: &Dom, node: NodeId, state: ElementState) -> bool {
+    let element = match node_window(dom, node) {
+        Some(window) => dom.window(window).element_with_state(state),
+        None => return false,
     };
-    content
+    match element {
+        Some(e) => is_inclusive_ancestor(dom, node, e),
+        None => false,
+    }
+}
+
+/// href 属性を持つ <a> 要素のリンク先
+fn link_destination(dom: &Dom, node: NodeId) -> Option<String> {
+    let element = dom.get(node).get_element()?;
+    if element.kind() != ElementKind::A {
+        return None;
+    }
+    element.get_attribute("href")
+}
+
+fn is_visited(dom: &Dom, node: NodeId, url: &str) -> bool {
+    match node_window(dom, node) {
+        Some(window) => dom.window(window).is_visited(url),
+        None => false,
+    }
+}
+
+/// テキストノードを飛ばして、直後の兄弟要素を返す
+fn next_element_sibling(dom: &Dom, node: NodeId) -> Option<NodeId> {
+    let mut sibling = dom.get(node).next_sibling();
+    while let Some(s) = sibling {
+        if dom.get(s).element_kind().is_some() {
+            return Some(s);
+        }
+        sibling = dom.get(s).next_sibling();
+    }
+    None
+}
+
+/// テキストノードを飛ばして、直前の兄弟要素を返す
+fn previous_element_sibling(dom: &Dom, node: NodeId) -> Option<NodeId> {
+    let mut sibling = dom.get(node).previous_sibling();
+    while let Some(s) = sibling {
+        if dom.get(s).element_kind().is_some() {
+            return Some(s);
+        }
+        sibling = dom.get(s).previous_sibling();
+    }
+    None
+}
+
+fn parse_selectors(selectors: &str) -> Result<Vec<Selector>, Error> {
+    let t = CssTokenizer::new(selectors.to_string());
+    CssParser::new(t).parse_selector_list()
+}
+
+/// `root` の子孫のうち、セレクタに一致する最初の要素を返す
+/// https://dom.spec.whatwg.org/#dom-parentnode-queryselector
+pub fn query_selector(dom: &Dom, root: NodeId, selectors: &str) -> Result<Option<NodeId>, Error> {
+    Ok(query_selector_all(dom, root, selectors)?.into_iter().next())
+}
+
+/// `root` の子孫のうち、セレクタに一致するすべての要素を文書順に返す
+/// https://dom.spec.whatwg.org/#dom-parentnode-queryselectorall
+pub fn query_selector_all(dom: &Dom, root: NodeId, selectors: &str) -> Result<Vec<NodeId>, Error> {
+    let selectors = parse_selectors(selectors)?;
+
+    Ok(descendants(dom, root)
+        .filter(|node| selectors.iter().any(|s| matches_selector(dom, *node, s)))
+        .collect())
+}
+
+/// `node` とその子孫のうち、文書順で最初に `id_name` の id を持つ要素を返す
+pub fn get_element_by_id(dom: &Dom, node: Option<NodeId>, id_name: &String) -> Option<NodeId> {
+    preorder(dom, node?).find(|n| match dom.get(*n).get_element() {
+        Some(e) => e.get_attribute("id").as_ref() == Some(id_name),
+        None => false,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::renderer::html::parser::HtmlParser;
+    use crate::renderer::html::serializer::serialize_children;
+    use crate::renderer::html::token::HtmlTokenizer;
+    use alloc::vec;
+
+    fn parse_document(dom: &mut Dom, html: &str) -> NodeId {
+        let t = HtmlTokenizer::new(html.to_string());
+        HtmlParser::new(dom, t).construct_tree(dom)
+    }
+
+    fn parse_body(dom: &mut Dom, html: &str) -> NodeId {
+        let document = parse_document(dom, html);
+        get_target_element_node(dom, Some(document), ElementKind::Body).expect("body should exist")
+    }
+
+    fn element(dom: &mut Dom, name: &str) -> NodeId {
+        dom.create_node(NodeKind::Element(Element::new(name, Vec::new())))
+    }
+
+    fn text(dom: &mut Dom, s: &str) -> NodeId {
+        dom.create_node(NodeKind::Text(s.to_string()))
+    }
+
+    /// 親ノード、兄弟ノード、最後の子ノードへのリンクが矛盾していないかを確かめる
+    fn assert_links(dom: &Dom, parent: NodeId) {
+        let mut previous = None;
+        let mut child = dom.get(parent).first_child();
+        while let Some(c) = child {
+            assert!(is_parent_of(dom, parent, c));
+            assert_eq!(previous, dom.get(c).previous_sibling());
+            assert_links(dom, c);
+            child = dom.get(c).next_sibling();
+            previous = Some(c);
+        }
+        assert_eq!(previous, dom.get(parent).last_child());
+    }
+
+    #[test]
+    fn test_append_and_insert() {
+        let mut dom = Dom::new();
+        let body = parse_body(&mut dom, "<html><head></head><body><p>a</p></body></html>");
+        let p = dom.get(body).first_child().expect("p should exist");
+
+        let h1 = element(&mut dom, "h1");
+        assert!(append_child(&mut dom, body, h1).is_ok());
+        let x = text(&mut dom, "x");
+        assert!(insert_before(&mut dom, body, x, Some(p)).is_ok());
+        let b = text(&mut dom, "b");
+        assert!(append_child(&mut dom, p, b).is_ok());
+        assert_eq!("x<p>ab</p><h1></h1>", serialize_children(&dom, body));
+        assert_links(&dom, body);
+
+        // すでにツリーの中にあるノードは移動する
+        assert!(append_child(&mut dom, body, p).is_ok());
+        assert_eq!("x<h1></h1><p>ab</p>", serialize_children(&dom, body));
+        assert_links(&dom, body);
+
+        // 自分自身の直前に挿入しても位置は変わらない
+        assert!(insert_before(&mut dom, body, p, Some(p)).is_ok());
+        assert_eq!("x<h1></h1><p>ab</p>", serialize_children(&dom, body));
+        assert_links(&dom, body);
+    }
+
+    #[test]
+    fn test_remove_and_replace() {
+        let mut dom = Dom::new();
+        let body = parse_body(
+            &mut dom,
+            "<html><head></head><body><p>a</p><h1>b</h1></body></html>",
+        );
+        let p = dom.get(body).first_child().expect("p should exist");
+        let h1 = dom.get(p).next_sibling().expect("h1 should exist");
+
+        // 後ろの兄弟ノードで前の兄弟ノードを置き換える
+        let removed = replace_child(&mut dom, body, h1, p).expect("p should be replaced");
+        assert_eq!(p, removed);
+        assert!(dom.get(p).parent().is_none());
+        assert_eq!("<h1>b</h1>", serialize_children(&dom, body));
+        assert_links(&dom, body);
+
+        assert!(remove_child(&mut dom, body, p).is_err());
+        assert!(remove_child(&mut dom, body, h1).is_ok());
+        assert_eq!("", serialize_children(&dom, body));
+        assert_links(&dom, body);
+    }
+
+    #[test]
+    fn test_reject_invalid_insertion() {
+        let mut dom = Dom::new();
+        let body = parse_body(&mut dom, "<html><head></head><body><p>a</p></body></html>");
+        let p = dom.get(body).first_child().expect("p should exist");
+        let a = dom.get(p).first_child().expect("text should exist");
+
+        // ノードを自分自身や子孫の下に挿入することはできない
+        assert!(append_child(&mut dom, p, p).is_err());
+        assert!(append_child(&mut dom, p, body).is_err());
+        // テキストノードは子ノードを持てない
+        let h1 = element(&mut dom, "h1");
+        assert!(append_child(&mut dom, a, h1).is_err());
+        // 参照するノードは親ノードの子でなければならない
+        assert!(insert_before(&mut dom, body, h1, Some(a)).is_err());
+        assert_eq!("<p>a</p>", serialize_children(&dom, body));
+        assert_links(&dom, body);
+    }
+
+    #[test]
+    fn test_clone_node() {
+        let mut dom = Dom::new();
+        let body = parse_body(
+            &mut dom,
+            "<html><head></head><body><p>a<a href=\"x\">b</a></p></body></html>",
+        );
+        let p = dom.get(body).first_child().expect("p should exist");
+
+        let shallow = clone_node(&mut dom, p, false);
+        assert!(dom.get(shallow).first_child().is_none());
+        assert!(dom.get(shallow).parent().is_none());
+
+        let deep = clone_node(&mut dom, p, true);
+        assert_eq!(serialize_children(&dom, p), serialize_children(&dom, deep));
+        assert_links(&dom, deep);
+        assert_ne!(dom.get(p).first_child(), dom.get(deep).first_child());
+    }
+
+    #[test]
+    fn test_query_selector() {
+        let mut dom = Dom::new();
+        let body = parse_body(
+            &mut dom,
+            "<html><head></head><body><p id=\"a\" class=\"x y\">a</p><h1 class=\"y\">b</h1><p>c</p></body></html>",
+        );
+
+        let first = query_selector(&dom, body, "p").expect("selector should be valid");
+        assert_eq!(
+            Some("a".to_string()),
+            dom.get(first.expect("p should exist"))
+                .get_element()
+                .expect("element")
+                .get_attribute("id")
+        );
+        assert!(query_selector(&dom, body, "a")
+            .expect("selector should be valid")
+            .is_none());
+
+        let texts = |selectors: &str| -> Vec<String> {
+            query_selector_all(&dom, body, selectors)
+                .expect("selector should be valid")
+                .into_iter()
+                .map(|n| get_text_content(&dom, n))
+                .collect()
+        };
+        assert_eq!(vec!["b".to_string()], texts("H1"));
+        assert_eq!(vec!["a".to_string(), "b".to_string()], texts(".y"));
+        // 一致した要素は文書順に並ぶ
+        assert_eq!(
+            vec!["a".to_string(), "b".to_string(), "c".to_string()],
+            texts("h1, p")
+        );
+        assert_eq!(vec!["a".to_string()], texts("#a"));
+
+        assert!(query_selector_all(&dom, body, "p,").is_err());
+    }
+
+    #[test]
+    fn test_combinators() {
+        let mut dom = Dom::new();
+        let body = parse_body(
+            &mut dom,
+            "<html><head></head><body><p class=\"n\" id=\"x\">a</p><h1>b<a>c</a></h1><p>d</p><p class=\"n\">e</p><h2><a>f</a></h2></body></html>",
+        );
+        let texts = |selectors: &str| -> Vec<String> {
+            query_selector_all(&dom, body, selectors)
+                .expect("selector should be valid")
+                .into_iter()
+                .map(|n| get_text_content(&dom, n))
+                .collect()
+        };
+
+        assert_eq!(vec!["a", "d", "e"], texts("body p"));
+        assert_eq!(vec!["c"], texts("h1 > a"));
+        assert_eq!(vec!["d"], texts("h1+p"));
+        assert_eq!(vec!["d", "e"], texts("h1 ~ p"));
+        assert_eq!(vec!["a"], texts("p.n#x"));
+        assert_eq!(vec!["e"], texts("body > h1 ~ .n"));
+        assert_eq!(vec!["c"], texts("h1 *"));
+        assert_eq!(vec!["c", "f"], texts("h1 a, h2 > a"));
+        assert!(texts("body > a").is_empty());
+
+        assert!(query_selector_all(&dom, body, "h1 >").is_err());
+        assert!(query_selector_all(&dom, body, "p.").is_err());
+    }
+
+    #[test]
+    fn test_attribute_and_pseudo_class_selectors() {
+        let mut dom = Dom::new();
+        let body = parse_body(
+            &mut dom,
+            "<html><head></head><body><p lang=\"en-US\">a</p><a href=\"https://example.com/a.html\" class=\"x y\">b</a><p></p><p>c</p><h1>d</h1></body></html>",
+        );
+        let texts = |selectors: &str| -> Vec<String> {
+            query_selector_all(&dom, body, selectors)
+                .expect("selector should be valid")
+                .into_iter()
+                .map(|n| get_text_content(&dom, n))
+                .collect()
+        };
+
+        assert_eq!(vec!["b"], texts("[href]"));
+        assert_eq!(vec!["b"], texts("[class=\"x y\"]"));
+        assert_eq!(vec!["b"], texts("[class~=y]"));
+        assert!(texts("[class~=\"x y\"]").is_empty());
+        assert_eq!(vec!["a"], texts("[lang|=en]"));
+        assert!(texts("[lang|=e]").is_empty());
+        assert_eq!(
+            vec!["b"],
+            texts("[href^=https][href$='.html'][href*=example]")
+        );
+        assert!(texts("[href^='']").is_empty());
+
+        assert_eq!(vec!["a"], texts("body > :first-child"));
+        assert_eq!(vec!["d"], texts("body > :last-child"));
+        assert_eq!(vec!["a", "", "d"], texts("body > :nth-child(odd)"));
+        assert_eq!(vec!["b", "c"], texts("body > :nth-child(2n)"));
+        assert_eq!(vec!["a", "b"], texts("body > :nth-child(-n+2)"));
+        assert_eq!(vec!["c"], texts("body > :nth-child(4)"));
+        assert!(texts("body > :nth-child(-n-2147483648)").is_empty());
+        assert_eq!(5, texts("body > :nth-child(n-2147483648)").len());
+        assert!(texts("body > :nth-child(2147483647n+2147483647)").is_empty());
+        assert_eq!(vec![""], texts("p:empty"));
+        assert_eq!(vec!["a", "c"], texts("p:not(:empty, h1)"));
+
+        let document = dom
+            .get(dom.get(body).parent().expect("html should exist"))
+            .parent()
+            .expect("document should exist");
+        let root = query_selector_all(&dom, document, ":root").expect("selector should be valid");
+        assert_eq!(1, root.len());
+        assert_eq!(Some(ElementKind::Html), dom.get(root[0]).element_kind());
+    }
+
+    #[test]
+    fn test_owner_document() {
+        let mut dom = Dom::new();
+        let document = parse_document(&mut dom, "<html><head></head><body><p>a</p></body></html>");
+        let window = dom.get(document).window().expect("window should exist");
+        let p = get_target_element_node(&dom, Some(document), ElementKind::P).unwrap();
+
+        assert!(dom.get(document).owner_document().is_none());
+        assert_eq!(Some(document), node_document(&dom, document));
+        assert_eq!(Some(document), dom.get(p).owner_document());
+        assert_eq!(Some(window), node_window(&dom, p));
+        assert!(node_page(&dom, p).is_none());
+
+        // 別の文書のノードを挿入すると、その子孫も含めて挿入先の文書に属するようになる
+        let other = parse_document(
+            &mut dom,
+            "<html><head></head><body><h1>b<a>c</a></h1></body></html>",
+        );
+        let other_window = dom.get(other).window().expect("window should exist");
+        let h1 = get_target_element_node(&dom, Some(other), ElementKind::H1).unwrap();
+        let a = get_target_element_node(&dom, Some(h1), ElementKind::A).unwrap();
+        assert_eq!(Some(other_window), node_window(&dom, a));
+        append_child(&mut dom, p, h1).unwrap();
+        assert_eq!(Some(document), dom.get(h1).owner_document());
+        assert_eq!(Some(window), node_window(&dom, a));
+
+        // 複製したノードは、元のノードと同じ文書に属する
+        let copy = clone_node(&mut dom, p, false);
+        assert_eq!(Some(document), dom.get(copy).owner_document());
+    }
 }
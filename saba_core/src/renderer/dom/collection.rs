@@ -0,0 +1,180 @@
+//! 常に現在の DOM ツリーを反映するノードのコレクション
+//! https://dom.spec.whatwg.org/#concept-collection
+
+use crate::renderer::dom::node::Dom;
+use crate::renderer::dom::node::NodeId;
+use crate::renderer::dom::traversal::descendants;
+use alloc::string::String;
+use alloc::string::ToString;
+use alloc::vec::Vec;
+
+/// コレクションに含めるノードの条件
+#[derive(Debug, Clone, PartialEq)]
+pub enum CollectionFilter {
+    /// 指定されたタグ名の子孫要素 (getElementsByTagName)。"*" はすべての要素に一致する
+    TagName(String),
+    /// 指定されたすべてのクラス名を持つ子孫要素 (getElementsByClassName)
+    ClassNames(Vec<String>),
+    /// 子要素 (children)
+    Children,
+    /// すべての子ノード (childNodes)
+    ChildNodes,
+}
+
+/// HTMLCollection のように、ノードの一覧をその都度 DOM ツリーから求めるコレクション
+/// ノードの一覧を保持しないので、DOM ツリーが変更されると結果も変わる
+/// https://dom.spec.whatwg.org/#interface-htmlcollection
+#[derive(Debug, Clone, PartialEq)]
+pub struct HtmlCollection {
+    root: NodeId,
+    filter: CollectionFilter,
+}
+
+impl HtmlCollection {
+    pub fn new(root: NodeId, filter: CollectionFilter) -> Self {
+        Self { root, filter }
+    }
+
+    /// `classes` を空白で区切ったクラス名に一致する要素のコレクションを作成する
+    pub fn by_class_names(root: NodeId, classes: &str) -> Self {
+        let classes = classes
+            .split_ascii_whitespace()
+            .map(|c| c.to_string())
+            .collect();
+        Self::new(root, CollectionFilter::ClassNames(classes))
+    }
+
+    pub fn root(&self) -> NodeId {
+        self.root
+    }
+
+    pub fn filter(&self) -> CollectionFilter {
+        self.filter.clone()
+    }
+
+    /// 現在の DOM ツリーでコレクションに含まれるノードを文書順に返す
+    pub fn nodes(&self, dom: &Dom) -> Vec<NodeId> {
+        let mut result = Vec::new();
+        match self.filter {
+            CollectionFilter::Children | CollectionFilter::ChildNodes => {
+                let mut child = dom.get(self.root).first_child();
+                while let Some(c) = child {
+                    if self.matches(dom, c) {
+                        result.push(c);
+                    }
+                    child = dom.get(c).next_sibling();
+                }
+            }
+            CollectionFilter::TagName(_) | CollectionFilter::ClassNames(_) => {
+                result.extend(descendants(dom, self.root).filter(|n| self.matches(dom, *n)));
+            }
+        }
+        result
+    }
+
+    pub fn length(&self, dom: &Dom) -> usize {
+        self.nodes(dom).len()
+    }
+
+    pub fn item(&self, dom: &Dom, index: usize) -> Option<NodeId> {
+        self.nodes(dom).into_iter().nth(index)
+    }
+
+    fn matches(&self, dom: &Dom, node: NodeId) -> bool {
+        if self.filter == CollectionFilter::ChildNodes {
+            return true;
+        }
+
+        let element = match dom.get(node).get_element() {
+            Some(e) => e,
+            None => return false,
+        };
+
+        match self.filter {
+            CollectionFilter::TagName(ref name) => {
+                name == "*" || element.kind().to_string() == name.to_ascii_lowercase()
+            }
+            CollectionFilter::ClassNames(ref classes) => {
+                // クラス名が１つも指定されていない場合は、どの要素にも一致しない
+                !classes.is_empty() && classes.iter().all(|c| element.contains_class(c))
+            }
+            CollectionFilter::Children => true,
+            CollectionFilter::ChildNodes => true,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::renderer::dom::api::append_child;
+    use crate::renderer::dom::api::get_target_element_node;
+    use crate::renderer::dom::api::get_text_content;
+    use crate::renderer::dom::api::remove_child;
+    use crate::renderer::dom::node::Element;
+    use crate::renderer::dom::node::ElementKind;
+    use crate::renderer::dom::node::NodeKind;
+    use crate::renderer::html::parser::HtmlParser;
+    use crate::renderer::html::token::HtmlTokenizer;
+    use alloc::vec;
+
+    fn parse_body(dom: &mut Dom, html: &str) -> NodeId {
+        let t = HtmlTokenizer::new(html.to_string());
+        let document = HtmlParser::new(dom, t).construct_tree(dom);
+        get_target_element_node(dom, Some(document), ElementKind::Body).expect("body should exist")
+    }
+
+    fn texts(dom: &Dom, collection: &HtmlCollection) -> Vec<String> {
+        collection
+            .nodes(dom)
+            .into_iter()
+            .map(|n| get_text_content(dom, n))
+            .collect()
+    }
+
+    #[test]
+    fn test_tag_name_and_class_names() {
+        let mut dom = Dom::new();
+        let body = parse_body(
+            &mut dom,
+            "<html><head></head><body><p class=\"a b\">1</p><h1 class=\"b\">2<a class=\"a b\">3</a></h1></body></html>",
+        );
+
+        let p = HtmlCollection::new(body, CollectionFilter::TagName("P".to_string()));
+        assert_eq!(vec!["1".to_string()], texts(&dom, &p));
+
+        let all = HtmlCollection::new(body, CollectionFilter::TagName("*".to_string()));
+        assert_eq!(3, all.length(&dom));
+
+        let ab = HtmlCollection::by_class_names(body, " b  a ");
+        assert_eq!(vec!["1".to_string(), "3".to_string()], texts(&dom, &ab));
+        assert_eq!(0, HtmlCollection::by_class_names(body, " ").length(&dom));
+    }
+
+    #[test]
+    fn test_live() {
+        let mut dom = Dom::new();
+        let body = parse_body(
+            &mut dom,
+            "<html><head></head><body><p>1</p>text</body></html>",
+        );
+        let children = HtmlCollection::new(body, CollectionFilter::Children);
+        let child_nodes = HtmlCollection::new(body, CollectionFilter::ChildNodes);
+        let p = HtmlCollection::new(body, CollectionFilter::TagName("p".to_string()));
+        assert_eq!(1, children.length(&dom));
+        assert_eq!(2, child_nodes.length(&dom));
+
+        // DOM ツリーの変更がコレクションに反映される
+        let new_p = dom.create_node(NodeKind::Element(Element::new("p", Vec::new())));
+        append_child(&mut dom, body, new_p).expect("p should be appended");
+        assert_eq!(2, children.length(&dom));
+        assert_eq!(3, child_nodes.length(&dom));
+        assert_eq!(2, p.length(&dom));
+        assert_eq!(Some(new_p), p.item(&dom, 1));
+
+        let first = p.item(&dom, 0).expect("p should exist");
+        remove_child(&mut dom, body, first).expect("p should be removed");
+        assert_eq!(1, p.length(&dom));
+        assert!(p.item(&dom, 1).is_none());
+    }
+}
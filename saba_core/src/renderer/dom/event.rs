@@ -0,0 +1,396 @@
+//! DOM イベントの伝播
+//! https://dom.spec.whatwg.org/#events
+
+use crate::renderer::dom::node::Dom;
+use crate::renderer::dom::node::NodeId;
+use crate::renderer::dom::node::NodeKind;
+use crate::renderer::dom::node::WindowId;
+use alloc::rc::Rc;
+use alloc::string::String;
+use alloc::string::ToString;
+use alloc::vec::Vec;
+use core::cell::RefCell;
+
+/// https://dom.spec.whatwg.org/#dom-event-eventphase
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum EventPhase {
+    None = 0,
+    Capturing = 1,
+    AtTarget = 2,
+    Bubbling = 3,
+}
+
+/// イベントを受け取るオブジェクト。DOM ノードとウィンドウがある
+/// https://dom.spec.whatwg.org/#interface-eventtarget
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum EventTarget {
+    Node(NodeId),
+    Window(WindowId),
+}
+
+impl EventTarget {
+    pub fn event_listeners(&self, dom: &Dom) -> Vec<EventListener> {
+        match *self {
+            EventTarget::Node(n) => dom.get(n).event_listeners(),
+            EventTarget::Window(w) => dom.window(w).event_listeners(),
+        }
+    }
+
+    pub fn add_event_listener(&self, dom: &mut Dom, listener: EventListener) {
+        match *self {
+            EventTarget::Node(n) => dom.get_mut(n).add_event_listener(listener),
+            EventTarget::Window(w) => dom.window_mut(w).add_event_listener(listener),
+        }
+    }
+
+    pub fn remove_event_listener(&self, dom: &mut Dom, listener: &EventListener) {
+        match *self {
+            EventTarget::Node(n) => dom.get_mut(n).remove_event_listener(listener),
+            EventTarget::Window(w) => dom.window_mut(w).remove_event_listener(listener),
+        }
+    }
+
+    /// イベントの伝播経路で次に来るオブジェクト
+    /// 文書の次はその文書を表示しているウィンドウになるが、load イベントはウィンドウまで伝播しない
+    /// https://dom.spec.whatwg.org/#get-the-parent
+    fn parent(&self, dom: &Dom, event: &Event) -> Option<EventTarget> {
+        let node = match *self {
+            EventTarget::Node(n) => dom.get(n),
+            EventTarget::Window(_) => return None,
+        };
+        if let Some(parent) = node.parent() {
+            return Some(EventTarget::Node(parent));
+        }
+        if matches!(node.kind, NodeKind::Document) && event.event_type != "load" {
+            return node.window().map(EventTarget::Window);
+        }
+        None
+    }
+}
+
+/// https://dom.spec.whatwg.org/#interface-event
+#[derive(Debug, Clone, PartialEq)]
+pub struct Event {
+    event_type: String,
+    bubbles: bool,
+    cancelable: bool,
+    target: Option<EventTarget>,
+    current_target: Option<EventTarget>,
+    phase: EventPhase,
+    stop_propagation: bool,
+    stop_immediate_propagation: bool,
+    canceled: bool,
+}
+
+impl Event {
+    pub fn new(event_type: &str, bubbles: bool, cancelable: bool) -> Self {
+        Self {
+            event_type: event_type.to_string(),
+            bubbles,
+            cancelable,
+            target: None,
+            current_target: None,
+            phase: EventPhase::None,
+            stop_propagation: false,
+            stop_immediate_propagation: false,
+            canceled: false,
+        }
+    }
+
+    pub fn event_type(&self) -> String {
+        self.event_type.clone()
+    }
+
+    pub fn bubbles(&self) -> bool {
+        self.bubbles
+    }
+
+    pub fn cancelable(&self) -> bool {
+        self.cancelable
+    }
+
+    pub fn target(&self) -> Option<EventTarget> {
+        self.target
+    }
+
+    pub fn current_target(&self) -> Option<EventTarget> {
+        self.current_target
+    }
+
+    pub fn phase(&self) -> EventPhase {
+        self.phase
+    }
+
+    /// 以降のノードへイベントが伝播しないようにする。現在のノードの残りのリスナーは呼ばれる
+    pub fn stop_propagation(&mut self) {
+        self.stop_propagation = true;
+    }
+
+    /// 現在のノードの残りのリスナーも含めて、以降のリスナーが呼ばれないようにする
+    pub fn stop_immediate_propagation(&mut self) {
+        self.stop_propagation = true;
+        self.stop_immediate_propagation = true;
+    }
+
+    /// キャンセル可能なイベントの既定の動作を取り消す
+    pub fn prevent_default(&mut self) {
+        if self.cancelable {
+            self.canceled = true;
+        }
+    }
+
+    pub fn default_prevented(&self) -> bool {
+        self.canceled
+    }
+}
+
+/// ノードに登録されたイベントリスナー
+/// 本書のブラウザでは、コールバックは JavaScript の関数名で表す
+/// https://dom.spec.whatwg.org/#concept-event-listener
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EventListener {
+    event_type: String,
+    callback: String,
+    capture: bool,
+}
+
+impl EventListener {
+    pub fn new(event_type: &str, callback: &str, capture: bool) -> Self {
+        Self {
+            event_type: event_type.to_string(),
+            callback: callback.to_string(),
+            capture,
+        }
+    }
+
+    pub fn event_type(&self) -> String {
+        self.event_type.clone()
+    }
+
+    pub fn callback(&self) -> String {
+        self.callback.clone()
+    }
+
+    pub fn capture(&self) -> bool {
+        self.capture
+    }
+}
+
+/// `dispatch_event` がリスナーを呼び出すために使う関数
+pub type InvokeListener<'a> = dyn FnMut(&mut Dom, &EventListener, &Rc<RefCell<Event>>) + 'a;
+
+/// `target` にイベントを送る。キャプチャフェーズではウィンドウから `target` の親へ、
+/// バブリングフェーズでは `target` の親からウィンドウへとイベントが伝播する
+/// 各リスナーは `invoke` によって呼び出される。リスナーは `dom` を変更してもよい
+/// 既定の動作が取り消されなかった場合は true を返す
+/// https://dom.spec.whatwg.org/#concept-event-dispatch
+pub fn dispatch_event(
+    dom: &mut Dom,
+    target: EventTarget,
+    event: &Rc<RefCell<Event>>,
+    invoke: &mut InvokeListener,
+) -> bool {
+    // イベントの伝播経路は、リスナーの呼び出し中に DOM ツリーが変更されても変わらない
+    let mut path = Vec::new();
+    let mut current = Some(target);
+    while let Some(t) = current {
+        current = t.parent(dom, &event.borrow());
+        path.push(t);
+    }
+
+    event.borrow_mut().target = Some(target);
+
+    for t in path.iter().skip(1).rev() {
+        invoke_listeners(dom, *t, event, EventPhase::Capturing, invoke);
+    }
+    invoke_listeners(dom, target, event, EventPhase::AtTarget, invoke);
+    if event.borrow().bubbles {
+        for t in path.iter().skip(1) {
+            invoke_listeners(dom, *t, event, EventPhase::Bubbling, invoke);
+        }
+    }
+
+    let mut e = event.borrow_mut();
+    e.phase = EventPhase::None;
+    e.current_target = None;
+    !e.canceled
+}
+
+fn invoke_listeners(
+    dom: &mut Dom,
+    target: EventTarget,
+    event: &Rc<RefCell<Event>>,
+    phase: EventPhase,
+    invoke: &mut InvokeListener,
+) {
+    if event.borrow().stop_propagation {
+        return;
+    }
+
+    {
+        let mut e = event.borrow_mut();
+        e.phase = phase;
+        e.current_target = Some(target);
+    }
+
+    // リスナーの中で追加されたリスナーは、今回のイベントでは呼ばれない
+    let listeners = target.event_listeners(dom);
+    for listener in listeners {
+        if listener.event_type != event.borrow().event_type {
+            continue;
+        }
+        // ターゲット上では、キャプチャかどうかに関わらずすべてのリスナーが呼ばれる
+        match phase {
+            EventPhase::Capturing if !listener.capture => continue,
+            EventPhase::Bubbling if listener.capture => continue,
+            _ => {}
+        }
+        // リスナーの中で削除されたリスナーは呼ばれない
+        if !target.event_listeners(dom).contains(&listener) {
+            continue;
+        }
+
+        invoke(dom, &listener, event);
+
+        if event.borrow().stop_immediate_propagation {
+            return;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::renderer::dom::api::get_target_element_node;
+    use crate::renderer::dom::node::ElementKind;
+    use crate::renderer::html::parser::HtmlParser;
+    use crate::renderer::html::token::HtmlTokenizer;
+    use alloc::format;
+    use alloc::vec;
+
+    fn parse(dom: &mut Dom, html: &str) -> NodeId {
+        let t = HtmlTokenizer::new(html.to_string());
+        HtmlParser::new(dom, t).construct_tree(dom)
+    }
+
+    fn listen(dom: &mut Dom, node: NodeId, name: &str, capture: bool) {
+        dom.get_mut(node)
+            .add_event_listener(EventListener::new("click", name, capture));
+    }
+
+    /// イベントを送り、呼ばれたリスナーの名前とその時のフェーズを記録する
+    fn dispatch(
+        dom: &mut Dom,
+        target: NodeId,
+        event: Event,
+        on_call: &dyn Fn(&str, &mut Event),
+    ) -> (Vec<String>, bool) {
+        let event = Rc::new(RefCell::new(event));
+        let mut calls = Vec::new();
+        let target = EventTarget::Node(target);
+        let result = dispatch_event(dom, target, &event, &mut |_, listener, event| {
+            let phase = event.borrow().phase() as u8;
+            calls.push(format!("{}:{}", listener.callback(), phase));
+            on_call(&listener.callback(), &mut event.borrow_mut());
+        });
+        (calls, result)
+    }
+
+    #[test]
+    fn test_capture_and_bubble() {
+        let mut dom = Dom::new();
+        let document = parse(
+            &mut dom,
+            "<html><head></head><body><p><a>link</a></p></body></html>",
+        );
+        let body = get_target_element_node(&dom, Some(document), ElementKind::Body).unwrap();
+        let a = get_target_element_node(&dom, Some(document), ElementKind::A).unwrap();
+        listen(&mut dom, document, "document", true);
+        listen(&mut dom, body, "body-bubble", false);
+        listen(&mut dom, body, "body-capture", true);
+        listen(&mut dom, a, "a", false);
+        listen(&mut dom, a, "a-capture", true);
+        // 同じリスナーは2回登録されない
+        listen(&mut dom, a, "a", false);
+
+        let (calls, result) = dispatch(&mut dom, a, Event::new("click", true, true), &|_, _| {});
+        assert_eq!(
+            vec![
+                "document:1",
+                "body-capture:1",
+                "a:2",
+                "a-capture:2",
+                "body-bubble:3"
+            ],
+            calls
+        );
+        assert!(result);
+
+        // バブリングしないイベントは、キャプチャフェーズとターゲットでのみ呼ばれる
+        let (calls, _) = dispatch(&mut dom, a, Event::new("click", false, true), &|_, _| {});
+        assert_eq!(
+            vec!["document:1", "body-capture:1", "a:2", "a-capture:2"],
+            calls
+        );
+
+        // 別の種類のイベントのリスナーは呼ばれない
+        let (calls, _) = dispatch(&mut dom, a, Event::new("input", true, true), &|_, _| {});
+        assert!(calls.is_empty());
+    }
+
+    #[test]
+    fn test_stop_propagation_and_prevent_default() {
+        let mut dom = Dom::new();
+        let document = parse(
+            &mut dom,
+            "<html><head></head><body><p><a>link</a></p></body></html>",
+        );
+        let p = get_target_element_node(&dom, Some(document), ElementKind::P).unwrap();
+        let a = get_target_element_node(&dom, Some(document), ElementKind::A).unwrap();
+        listen(&mut dom, document, "document", false);
+        listen(&mut dom, p, "p1", false);
+        listen(&mut dom, p, "p2", false);
+        listen(&mut dom, a, "a", false);
+
+        let (calls, result) = dispatch(
+            &mut dom,
+            a,
+            Event::new("click", true, true),
+            &|name, event| {
+                if name == "p1" {
+                    event.stop_propagation();
+                    event.prevent_default();
+                }
+            },
+        );
+        assert_eq!(vec!["a:2", "p1:3", "p2:3"], calls);
+        assert!(!result);
+
+        let (calls, _) = dispatch(
+            &mut dom,
+            a,
+            Event::new("click", true, true),
+            &|name, event| {
+                if name == "p1" {
+                    event.stop_immediate_propagation();
+                }
+            },
+        );
+        assert_eq!(vec!["a:2", "p1:3"], calls);
+
+        // キャンセルできないイベントの既定の動作は取り消せない
+        let (_, result) = dispatch(
+            &mut dom,
+            a,
+            Event::new("click", true, false),
+            &|_, event| event.prevent_default(),
+        );
+        assert!(result);
+
+        // 削除されたリスナーは呼ばれない
+        dom.get_mut(p)
+            .remove_event_listener(&EventListener::new("click", "p1", false));
+        let (calls, _) = dispatch(&mut dom, a, Event::new("click", true, true), &|_, _| {});
+        assert_eq!(vec!["a:2", "p2:3", "document:3"], calls);
+    }
+}
@@ -1,2 +1,6 @@
 pub mod api;
+pub mod collection;
+pub mod event;
+pub mod mutation;
 pub mod node;
+pub mod traversal;
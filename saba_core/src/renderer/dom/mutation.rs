@@ -0,0 +1,307 @@
+//! DOM ツリーの変更の監視
+//! https://dom.spec.whatwg.org/#mutation-observers
+
+use crate::error::Error;
+use crate::renderer::dom::node::Dom;
+use crate::renderer::dom::node::NodeId;
+use alloc::rc::Rc;
+use alloc::string::String;
+use alloc::string::ToString;
+use alloc::vec::Vec;
+use core::cell::RefCell;
+use core::fmt::Display;
+use core::fmt::Formatter;
+
+/// https://dom.spec.whatwg.org/#dom-mutationrecord-type
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum MutationRecordType {
+    ChildList,
+    Attributes,
+    CharacterData,
+}
+
+impl Display for MutationRecordType {
+    fn fmt(&self, f: &mut Formatter) -> core::fmt::Result {
+        let s = match self {
+            MutationRecordType::ChildList => "childList",
+            MutationRecordType::Attributes => "attributes",
+            MutationRecordType::CharacterData => "characterData",
+        };
+        write!(f, "{}", s)
+    }
+}
+
+/// 1回の DOM ツリーの変更を表す
+/// https://dom.spec.whatwg.org/#interface-mutationrecord
+#[derive(Debug, Clone, PartialEq)]
+pub struct MutationRecord {
+    record_type: MutationRecordType,
+    target: NodeId,
+    added_nodes: Vec<NodeId>,
+    removed_nodes: Vec<NodeId>,
+    previous_sibling: Option<NodeId>,
+    next_sibling: Option<NodeId>,
+    attribute_name: Option<String>,
+    old_value: Option<String>,
+}
+
+impl MutationRecord {
+    pub fn record_type(&self) -> MutationRecordType {
+        self.record_type
+    }
+
+    pub fn target(&self) -> NodeId {
+        self.target
+    }
+
+    pub fn added_nodes(&self) -> Vec<NodeId> {
+        self.added_nodes.clone()
+    }
+
+    pub fn removed_nodes(&self) -> Vec<NodeId> {
+        self.removed_nodes.clone()
+    }
+
+    pub fn previous_sibling(&self) -> Option<NodeId> {
+        self.previous_sibling
+    }
+
+    pub fn next_sibling(&self) -> Option<NodeId> {
+        self.next_sibling
+    }
+
+    pub fn attribute_name(&self) -> Option<String> {
+        self.attribute_name.clone()
+    }
+
+    pub fn old_value(&self) -> Option<String> {
+        self.old_value.clone()
+    }
+}
+
+/// 監視する変更の種類
+/// https://dom.spec.whatwg.org/#dictdef-mutationobserverinit
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub struct MutationObserverInit {
+    pub child_list: bool,
+    pub attributes: bool,
+    pub character_data: bool,
+    pub subtree: bool,
+    pub attribute_old_value: bool,
+    pub character_data_old_value: bool,
+}
+
+/// 本書のブラウザでは、コールバックは JavaScript の関数名で表す
+/// https://dom.spec.whatwg.org/#interface-mutationobserver
+#[derive(Debug)]
+pub struct MutationObserver {
+    callback: String,
+    records: Vec<MutationRecord>,
+    // 監視しているノード。disconnect のときに登録を取り除くために使う
+    nodes: Vec<NodeId>,
+}
+
+impl MutationObserver {
+    pub fn new(callback: &str) -> Rc<RefCell<Self>> {
+        Rc::new(RefCell::new(Self {
+            callback: callback.to_string(),
+            records: Vec::new(),
+            nodes: Vec::new(),
+        }))
+    }
+
+    pub fn callback(&self) -> String {
+        self.callback.clone()
+    }
+
+    pub fn has_records(&self) -> bool {
+        !self.records.is_empty()
+    }
+
+    /// たまっている変更の記録を取り出す
+    /// https://dom.spec.whatwg.org/#dom-mutationobserver-takerecords
+    pub fn take_records(&mut self) -> Vec<MutationRecord> {
+        core::mem::take(&mut self.records)
+    }
+}
+
+impl PartialEq for MutationObserver {
+    fn eq(&self, other: &Self) -> bool {
+        core::ptr::eq(self, other)
+    }
+}
+
+/// ノードに登録された MutationObserver と、その監視の設定
+/// https://dom.spec.whatwg.org/#registered-observer
+#[derive(Debug, Clone)]
+pub struct RegisteredObserver {
+    observer: Rc<RefCell<MutationObserver>>,
+    options: MutationObserverInit,
+}
+
+/// `target` の変更を `observer` で監視する。すでに監視している場合は設定を置き換える
+/// https://dom.spec.whatwg.org/#dom-mutationobserver-observe
+pub fn observe(
+    dom: &mut Dom,
+    observer: &Rc<RefCell<MutationObserver>>,
+    target: NodeId,
+    mut options: MutationObserverInit,
+) -> Result<(), Error> {
+    if options.attribute_old_value {
+        options.attributes = true;
+    }
+    if options.character_data_old_value {
+        options.character_data = true;
+    }
+    if !options.child_list && !options.attributes && !options.character_data {
+        return Err(Error::UnexpectedInput(
+            "one of childList, attributes or characterData must be true".to_string(),
+        ));
+    }
+
+    let mut registered = dom.get(target).registered_observers();
+    match registered
+        .iter_mut()
+        .find(|r| Rc::ptr_eq(&r.observer, observer))
+    {
+        Some(r) => r.options = options,
+        None => {
+            registered.push(RegisteredObserver {
+                observer: observer.clone(),
+                options,
+            });
+            observer.borrow_mut().nodes.push(target);
+        }
+    }
+    dom.get_mut(target).set_registered_observers(registered);
+
+    Ok(())
+}
+
+/// `observer` によるすべての監視をやめ、たまっている記録を捨てる
+/// https://dom.spec.whatwg.org/#dom-mutationobserver-disconnect
+pub fn disconnect(dom: &mut Dom, observer: &Rc<RefCell<MutationObserver>>) {
+    let nodes = core::mem::take(&mut observer.borrow_mut().nodes);
+    for node in nodes {
+        let mut registered = dom.get(node).registered_observers();
+        registered.retain(|r| !Rc::ptr_eq(&r.observer, observer));
+        dom.get_mut(node).set_registered_observers(registered);
+    }
+    observer.borrow_mut().records.clear();
+}
+
+/// `target` の `record_type` の変更を監視している MutationObserver と、変更前の値が必要かどうかを返す
+/// https://dom.spec.whatwg.org/#queue-a-mutation-record
+fn interested_observers(
+    dom: &Dom,
+    target: NodeId,
+    record_type: MutationRecordType,
+) -> Vec<(Rc<RefCell<MutationObserver>>, bool)> {
+    let mut interested: Vec<(Rc<RefCell<MutationObserver>>, bool)> = Vec::new();
+
+    let mut node = Some(target);
+    while let Some(n) = node {
+        for registered in dom.get(n).registered_observers() {
+            let options = registered.options;
+            // 祖先に登録された監視は、subtree が true の場合のみ子孫の変更を受け取る
+            if n != target && !options.subtree {
+                continue;
+            }
+            let (observed, old_value) = match record_type {
+                MutationRecordType::ChildList => (options.child_list, false),
+                MutationRecordType::Attributes => (options.attributes, options.attribute_old_value),
+                MutationRecordType::CharacterData => {
+                    (options.character_data, options.character_data_old_value)
+                }
+            };
+            if !observed {
+                continue;
+            }
+
+            // 同じ MutationObserver が複数のノードで監視している場合も、記録は1つだけ受け取る
+            match interested
+                .iter_mut()
+                .find(|(o, _)| Rc::ptr_eq(o, &registered.observer))
+            {
+                Some(entry) => entry.1 |= old_value,
+                None => interested.push((registered.observer.clone(), old_value)),
+            }
+        }
+        node = dom.get(n).parent();
+    }
+
+    interested
+}
+
+/// `target` の `record_type` の変更を監視している MutationObserver があるかどうか
+pub fn is_observed(dom: &Dom, target: NodeId, record_type: MutationRecordType) -> bool {
+    !interested_observers(dom, target, record_type).is_empty()
+}
+
+fn queue_mutation_record(dom: &Dom, record: MutationRecord) {
+    for (observer, old_value) in interested_observers(dom, record.target, record.record_type) {
+        let mut record = record.clone();
+        if !old_value {
+            record.old_value = None;
+        }
+        observer.borrow_mut().records.push(record);
+    }
+}
+
+/// `target` の子ノードの追加と削除を記録する
+pub fn queue_child_list_mutation(
+    dom: &Dom,
+    target: NodeId,
+    added_nodes: Vec<NodeId>,
+    removed_nodes: Vec<NodeId>,
+    previous_sibling: Option<NodeId>,
+    next_sibling: Option<NodeId>,
+) {
+    queue_mutation_record(
+        dom,
+        MutationRecord {
+            record_type: MutationRecordType::ChildList,
+            target,
+            added_nodes,
+            removed_nodes,
+            previous_sibling,
+            next_sibling,
+            attribute_name: None,
+            old_value: None,
+        },
+    );
+}
+
+/// `target` の属性 `name` の変更を記録する
+pub fn queue_attribute_mutation(dom: &Dom, target: NodeId, name: &str, old_value: Option<String>) {
+    queue_mutation_record(
+        dom,
+        MutationRecord {
+            record_type: MutationRecordType::Attributes,
+            target,
+            added_nodes: Vec::new(),
+            removed_nodes: Vec::new(),
+            previous_sibling: None,
+            next_sibling: None,
+            attribute_name: Some(name.to_string()),
+            old_value,
+        },
+    );
+}
+
+/// テキストノード `target` の文字列の変更を記録する
+pub fn queue_character_data_mutation(dom: &Dom, target: NodeId, old_value: String) {
+    queue_mutation_record(
+        dom,
+        MutationRecord {
+            record_type: MutationRecordType::CharacterData,
+            target,
+            added_nodes: Vec::new(),
+            removed_nodes: Vec::new(),
+            previous_sibling: None,
+            next_sibling: None,
+            attribute_name: None,
+            old_value: Some(old_value),
+        },
+    );
+}
@@ -1,6 +1,8 @@
+use crate::renderer::dom::event::EventListener;
+use crate::renderer::dom::mutation::RegisteredObserver;
 use crate::renderer::html::attribute::Attribute;
+use crate::renderer::page::Page;
 use alloc::format;
-use alloc::rc::Rc;
 use alloc::rc::Weak;
 use alloc::string::String;
 use alloc::vec::Vec;
@@ -9,15 +11,94 @@ use core::fmt::Display;
 use core::fmt::Formatter;
 use core::str::FromStr;
 
+/// DOM ツリーのノードを指す ID
+/// Dom が持つ配列 (アリーナ) の添字で、ノードがツリーから取り除かれても変わらない
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct NodeId(usize);
+
+impl NodeId {
+    pub fn index(&self) -> usize {
+        self.0
+    }
+}
+
+/// ウィンドウを指す ID。Dom が持つウィンドウの配列の添字
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct WindowId(usize);
+
+/// DOM ツリーのノードと、文書を表示するウィンドウを持つアリーナ
+/// ノード同士は NodeId で参照しあう。ツリーから取り除かれたノードも、アリーナが破棄されるまで残る
+/// ノードを個別に解放することはせず、Page が新しい文書を読み込むたびにアリーナごと作りなおす
+#[derive(Debug, Clone, Default)]
+pub struct Dom {
+    nodes: Vec<Node>,
+    windows: Vec<Window>,
+}
+
+impl Dom {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// どの文書にも属さない新しいノードを作成する
+    pub fn create_node(&mut self, kind: NodeKind) -> NodeId {
+        let id = NodeId(self.nodes.len());
+        self.nodes.push(Node::new(kind));
+        id
+    }
+
+    /// ウィンドウに表示されない新しい文書を作成する
+    pub fn create_document(&mut self) -> NodeId {
+        self.create_node(NodeKind::Document)
+    }
+
+    /// 新しい文書とそれを表示するウィンドウを作成する
+    pub fn create_window(&mut self) -> WindowId {
+        let document = self.create_document();
+        let id = WindowId(self.windows.len());
+        self.windows.push(Window::new(document));
+        self.get_mut(document).window = Some(id);
+        id
+    }
+
+    /// アリーナが持つノードの数。ツリーから取り除かれたノードも数える
+    pub fn node_count(&self) -> usize {
+        self.nodes.len()
+    }
+
+    /// `id` が指すノードを返す
+    pub fn get(&self, id: NodeId) -> &Node {
+        &self.nodes[id.0]
+    }
+
+    pub fn get_mut(&mut self, id: NodeId) -> &mut Node {
+        &mut self.nodes[id.0]
+    }
+
+    /// `id` が指すウィンドウを返す
+    pub fn window(&self, id: WindowId) -> &Window {
+        &self.windows[id.0]
+    }
+
+    pub fn window_mut(&mut self, id: WindowId) -> &mut Window {
+        &mut self.windows[id.0]
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct Node {
     pub kind: NodeKind,
-    window: Weak<RefCell<Window>>,
-    parent: Weak<RefCell<Node>>,
-    first_child: Option<Rc<RefCell<Node>>>,
-    last_child: Weak<RefCell<Node>>,
-    previous_sibling: Weak<RefCell<Node>>,
-    next_sibling: Option<Rc<RefCell<Node>>>,
+    // 文書ノードの場合のみ、その文書を表示するウィンドウを指す
+    window: Option<WindowId>,
+    // ノードが属する文書。文書ノード自身の場合は空になる
+    owner_document: Option<NodeId>,
+    parent: Option<NodeId>,
+    first_child: Option<NodeId>,
+    last_child: Option<NodeId>,
+    previous_sibling: Option<NodeId>,
+    next_sibling: Option<NodeId>,
+    event_listeners: Vec<EventListener>,
+    registered_observers: Vec<RegisteredObserver>,
 }
 
 impl PartialEq for Node {
@@ -52,16 +133,71 @@ pub enum ElementKind {
     Head,
     Style,
     Script,
+    Title,
+    Meta,
+    Link,
     Body,
     P,
+    Pre,
     H1,
     H2,
     A,
+    Br,
+    Hr,
+    Img,
+    Input,
+    Textarea,
+    Xmp,
+    Plaintext,
 }
 
 #[derive(Debug, Clone)]
 pub struct Window {
-    pub document: Rc<RefCell<Node>>,
+    document: NodeId,
+    page: Weak<RefCell<Page>>,
+    // 表示している文書の読み込みの状態 (document.readyState)
+    ready_state: DocumentReadyState,
+    event_listeners: Vec<EventListener>,
+    // マウスカーソルの下にある要素 (:hover)
+    hovered_element: Option<NodeId>,
+    // マウスのボタンが押されている要素 (:active)
+    active_element: Option<NodeId>,
+    // フォーカスされている要素 (:focus)
+    focused_element: Option<NodeId>,
+    // これまでに移動した URL。:visited に使う
+    visited_urls: Vec<String>,
+}
+
+/// ユーザーの操作によって変わる要素の状態。動的な擬似クラスに対応する
+/// https://www.w3.org/TR/selectors-4/#useraction-pseudos
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ElementState {
+    Hover,
+    Active,
+    Focus,
+}
+
+/// 文書の読み込みの状態
+/// https://html.spec.whatwg.org/multipage/dom.html#current-document-readiness
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum DocumentReadyState {
+    // HTML を解釈している
+    Loading,
+    // HTML の解釈が終わった
+    Interactive,
+    // 文書の読み込みが終わった
+    Complete,
+}
+
+impl Display for DocumentReadyState {
+    fn fmt(&self, f: &mut Formatter) -> core::fmt::Result {
+        let s = match self {
+            DocumentReadyState::Loading => "loading",
+            DocumentReadyState::Interactive => "interactive",
+            DocumentReadyState::Complete => "complete",
+        };
+        write!(f, "{}", s)
+    }
 }
 
 #[derive(Debug, Clone, PartialEq, Eq)]
@@ -74,57 +210,69 @@ impl Node {
     pub fn new(kind: NodeKind) -> Self {
         Self {
             kind,
-            window: Weak::new(),
-            parent: Weak::new(),
+            window: None,
+            owner_document: None,
+            parent: None,
             first_child: None,
-            last_child: Weak::new(),
-            previous_sibling: Weak::new(),
+            last_child: None,
+            previous_sibling: None,
             next_sibling: None,
+            event_listeners: Vec::new(),
+            registered_observers: Vec::new(),
         }
     }
 
-    pub fn set_parent(&mut self, parent: Weak<RefCell<Node>>) {
+    pub fn set_parent(&mut self, parent: Option<NodeId>) {
         self.parent = parent;
     }
 
-    pub fn parent(&self) -> Weak<RefCell<Node>> {
-        self.parent.clone()
+    pub fn parent(&self) -> Option<NodeId> {
+        self.parent
     }
 
-    pub fn set_first_child(&mut self, first_child: Option<Rc<RefCell<Node>>>) {
+    pub fn set_first_child(&mut self, first_child: Option<NodeId>) {
         self.first_child = first_child;
     }
 
-    pub fn first_child(&self) -> Option<Rc<RefCell<Node>>> {
-        self.first_child.as_ref().cloned()
+    pub fn first_child(&self) -> Option<NodeId> {
+        self.first_child
     }
 
-    pub fn set_last_child(&mut self, last_child: Weak<RefCell<Node>>) {
+    pub fn set_last_child(&mut self, last_child: Option<NodeId>) {
         self.last_child = last_child;
     }
 
-    pub fn last_child(&self) -> Weak<RefCell<Node>> {
-        self.last_child.clone()
+    pub fn last_child(&self) -> Option<NodeId> {
+        self.last_child
     }
 
-    pub fn set_previous_sibling(&mut self, previous_sibling: Weak<RefCell<Node>>) {
+    pub fn set_previous_sibling(&mut self, previous_sibling: Option<NodeId>) {
         self.previous_sibling = previous_sibling;
     }
 
-    pub fn previous_sibling(&self) -> Weak<RefCell<Node>> {
-        self.previous_sibling.clone()
+    pub fn previous_sibling(&self) -> Option<NodeId> {
+        self.previous_sibling
     }
 
-    pub fn set_next_sibling(&mut self, next_sibling: Option<Rc<RefCell<Node>>>) {
+    pub fn set_next_sibling(&mut self, next_sibling: Option<NodeId>) {
         self.next_sibling = next_sibling;
     }
 
-    pub fn next_sibling(&self) -> Option<Rc<RefCell<Node>>> {
-        self.next_sibling.as_ref().cloned()
+    pub fn next_sibling(&self) -> Option<NodeId> {
+        self.next_sibling
+    }
+
+    pub fn window(&self) -> Option<WindowId> {
+        self.window
+    }
+
+    pub fn set_owner_document(&mut self, document: Option<NodeId>) {
+        self.owner_document = document;
     }
 
-    pub fn set_window(&mut self, window: Weak<RefCell<Window>>) {
-        self.window = window;
+    /// https://dom.spec.whatwg.org/#dom-node-ownerdocument
+    pub fn owner_document(&self) -> Option<NodeId> {
+        self.owner_document
     }
 
     pub fn kind(&self) -> NodeKind {
@@ -138,30 +286,122 @@ impl Node {
         }
     }
 
+    pub fn get_element_mut(&mut self) -> Option<&mut Element> {
+        match self.kind {
+            NodeKind::Document | NodeKind::Text(_) => None,
+            NodeKind::Element(ref mut e) => Some(e),
+        }
+    }
+
     pub fn element_kind(&self) -> Option<ElementKind> {
         match self.kind {
             NodeKind::Document | NodeKind::Text(_) => None,
             NodeKind::Element(ref e) => Some(e.kind),
         }
     }
+
+    /// https://dom.spec.whatwg.org/#add-an-event-listener
+    pub fn add_event_listener(&mut self, listener: EventListener) {
+        // 同じ種類、コールバック、キャプチャの組み合わせのリスナーは1つしか登録しない
+        if !self.event_listeners.contains(&listener) {
+            self.event_listeners.push(listener);
+        }
+    }
+
+    /// https://dom.spec.whatwg.org/#remove-an-event-listener
+    pub fn remove_event_listener(&mut self, listener: &EventListener) {
+        self.event_listeners.retain(|l| l != listener);
+    }
+
+    pub fn event_listeners(&self) -> Vec<EventListener> {
+        self.event_listeners.clone()
+    }
+
+    /// このノードの変更を監視している MutationObserver
+    /// https://dom.spec.whatwg.org/#registered-observer-list
+    pub fn registered_observers(&self) -> Vec<RegisteredObserver> {
+        self.registered_observers.clone()
+    }
+
+    pub fn set_registered_observers(&mut self, registered_observers: Vec<RegisteredObserver>) {
+        self.registered_observers = registered_observers;
+    }
 }
 
 impl Window {
-    pub fn new() -> Self {
-        let window = Self {
-            document: Rc::new(RefCell::new(Node::new(NodeKind::Document))),
-        };
+    fn new(document: NodeId) -> Self {
+        Self {
+            document,
+            page: Weak::new(),
+            ready_state: DocumentReadyState::Loading,
+            event_listeners: Vec::new(),
+            hovered_element: None,
+            active_element: None,
+            focused_element: None,
+            visited_urls: Vec::new(),
+        }
+    }
+
+    pub fn document(&self) -> NodeId {
+        self.document
+    }
+
+    pub fn set_page(&mut self, page: Weak<RefCell<Page>>) {
+        self.page = page;
+    }
 
-        window
-            .document
-            .borrow_mut()
-            .set_window(Rc::downgrade(&Rc::new(RefCell::new(window.clone()))));
+    /// ウィンドウが表示されているページ
+    pub fn page(&self) -> Weak<RefCell<Page>> {
+        self.page.clone()
+    }
+
+    pub fn set_ready_state(&mut self, ready_state: DocumentReadyState) {
+        self.ready_state = ready_state;
+    }
+
+    /// https://html.spec.whatwg.org/multipage/dom.html#dom-document-readystate
+    pub fn ready_state(&self) -> DocumentReadyState {
+        self.ready_state
+    }
+
+    pub fn add_event_listener(&mut self, listener: EventListener) {
+        if !self.event_listeners.contains(&listener) {
+            self.event_listeners.push(listener);
+        }
+    }
+
+    pub fn remove_event_listener(&mut self, listener: &EventListener) {
+        self.event_listeners.retain(|l| l != listener);
+    }
+
+    pub fn event_listeners(&self) -> Vec<EventListener> {
+        self.event_listeners.clone()
+    }
+
+    /// `state` の状態にある要素を返す
+    pub fn element_with_state(&self, state: ElementState) -> Option<NodeId> {
+        match state {
+            ElementState::Hover => self.hovered_element,
+            ElementState::Active => self.active_element,
+            ElementState::Focus => self.focused_element,
+        }
+    }
+
+    pub fn set_element_with_state(&mut self, state: ElementState, element: Option<NodeId>) {
+        match state {
+            ElementState::Hover => self.hovered_element = element,
+            ElementState::Active => self.active_element = element,
+            ElementState::Focus => self.focused_element = element,
+        }
+    }
 
-        window
+    pub fn set_visited_urls(&mut self, visited_urls: Vec<String>) {
+        self.visited_urls = visited_urls;
     }
 
-    pub fn document(&self) -> Rc<RefCell<Node>> {
-        self.document.clone()
+    /// `url` に移動したことがあるかどうか
+    pub fn is_visited(&self, url: &str) -> bool {
+        self.visited_urls.iter().any(|u| u == url)
     }
 }
 
@@ -178,11 +418,18 @@ impl Element {
         self.kind
     }
 
-    pub fn is_block_element(&self) -> bool {
-        match self.kind {
-            ElementKind::Body | ElementKind::H1 | ElementKind::H2 | ElementKind::P => true,
-            _ => false,
-        }
+    /// 終了タグを持たず、子ノードも持たない空要素かどうか
+    /// https://html.spec.whatwg.org/multipage/syntax.html#void-elements
+    pub fn is_void_element(&self) -> bool {
+        matches!(
+            self.kind,
+            ElementKind::Meta
+                | ElementKind::Link
+                | ElementKind::Br
+                | ElementKind::Hr
+                | ElementKind::Img
+                | ElementKind::Input
+        )
     }
 
     pub fn attributes(&self) -> Vec<Attribute> {
@@ -197,6 +444,135 @@ impl Element {
         }
         None
     }
+
+    pub fn has_attribute(&self, name: &str) -> bool {
+        self.get_attribute(name).is_some()
+    }
+
+    /// 属性の値を設定する。属性が存在しない場合は追加する
+    /// https://dom.spec.whatwg.org/#dom-element-setattribute
+    pub fn set_attribute(&mut self, name: &str, value: &str) {
+        // HTML 文書の属性名は小文字として扱われる
+        let name = name.to_ascii_lowercase();
+        for attr in self.attributes.iter_mut() {
+            if attr.name() == name {
+                attr.set_value(value);
+                return;
+            }
+        }
+
+        let mut attr = Attribute::new();
+        attr.set_name(&name);
+        attr.set_value(value);
+        self.attributes.push(attr);
+    }
+
+    pub fn remove_attribute(&mut self, name: &str) {
+        let name = name.to_ascii_lowercase();
+        self.attributes.retain(|attr| attr.name() != name);
+    }
+
+    /// class 属性を空白で区切ったクラス名の一覧 (classList)
+    /// https://dom.spec.whatwg.org/#dom-element-classlist
+    pub fn class_list(&self) -> Vec<String> {
+        let mut classes: Vec<String> = Vec::new();
+        if let Some(value) = self.get_attribute("class") {
+            for class in value.split_ascii_whitespace() {
+                // 重複するクラス名は1つにまとめる
+                if !classes.iter().any(|c| c == class) {
+                    classes.push(String::from(class));
+                }
+            }
+        }
+        classes
+    }
+
+    pub fn contains_class(&self, class: &str) -> bool {
+        self.class_list().iter().any(|c| c == class)
+    }
+
+    pub fn add_class(&mut self, class: &str) {
+        let mut classes = self.class_list();
+        if !classes.iter().any(|c| c == class) {
+            classes.push(String::from(class));
+        }
+        self.set_attribute("class", &classes.join(" "));
+    }
+
+    pub fn remove_class(&mut self, class: &str) {
+        let mut classes = self.class_list();
+        classes.retain(|c| c != class);
+        self.set_attribute("class", &classes.join(" "));
+    }
+
+    /// クラス名があれば取り除き、なければ追加する。追加した場合は true を返す
+    pub fn toggle_class(&mut self, class: &str) -> bool {
+        if self.contains_class(class) {
+            self.remove_class(class);
+            false
+        } else {
+            self.add_class(class);
+            true
+        }
+    }
+
+    /// data-* 属性の名前と値の一覧 (dataset)。名前は "data-" を除いたキャメルケースになる
+    /// https://html.spec.whatwg.org/multipage/dom.html#dom-dataset
+    pub fn dataset(&self) -> Vec<(String, String)> {
+        self.attributes
+            .iter()
+            .filter_map(|attr| {
+                let name = attr.name();
+                let key = name.strip_prefix("data-")?;
+                let mut camel = String::new();
+                let mut upper = false;
+                for c in key.chars() {
+                    if c == '-' {
+                        if upper {
+                            camel.push('-');
+                        }
+                        upper = true;
+                        continue;
+                    }
+                    if upper && c.is_ascii_lowercase() {
+                        camel.push(c.to_ascii_uppercase());
+                    } else {
+                        if upper {
+                            camel.push('-');
+                        }
+                        camel.push(c);
+                    }
+                    upper = false;
+                }
+                if upper {
+                    camel.push('-');
+                }
+                Some((camel, attr.value()))
+            })
+            .collect()
+    }
+
+    pub fn get_data(&self, key: &str) -> Option<String> {
+        self.get_attribute(&data_attribute_name(key))
+    }
+
+    pub fn set_data(&mut self, key: &str, value: &str) {
+        self.set_attribute(&data_attribute_name(key), value);
+    }
+}
+
+/// dataset のキャメルケースのキーを data-* 属性の名前に変換する。"fooBar" は "data-foo-bar" になる
+fn data_attribute_name(key: &str) -> String {
+    let mut name = String::from("data-");
+    for c in key.chars() {
+        if c.is_ascii_uppercase() {
+            name.push('-');
+            name.push(c.to_ascii_lowercase());
+        } else {
+            name.push(c);
+        }
+    }
+    name
 }
 
 impl FromStr for ElementKind {
@@ -208,11 +584,22 @@ impl FromStr for ElementKind {
             "head" => Ok(ElementKind::Head),
             "style" => Ok(ElementKind::Style),
             "script" => Ok(ElementKind::Script),
+            "title" => Ok(ElementKind::Title),
+            "meta" => Ok(ElementKind::Meta),
+            "link" => Ok(ElementKind::Link),
             "body" => Ok(ElementKind::Body),
             "p" => Ok(ElementKind::P),
+            "pre" => Ok(ElementKind::Pre),
             "h1" => Ok(ElementKind::H1),
             "h2" => Ok(ElementKind::H2),
             "a" => Ok(ElementKind::A),
+            "br" => Ok(ElementKind::Br),
+            "hr" => Ok(ElementKind::Hr),
+            "img" => Ok(ElementKind::Img),
+            "input" => Ok(ElementKind::Input),
+            "textarea" => Ok(ElementKind::Textarea),
+            "xmp" => Ok(ElementKind::Xmp),
+            "plaintext" => Ok(ElementKind::Plaintext),
             _ => Err(format!("unimplemented element name: {:?}", s)),
         }
     }
@@ -225,12 +612,78 @@ impl Display for ElementKind {
             ElementKind::Head => "head",
             ElementKind::Style => "style",
             ElementKind::Script => "script",
+            ElementKind::Title => "title",
+            ElementKind::Meta => "meta",
+            ElementKind::Link => "link",
             ElementKind::Body => "body",
             ElementKind::H1 => "h1",
             ElementKind::H2 => "h2",
             ElementKind::P => "p",
+            ElementKind::Pre => "pre",
             ElementKind::A => "a",
+            ElementKind::Br => "br",
+            ElementKind::Hr => "hr",
+            ElementKind::Img => "img",
+            ElementKind::Input => "input",
+            ElementKind::Textarea => "textarea",
+            ElementKind::Xmp => "xmp",
+            ElementKind::Plaintext => "plaintext",
         };
         write!(f, "{}", s)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::string::ToString;
+    use alloc::vec;
+
+    fn element(attributes: &[(&str, &str)]) -> Element {
+        let mut e = Element::new("p", Vec::new());
+        for (name, value) in attributes {
+            e.set_attribute(name, value);
+        }
+        e
+    }
+
+    #[test]
+    fn test_attribute() {
+        let mut e = element(&[("id", "a")]);
+        assert!(e.has_attribute("id"));
+        assert_eq!(Some("a".to_string()), e.get_attribute("id"));
+
+        e.set_attribute("ID", "b");
+        assert_eq!(1, e.attributes().len());
+        assert_eq!(Some("b".to_string()), e.get_attribute("id"));
+
+        e.remove_attribute("id");
+        assert!(!e.has_attribute("id"));
+    }
+
+    #[test]
+    fn test_class_list() {
+        let mut e = element(&[("class", "  a b\ta ")]);
+        assert_eq!(vec!["a".to_string(), "b".to_string()], e.class_list());
+        assert!(e.contains_class("b"));
+        assert!(!e.contains_class("a b"));
+
+        e.add_class("c");
+        e.remove_class("a");
+        assert_eq!(Some("b c".to_string()), e.get_attribute("class"));
+
+        assert!(!e.toggle_class("b"));
+        assert!(e.toggle_class("d"));
+        assert_eq!(vec!["c".to_string(), "d".to_string()], e.class_list());
+    }
+
+    #[test]
+    fn test_dataset() {
+        let mut e = element(&[("data-foo-bar", "1"), ("title", "x")]);
+        assert_eq!(vec![("fooBar".to_string(), "1".to_string())], e.dataset());
+        assert_eq!(Some("1".to_string()), e.get_data("fooBar"));
+
+        e.set_data("baz", "2");
+        assert_eq!(Some("2".to_string()), e.get_attribute("data-baz"));
+    }
+}
@@ -0,0 +1,623 @@
+//! 再帰を使わない DOM ツリーの走査
+//! 兄弟ノードや子孫が多くてもスタックを消費しないように、親と兄弟へのリンクをたどって走査する
+//! https://dom.spec.whatwg.org/#traversal
+
+use crate::renderer::dom::node::Dom;
+use crate::renderer::dom::node::NodeId;
+use crate::renderer::dom::node::NodeKind;
+
+/// `node` の次のノードを、`root` の部分木の中で文書順に返す
+/// https://dom.spec.whatwg.org/#concept-tree-following
+pub fn following(dom: &Dom, node: NodeId, root: NodeId) -> Option<NodeId> {
+    if let Some(child) = dom.get(node).first_child() {
+        return Some(child);
+    }
+    following_skipping_children(dom, node, root)
+}
+
+/// `node` の子孫を飛ばして、次のノードを `root` の部分木の中で文書順に返す
+pub fn following_skipping_children(dom: &Dom, node: NodeId, root: NodeId) -> Option<NodeId> {
+    let mut current = node;
+    loop {
+        if current == root {
+            return None;
+        }
+        if let Some(sibling) = dom.get(current).next_sibling() {
+            return Some(sibling);
+        }
+        current = dom.get(current).parent()?;
+    }
+}
+
+/// `node` の前のノードを、`root` の部分木の中で文書順に返す
+/// https://dom.spec.whatwg.org/#concept-tree-preceding
+pub fn preceding(dom: &Dom, node: NodeId, root: NodeId) -> Option<NodeId> {
+    if node == root {
+        return None;
+    }
+    match dom.get(node).previous_sibling() {
+        // 前の兄弟ノードの、最も後ろにある子孫
+        Some(sibling) => Some(last_descendant(dom, sibling)),
+        None => dom.get(node).parent(),
+    }
+}
+
+/// `node` の子孫のうち、文書順で最後のノードを返す。子を持たない場合は `node` 自身を返す
+fn last_descendant(dom: &Dom, node: NodeId) -> NodeId {
+    let mut current = node;
+    while let Some(last) = dom.get(current).last_child() {
+        current = last;
+    }
+    current
+}
+
+/// `node` の子孫のうち、最初の子をたどって最も深いノードを返す。子を持たない場合は `node` 自身を返す
+fn first_leaf(dom: &Dom, node: NodeId) -> NodeId {
+    let mut current = node;
+    while let Some(first) = dom.get(current).first_child() {
+        current = first;
+    }
+    current
+}
+
+/// `root` とその子孫を行きがけ順 (文書順) に返すイテレータ
+#[derive(Debug, Clone)]
+pub struct Preorder<'a> {
+    dom: &'a Dom,
+    root: NodeId,
+    next: Option<NodeId>,
+}
+
+impl<'a> Preorder<'a> {
+    pub fn new(dom: &'a Dom, root: NodeId) -> Self {
+        Self {
+            dom,
+            root,
+            next: Some(root),
+        }
+    }
+
+    /// 直前に返したノードの子孫を飛ばして、その次のノードから走査を続ける
+    pub fn skip_children(&mut self, node: NodeId) {
+        self.next = following_skipping_children(self.dom, node, self.root);
+    }
+}
+
+impl Iterator for Preorder<'_> {
+    type Item = NodeId;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let current = self.next.take()?;
+        self.next = following(self.dom, current, self.root);
+        Some(current)
+    }
+}
+
+/// `root` とその子孫を帰りがけ順に返すイテレータ。子孫はすべて親より先に返される
+#[derive(Debug, Clone)]
+pub struct Postorder<'a> {
+    dom: &'a Dom,
+    root: NodeId,
+    next: Option<NodeId>,
+}
+
+impl<'a> Postorder<'a> {
+    pub fn new(dom: &'a Dom, root: NodeId) -> Self {
+        Self {
+            dom,
+            root,
+            next: Some(first_leaf(dom, root)),
+        }
+    }
+}
+
+impl Iterator for Postorder<'_> {
+    type Item = NodeId;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let current = self.next.take()?;
+        if current != self.root {
+            self.next = match self.dom.get(current).next_sibling() {
+                Some(s) => Some(first_leaf(self.dom, s)),
+                None => self.dom.get(current).parent(),
+            };
+        }
+        Some(current)
+    }
+}
+
+/// `root` とその子孫を文書順に返す
+pub fn preorder(dom: &Dom, root: NodeId) -> Preorder<'_> {
+    Preorder::new(dom, root)
+}
+
+/// `root` の子孫を文書順に返す。`root` 自身は含まない
+pub fn descendants(dom: &Dom, root: NodeId) -> impl Iterator<Item = NodeId> + '_ {
+    Preorder::new(dom, root).skip(1)
+}
+
+/// `root` とその子孫を、子孫が親より先になる順に返す
+pub fn postorder(dom: &Dom, root: NodeId) -> Postorder<'_> {
+    Postorder::new(dom, root)
+}
+
+/// https://dom.spec.whatwg.org/#interface-nodefilter
+pub const SHOW_ALL: u32 = 0xFFFF_FFFF;
+pub const SHOW_ELEMENT: u32 = 0x1;
+pub const SHOW_TEXT: u32 = 0x4;
+pub const SHOW_DOCUMENT: u32 = 0x100;
+
+/// NodeFilter の結果
+/// https://dom.spec.whatwg.org/#interface-nodefilter
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum FilterResult {
+    /// ノードを返す
+    Accept,
+    /// ノードとその子孫を返さない
+    Reject,
+    /// ノードを返さないが、その子孫は調べる
+    Skip,
+}
+
+/// ノードを走査結果に含めるかどうかを決める関数
+pub type NodeFilter = fn(&Dom, NodeId) -> FilterResult;
+
+/// whatToShow とフィルタ関数によってノードを選別する
+/// https://dom.spec.whatwg.org/#concept-node-filter
+fn filter_node(
+    dom: &Dom,
+    node: NodeId,
+    what_to_show: u32,
+    filter: Option<NodeFilter>,
+) -> FilterResult {
+    let mask = match dom.get(node).kind {
+        NodeKind::Element(_) => SHOW_ELEMENT,
+        NodeKind::Text(_) => SHOW_TEXT,
+        NodeKind::Document => SHOW_DOCUMENT,
+    };
+    if what_to_show & mask == 0 {
+        return FilterResult::Skip;
+    }
+
+    match filter {
+        Some(f) => f(dom, node),
+        None => FilterResult::Accept,
+    }
+}
+
+/// 現在のノードを起点に、`root` の部分木の中を自由に移動するための API
+/// https://dom.spec.whatwg.org/#interface-treewalker
+#[derive(Debug, Clone)]
+pub struct TreeWalker {
+    root: NodeId,
+    what_to_show: u32,
+    filter: Option<NodeFilter>,
+    current: NodeId,
+}
+
+impl TreeWalker {
+    pub fn new(root: NodeId, what_to_show: u32, filter: Option<NodeFilter>) -> Self {
+        Self {
+            root,
+            what_to_show,
+            filter,
+            current: root,
+        }
+    }
+
+    pub fn root(&self) -> NodeId {
+        self.root
+    }
+
+    pub fn current_node(&self) -> NodeId {
+        self.current
+    }
+
+    pub fn set_current_node(&mut self, node: NodeId) {
+        self.current = node;
+    }
+
+    fn filter(&self, dom: &Dom, node: NodeId) -> FilterResult {
+        filter_node(dom, node, self.what_to_show, self.filter)
+    }
+
+    /// https://dom.spec.whatwg.org/#dom-treewalker-parentnode
+    pub fn parent_node(&mut self, dom: &Dom) -> Option<NodeId> {
+        let mut node = self.current;
+        while node != self.root {
+            node = dom.get(node).parent()?;
+            if self.filter(dom, node) == FilterResult::Accept {
+                self.current = node;
+                return Some(node);
+            }
+        }
+        None
+    }
+
+    /// https://dom.spec.whatwg.org/#dom-treewalker-firstchild
+    pub fn first_child(&mut self, dom: &Dom) -> Option<NodeId> {
+        self.traverse_children(dom, true)
+    }
+
+    /// https://dom.spec.whatwg.org/#dom-treewalker-lastchild
+    pub fn last_child(&mut self, dom: &Dom) -> Option<NodeId> {
+        self.traverse_children(dom, false)
+    }
+
+    /// https://dom.spec.whatwg.org/#dom-treewalker-nextsibling
+    pub fn next_sibling(&mut self, dom: &Dom) -> Option<NodeId> {
+        self.traverse_siblings(dom, true)
+    }
+
+    /// https://dom.spec.whatwg.org/#dom-treewalker-previoussibling
+    pub fn previous_sibling(&mut self, dom: &Dom) -> Option<NodeId> {
+        self.traverse_siblings(dom, false)
+    }
+
+    /// https://dom.spec.whatwg.org/#concept-traverse-children
+    fn traverse_children(&mut self, dom: &Dom, first: bool) -> Option<NodeId> {
+        let mut node = child_of(dom, self.current, first);
+        while let Some(n) = node {
+            match self.filter(dom, n) {
+                FilterResult::Accept => {
+                    self.current = n;
+                    return Some(n);
+                }
+                FilterResult::Skip => {
+                    if let Some(child) = child_of(dom, n, first) {
+                        node = Some(child);
+                        continue;
+                    }
+                }
+                FilterResult::Reject => {}
+            }
+
+            // 兄弟ノードがなければ、兄弟ノードを持つ祖先までさかのぼる
+            let mut current = n;
+            node = loop {
+                if let Some(sibling) = sibling_of(dom, current, first) {
+                    break Some(sibling);
+                }
+                let parent = dom.get(current).parent()?;
+                if parent == self.root || parent == self.current {
+                    return None;
+                }
+                current = parent;
+            };
+        }
+        None
+    }
+
+    /// https://dom.spec.whatwg.org/#concept-traverse-siblings
+    fn traverse_siblings(&mut self, dom: &Dom, next: bool) -> Option<NodeId> {
+        let mut node = self.current;
+        if node == self.root {
+            return None;
+        }
+
+        loop {
+            let mut sibling = sibling_of(dom, node, next);
+            while let Some(s) = sibling {
+                node = s;
+                let result = self.filter(dom, node);
+                if result == FilterResult::Accept {
+                    self.current = node;
+                    return Some(node);
+                }
+                sibling = child_of(dom, node, next);
+                if result == FilterResult::Reject || sibling.is_none() {
+                    sibling = sibling_of(dom, node, next);
+                }
+            }
+
+            node = dom.get(node).parent()?;
+            if node == self.root || self.filter(dom, node) == FilterResult::Accept {
+                return None;
+            }
+        }
+    }
+
+    /// https://dom.spec.whatwg.org/#dom-treewalker-previousnode
+    pub fn previous_node(&mut self, dom: &Dom) -> Option<NodeId> {
+        let mut node = self.current;
+        while node != self.root {
+            let mut sibling = dom.get(node).previous_sibling();
+            while let Some(s) = sibling {
+                node = s;
+                let mut result = self.filter(dom, node);
+                while result != FilterResult::Reject {
+                    match dom.get(node).last_child() {
+                        Some(l) => {
+                            node = l;
+                            result = self.filter(dom, node);
+                        }
+                        None => break,
+                    }
+                }
+                if result == FilterResult::Accept {
+                    self.current = node;
+                    return Some(node);
+                }
+                sibling = dom.get(node).previous_sibling();
+            }
+
+            node = dom.get(node).parent()?;
+            if self.filter(dom, node) == FilterResult::Accept {
+                self.current = node;
+                return Some(node);
+            }
+        }
+        None
+    }
+
+    /// https://dom.spec.whatwg.org/#dom-treewalker-nextnode
+    pub fn next_node(&mut self, dom: &Dom) -> Option<NodeId> {
+        let mut node = self.current;
+        let mut result = FilterResult::Accept;
+        loop {
+            while result != FilterResult::Reject {
+                match dom.get(node).first_child() {
+                    Some(f) => {
+                        node = f;
+                        result = self.filter(dom, node);
+                        if result == FilterResult::Accept {
+                            self.current = node;
+                            return Some(node);
+                        }
+                    }
+                    None => break,
+                }
+            }
+
+            node = following_skipping_children(dom, node, self.root)?;
+            result = self.filter(dom, node);
+            if result == FilterResult::Accept {
+                self.current = node;
+                return Some(node);
+            }
+        }
+    }
+}
+
+/// JavaScript から参照される TreeWalker は、同じオブジェクトかどうかで比べる
+impl PartialEq for TreeWalker {
+    fn eq(&self, other: &Self) -> bool {
+        core::ptr::eq(self, other)
+    }
+}
+
+fn child_of(dom: &Dom, node: NodeId, first: bool) -> Option<NodeId> {
+    if first {
+        dom.get(node).first_child()
+    } else {
+        dom.get(node).last_child()
+    }
+}
+
+fn sibling_of(dom: &Dom, node: NodeId, next: bool) -> Option<NodeId> {
+    if next {
+        dom.get(node).next_sibling()
+    } else {
+        dom.get(node).previous_sibling()
+    }
+}
+
+/// `root` の部分木のノードを文書順に前後へ1つずつたどるための API
+/// 本書のブラウザでは、走査中にノードが削除された場合の参照ノードの更新は行わない
+/// https://dom.spec.whatwg.org/#interface-nodeiterator
+#[derive(Debug, Clone)]
+pub struct NodeIterator {
+    root: NodeId,
+    what_to_show: u32,
+    filter: Option<NodeFilter>,
+    reference: NodeId,
+    pointer_before_reference: bool,
+}
+
+impl NodeIterator {
+    pub fn new(root: NodeId, what_to_show: u32, filter: Option<NodeFilter>) -> Self {
+        Self {
+            root,
+            what_to_show,
+            filter,
+            reference: root,
+            pointer_before_reference: true,
+        }
+    }
+
+    pub fn root(&self) -> NodeId {
+        self.root
+    }
+
+    pub fn reference_node(&self) -> NodeId {
+        self.reference
+    }
+
+    /// https://dom.spec.whatwg.org/#dom-nodeiterator-nextnode
+    pub fn next_node(&mut self, dom: &Dom) -> Option<NodeId> {
+        self.traverse(dom, true)
+    }
+
+    /// https://dom.spec.whatwg.org/#dom-nodeiterator-previousnode
+    pub fn previous_node(&mut self, dom: &Dom) -> Option<NodeId> {
+        self.traverse(dom, false)
+    }
+
+    /// https://dom.spec.whatwg.org/#concept-nodeiterator-traverse
+    fn traverse(&mut self, dom: &Dom, next: bool) -> Option<NodeId> {
+        let mut node = self.reference;
+        let mut before = self.pointer_before_reference;
+
+        loop {
+            if next {
+                if before {
+                    before = false;
+                } else {
+                    node = following(dom, node, self.root)?;
+                }
+            } else if before {
+                node = preceding(dom, node, self.root)?;
+            } else {
+                before = true;
+            }
+
+            // NodeIterator では、Reject は Skip と同じく子孫を調べる
+            if filter_node(dom, node, self.what_to_show, self.filter) == FilterResult::Accept {
+                break;
+            }
+        }
+
+        self.reference = node;
+        self.pointer_before_reference = before;
+        Some(node)
+    }
+}
+
+/// JavaScript から参照される NodeIterator は、同じオブジェクトかどうかで比べる
+impl PartialEq for NodeIterator {
+    fn eq(&self, other: &Self) -> bool {
+        core::ptr::eq(self, other)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::renderer::dom::api::append_child;
+    use crate::renderer::dom::api::get_target_element_node;
+    use crate::renderer::dom::node::Element;
+    use crate::renderer::dom::node::ElementKind;
+    use crate::renderer::html::parser::HtmlParser;
+    use crate::renderer::html::token::HtmlTokenizer;
+    use alloc::string::String;
+    use alloc::string::ToString;
+    use alloc::vec::Vec;
+
+    fn parse_body(dom: &mut Dom, html: &str) -> NodeId {
+        let t = HtmlTokenizer::new(html.to_string());
+        let document = HtmlParser::new(dom, t).construct_tree(dom);
+        get_target_element_node(dom, Some(document), ElementKind::Body).expect("body should exist")
+    }
+
+    /// ノードを、要素ならタグ名、テキストならその文字列で表す
+    fn name(dom: &Dom, node: NodeId) -> String {
+        match dom.get(node).kind() {
+            NodeKind::Element(e) => e.kind().to_string(),
+            NodeKind::Text(s) => s,
+            NodeKind::Document => "#document".to_string(),
+        }
+    }
+
+    fn names(dom: &Dom, nodes: impl Iterator<Item = NodeId>) -> Vec<String> {
+        nodes.map(|n| name(dom, n)).collect()
+    }
+
+    const HTML: &str = "<html><head></head><body><p>a<a>b</a></p><h1>c</h1>d</body></html>";
+
+    #[test]
+    fn test_preorder_and_postorder() {
+        let mut dom = Dom::new();
+        let body = parse_body(&mut dom, HTML);
+        let dom = &dom;
+        assert_eq!(
+            ["body", "p", "a", "a", "b", "h1", "c", "d"].to_vec(),
+            names(dom, preorder(dom, body))
+        );
+        assert_eq!(
+            ["p", "a", "a", "b", "h1", "c", "d"].to_vec(),
+            names(dom, descendants(dom, body))
+        );
+        assert_eq!(
+            ["a", "b", "a", "p", "c", "h1", "d", "body"].to_vec(),
+            names(dom, postorder(dom, body))
+        );
+
+        // 部分木の外には出ない
+        let p = get_target_element_node(dom, Some(body), ElementKind::P).unwrap();
+        assert_eq!(["p", "a", "a", "b"].to_vec(), names(dom, preorder(dom, p)));
+        assert_eq!(["a", "b", "a", "p"].to_vec(), names(dom, postorder(dom, p)));
+
+        let mut iter = preorder(dom, body);
+        assert_eq!("body", name(dom, iter.next().unwrap()));
+        let p = iter.next().unwrap();
+        iter.skip_children(p);
+        assert_eq!(["h1", "c", "d"].to_vec(), names(dom, iter));
+    }
+
+    #[test]
+    fn test_many_siblings() {
+        // 兄弟ノードが非常に多くても、スタックを使い果たさずに走査できる
+        let mut dom = Dom::new();
+        let body = parse_body(&mut dom, "<html><head></head><body></body></html>");
+        for _ in 0..100_000 {
+            let p = dom.create_node(NodeKind::Element(Element::new("p", Vec::new())));
+            append_child(&mut dom, body, p).unwrap();
+        }
+        assert_eq!(100_001, preorder(&dom, body).count());
+        assert_eq!(100_001, postorder(&dom, body).count());
+    }
+
+    fn skip_p(dom: &Dom, node: NodeId) -> FilterResult {
+        match dom.get(node).element_kind() {
+            Some(ElementKind::P) => FilterResult::Skip,
+            Some(ElementKind::H1) => FilterResult::Reject,
+            _ => FilterResult::Accept,
+        }
+    }
+
+    #[test]
+    fn test_tree_walker() {
+        let mut dom = Dom::new();
+        let body = parse_body(&mut dom, HTML);
+        let dom = &dom;
+        let mut walker = TreeWalker::new(body, SHOW_ALL, Some(skip_p));
+        let mut visited = Vec::new();
+        while let Some(n) = walker.next_node(dom) {
+            visited.push(name(dom, n));
+        }
+        // <p> は飛ばしてその子孫を調べ、<h1> はその子孫も含めて飛ばす
+        assert_eq!(["a", "a", "b", "d"].to_vec(), visited);
+
+        let mut back = Vec::new();
+        while let Some(n) = walker.previous_node(dom) {
+            back.push(name(dom, n));
+        }
+        assert_eq!(["b", "a", "a", "body"].to_vec(), back);
+
+        let mut walker = TreeWalker::new(body, SHOW_ELEMENT, None);
+        assert_eq!("p", name(dom, walker.first_child(dom).unwrap()));
+        assert_eq!("h1", name(dom, walker.next_sibling(dom).unwrap()));
+        assert!(walker.next_sibling(dom).is_none());
+        assert_eq!("p", name(dom, walker.previous_sibling(dom).unwrap()));
+        assert_eq!("a", name(dom, walker.last_child(dom).unwrap()));
+        assert_eq!("p", name(dom, walker.parent_node(dom).unwrap()));
+        assert_eq!("body", name(dom, walker.parent_node(dom).unwrap()));
+        // ルートより上には移動しない
+        assert!(walker.parent_node(dom).is_none());
+    }
+
+    #[test]
+    fn test_node_iterator() {
+        let mut dom = Dom::new();
+        let body = parse_body(&mut dom, HTML);
+        let dom = &dom;
+        let mut iter = NodeIterator::new(body, SHOW_TEXT, None);
+        let mut visited = Vec::new();
+        while let Some(n) = iter.next_node(dom) {
+            visited.push(name(dom, n));
+        }
+        assert_eq!(["a", "b", "c", "d"].to_vec(), visited);
+
+        assert_eq!("d", name(dom, iter.previous_node(dom).unwrap()));
+        assert_eq!("c", name(dom, iter.previous_node(dom).unwrap()));
+        assert_eq!("c", name(dom, iter.next_node(dom).unwrap()));
+
+        // NodeIterator では Reject された要素の子孫も調べる
+        let mut iter = NodeIterator::new(body, SHOW_ALL, Some(skip_p));
+        let mut visited = Vec::new();
+        while let Some(n) = iter.next_node(dom) {
+            visited.push(name(dom, n));
+        }
+        assert_eq!(["body", "a", "a", "b", "c", "d"].to_vec(), visited);
+    }
+}
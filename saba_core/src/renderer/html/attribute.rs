@@ -22,6 +22,14 @@ impl Attribute {
         }
     }
 
+    pub fn set_name(&mut self, name: &str) {
+        self.name = String::from(name);
+    }
+
+    pub fn set_value(&mut self, value: &str) {
+        self.value = String::from(value);
+    }
+
     pub fn name(&self) -> String {
         self.name.clone()
     }
@@ -1,3 +1,4 @@
 pub mod attribute;
 pub mod parser;
+pub mod serializer;
 pub mod token;
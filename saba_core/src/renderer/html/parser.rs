@@ -1,41 +1,128 @@
+use crate::error::Error;
+use crate::renderer::dom::api::append_node;
+use crate::renderer::dom::api::detach_node;
+use crate::renderer::dom::api::insert_node;
+use crate::renderer::dom::api::replace_data;
+use crate::renderer::dom::node::Dom;
 use crate::renderer::dom::node::Element;
 use crate::renderer::dom::node::ElementKind;
-use crate::renderer::dom::node::Node;
+use crate::renderer::dom::node::NodeId;
 use crate::renderer::dom::node::NodeKind;
-use crate::renderer::dom::node::Window;
 use crate::renderer::html::attribute::Attribute;
 use crate::renderer::html::token::HtmlToken;
 use crate::renderer::html::token::HtmlTokenizer;
-use alloc::rc::Rc;
+use crate::renderer::html::token::State;
+use alloc::format;
 use alloc::string::String;
 use alloc::vec::Vec;
-use core::cell::RefCell;
 use core::str::FromStr;
 
 #[derive(Debug, Clone)]
 pub struct HtmlParser {
-    window: Rc<RefCell<Window>>,
+    // 構築している文書ノード
+    document: NodeId,
     mode: InsertionMode,
     original_insertion_mode: InsertionMode,
-    stack_of_open_elements: Vec<Rc<RefCell<Node>>>,
+    stack_of_open_elements: Vec<NodeId>,
+    // <pre> や <textarea> の開始タグの直後にある改行を無視するかどうか
+    ignore_line_feed: bool,
     t: HtmlTokenizer,
 }
 
 impl HtmlParser {
-    pub fn new(t: HtmlTokenizer) -> Self {
+    /// `dom` の中に新しいウィンドウと文書を作成し、その文書を構築するパーサーを作成する
+    pub fn new(dom: &mut Dom, t: HtmlTokenizer) -> Self {
+        let window = dom.create_window();
+        Self::with_document(dom.window(window).document(), t)
+    }
+
+    fn with_document(document: NodeId, t: HtmlTokenizer) -> Self {
         Self {
-            window: Rc::new(RefCell::new(Window::new())),
+            document,
             mode: InsertionMode::Initial,
             original_insertion_mode: InsertionMode::Initial,
             stack_of_open_elements: Vec::new(),
+            ignore_line_feed: false,
             t,
         }
     }
 
-    pub fn construct_tree(&mut self) -> Rc<RefCell<Window>> {
+    /// HTML 断片を解釈するためのパーサーを作成する
+    /// `context` は断片が挿入される要素で、最初の挿入モードを決めるために使われる
+    /// https://html.spec.whatwg.org/multipage/parsing.html#parsing-html-fragments
+    pub fn new_fragment(dom: &mut Dom, t: HtmlTokenizer, context: NodeId) -> Self {
+        // 断片は、ウィンドウに表示されない一時的な文書の中に構築する
+        let document = dom.create_document();
+        let mut parser = Self::with_document(document, t);
+
+        // 断片の子ノードを保持するための <html> 要素をルートとして作成する
+        parser.insert_element(dom, "html", Vec::new());
+
+        let context_kind = dom.get(context).element_kind();
+        parser.mode = match context_kind {
+            Some(ElementKind::Html) => InsertionMode::BeforeHead,
+            _ => InsertionMode::InBody,
+        };
+        parser.original_insertion_mode = parser.mode;
+
+        // <textarea> などの中に挿入する場合、断片の中身は文字として扱われる
+        if let Some(state) = context_kind.and_then(text_state_for) {
+            parser.t.switch_to(state);
+        }
+
+        parser
+    }
+
+    /// HTML 断片を解釈し、ルートの <html> 要素の子ノードを親から切り離して返す
+    pub fn construct_fragment(&mut self, dom: &mut Dom) -> Vec<NodeId> {
+        let document = self.construct_tree(dom);
+        let root = match dom.get(document).first_child() {
+            Some(root) => root,
+            None => return Vec::new(),
+        };
+
+        let mut nodes = Vec::new();
+        let mut child = dom.get(root).first_child();
+        while let Some(c) = child {
+            child = dom.get(c).next_sibling();
+            nodes.push(c);
+        }
+
+        for node in &nodes {
+            detach_node(dom, *node);
+        }
+
+        nodes
+    }
+
+    /// 入力全体を解釈して DOM ツリーを構築し、文書ノードを返す
+    pub fn construct_tree(&mut self, dom: &mut Dom) -> NodeId {
+        loop {
+            match self.parse(dom) {
+                // 文書全体を一度に解釈するので、<script> 要素で中断しても解釈を続ける
+                ParserStatus::PausedOnScript(_) => continue,
+                ParserStatus::NeedMoreInput | ParserStatus::Finished => return self.document,
+            }
+        }
+    }
+
+    /// 現在受け取っている入力を解釈して DOM ツリーを構築する
+    /// 入力が足りなくなるか、<script> 要素の終了タグに到達したところで中断する
+    /// 中断したあとに再び呼び出すと、続きから解釈する
+    pub fn parse(&mut self, dom: &mut Dom) -> ParserStatus {
         let mut token = self.t.next();
 
         while token.is_some() {
+            // <pre> の中身の最初の改行は、ソースを読みやすくするためのものなので DOM ツリーには含めない
+            // https://html.spec.whatwg.org/multipage/parsing.html#parsing-main-inbody
+            if self.ignore_line_feed {
+                self.ignore_line_feed = false;
+                if let Some(HtmlToken::Char('\n')) = token {
+                    token = self.t.next();
+                    continue;
+                }
+            }
+
             match self.mode {
                 InsertionMode::Initial => {
                     // 文字トークンは無視する
@@ -61,18 +148,18 @@ impl HtmlParser {
                             ref attributes,
                         }) => {
                             if tag == "html" {
-                                self.insert_element(tag, attributes.to_vec());
+                                self.insert_element(dom, tag, attributes.to_vec());
                                 self.mode = InsertionMode::BeforeHead;
                                 token = self.t.next();
                                 continue;
                             }
                         }
                         Some(HtmlToken::Eof) | None => {
-                            return self.window.clone();
+                            return self.input_exhausted();
                         }
                         _ => {}
                     }
-                    self.insert_element("html", Vec::new());
+                    self.insert_element(dom, "html", Vec::new());
                     self.mode = InsertionMode::BeforeHead;
                     continue;
                 }
@@ -90,18 +177,18 @@ impl HtmlParser {
                             ref attributes,
                         }) => {
                             if tag == "head" {
-                                self.insert_element(tag, attributes.to_vec());
+                                self.insert_element(dom, tag, attributes.to_vec());
                                 self.mode = InsertionMode::InHead;
                                 token = self.t.next();
                                 continue;
                             }
                         }
                         Some(HtmlToken::Eof) | None => {
-                            return self.window.clone();
+                            return self.input_exhausted();
                         }
                         _ => {}
                     }
-                    self.insert_element("head", Vec::new());
+                    self.insert_element(dom, "head", Vec::new());
                     self.mode = InsertionMode::InHead;
                     continue;
                 }
@@ -109,7 +196,7 @@ impl HtmlParser {
                     match token {
                         Some(HtmlToken::Char(c)) => {
                             if c == ' ' || c == '\n' {
-                                self.insert_char(c);
+                                self.insert_char(dom, c);
                                 token = self.t.next();
                                 continue;
                             }
@@ -119,20 +206,23 @@ impl HtmlParser {
                             self_closing: _,
                             ref attributes,
                         }) => {
-                            if tag == "style" || tag == "script" {
-                                self.insert_element(tag, attributes.to_vec());
-                                self.original_insertion_mode = self.mode;
-                                self.mode = InsertionMode::Text;
+                            if tag == "title" || tag == "style" || tag == "script" {
+                                self.insert_text_element(dom, tag, attributes.to_vec());
+                                token = self.t.next();
+                                continue;
+                            }
+                            if tag == "meta" || tag == "link" {
+                                self.insert_void_element(dom, tag, attributes.to_vec());
                                 token = self.t.next();
                                 continue;
                             }
                             if tag == "body" {
-                                self.pop_until(ElementKind::Head);
+                                self.pop_until(dom, ElementKind::Head);
                                 self.mode = InsertionMode::AfterHead;
                                 continue;
                             }
                             if let Ok(_element_kind) = ElementKind::from_str(tag) {
-                                self.pop_until(ElementKind::Head);
+                                self.pop_until(dom, ElementKind::Head);
                                 self.mode = InsertionMode::AfterHead;
                                 continue;
                             }
@@ -141,12 +231,12 @@ impl HtmlParser {
                             if tag == "head" {
                                 self.mode = InsertionMode::AfterHead;
                                 token = self.t.next();
-                                self.pop_until(ElementKind::Head);
+                                self.pop_until(dom, ElementKind::Head);
                                 continue;
                             }
                         }
                         Some(HtmlToken::Eof) | None => {
-                            return self.window.clone();
+                            return self.input_exhausted();
                         }
                     }
                     // <meta> や <title> などのサポートしていないタグは無視する
@@ -157,7 +247,7 @@ impl HtmlParser {
                     match token {
                         Some(HtmlToken::Char(c)) => {
                             if c == ' ' || c == '\n' {
-                                self.insert_char(c);
+                                self.insert_char(dom, c);
                                 token = self.t.next();
                                 continue;
                             }
@@ -168,18 +258,18 @@ impl HtmlParser {
                             ref attributes,
                         }) => {
                             if tag == "body" {
-                                self.insert_element(tag, attributes.to_vec());
+                                self.insert_element(dom, tag, attributes.to_vec());
                                 token = self.t.next();
                                 self.mode = InsertionMode::InBody;
                                 continue;
                             }
                         }
                         Some(HtmlToken::Eof) | None => {
-                            return self.window.clone();
+                            return self.input_exhausted();
                         }
                         _ => {}
                     }
-                    self.insert_element("body", Vec::new());
+                    self.insert_element(dom, "body", Vec::new());
                     self.mode = InsertionMode::InBody;
                     continue;
                 }
@@ -191,17 +281,47 @@ impl HtmlParser {
                             ref attributes,
                         }) => match tag.as_str() {
                             "p" => {
-                                self.insert_element(tag, attributes.to_vec());
+                                self.insert_element(dom, tag, attributes.to_vec());
                                 token = self.t.next();
                                 continue;
                             }
                             "h1" | "h2" => {
-                                self.insert_element(tag, attributes.to_vec());
+                                self.insert_element(dom, tag, attributes.to_vec());
                                 token = self.t.next();
                                 continue;
                             }
                             "a" => {
-                                self.insert_element(tag, attributes.to_vec());
+                                self.insert_element(dom, tag, attributes.to_vec());
+                                token = self.t.next();
+                                continue;
+                            }
+                            "pre" => {
+                                self.insert_element(dom, tag, attributes.to_vec());
+                                self.ignore_line_feed = true;
+                                token = self.t.next();
+                                continue;
+                            }
+                            "title" | "style" | "xmp" | "script" => {
+                                // <body> の中に現れた場合も <head> の中と同じように扱う
+                                self.insert_text_element(dom, tag, attributes.to_vec());
+                                token = self.t.next();
+                                continue;
+                            }
+                            "textarea" => {
+                                self.insert_text_element(dom, tag, attributes.to_vec());
+                                self.ignore_line_feed = true;
+                                token = self.t.next();
+                                continue;
+                            }
+                            "plaintext" => {
+                                // <plaintext> には終了タグがなく、以降の入力はすべて文字になる
+                                self.insert_element(dom, tag, attributes.to_vec());
+                                self.t.switch_to(State::PlainText);
+                                token = self.t.next();
+                                continue;
+                            }
+                            "br" | "hr" | "img" | "input" | "meta" | "link" => {
+                                self.insert_void_element(dom, tag, attributes.to_vec());
                                 token = self.t.next();
                                 continue;
                             }
@@ -214,41 +334,58 @@ impl HtmlParser {
                                 "body" => {
                                     self.mode = InsertionMode::AfterBody;
                                     token = self.t.next();
-                                    if !self.contain_in_stack(ElementKind::Body) {
+                                    if !self.contain_in_stack(dom, ElementKind::Body) {
                                         // パースの失敗。トークンを無視する
                                         continue;
                                     }
-                                    self.pop_until(ElementKind::Body);
+                                    self.pop_until(dom, ElementKind::Body);
                                     continue;
                                 }
                                 "html" => {
-                                    if self.pop_current_node(ElementKind::Body) {
+                                    if !self.contain_in_stack(dom, ElementKind::Html) {
+                                        // パースの失敗。トークンを無視する
+                                        token = self.t.next();
+                                        continue;
+                                    }
+                                    if self.pop_current_node(dom, ElementKind::Body) {
                                         self.mode = InsertionMode::AfterBody;
-                                        assert!(self.pop_current_node(ElementKind::Html));
+                                        self.pop_until(dom, ElementKind::Html);
                                     } else {
                                         token = self.t.next();
                                     }
                                     continue;
                                 }
-                                "p" => {
+                                "p" | "pre" => {
                                     let element_kind = ElementKind::from_str(tag)
                                         .expect("failed to convert string to ElementKind");
                                     token = self.t.next();
-                                    self.pop_until(element_kind);
+                                    if !self.contain_in_stack(dom, element_kind) {
+                                        // パースの失敗。トークンを無視する
+                                        continue;
+                                    }
+                                    self.pop_until(dom, element_kind);
                                     continue;
                                 }
                                 "h1" | "h2" => {
                                     let element_kind = ElementKind::from_str(tag)
                                         .expect("failed to convert string to ElementKind");
                                     token = self.t.next();
-                                    self.pop_until(element_kind);
+                                    if !self.contain_in_stack(dom, element_kind) {
+                                        // パースの失敗。トークンを無視する
+                                        continue;
+                                    }
+                                    self.pop_until(dom, element_kind);
                                     continue;
                                 }
                                 "a" => {
                                     let element_kind = ElementKind::from_str(tag)
                                         .expect("failed to convert string to ElementKind");
                                     token = self.t.next();
-                                    self.pop_until(element_kind);
+                                    if !self.contain_in_stack(dom, element_kind) {
+                                        // パースの失敗。トークンを無視する
+                                        continue;
+                                    }
+                                    self.pop_until(dom, element_kind);
                                     continue;
                                 }
                                 _ => {
@@ -257,36 +394,41 @@ impl HtmlParser {
                             }
                         }
                         Some(HtmlToken::Char(c)) => {
-                            self.insert_char(c);
+                            self.insert_char(dom, c);
                             token = self.t.next();
                             continue;
                         }
                         Some(HtmlToken::Eof) | None => {
-                            return self.window.clone();
+                            return self.input_exhausted();
                         }
                     }
                 }
                 InsertionMode::Text => {
                     match token {
                         Some(HtmlToken::Eof) | None => {
-                            return self.window.clone();
+                            return self.input_exhausted();
                         }
                         Some(HtmlToken::EndTag { ref tag }) => {
-                            if tag == "style" {
-                                self.pop_until(ElementKind::Style);
-                                self.mode = self.original_insertion_mode;
-                                token = self.t.next();
-                                continue;
-                            }
                             if tag == "script" {
-                                self.pop_until(ElementKind::Script);
+                                let script = self.stack_of_open_elements.last().copied();
+                                self.pop_until(dom, ElementKind::Script);
                                 self.mode = self.original_insertion_mode;
+                                // スクリプトを実行できるように、パースを中断する
+                                if let Some(script) = script {
+                                    return ParserStatus::PausedOnScript(script);
+                                }
                                 token = self.t.next();
                                 continue;
                             }
+
+                            // トークナイザは対応する終了タグしか返さないので、現在のノードを取り除く
+                            self.stack_of_open_elements.pop();
+                            self.mode = self.original_insertion_mode;
+                            token = self.t.next();
+                            continue;
                         }
                         Some(HtmlToken::Char(c)) => {
-                            self.insert_char(c);
+                            self.insert_char(dom, c);
                             token = self.t.next();
                             continue;
                         }
@@ -308,7 +450,7 @@ impl HtmlParser {
                             }
                         }
                         Some(HtmlToken::Eof) | None => {
-                            return self.window.clone();
+                            return self.input_exhausted();
                         }
                         _ => {}
                     }
@@ -321,7 +463,7 @@ impl HtmlParser {
                             continue;
                         }
                         Some(HtmlToken::Eof) | None => {
-                            return self.window.clone();
+                            return self.input_exhausted();
                         }
                         _ => {}
                     }
@@ -331,64 +473,72 @@ impl HtmlParser {
                 }
             }
         }
-        self.window.clone()
+        self.input_exhausted()
+    }
+
+    /// トークナイザから次のトークンを得られなかったときの状態を返す
+    fn input_exhausted(&self) -> ParserStatus {
+        if self.t.is_closed() {
+            ParserStatus::Finished
+        } else {
+            ParserStatus::NeedMoreInput
+        }
+    }
+
+    /// 入力を末尾に追加する
+    pub fn push_str(&mut self, chunk: &str) {
+        self.t.push_str(chunk);
+    }
+
+    /// これ以上入力が追加されないことを知らせる
+    pub fn close(&mut self) {
+        self.t.close();
+    }
+
+    pub fn document(&self) -> NodeId {
+        self.document
     }
 
-    fn create_element(&self, tag: &str, attributes: Vec<Attribute>) -> Node {
-        Node::new(NodeKind::Element(Element::new(tag, attributes)))
+    fn create_element(&self, tag: &str, attributes: Vec<Attribute>) -> NodeKind {
+        NodeKind::Element(Element::new(tag, attributes))
     }
 
-    fn insert_element(&mut self, tag: &str, attributes: Vec<Attribute>) {
-        let window = self.window.borrow();
+    fn insert_element(&mut self, dom: &mut Dom, tag: &str, attributes: Vec<Attribute>) {
         let current = match self.stack_of_open_elements.last() {
-            Some(n) => n.clone(),
-            None => window.document(),
+            Some(n) => *n,
+            None => self.document,
         };
 
-        let node = Rc::new(RefCell::new(self.create_element(tag, attributes)));
-
-        if current.borrow().first_child().is_some() {
-            let mut last_sibling = current.borrow().first_child();
-            loop {
-                last_sibling = match last_sibling {
-                    Some(ref node) => {
-                        if node.borrow().next_sibling().is_some() {
-                            node.borrow().next_sibling()
-                        } else {
-                            break;
-                        }
-                    }
-                    None => unimplemented!("last_sibling should be Some"),
-                };
-            }
+        let node = dom.create_node(self.create_element(tag, attributes));
+        append_node(dom, current, node);
 
-            last_sibling
-                .unwrap()
-                .borrow_mut()
-                .set_next_sibling(Some(node.clone()));
-            node.borrow_mut().set_previous_sibling(Rc::downgrade(
-                &current
-                    .borrow()
-                    .first_child()
-                    .expect("failed to get a first child"),
-            ))
-        } else {
-            current.borrow_mut().set_first_child(Some(node.clone()));
-        }
+        self.stack_of_open_elements.push(node);
+    }
 
-        current.borrow_mut().set_last_child(Rc::downgrade(&node));
-        node.borrow_mut().set_parent(Rc::downgrade(&current));
+    /// 空要素を挿入する。空要素は子ノードを持たないので、スタックには積まない
+    fn insert_void_element(&mut self, dom: &mut Dom, tag: &str, attributes: Vec<Attribute>) {
+        self.insert_element(dom, tag, attributes);
+        self.stack_of_open_elements.pop();
+    }
 
-        self.stack_of_open_elements.push(node);
+    /// 中身を文字として扱う要素を挿入し、終了タグが現れるまでトークナイザの状態を切り替える
+    /// https://html.spec.whatwg.org/multipage/parsing.html#generic-rcdata-element-parsing-algorithm
+    fn insert_text_element(&mut self, dom: &mut Dom, tag: &str, attributes: Vec<Attribute>) {
+        self.insert_element(dom, tag, attributes);
+        if let Some(state) = ElementKind::from_str(tag).ok().and_then(text_state_for) {
+            self.t.switch_to(state);
+        }
+        self.original_insertion_mode = self.mode;
+        self.mode = InsertionMode::Text;
     }
 
-    fn pop_current_node(&mut self, element_kind: ElementKind) -> bool {
+    fn pop_current_node(&mut self, dom: &Dom, element_kind: ElementKind) -> bool {
         let current = match self.stack_of_open_elements.last() {
-            Some(n) => n,
+            Some(n) => *n,
             None => return false,
         };
 
-        if current.borrow().element_kind() == Some(element_kind) {
+        if dom.get(current).element_kind() == Some(element_kind) {
             self.stack_of_open_elements.pop();
             return true;
         }
@@ -396,9 +546,9 @@ impl HtmlParser {
         false
     }
 
-    fn pop_until(&mut self, element_kind: ElementKind) {
+    fn pop_until(&mut self, dom: &Dom, element_kind: ElementKind) {
         assert!(
-            self.contain_in_stack(element_kind),
+            self.contain_in_stack(dom, element_kind),
             "stack doesn't have an element {:?}",
             element_kind
         );
@@ -409,15 +559,15 @@ impl HtmlParser {
                 None => return,
             };
 
-            if current.borrow().element_kind() == Some(element_kind) {
+            if dom.get(current).element_kind() == Some(element_kind) {
                 return;
             }
         }
     }
 
-    fn contain_in_stack(&mut self, element_kind: ElementKind) -> bool {
+    fn contain_in_stack(&mut self, dom: &Dom, element_kind: ElementKind) -> bool {
         for i in 0..self.stack_of_open_elements.len() {
-            if self.stack_of_open_elements[i].borrow().element_kind() == Some(element_kind) {
+            if dom.get(self.stack_of_open_elements[i]).element_kind() == Some(element_kind) {
                 return true;
             }
         }
@@ -425,22 +575,24 @@ impl HtmlParser {
         false
     }
 
-    fn create_char(&self, c: char) -> Node {
+    fn create_char(&self, c: char) -> NodeKind {
         let mut s = String::new();
         s.push(c);
-        Node::new(NodeKind::Text(s))
+        NodeKind::Text(s)
     }
 
-    fn insert_char(&mut self, c: char) {
+    fn insert_char(&mut self, dom: &mut Dom, c: char) {
         let current = match self.stack_of_open_elements.last() {
-            Some(n) => n.clone(),
+            Some(n) => *n,
             None => return,
         };
 
-        // 現在参照しているノードがテキストノードの場合、そのノードに文字を追加する
-        if let NodeKind::Text(ref mut s) = current.borrow_mut().kind {
-            s.push(c);
-            return;
+        // 現在のノードの最後の子ノードがテキストノードの場合、そのノードに文字を追加する
+        if let Some(last) = dom.get(current).last_child() {
+            if matches!(dom.get(last).kind, NodeKind::Text(_)) {
+                replace_data(dom, last, |s| s.push(c));
+                return;
+            }
         }
 
         // 改行文字や空白文字のときはテキストノードを追加しない
@@ -448,30 +600,102 @@ impl HtmlParser {
             return;
         }
 
-        let node = Rc::new(RefCell::new(self.create_char(c)));
-
-        if current.borrow().first_child().is_some() {
-            current
-                .borrow()
-                .first_child()
-                .unwrap()
-                .borrow_mut()
-                .set_next_sibling(Some(node.clone()));
-            node.borrow_mut().set_previous_sibling(Rc::downgrade(
-                &current
-                    .borrow()
-                    .first_child()
-                    .expect("failed to get a first child"),
-            ))
-        } else {
-            current.borrow_mut().set_first_child(Some(node.clone()));
-        }
+        let node = dom.create_node(self.create_char(c));
+        append_node(dom, current, node);
+    }
+}
+
+/// 要素の中身を解釈するときのトークナイザの状態
+fn text_state_for(kind: ElementKind) -> Option<State> {
+    match kind {
+        ElementKind::Title | ElementKind::Textarea => Some(State::RcData),
+        ElementKind::Style | ElementKind::Xmp => Some(State::RawText),
+        ElementKind::Script => Some(State::ScriptData),
+        ElementKind::Plaintext => Some(State::PlainText),
+        _ => None,
+    }
+}
 
-        current.borrow_mut().set_last_child(Rc::downgrade(&node));
-        node.borrow_mut().set_parent(Rc::downgrade(&current));
+/// `html` を `context` の子ノードとして解釈する
+fn parse_fragment(dom: &mut Dom, context: NodeId, html: String) -> Vec<NodeId> {
+    let t = HtmlTokenizer::new(html);
+    HtmlParser::new_fragment(dom, t, context).construct_fragment(dom)
+}
 
-        self.stack_of_open_elements.push(node);
+/// `element` の子ノードを `html` を解釈したノードで置き換える (innerHTML への代入)
+pub fn set_inner_html(dom: &mut Dom, element: NodeId, html: String) {
+    let mut child = dom.get(element).first_child();
+    while let Some(c) = child {
+        child = dom.get(c).next_sibling();
+        detach_node(dom, c);
     }
+
+    for node in parse_fragment(dom, element, html) {
+        append_node(dom, element, node);
+    }
+}
+
+/// `html` を解釈したノードを `element` を基準とした `position` の位置に挿入する
+/// https://html.spec.whatwg.org/multipage/dynamic-markup-insertion.html#dom-element-insertadjacenthtml
+pub fn insert_adjacent_html(
+    dom: &mut Dom,
+    element: NodeId,
+    position: &str,
+    html: String,
+) -> Result<(), Error> {
+    let parent = dom.get(element).parent();
+
+    match position.to_ascii_lowercase().as_str() {
+        "beforebegin" | "afterend" => {
+            // 要素の外側に挿入するので、親ノードが要素である必要がある
+            let parent = match parent {
+                Some(p) if dom.get(p).element_kind().is_some() => p,
+                _ => {
+                    return Err(Error::UnexpectedInput(format!(
+                        "cannot insert HTML {} an element without a parent element",
+                        position
+                    )))
+                }
+            };
+
+            let reference = if position.eq_ignore_ascii_case("beforebegin") {
+                Some(element)
+            } else {
+                dom.get(element).next_sibling()
+            };
+            for node in parse_fragment(dom, parent, html) {
+                insert_node(dom, parent, node, reference);
+            }
+        }
+        "afterbegin" | "beforeend" => {
+            let reference = if position.eq_ignore_ascii_case("afterbegin") {
+                dom.get(element).first_child()
+            } else {
+                None
+            };
+            for node in parse_fragment(dom, element, html) {
+                insert_node(dom, element, node, reference);
+            }
+        }
+        _ => {
+            return Err(Error::UnexpectedInput(format!(
+                "invalid position for insertAdjacentHTML: {:?}",
+                position
+            )))
+        }
+    }
+
+    Ok(())
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ParserStatus {
+    // すべての入力を解釈し終えた
+    Finished,
+    // 受け取った入力をすべて解釈したので、次の入力を待っている
+    NeedMoreInput,
+    // <script> 要素を解釈し終えたので、スクリプトを実行するために中断している
+    PausedOnScript(NodeId),
 }
 
 #[derive(Debug, Copy, Clone, PartialEq, Eq)]
@@ -491,149 +715,130 @@ pub enum InsertionMode {
 mod tests {
     use super::*;
     use crate::alloc::string::ToString;
+    use crate::renderer::dom::api::get_target_element_node;
+    use crate::renderer::dom::api::get_text_content;
+    use crate::renderer::dom::node::Node;
+    use crate::renderer::html::serializer::serialize_children;
+    use crate::renderer::html::serializer::serialize_node;
     use alloc::vec;
 
+    fn parse(dom: &mut Dom, html: &str) -> NodeId {
+        let t = HtmlTokenizer::new(html.to_string());
+        HtmlParser::new(dom, t).construct_tree(dom)
+    }
+
     #[test]
     fn test_empty() {
-        let html = "".to_string();
-        let t = HtmlTokenizer::new(html);
-        let window = HtmlParser::new(t).construct_tree();
-        let expected = Rc::new(RefCell::new(Node::new(NodeKind::Document)));
-        assert_eq!(expected, window.borrow().document());
+        let mut dom = Dom::new();
+        let document = parse(&mut dom, "");
+        assert_eq!(&Node::new(NodeKind::Document), dom.get(document));
+        assert!(dom.get(document).first_child().is_none());
     }
 
     #[test]
     fn test_body() {
-        let html = "<html><head></head><body></body></html>".to_string();
-        let t = HtmlTokenizer::new(html);
-        let window = HtmlParser::new(t).construct_tree();
-        let document = window.borrow().document();
-        assert_eq!(
-            Rc::new(RefCell::new(Node::new(NodeKind::Document))),
-            document
-        );
+        let mut dom = Dom::new();
+        let document = parse(&mut dom, "<html><head></head><body></body></html>");
+        assert_eq!(&Node::new(NodeKind::Document), dom.get(document));
 
-        let html = document
-            .borrow()
+        let html = dom
+            .get(document)
             .first_child()
             .expect("failed to get a first child of document");
         assert_eq!(
-            Rc::new(RefCell::new(Node::new(NodeKind::Element(Element::new(
-                "html",
-                Vec::new(),
-            ))))),
-            html
+            &Node::new(NodeKind::Element(Element::new("html", Vec::new()))),
+            dom.get(html)
         );
 
-        let head = html
-            .borrow()
+        let head = dom
+            .get(html)
             .first_child()
             .expect("failed to get a first child of html");
         assert_eq!(
-            Rc::new(RefCell::new(Node::new(NodeKind::Element(Element::new(
-                "head",
-                Vec::new(),
-            ))))),
-            head
+            &Node::new(NodeKind::Element(Element::new("head", Vec::new()))),
+            dom.get(head)
         );
 
-        let body = head
-            .borrow()
+        let body = dom
+            .get(head)
             .next_sibling()
             .expect("failed to get a next sibling of head");
         assert_eq!(
-            Rc::new(RefCell::new(Node::new(NodeKind::Element(Element::new(
-                "body",
-                Vec::new(),
-            ))))),
-            body
+            &Node::new(NodeKind::Element(Element::new("body", Vec::new()))),
+            dom.get(body)
         );
     }
 
     #[test]
     fn test_text() {
-        let html = "<html><head></head><body>text</body></html>".to_string();
-        let t = HtmlTokenizer::new(html);
-        let window = HtmlParser::new(t).construct_tree();
-        let document = window.borrow().document();
-        assert_eq!(
-            Rc::new(RefCell::new(Node::new(NodeKind::Document))),
-            document
-        );
+        let mut dom = Dom::new();
+        let document = parse(&mut dom, "<html><head></head><body>text</body></html>");
+        assert_eq!(&Node::new(NodeKind::Document), dom.get(document));
 
-        let html = document
-            .borrow()
+        let html = dom
+            .get(document)
             .first_child()
             .expect("failed to get a first child of document");
         assert_eq!(
-            Rc::new(RefCell::new(Node::new(NodeKind::Element(Element::new(
-                "html",
-                Vec::new(),
-            ))))),
-            html
+            &Node::new(NodeKind::Element(Element::new("html", Vec::new()))),
+            dom.get(html)
         );
 
-        let body = html
-            .borrow()
+        let head = dom
+            .get(html)
             .first_child()
-            .expect("failed to get first child of document")
-            .borrow()
+            .expect("failed to get first child of document");
+        let body = dom
+            .get(head)
             .next_sibling()
             .expect("failed to get a next sibling of head");
         assert_eq!(
-            Rc::new(RefCell::new(Node::new(NodeKind::Element(Element::new(
-                "body",
-                Vec::new(),
-            ))))),
-            body
+            &Node::new(NodeKind::Element(Element::new("body", Vec::new()))),
+            dom.get(body)
         );
 
-        let text = body
-            .borrow()
+        let text = dom
+            .get(body)
             .first_child()
             .expect("failed to get a first child of document");
         assert_eq!(
-            Rc::new(RefCell::new(Node::new(NodeKind::Text("text".to_string())))),
-            text
+            &Node::new(NodeKind::Text("text".to_string())),
+            dom.get(text)
         );
     }
 
     #[test]
     fn test_multiple_nodes() {
-        let html =
-            "<html><head></head><body><body><p><a foo=bar>text</a></p></body></html>".to_string();
-        let t = HtmlTokenizer::new(html);
-        let window = HtmlParser::new(t).construct_tree();
-        let document = window.borrow().document();
-
-        let body = document
-            .borrow()
+        let mut dom = Dom::new();
+        let document = parse(
+            &mut dom,
+            "<html><head></head><body><body><p><a foo=bar>text</a></p></body></html>",
+        );
+
+        let html = dom
+            .get(document)
             .first_child()
-            .expect("failed to get a first child of document")
-            .borrow()
+            .expect("failed to get a first child of document");
+        let head = dom
+            .get(html)
             .first_child()
-            .expect("failed to get a first child of document")
-            .borrow()
+            .expect("failed to get a first child of document");
+        let body = dom
+            .get(head)
             .next_sibling()
             .expect("failed to get a next sibling of head");
         assert_eq!(
-            Rc::new(RefCell::new(Node::new(NodeKind::Element(Element::new(
-                "body",
-                Vec::new(),
-            ))))),
-            body
+            &Node::new(NodeKind::Element(Element::new("body", Vec::new()))),
+            dom.get(body)
         );
 
-        let p = body
-            .borrow()
+        let p = dom
+            .get(body)
             .first_child()
             .expect("failed to get a first child of body");
         assert_eq!(
-            Rc::new(RefCell::new(Node::new(NodeKind::Element(Element::new(
-                "p",
-                Vec::new(),
-            ))))),
-            p
+            &Node::new(NodeKind::Element(Element::new("p", Vec::new()))),
+            dom.get(p)
         );
 
         let mut attr = Attribute::new();
@@ -644,25 +849,183 @@ mod tests {
         attr.add_char('a', false);
         attr.add_char('r', false);
 
-        let a = p
-            .borrow()
+        let a = dom
+            .get(p)
             .first_child()
             .expect("failed to get a first child of p");
         assert_eq!(
-            Rc::new(RefCell::new(Node::new(NodeKind::Element(Element::new(
-                "a",
-                vec![attr],
-            ))))),
-            a
+            &Node::new(NodeKind::Element(Element::new("a", vec![attr]))),
+            dom.get(a)
         );
 
-        let text = a
-            .borrow()
+        let text = dom
+            .get(a)
             .first_child()
             .expect("failed to get a first child of a");
         assert_eq!(
-            Rc::new(RefCell::new(Node::new(NodeKind::Text("text".to_string())))),
-            text
+            &Node::new(NodeKind::Text("text".to_string())),
+            dom.get(text)
+        );
+    }
+
+    #[test]
+    fn test_streaming() {
+        let mut dom = Dom::new();
+        let mut parser = HtmlParser::new(&mut dom, HtmlTokenizer::new_streaming());
+        parser.push_str("<html><head><script>var a");
+        assert_eq!(ParserStatus::NeedMoreInput, parser.parse(&mut dom));
+
+        parser.push_str("=1;</script></head><body><p>te");
+        let script = match parser.parse(&mut dom) {
+            ParserStatus::PausedOnScript(script) => script,
+            status => panic!("parser should pause on </script> but got {:?}", status),
+        };
+        assert_eq!(Some(ElementKind::Script), dom.get(script).element_kind());
+        assert_eq!(ParserStatus::NeedMoreInput, parser.parse(&mut dom));
+
+        // 途中までの入力でも DOM ツリーが構築されている
+        let document = parser.document();
+        let p =
+            get_target_element_node(&dom, Some(document), ElementKind::P).expect("p should exist");
+        assert_eq!("te", serialize_children(&dom, p));
+
+        parser.push_str("xt</p></body></html>");
+        parser.close();
+        assert_eq!(ParserStatus::Finished, parser.parse(&mut dom));
+        assert_eq!(
+            "<html><head><script>var a=1;</script></head><body><p>text</p></body></html>",
+            serialize_children(&dom, document)
+        );
+    }
+
+    fn parse_body(dom: &mut Dom, html: &str) -> NodeId {
+        let document = parse(dom, html);
+        get_target_element_node(dom, Some(document), ElementKind::Body).expect("body should exist")
+    }
+
+    #[test]
+    fn test_fragment() {
+        let mut dom = Dom::new();
+        let body = parse_body(&mut dom, "<html><head></head><body></body></html>");
+        let t = HtmlTokenizer::new("<p>a</p>b<a href=x>c</a>".to_string());
+        let nodes = HtmlParser::new_fragment(&mut dom, t, body).construct_fragment(&mut dom);
+
+        assert_eq!(3, nodes.len());
+        assert_eq!(
+            &Node::new(NodeKind::Element(Element::new("p", Vec::new()))),
+            dom.get(nodes[0])
+        );
+        assert_eq!(
+            &Node::new(NodeKind::Text("b".to_string())),
+            dom.get(nodes[1])
+        );
+        assert_eq!(
+            &Node::new(NodeKind::Element(Element::new("a", Vec::new()))),
+            dom.get(nodes[2])
+        );
+        for node in &nodes {
+            assert!(dom.get(*node).parent().is_none());
+        }
+    }
+
+    #[test]
+    fn test_set_inner_html() {
+        let mut dom = Dom::new();
+        let body = parse_body(
+            &mut dom,
+            "<html><head></head><body><p>old</p></body></html>",
+        );
+        set_inner_html(&mut dom, body, "<h1>title</h1><p>new</p>".to_string());
+        assert_eq!("<h1>title</h1><p>new</p>", serialize_children(&dom, body));
+
+        let h1 = dom.get(body).first_child().expect("h1 should exist");
+        assert_eq!(Some(body), dom.get(h1).parent());
+        // 挿入されたノードは、挿入先の文書に属する
+        assert_eq!(dom.get(body).owner_document(), dom.get(h1).owner_document());
+    }
+
+    #[test]
+    fn test_insert_adjacent_html() {
+        let mut dom = Dom::new();
+        let body = parse_body(&mut dom, "<html><head></head><body><p>x</p></body></html>");
+        let p = dom.get(body).first_child().expect("p should exist");
+
+        assert!(insert_adjacent_html(&mut dom, p, "beforebegin", "<h1>1</h1>".to_string()).is_ok());
+        assert!(insert_adjacent_html(&mut dom, p, "afterbegin", "2".to_string()).is_ok());
+        assert!(insert_adjacent_html(&mut dom, p, "beforeend", "<a>3</a>".to_string()).is_ok());
+        assert!(insert_adjacent_html(&mut dom, p, "afterend", "<h2>4</h2>".to_string()).is_ok());
+        assert_eq!(
+            "<h1>1</h1><p>2x<a>3</a></p><h2>4</h2>",
+            serialize_children(&dom, body)
+        );
+
+        assert!(insert_adjacent_html(&mut dom, p, "middle", "".to_string()).is_err());
+    }
+
+    #[test]
+    fn test_stray_end_tags_in_fragment() {
+        let mut dom = Dom::new();
+        let body = parse_body(&mut dom, "<html><head></head><body><p>x</p></body></html>");
+        let p = dom.get(body).first_child().expect("p should exist");
+
+        // 対応する開始タグのない終了タグは無視される
+        set_inner_html(&mut dom, p, "</a>x</p></h1></html>y".to_string());
+        assert_eq!("xy", serialize_children(&dom, p));
+
+        assert!(insert_adjacent_html(&mut dom, p, "beforeend", "</h2>z</pre>".to_string()).is_ok());
+        assert!(
+            insert_adjacent_html(&mut dom, p, "afterend", "</a><a>w</a></p>".to_string()).is_ok()
+        );
+        assert_eq!("<p>xyz</p><a>w</a>", serialize_children(&dom, body));
+    }
+
+    #[test]
+    fn test_text_elements() {
+        let mut dom = Dom::new();
+        let document = parse(
+            &mut dom,
+            "<html><head><title>a &amp; <b></title></head><body><textarea><p>x</p></textarea><xmp>&lt;</xmp></body></html>",
+        );
+
+        let title = get_target_element_node(&dom, Some(document), ElementKind::Title)
+            .expect("title should exist");
+        assert_eq!("a & <b>", get_text_content(&dom, title));
+        let textarea = get_target_element_node(&dom, Some(document), ElementKind::Textarea)
+            .expect("textarea should exist");
+        assert_eq!("<p>x</p>", get_text_content(&dom, textarea));
+        let xmp = get_target_element_node(&dom, Some(document), ElementKind::Xmp)
+            .expect("xmp should exist");
+        assert_eq!("&lt;", get_text_content(&dom, xmp));
+
+        // <textarea> の中に要素は作られない
+        let text = dom.get(textarea).first_child().expect("text");
+        assert!(dom.get(text).first_child().is_none());
+        assert_eq!(
+            "<textarea>&lt;p&gt;x&lt;/p&gt;</textarea>",
+            serialize_node(&dom, textarea)
+        );
+    }
+
+    #[test]
+    fn test_plaintext() {
+        let mut dom = Dom::new();
+        let body = parse_body(
+            &mut dom,
+            "<html><head></head><body><plaintext><p>a</body></html>",
+        );
+        let plaintext = dom.get(body).first_child().expect("plaintext should exist");
+        assert_eq!("<p>a</body></html>", get_text_content(&dom, plaintext));
+    }
+
+    #[test]
+    fn test_fragment_in_textarea() {
+        let mut dom = Dom::new();
+        let body = parse_body(
+            &mut dom,
+            "<html><head></head><body><textarea></textarea></body></html>",
         );
+        let textarea = dom.get(body).first_child().expect("textarea should exist");
+        set_inner_html(&mut dom, textarea, "<b>x</b>".to_string());
+        assert_eq!("<b>x</b>", get_text_content(&dom, textarea));
     }
 }
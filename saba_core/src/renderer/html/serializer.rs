@@ -0,0 +1,177 @@
+//! DOM ツリーを HTML 文字列に変換する
+//! https://html.spec.whatwg.org/multipage/parsing.html#serialising-html-fragments
+
+use crate::renderer::dom::node::Dom;
+use crate::renderer::dom::node::Element;
+use crate::renderer::dom::node::ElementKind;
+use crate::renderer::dom::node::NodeId;
+use crate::renderer::dom::node::NodeKind;
+use alloc::string::String;
+use alloc::string::ToString;
+
+/// ノードの子孫を HTML 文字列に変換する。innerHTML の値になる
+pub fn serialize_children(dom: &Dom, node: NodeId) -> String {
+    let mut result = String::new();
+
+    let mut child = dom.get(node).first_child();
+    while let Some(c) = child {
+        serialize_node_internal(dom, c, &dom.get(node).kind, &mut result);
+        child = dom.get(c).next_sibling();
+    }
+
+    result
+}
+
+/// ノード自身を含めて HTML 文字列に変換する。outerHTML の値になる
+pub fn serialize_node(dom: &Dom, node: NodeId) -> String {
+    let parent_kind = match dom.get(node).parent() {
+        Some(parent) => dom.get(parent).kind(),
+        None => NodeKind::Document,
+    };
+
+    let mut result = String::new();
+    serialize_node_internal(dom, node, &parent_kind, &mut result);
+    result
+}
+
+fn serialize_node_internal(dom: &Dom, node: NodeId, parent_kind: &NodeKind, result: &mut String) {
+    match dom.get(node).kind {
+        NodeKind::Document => result.push_str(&serialize_children(dom, node)),
+        NodeKind::Element(ref e) => {
+            result.push_str(&start_tag(e));
+
+            // 空要素は終了タグも子ノードも持たない
+            if e.is_void_element() {
+                return;
+            }
+
+            result.push_str(&serialize_children(dom, node));
+            result.push_str("</");
+            result.push_str(&e.kind().to_string());
+            result.push('>');
+        }
+        NodeKind::Text(ref s) => {
+            // <style> や <script> などの中身は文字参照として解釈されないので、エスケープせずにそのまま出力する
+            if let NodeKind::Element(parent) = parent_kind {
+                if is_raw_text_element(parent.kind()) {
+                    result.push_str(s);
+                    return;
+                }
+            }
+            result.push_str(&escape_string(s, /*attribute_mode*/ false));
+        }
+    }
+}
+
+fn start_tag(element: &Element) -> String {
+    let mut tag = String::from("<");
+    tag.push_str(&element.kind().to_string());
+
+    for attr in element.attributes() {
+        tag.push(' ');
+        tag.push_str(&attr.name());
+        tag.push_str("=\"");
+        tag.push_str(&escape_string(&attr.value(), /*attribute_mode*/ true));
+        tag.push('"');
+    }
+
+    tag.push('>');
+    tag
+}
+
+fn is_raw_text_element(kind: ElementKind) -> bool {
+    matches!(
+        kind,
+        ElementKind::Style | ElementKind::Script | ElementKind::Xmp | ElementKind::Plaintext
+    )
+}
+
+/// https://html.spec.whatwg.org/multipage/parsing.html#escapingString
+fn escape_string(s: &str, attribute_mode: bool) -> String {
+    let mut result = String::new();
+
+    for c in s.chars() {
+        match c {
+            '&' => result.push_str("&amp;"),
+            '\u{a0}' => result.push_str("&nbsp;"),
+            '"' if attribute_mode => result.push_str("&quot;"),
+            '<' if !attribute_mode => result.push_str("&lt;"),
+            '>' if !attribute_mode => result.push_str("&gt;"),
+            _ => result.push(c),
+        }
+    }
+
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::renderer::dom::api::get_target_element_node;
+    use crate::renderer::html::parser::HtmlParser;
+    use crate::renderer::html::token::HtmlTokenizer;
+
+    fn parse(dom: &mut Dom, html: &str) -> NodeId {
+        let t = HtmlTokenizer::new(html.to_string());
+        HtmlParser::new(dom, t).construct_tree(dom)
+    }
+
+    #[test]
+    fn test_empty() {
+        let mut dom = Dom::new();
+        let document = parse(&mut dom, "");
+        assert_eq!("", serialize_children(&dom, document));
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let html = "<html><head><style>p > a { color: red; }</style></head><body><h1 id=\"title\">Tom &amp; Jerry</h1><p class=\"a\">1 &lt; 2<a href=\"/?q=&quot;x&quot;\">link</a>end<br>new line</p><hr></body></html>";
+        let mut dom = Dom::new();
+        let document = parse(&mut dom, html);
+        assert_eq!(html, serialize_children(&dom, document));
+
+        // 変換結果を再びパースしても同じ文字列が得られる
+        let serialized = serialize_children(&dom, document);
+        let reparsed = parse(&mut dom, &serialized);
+        assert_eq!(html, serialize_children(&dom, reparsed));
+    }
+
+    #[test]
+    fn test_inner_and_outer_html() {
+        let mut dom = Dom::new();
+        let document = parse(
+            &mut dom,
+            "<html><head></head><body><p>a<a href=\"x\">b</a></p></body></html>",
+        );
+        let p =
+            get_target_element_node(&dom, Some(document), ElementKind::P).expect("p should exist");
+        assert_eq!("a<a href=\"x\">b</a>", serialize_children(&dom, p));
+        assert_eq!("<p>a<a href=\"x\">b</a></p>", serialize_node(&dom, p));
+    }
+
+    #[test]
+    fn test_script_is_not_escaped() {
+        let mut dom = Dom::new();
+        let document = parse(
+            &mut dom,
+            "<html><head><script>a < b && c</script></head></html>",
+        );
+        let script = get_target_element_node(&dom, Some(document), ElementKind::Script)
+            .expect("script exists");
+        assert_eq!("<script>a < b && c</script>", serialize_node(&dom, script));
+    }
+
+    #[test]
+    fn test_null_character_reference() {
+        let mut dom = Dom::new();
+        let document = parse(
+            &mut dom,
+            "<html><head></head><body><p title=\"&#0;\">a&#0;b</p></body></html>",
+        );
+        let p =
+            get_target_element_node(&dom, Some(document), ElementKind::P).expect("p should exist");
+        let html = serialize_node(&dom, p);
+        assert!(!html.contains('\0'));
+        assert_eq!("<p title=\"\u{fffd}\">a\u{fffd}b</p>", html);
+    }
+}
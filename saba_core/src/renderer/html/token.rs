@@ -2,13 +2,25 @@ use crate::renderer::html::attribute::Attribute;
 use alloc::string::String;
 use alloc::vec::Vec;
 
+// "#x10FFFF" のような最も長い文字参照でも収まる長さ
+const MAX_CHARACTER_REFERENCE_LENGTH: usize = 10;
+
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct HtmlTokenizer {
     state: State, // ステートマシンの状態
-    pos: usize,   // 現在処理している文字の位置
+    pos: usize,   // 現在処理している文字の位置 (input のバイト位置)
     reconsume: bool,
     latest_token: Option<HtmlToken>,
-    input: Vec<char>,
+    // まだ処理していない入力。処理済みの部分は次の入力が追加されるときに捨てる
+    input: String,
+    // 最後に消費した文字。再消費 (reconsume) するときに使う
+    last_char: Option<char>,
+    // すべての入力を受け取ったかどうか
+    closed: bool,
+    // 最後に返した開始タグの名前。RCDATA などの終了タグが対応しているかを調べるために使う
+    last_start_tag: String,
+    // RCDATA、RAWTEXT、スクリプトデータのうち、終了タグを調べ終えたあとに戻る状態
+    text_state: State,
     buf: String,
 }
 
@@ -17,21 +29,72 @@ impl HtmlTokenizer {
         self.pos > self.input.len()
     }
 
+    /// 文書全体を一度に受け取るトークナイザを作成する
     pub fn new(html: String) -> Self {
+        let mut t = Self::new_streaming();
+        t.input = html;
+        t.closed = true;
+        t
+    }
+
+    /// 入力を少しずつ受け取るトークナイザを作成する
+    /// push_str で入力を追加し、すべての入力を追加したら close を呼ぶ
+    pub fn new_streaming() -> Self {
         Self {
             state: State::Data,
             pos: 0,
             reconsume: false,
             latest_token: None,
-            input: html.chars().collect(),
+            input: String::new(),
+            last_char: None,
+            closed: false,
+            last_start_tag: String::new(),
+            text_state: State::Data,
             buf: String::new(),
         }
     }
 
-    fn consume_next_input(&mut self) -> char {
-        let c = self.input[self.pos];
-        self.pos += 1;
-        c
+    /// 入力を末尾に追加する
+    pub fn push_str(&mut self, chunk: &str) {
+        assert!(!self.closed, "cannot push input to a closed tokenizer");
+
+        // 処理済みの入力を捨てて、メモリの使用量を抑える
+        self.input.drain(..self.pos);
+        self.pos = 0;
+        self.input.push_str(chunk);
+    }
+
+    /// これ以上入力が追加されないことを知らせる
+    pub fn close(&mut self) {
+        self.closed = true;
+    }
+
+    pub fn is_closed(&self) -> bool {
+        self.closed
+    }
+
+    /// トークナイザの状態を切り替える
+    /// <title> や <style> などの中身を文字として扱うために、ツリー構築段階から呼ばれる
+    pub fn switch_to(&mut self, state: State) {
+        self.state = state;
+    }
+
+    /// 現在の終了タグが最後に返した開始タグと対応しているかどうか
+    /// https://html.spec.whatwg.org/multipage/parsing.html#appropriate-end-tag-token
+    fn is_appropriate_end_tag(&self) -> bool {
+        match self.latest_token {
+            Some(HtmlToken::EndTag { ref tag }) => {
+                !self.last_start_tag.is_empty() && *tag == self.last_start_tag
+            }
+            _ => false,
+        }
+    }
+
+    fn consume_next_input(&mut self) -> Option<char> {
+        let c = self.input[self.pos..].chars().next()?;
+        self.pos += c.len_utf8();
+        self.last_char = Some(c);
+        Some(c)
     }
 
     fn create_tag(&mut self, start_tag_token: bool) {
@@ -48,7 +111,8 @@ impl HtmlTokenizer {
 
     fn reconsume_input(&mut self) -> char {
         self.reconsume = false;
-        self.input[self.pos - 1]
+        self.last_char
+            .expect("should have consumed at least 1 char before reconsuming")
     }
 
     fn append_tag_name(&mut self, c: char) {
@@ -74,6 +138,10 @@ impl HtmlTokenizer {
         let t = self.latest_token.as_ref().cloned();
         self.latest_token = None;
         assert!(self.latest_token.is_none());
+
+        if let Some(HtmlToken::StartTag { ref tag, .. }) = t {
+            self.last_start_tag = tag.clone();
+        }
         t
     }
 
@@ -114,6 +182,58 @@ impl HtmlTokenizer {
         }
     }
 
+    /// 文字参照 (`&amp;` や `&#60;` など) を解釈する
+    /// `&` の直後から入力を先読みし、有効な文字参照であれば参照先の文字を返して位置を進める
+    /// 無効な場合は位置を進めずに None を返すので、呼び出し側は `&` をそのまま扱う
+    /// https://html.spec.whatwg.org/multipage/parsing.html#character-reference-state
+    fn consume_character_reference(&mut self) -> Option<char> {
+        let rest: Vec<char> = self.input[self.pos..]
+            .chars()
+            .take(MAX_CHARACTER_REFERENCE_LENGTH)
+            .collect();
+        let end = rest.iter().position(|c| *c == ';')?;
+        let name: String = rest[..end].iter().collect();
+
+        let c = if let Some(number) = name.strip_prefix('#') {
+            let code = match number.strip_prefix(['x', 'X']) {
+                Some(hex) => u32::from_str_radix(hex, 16).ok()?,
+                None => number.parse::<u32>().ok()?,
+            };
+            // NULL、サロゲート、Unicode の範囲外の値は置換文字 U+FFFD にする
+            // https://html.spec.whatwg.org/multipage/parsing.html#numeric-character-reference-end-state
+            match code {
+                0 | 0xd800..=0xdfff | 0x110000.. => '\u{fffd}',
+                _ => char::from_u32(code)?,
+            }
+        } else {
+            match name.as_str() {
+                "amp" => '&',
+                "lt" => '<',
+                "gt" => '>',
+                "quot" => '"',
+                "apos" => '\'',
+                "nbsp" => '\u{a0}',
+                _ => return None,
+            }
+        };
+
+        // 文字参照の名前と ';' を読み飛ばす
+        self.pos += name.len() + 1;
+        Some(c)
+    }
+
+    /// 文字参照を判断するのに十分な入力がまだ届いていないかどうか
+    fn is_waiting_for_character_reference(&self) -> bool {
+        if self.closed {
+            return false;
+        }
+        // 文字参照の長さには上限があるので、入力の先頭の上限までの文字だけを調べる
+        let prefix = self.input[self.pos..]
+            .chars()
+            .take(MAX_CHARACTER_REFERENCE_LENGTH);
+        !prefix.clone().any(|c| c == ';') && prefix.count() < MAX_CHARACTER_REFERENCE_LENGTH
+    }
+
     fn set_self_closing_flag(&mut self) {
         assert!(self.latest_token.is_some());
 
@@ -133,15 +253,14 @@ impl HtmlTokenizer {
 impl Iterator for HtmlTokenizer {
     type Item = HtmlToken;
 
+    /// 次のトークンを返す
+    /// 入力の終わりに到達した場合は None を返す。まだ入力が閉じられていない場合は、
+    /// 途中の状態を保ったまま中断するので、push_str で入力を追加したあとに再び呼び出すと続きから処理する
     fn next(&mut self) -> Option<Self::Item> {
-        if self.pos >= self.input.len() {
-            return None;
-        }
-
         loop {
             let c = match self.reconsume {
                 true => self.reconsume_input(),
-                false => self.consume_next_input(),
+                false => self.consume_next_input()?,
             };
 
             match self.state {
@@ -151,6 +270,17 @@ impl Iterator for HtmlTokenizer {
                         continue;
                     }
 
+                    if c == '&' {
+                        if self.is_waiting_for_character_reference() {
+                            // 次の入力が届いたら '&' から処理し直す
+                            self.reconsume = true;
+                            return None;
+                        }
+                        if let Some(decoded) = self.consume_character_reference() {
+                            return Some(HtmlToken::Char(decoded));
+                        }
+                    }
+
                     if self.is_eof() {
                         return Some(HtmlToken::Eof);
                     }
@@ -173,8 +303,10 @@ impl Iterator for HtmlTokenizer {
                         return Some(HtmlToken::Eof);
                     }
 
+                    // タグの開始ではなかったので、'<' を文字として扱う
                     self.reconsume = true;
                     self.state = State::Data;
+                    return Some(HtmlToken::Char('<'));
                 }
                 State::EndTagOpen => {
                     if self.is_eof() {
@@ -303,6 +435,17 @@ impl Iterator for HtmlTokenizer {
                         continue;
                     }
 
+                    if c == '&' {
+                        if self.is_waiting_for_character_reference() {
+                            self.reconsume = true;
+                            return None;
+                        }
+                        if let Some(decoded) = self.consume_character_reference() {
+                            self.append_attribute(decoded, /*is_name*/ false);
+                            continue;
+                        }
+                    }
+
                     if self.is_eof() {
                         return Some(HtmlToken::Eof);
                     }
@@ -316,6 +459,17 @@ impl Iterator for HtmlTokenizer {
                         continue;
                     }
 
+                    if c == '&' {
+                        if self.is_waiting_for_character_reference() {
+                            self.reconsume = true;
+                            return None;
+                        }
+                        if let Some(decoded) = self.consume_character_reference() {
+                            self.append_attribute(decoded, /*is_name*/ false);
+                            continue;
+                        }
+                    }
+
                     if self.is_eof() {
                         return Some(HtmlToken::Eof);
                     }
@@ -329,6 +483,17 @@ impl Iterator for HtmlTokenizer {
                         continue;
                     }
 
+                    if c == '&' {
+                        if self.is_waiting_for_character_reference() {
+                            self.reconsume = true;
+                            return None;
+                        }
+                        if let Some(decoded) = self.consume_character_reference() {
+                            self.append_attribute(decoded, /*is_name*/ false);
+                            continue;
+                        }
+                    }
+
                     if c == '>' {
                         self.state = State::Data;
                         return self.take_latest_token();
@@ -378,12 +543,25 @@ impl Iterator for HtmlTokenizer {
                     }
                 }
 
-                State::ScriptData => {
+                State::RcData | State::RawText | State::ScriptData => {
                     if c == '<' {
-                        self.state = State::ScriptDataLessThanSign;
+                        // 終了タグのあとに戻る状態を覚えておく
+                        self.text_state = self.state.clone();
+                        self.state = State::TextLessThanSign;
                         continue;
                     }
 
+                    // RCDATA の中では文字参照が使える
+                    if c == '&' && self.state == State::RcData {
+                        if self.is_waiting_for_character_reference() {
+                            self.reconsume = true;
+                            return None;
+                        }
+                        if let Some(decoded) = self.consume_character_reference() {
+                            return Some(HtmlToken::Char(decoded));
+                        }
+                    }
+
                     if self.is_eof() {
                         return Some(HtmlToken::Eof);
                     }
@@ -391,37 +569,43 @@ impl Iterator for HtmlTokenizer {
                     return Some(HtmlToken::Char(c));
                 }
 
-                State::ScriptDataLessThanSign => {
+                State::PlainText => {
+                    // PLAINTEXT の中ではタグは存在しないので、すべての文字をそのまま返す
+                    return Some(HtmlToken::Char(c));
+                }
+
+                State::TextLessThanSign => {
                     if c == '/' {
                         // 一時的なバッファを空文字でリセットする
                         self.buf = String::new();
-                        self.state = State::ScriptDataEndTagOpen;
+                        self.state = State::TextEndTagOpen;
                         continue;
                     }
 
                     self.reconsume = true;
-                    self.state = State::ScriptData;
+                    self.state = self.text_state.clone();
                     return Some(HtmlToken::Char('<'));
                 }
 
-                State::ScriptDataEndTagOpen => {
+                State::TextEndTagOpen => {
                     if c.is_ascii_alphabetic() {
                         self.reconsume = true;
-                        self.state = State::ScriptDataEndTagName;
+                        self.state = State::TextEndTagName;
                         self.create_tag(false);
                         continue;
                     }
 
-                    self.reconsume = true;
-                    self.state = State::ScriptData;
                     // 仕様では、"<" と "/"の２つの文字トークンを返すとなっているが、
                     // 私達の実装ではnextメソッドからは一つのトークンしか返せないため、
-                    // ここでは"<" のトークンのみを返す
+                    // "/" は一時的なバッファに入れて次の呼び出しで返す
+                    self.reconsume = true;
+                    self.buf = String::from("/");
+                    self.state = State::TemporaryBuffer;
                     return Some(HtmlToken::Char('<'));
                 }
 
-                State::ScriptDataEndTagName => {
-                    if c == '>' {
+                State::TextEndTagName => {
+                    if c == '>' && self.is_appropriate_end_tag() {
                         self.state = State::Data;
                         return self.take_latest_token();
                     }
@@ -432,9 +616,11 @@ impl Iterator for HtmlTokenizer {
                         continue;
                     }
 
+                    // 対応する終了タグではなかったので、"</" とタグ名を文字として返す
+                    self.latest_token = None;
+                    self.reconsume = true;
                     self.state = State::TemporaryBuffer;
                     self.buf = String::from("</") + &self.buf;
-                    self.buf.push(c);
                     continue;
                 }
 
@@ -442,7 +628,7 @@ impl Iterator for HtmlTokenizer {
                     self.reconsume = true;
 
                     if self.buf.chars().count() == 0 {
-                        self.state = State::ScriptData;
+                        self.state = self.text_state.clone();
                         continue;
                     }
 
@@ -478,9 +664,15 @@ pub enum HtmlToken {
     Eof,
 }
 
+/// トークナイザの状態
+/// https://html.spec.whatwg.org/multipage/parsing.html#tokenization
 #[derive(Debug, Clone, PartialEq, Eq)]
-enum State {
+pub enum State {
     Data,
+    RcData,
+    RawText,
+    ScriptData,
+    PlainText,
     TagOpen,
     EndTagOpen,
     TagName,
@@ -493,10 +685,11 @@ enum State {
     AttributeValueUnquoted,
     AfterAttributeValueQuoted,
     SelfClosingStartTag,
-    ScriptData,
-    ScriptDataLessThanSign,
-    ScriptDataEndTagOpen,
-    ScriptDataEndTagName,
+    // 以下の3つの状態は RCDATA、RAWTEXT、スクリプトデータで共通して使う
+    // 仕様ではそれぞれ別の状態として定義されているが、振る舞いは同じである
+    TextLessThanSign,
+    TextEndTagOpen,
+    TextEndTagName,
     TemporaryBuffer,
 }
 
@@ -504,6 +697,7 @@ enum State {
 mod tests {
     use super::*;
     use crate::alloc::string::ToString;
+    use alloc::format;
     use alloc::vec;
 
     #[test]
@@ -586,6 +780,88 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_character_reference() {
+        let html = "a&amp;&lt;&#62;&#x41;&unknown;".to_string();
+        let tokenizer = HtmlTokenizer::new(html);
+        let expected: Vec<HtmlToken> = "a&<>A&unknown;".chars().map(HtmlToken::Char).collect();
+        assert_eq!(expected, tokenizer.collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn test_invalid_numeric_character_reference() {
+        let html = "&#0;&#xD800;&#xdfff;&#x110000;&#1114111;".to_string();
+        let tokenizer = HtmlTokenizer::new(html);
+        let expected: Vec<HtmlToken> = "\u{fffd}\u{fffd}\u{fffd}\u{fffd}\u{10ffff}"
+            .chars()
+            .map(HtmlToken::Char)
+            .collect();
+        assert_eq!(expected, tokenizer.collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn test_character_reference_in_attribute() {
+        let html = "<a title=\"&quot;x&quot; &amp; y\"></a>".to_string();
+        let mut tokenizer = HtmlTokenizer::new(html);
+        let mut attr = Attribute::new();
+        for c in "title".chars() {
+            attr.add_char(c, true);
+        }
+        for c in "\"x\" & y".chars() {
+            attr.add_char(c, false);
+        }
+        assert_eq!(
+            Some(HtmlToken::StartTag {
+                tag: "a".to_string(),
+                self_closing: false,
+                attributes: vec![attr],
+            }),
+            tokenizer.next()
+        );
+    }
+
+    #[test]
+    fn test_streaming() {
+        let mut tokenizer = HtmlTokenizer::new_streaming();
+        tokenizer.push_str("<p cla");
+        assert_eq!(None, tokenizer.next());
+
+        tokenizer.push_str("ss=\"A\">x&am");
+        let mut attr = Attribute::new();
+        for c in "class".chars() {
+            attr.add_char(c, true);
+        }
+        attr.add_char('A', false);
+        assert_eq!(
+            Some(HtmlToken::StartTag {
+                tag: "p".to_string(),
+                self_closing: false,
+                attributes: vec![attr],
+            }),
+            tokenizer.next()
+        );
+        assert_eq!(Some(HtmlToken::Char('x')), tokenizer.next());
+        // 文字参照が途中で途切れているので、次の入力を待つ
+        assert_eq!(None, tokenizer.next());
+
+        tokenizer.push_str("p;</p>");
+        tokenizer.close();
+        assert_eq!(Some(HtmlToken::Char('&')), tokenizer.next());
+        assert_eq!(
+            Some(HtmlToken::EndTag {
+                tag: "p".to_string()
+            }),
+            tokenizer.next()
+        );
+        assert_eq!(None, tokenizer.next());
+
+        // 文字参照の最大の長さを超えて ; がない場合は、残りの入力を待たずに解釈する
+        let mut tokenizer = HtmlTokenizer::new_streaming();
+        tokenizer.push_str(&format!("&{};", "x".repeat(100_000)));
+        assert_eq!(Some(HtmlToken::Char('&')), tokenizer.next());
+        assert_eq!(Some(HtmlToken::Char('x')), tokenizer.next());
+    }
+
     #[test]
     fn test_script_tag() {
         let html = "<script>js code;</script>".to_string();
@@ -612,4 +888,49 @@ mod tests {
             assert_eq!(Some(e), tokenizer.next());
         }
     }
+
+    #[test]
+    fn test_rcdata() {
+        let html = "<title>a&lt;<b></tit></title>".to_string();
+        let mut tokenizer = HtmlTokenizer::new(html);
+        assert_eq!(
+            Some(HtmlToken::StartTag {
+                tag: "title".to_string(),
+                self_closing: false,
+                attributes: Vec::new(),
+            }),
+            tokenizer.next()
+        );
+
+        // ツリー構築段階が <title> を見てトークナイザの状態を切り替える
+        tokenizer.switch_to(State::RcData);
+        let mut expected: Vec<HtmlToken> = "a<<b></tit>".chars().map(HtmlToken::Char).collect();
+        expected.push(HtmlToken::EndTag {
+            tag: "title".to_string(),
+        });
+        assert_eq!(expected, tokenizer.collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn test_rawtext() {
+        let html = "<style>a&amp;</ b<c</style>".to_string();
+        let mut tokenizer = HtmlTokenizer::new(html);
+        tokenizer.next();
+        tokenizer.switch_to(State::RawText);
+        let mut expected: Vec<HtmlToken> = "a&amp;</ b<c".chars().map(HtmlToken::Char).collect();
+        expected.push(HtmlToken::EndTag {
+            tag: "style".to_string(),
+        });
+        assert_eq!(expected, tokenizer.collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn test_plaintext() {
+        let html = "<plaintext><p>a</plaintext>".to_string();
+        let mut tokenizer = HtmlTokenizer::new(html);
+        tokenizer.next();
+        tokenizer.switch_to(State::PlainText);
+        let expected: Vec<HtmlToken> = "<p>a</plaintext>".chars().map(HtmlToken::Char).collect();
+        assert_eq!(expected, tokenizer.collect::<Vec<_>>());
+    }
 }
@@ -2,6 +2,7 @@ use crate::renderer::js::token::JsLexer;
 use crate::renderer::js::token::Token;
 use alloc::rc::Rc;
 use alloc::string::String;
+use alloc::string::ToString;
 use alloc::vec::Vec;
 use core::iter::Peekable;
 
@@ -50,6 +51,20 @@ pub enum Node {
         callee: Option<Rc<Node>>,
         arguments: Vec<Option<Rc<Node>>>,
     },
+    // new から始まるオブジェクトの作成
+    NewExpression {
+        callee: Option<Rc<Node>>,
+        arguments: Vec<Option<Rc<Node>>>,
+    },
+    // { a: 1, b: 2 } のようなオブジェクトリテラル
+    ObjectExpression {
+        properties: Vec<Option<Rc<Node>>>,
+    },
+    // オブジェクトリテラルの中のキーと値の組
+    Property {
+        key: Option<Rc<Node>>,
+        value: Option<Rc<Node>>,
+    },
 }
 
 impl Node {
@@ -133,6 +148,21 @@ impl Node {
     ) -> Option<Rc<Self>> {
         Some(Rc::new(Node::CallExpression { callee, arguments }))
     }
+
+    pub fn new_new_expression(
+        callee: Option<Rc<Self>>,
+        arguments: Vec<Option<Rc<Self>>>,
+    ) -> Option<Rc<Self>> {
+        Some(Rc::new(Node::NewExpression { callee, arguments }))
+    }
+
+    pub fn new_object_expression(properties: Vec<Option<Rc<Self>>>) -> Option<Rc<Self>> {
+        Some(Rc::new(Node::ObjectExpression { properties }))
+    }
+
+    pub fn new_property(key: Option<Rc<Self>>, value: Option<Rc<Self>>) -> Option<Rc<Self>> {
+        Some(Rc::new(Node::Property { key, value }))
+    }
 }
 
 pub struct JsParser {
@@ -200,7 +230,7 @@ impl JsParser {
 
                     Node::new_return_statement(self.assignment_expression())
                 } else {
-                    None
+                    Node::new_expression_statement(self.assignment_expression())
                 }
             }
             _ => Node::new_expression_statement(self.assignment_expression()),
@@ -293,6 +323,9 @@ impl JsParser {
                         if c == &',' {
                             // ','を消費する
                             assert!(self.t.next().is_some());
+                        } else {
+                            // {a: 1} のように記号から始まる引数
+                            arguments.push(self.assignment_expression());
                         }
                     }
                     _ => arguments.push(self.assignment_expression()),
@@ -303,23 +336,56 @@ impl JsParser {
     }
 
     fn member_expression(&mut self) -> Option<Rc<Node>> {
-        let expr = self.primary_expression();
-
-        let t = match self.t.peek() {
-            Some(token) => token,
-            None => return expr,
+        let mut expr = match self.t.peek() {
+            Some(Token::Keyword(keyword)) if keyword == "new" => {
+                // 'new'を消費する
+                assert!(self.t.next().is_some());
+                let callee = self.member_expression();
+                // new Foo; のように引数を省略した場合は、引数なしで呼び出す
+                let arguments = match self.t.peek() {
+                    Some(Token::Punctuator('(')) => {
+                        // '('を消費する
+                        assert!(self.t.next().is_some());
+                        self.arguments()
+                    }
+                    _ => Vec::new(),
+                };
+                Node::new_new_expression(callee, arguments)
+            }
+            _ => self.primary_expression(),
         };
 
-        match t {
-            Token::Punctuator(c) => {
-                if c == &'.' {
-                    // '.'を消費する
-                    assert!(self.t.next().is_some());
-                    return Node::new_member_expression(expr, self.identifier());
+        // a.b.c のように続くメンバーアクセスは、左から順に MemberExpression ノードを入れ子にする
+        loop {
+            let t = match self.t.peek() {
+                Some(token) => token,
+                None => return expr,
+            };
+
+            match t {
+                Token::Punctuator(c) => {
+                    if c == &'.' {
+                        // '.'を消費する
+                        assert!(self.t.next().is_some());
+                        expr = Node::new_member_expression(expr, self.identifier());
+                        continue;
+                    }
+                    if c == &'[' {
+                        // '['を消費する
+                        assert!(self.t.next().is_some());
+                        // a[0] のような添字によるアクセスは、添字の式をプロパティとする
+                        let property = self.assignment_expression();
+                        if let Some(Token::Punctuator(']')) = self.t.peek() {
+                            // ']'を消費する
+                            assert!(self.t.next().is_some());
+                        }
+                        expr = Node::new_member_expression(expr, property);
+                        continue;
+                    }
+                    return expr;
                 }
-                expr
+                _ => return expr,
             }
-            _ => expr,
         }
     }
 
@@ -333,10 +399,64 @@ impl JsParser {
             Token::Identifier(value) => Node::new_identifier(value),
             Token::StringLiteral(value) => Node::new_string_literal(value),
             Token::Number(value) => Node::new_numeric_literal(value),
+            Token::Punctuator('{') => self.object_literal(),
             _ => None,
         }
     }
 
+    fn object_literal(&mut self) -> Option<Rc<Node>> {
+        let mut properties = Vec::new();
+
+        loop {
+            // '}'に到達するまで、キーと値の組を properties に追加する
+            let key = match self.t.next() {
+                Some(Token::Punctuator('}')) | None => {
+                    return Node::new_object_expression(properties)
+                }
+                Some(Token::Punctuator(',')) => continue,
+                Some(Token::Identifier(name)) => Node::new_identifier(name),
+                Some(Token::StringLiteral(value)) => Node::new_string_literal(value),
+                // {1: x} のような数値のキーは、文字列のキーとして扱う
+                Some(Token::Number(value)) => Node::new_string_literal(value.to_string()),
+                // {[a]: x} のような計算されたキーはサポートしていないので、読み飛ばしてプロパティを捨てる
+                Some(Token::Punctuator('[')) => {
+                    self.assignment_expression();
+                    if let Some(Token::Punctuator(']')) = self.t.peek() {
+                        assert!(self.t.next().is_some());
+                    }
+                    None
+                }
+                // キーとして不正なトークンは構文エラーとし、対応する'}'まで読み飛ばす
+                Some(_) => {
+                    self.skip_to_closing_brace();
+                    return None;
+                }
+            };
+
+            // ':'を消費する
+            if let Some(Token::Punctuator(':')) = self.t.peek() {
+                assert!(self.t.next().is_some());
+            }
+            let value = self.assignment_expression();
+            if key.is_some() {
+                properties.push(Node::new_property(key, value));
+            }
+        }
+    }
+
+    /// ネストした'{'と'}'の対応を数えながら、現在のブロックを閉じる'}'までトークンを消費する
+    fn skip_to_closing_brace(&mut self) {
+        let mut depth = 0;
+        for token in self.t.by_ref() {
+            match token {
+                Token::Punctuator('{') => depth += 1,
+                Token::Punctuator('}') if depth == 0 => return,
+                Token::Punctuator('}') => depth -= 1,
+                _ => {}
+            }
+        }
+    }
+
     fn variable_declaration(&mut self) -> Option<Rc<Node>> {
         let ident = self.identifier();
 
@@ -468,7 +588,6 @@ impl Program {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use alloc::string::ToString;
 
     #[test]
     fn test_empty() {
@@ -642,4 +761,105 @@ mod tests {
         expected.set_body(body);
         assert_eq!(expected, parser.parse_ast());
     }
+
+    #[test]
+    fn test_computed_member() {
+        let input = "a[0].b".to_string();
+        let lexer = JsLexer::new(input);
+        let mut parser = JsParser::new(lexer);
+        let mut expected = Program::new();
+        let mut body = Vec::new();
+        body.push(Rc::new(Node::ExpressionStatement(Some(Rc::new(
+            Node::MemberExpression {
+                object: Some(Rc::new(Node::MemberExpression {
+                    object: Some(Rc::new(Node::Identifier("a".to_string()))),
+                    property: Some(Rc::new(Node::NumericLiteral(0))),
+                })),
+                property: Some(Rc::new(Node::Identifier("b".to_string()))),
+            },
+        )))));
+        expected.set_body(body);
+        assert_eq!(expected, parser.parse_ast());
+    }
+
+    #[test]
+    fn test_new_expression() {
+        let input = "new Foo(bar, {a: 1, \"b\": c}).d".to_string();
+        let lexer = JsLexer::new(input);
+        let mut parser = JsParser::new(lexer);
+        let mut expected = Program::new();
+        let mut body = Vec::new();
+        body.push(Rc::new(Node::ExpressionStatement(Some(Rc::new(
+            Node::MemberExpression {
+                object: Some(Rc::new(Node::NewExpression {
+                    callee: Some(Rc::new(Node::Identifier("Foo".to_string()))),
+                    arguments: [
+                        Some(Rc::new(Node::Identifier("bar".to_string()))),
+                        Some(Rc::new(Node::ObjectExpression {
+                            properties: [
+                                Some(Rc::new(Node::Property {
+                                    key: Some(Rc::new(Node::Identifier("a".to_string()))),
+                                    value: Some(Rc::new(Node::NumericLiteral(1))),
+                                })),
+                                Some(Rc::new(Node::Property {
+                                    key: Some(Rc::new(Node::StringLiteral("b".to_string()))),
+                                    value: Some(Rc::new(Node::Identifier("c".to_string()))),
+                                })),
+                            ]
+                            .to_vec(),
+                        })),
+                    ]
+                    .to_vec(),
+                })),
+                property: Some(Rc::new(Node::Identifier("d".to_string()))),
+            },
+        )))));
+        expected.set_body(body);
+        assert_eq!(expected, parser.parse_ast());
+    }
+
+    #[test]
+    fn test_object_literal_keys() {
+        let input = "x = {1: a, [b]: c, d: e}; y = {f: 1, -: 2}; z = 3".to_string();
+        let lexer = JsLexer::new(input);
+        let mut parser = JsParser::new(lexer);
+        let mut expected = Program::new();
+        let mut body = Vec::new();
+        body.push(Rc::new(Node::ExpressionStatement(Some(Rc::new(
+            Node::AssignmentExpression {
+                operator: '=',
+                left: Some(Rc::new(Node::Identifier("x".to_string()))),
+                right: Some(Rc::new(Node::ObjectExpression {
+                    properties: [
+                        Some(Rc::new(Node::Property {
+                            key: Some(Rc::new(Node::StringLiteral("1".to_string()))),
+                            value: Some(Rc::new(Node::Identifier("a".to_string()))),
+                        })),
+                        Some(Rc::new(Node::Property {
+                            key: Some(Rc::new(Node::Identifier("d".to_string()))),
+                            value: Some(Rc::new(Node::Identifier("e".to_string()))),
+                        })),
+                    ]
+                    .to_vec(),
+                })),
+            },
+        )))));
+        // 不正なキーを含むオブジェクトリテラルは None になり、続く文は通常どおり解析される
+        body.push(Rc::new(Node::ExpressionStatement(Some(Rc::new(
+            Node::AssignmentExpression {
+                operator: '=',
+                left: Some(Rc::new(Node::Identifier("y".to_string()))),
+                right: None,
+            },
+        )))));
+        body.push(Rc::new(Node::ExpressionStatement(Some(Rc::new(
+            Node::AssignmentExpression {
+                operator: '=',
+                left: Some(Rc::new(Node::Identifier("z".to_string()))),
+                right: Some(Rc::new(Node::NumericLiteral(3))),
+            },
+        )))));
+        expected.set_body(body);
+        assert_eq!(expected, parser.parse_ast());
+    }
 }
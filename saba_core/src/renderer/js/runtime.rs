@@ -1,6 +1,43 @@
+use crate::renderer::dom::api::append_child;
+use crate::renderer::dom::api::clone_node;
+use crate::renderer::dom::api::get_document_title;
 use crate::renderer::dom::api::get_element_by_id;
-use crate::renderer::dom::node::Node as DomNode;
+use crate::renderer::dom::api::insert_before;
+use crate::renderer::dom::api::modify_element;
+use crate::renderer::dom::api::node_window;
+use crate::renderer::dom::api::query_selector;
+use crate::renderer::dom::api::query_selector_all;
+use crate::renderer::dom::api::remove_child;
+use crate::renderer::dom::api::replace_child;
+use crate::renderer::dom::api::set_attribute;
+use crate::renderer::dom::api::set_document_title;
+use crate::renderer::dom::api::set_text_content;
+use crate::renderer::dom::collection::CollectionFilter;
+use crate::renderer::dom::collection::HtmlCollection;
+use crate::renderer::dom::event::dispatch_event;
+use crate::renderer::dom::event::Event;
+use crate::renderer::dom::event::EventListener;
+use crate::renderer::dom::event::EventTarget;
+use crate::renderer::dom::mutation::disconnect;
+use crate::renderer::dom::mutation::observe;
+use crate::renderer::dom::mutation::MutationObserver;
+use crate::renderer::dom::mutation::MutationObserverInit;
+use crate::renderer::dom::mutation::MutationRecord;
+use crate::renderer::dom::node::Dom;
+use crate::renderer::dom::node::Element;
+use crate::renderer::dom::node::ElementKind;
+use crate::renderer::dom::node::NodeId;
 use crate::renderer::dom::node::NodeKind as DomNodeKind;
+use crate::renderer::dom::traversal::NodeIterator;
+use crate::renderer::dom::traversal::TreeWalker;
+use crate::renderer::dom::traversal::SHOW_ALL;
+use crate::renderer::dom::traversal::SHOW_DOCUMENT;
+use crate::renderer::dom::traversal::SHOW_ELEMENT;
+use crate::renderer::dom::traversal::SHOW_TEXT;
+use crate::renderer::html::parser::insert_adjacent_html;
+use crate::renderer::html::parser::set_inner_html;
+use crate::renderer::html::serializer::serialize_children;
+use crate::renderer::html::serializer::serialize_node;
 use crate::renderer::js::ast::Node;
 use crate::renderer::js::ast::Program;
 use alloc::format;
@@ -14,12 +51,16 @@ use core::fmt::Display;
 use core::fmt::Formatter;
 use core::ops::Add;
 use core::ops::Sub;
+use core::str::FromStr;
 
 #[derive(Debug, Clone)]
 pub struct JsRuntime {
-    dom_root: Rc<RefCell<DomNode>>,
+    // スクリプトが実行されている文書
+    document: NodeId,
     env: Rc<RefCell<Environment>>,
     functions: Vec<Function>,
+    // スクリプトが作成した MutationObserver。マイクロタスクのチェックポイントで記録を届ける
+    mutation_observers: Vec<Rc<RefCell<MutationObserver>>>,
 }
 
 type VariableMap = Vec<(String, Option<RuntimeValue>)>;
@@ -77,26 +118,109 @@ impl Environment {
                 return;
             }
         }
+        // このスコープにない変数は、外側のスコープの変数を更新する
+        if let Some(env) = &self.outer {
+            env.borrow_mut().update_variable(name, value);
+        }
     }
 }
 
 impl JsRuntime {
-    pub fn new(dom_root: Rc<RefCell<DomNode>>) -> Self {
+    pub fn new(document: NodeId) -> Self {
         Self {
-            dom_root,
+            document,
             env: Rc::new(RefCell::new(Environment::new(None))),
             functions: Vec::new(),
+            mutation_observers: Vec::new(),
         }
     }
 
-    pub fn execute(&mut self, program: &Program) {
+    pub fn execute(&mut self, dom: &mut Dom, program: &Program) {
         for node in program.body() {
-            self.eval(&Some(node.clone()), self.env.clone());
+            self.eval(dom, &Some(node.clone()), self.env.clone());
+        }
+        self.perform_microtask_checkpoint(dom);
+    }
+
+    /// `target` にイベントを送り、登録されている JavaScript の関数をリスナーとして呼び出す
+    /// 既定の動作が取り消されなかった場合は true を返す
+    pub fn dispatch_event(
+        &mut self,
+        dom: &mut Dom,
+        target: EventTarget,
+        event: &Rc<RefCell<Event>>,
+    ) -> bool {
+        dispatch_event(dom, target, event, &mut |dom, listener, event| {
+            let event = RuntimeValue::Event {
+                event: event.clone(),
+                property: None,
+            };
+            self.call_function(dom, &listener.callback(), [event].to_vec());
+            // リスナーの実行が終わるたびに、リスナーによる DOM ツリーの変更を届ける
+            self.perform_microtask_checkpoint(dom);
+        })
+    }
+
+    /// MutationObserver にたまっている変更の記録を、コールバックを呼び出して届ける
+    /// コールバックの中で DOM ツリーが変更された場合は、その記録も続けて届ける
+    /// https://html.spec.whatwg.org/multipage/webappapis.html#perform-a-microtask-checkpoint
+    pub fn perform_microtask_checkpoint(&mut self, dom: &mut Dom) {
+        loop {
+            let observers: Vec<Rc<RefCell<MutationObserver>>> = self
+                .mutation_observers
+                .iter()
+                .filter(|o| RefCell::borrow(o).has_records())
+                .cloned()
+                .collect();
+            if observers.is_empty() {
+                return;
+            }
+
+            // https://dom.spec.whatwg.org/#notify-mutation-observers
+            for observer in observers {
+                let records = observer.borrow_mut().take_records();
+                let callback = RefCell::borrow(&observer).callback();
+                let args = [
+                    RuntimeValue::MutationRecords {
+                        records,
+                        property: None,
+                    },
+                    RuntimeValue::MutationObserver {
+                        observer,
+                        property: None,
+                    },
+                ];
+                self.call_function(dom, &callback, args.to_vec());
+            }
+        }
+    }
+
+    /// 定義済みの関数を、評価済みの引数で呼び出す
+    /// 関数が存在しない場合は何もせずに None を返す
+    fn call_function(
+        &mut self,
+        dom: &mut Dom,
+        name: &str,
+        args: Vec<RuntimeValue>,
+    ) -> Option<RuntimeValue> {
+        let function = self.functions.iter().rev().find(|f| f.id == name)?.clone();
+
+        let new_env = Rc::new(RefCell::new(Environment::new(Some(self.env.clone()))));
+        // 引数が足りない場合、残りの引数は値を持たない
+        for (i, param) in function.params.iter().enumerate() {
+            if let Some(Node::Identifier(param)) = param.as_deref() {
+                new_env
+                    .borrow_mut()
+                    .add_variable(param.to_string(), args.get(i).cloned());
+            }
         }
+
+        self.eval(dom, &function.body, new_env)
     }
 
     fn eval(
         &mut self,
+        dom: &mut Dom,
         node: &Option<Rc<Node>>,
         env: Rc<RefCell<Environment>>,
     ) -> Option<RuntimeValue> {
@@ -106,17 +230,17 @@ impl JsRuntime {
         };
 
         match node.borrow() {
-            Node::ExpressionStatement(expr) => return self.eval(&expr, env.clone()),
+            Node::ExpressionStatement(expr) => return self.eval(dom, &expr, env.clone()),
             Node::AdditiveExpression {
                 operator,
                 left,
                 right,
             } => {
-                let left_value = match self.eval(&left, env.clone()) {
+                let left_value = match self.eval(dom, &left, env.clone()) {
                     Some(value) => value,
                     None => return None,
                 };
-                let right_value = match self.eval(&right, env.clone()) {
+                let right_value = match self.eval(dom, &right, env.clone()) {
                     Some(value) => value,
                     None => return None,
                 };
@@ -140,53 +264,174 @@ impl JsRuntime {
                 // 変数の再割り当て
                 if let Some(node) = left {
                     if let Node::Identifier(id) = node.borrow() {
-                        let new_value = self.eval(right, env.clone());
+                        let new_value = self.eval(dom, right, env.clone());
                         env.borrow_mut().update_variable(id.to_string(), new_value);
                         return None;
                     }
                 }
 
-                // もし左辺の値が DOM ツリーのノードを表す HtmlElement ならば、DOM ツリーを更新する
-                if let Some(RuntimeValue::HtmlElement { object, property }) =
-                    self.eval(left, env.clone())
+                // もし左辺の値が DOM ツリーのノードのプロパティならば、DOM ツリーを更新する
+                if let Some(RuntimeValue::HtmlElement {
+                    object,
+                    property: Some(p),
+                }) = self.eval_member_target(dom, left, env.clone())
                 {
-                    let right_value = match self.eval(right, env.clone()) {
+                    let right_value = match self.eval(dom, right, env.clone()) {
                         Some(value) => value,
                         None => return None,
                     };
 
-                    if let Some(p) = property {
-                        // target.textContent = "foobar"; のようにノードのテキストを更新する
-                        if p == "textContent" {
-                            object
-                                .borrow_mut()
-                                .set_first_child(Some(Rc::new(RefCell::new(DomNode::new(
-                                    DomNodeKind::Text(right_value.to_string()),
-                                )))));
-                        }
+                    // target.innerHTML = "<p>foo</p>"; のようにノードの子孫を更新する
+                    if p == "innerHTML" {
+                        set_inner_html(dom, object, right_value.to_string());
+                    }
+
+                    // target.textContent = "foobar"; のようにノードのテキストを更新する
+                    if p == "textContent" {
+                        set_text_content(dom, object, right_value.to_string());
+                    }
+
+                    // target.className = "foo"; のように属性を更新する
+                    if p == "className" || p == "id" {
+                        let name = if p == "className" { "class" } else { "id" };
+                        set_attribute(dom, object, name, &right_value.to_string());
+                    }
+
+                    // target.dataset.fooBar = "baz"; のように data-* 属性を更新する
+                    if let Some(key) = p.strip_prefix("dataset.") {
+                        modify_element(dom, object, |e| e.set_data(key, &right_value.to_string()));
+                    }
+
+                    // document.title = "foobar"; のように文書のタイトルを更新する
+                    if p == "title" && dom.get(object).kind == DomNodeKind::Document {
+                        set_document_title(dom, object, right_value.to_string());
                     }
                 }
                 None
             }
             Node::MemberExpression { object, property } => {
-                let object_value = match self.eval(object, env.clone()) {
+                let object_value = match self.eval(dom, object, env.clone()) {
                     Some(value) => value,
                     None => return None,
                 };
-                let property_value = match self.eval(property, env.clone()) {
+                let property_value = match self.eval(dom, property, env.clone()) {
                     Some(value) => value,
                     // プロパティが存在しないため、`object_value`をここで返す
                     None => return Some(object_value),
                 };
                 // もしオブジェクトがDOMノードの場合、HtmlElement の property を更新する
                 if let RuntimeValue::HtmlElement { object, property } = object_value {
-                    assert!(property.is_none());
+                    // target.classList.add のようにプロパティが続く場合は、"classList.add" のように
+                    // "." でつなげた名前を property とする
+                    let name = match property {
+                        Some(p) => format!("{}.{}", p, property_value),
+                        None => property_value.to_string(),
+                    };
+                    // target.innerHTML のようなプロパティの読み出しは、その値を返す
+                    if let Some(value) = self.get_dom_property(dom, object, &name) {
+                        return Some(value);
+                    }
                     // HtmlElement の property に property_value の文字列をセットする
                     return Some(RuntimeValue::HtmlElement {
                         object,
+                        property: Some(name),
+                    });
+                }
+                // list.length や list[0] のようなノードのリストのプロパティの読み出しは、その値を返す
+                if let RuntimeValue::NodeList { .. } | RuntimeValue::HtmlCollection { .. } =
+                    object_value
+                {
+                    return object_value.list_property(dom, property_value.to_string());
+                }
+                // records.length や records[0] のような変更の記録のリストのプロパティの読み出しは、その値を返す
+                if let RuntimeValue::MutationRecords { .. } = object_value {
+                    return object_value.list_property(dom, property_value.to_string());
+                }
+                // event.type のようなイベントのプロパティの読み出しは、その値を返す
+                if let RuntimeValue::Event { event, property: _ } = object_value {
+                    return Some(Self::get_event_property(event, property_value.to_string()));
+                }
+                // record.type のような変更の記録のプロパティの読み出しは、その値を返す
+                if let RuntimeValue::MutationRecord(record) = object_value {
+                    return Some(Self::get_record_property(
+                        &record,
+                        &property_value.to_string(),
+                    ));
+                }
+                // walker.currentNode のようなプロパティの読み出しは、その値を返す
+                // walker.nextNode のようなメソッドは、メソッド呼び出しのために property にセットする
+                if let RuntimeValue::TreeWalker { walker, .. } = object_value {
+                    let property = property_value.to_string();
+                    let node = match property.as_str() {
+                        "root" => RefCell::borrow(&walker).root(),
+                        "currentNode" => RefCell::borrow(&walker).current_node(),
+                        _ => {
+                            return Some(RuntimeValue::TreeWalker {
+                                walker,
+                                property: Some(property),
+                            })
+                        }
+                    };
+                    return Some(self.node_value(Some(node)));
+                }
+                if let RuntimeValue::NodeIterator { iterator, .. } = object_value {
+                    let property = property_value.to_string();
+                    let node = match property.as_str() {
+                        "root" => RefCell::borrow(&iterator).root(),
+                        "referenceNode" => RefCell::borrow(&iterator).reference_node(),
+                        _ => {
+                            return Some(RuntimeValue::NodeIterator {
+                                iterator,
+                                property: Some(property),
+                            })
+                        }
+                    };
+                    return Some(self.node_value(Some(node)));
+                }
+                // NodeFilter.SHOW_ELEMENT のような定数は、その値を返す
+                if object_value == RuntimeValue::StringLiteral("NodeFilter".to_string()) {
+                    let value = match property_value.to_string().as_str() {
+                        "SHOW_ALL" => Some(SHOW_ALL),
+                        "SHOW_ELEMENT" => Some(SHOW_ELEMENT),
+                        "SHOW_TEXT" => Some(SHOW_TEXT),
+                        "SHOW_DOCUMENT" => Some(SHOW_DOCUMENT),
+                        _ => None,
+                    };
+                    if let Some(value) = value {
+                        return Some(RuntimeValue::Number(value as u64));
+                    }
+                }
+                // observer.observe のようなメソッドは、メソッド呼び出しのために property にセットする
+                if let RuntimeValue::MutationObserver { observer, .. } = object_value {
+                    return Some(RuntimeValue::MutationObserver {
+                        observer,
                         property: Some(property_value.to_string()),
                     });
                 }
+                // options.childList のようなオブジェクトのプロパティの読み出しは、その値を返す
+                if let RuntimeValue::Object(properties) = object_value {
+                    let key = property_value.to_string();
+                    return properties
+                        .into_iter()
+                        .rev()
+                        .find(|(k, _)| k == &key)
+                        .map(|(_, v)| v);
+                }
+                // window.document のようなウィンドウのプロパティの読み出しは、その値を返す
+                if object_value == RuntimeValue::StringLiteral("window".to_string()) {
+                    if let Some(value) = self.get_window_property(dom, &property_value.to_string())
+                    {
+                        return Some(value);
+                    }
+                }
+                // document.title のような文書のプロパティの読み出しは、その値を返す
+                if object_value == RuntimeValue::StringLiteral("document".to_string()) {
+                    if let Some(value) =
+                        self.get_dom_property(dom, self.document, &property_value.to_string())
+                    {
+                        return Some(value);
+                    }
+                }
                 // document.getElementById は "document.getElementById"という一つの文字列として扱う。
                 // このメソッドへの呼び出しは、"document.getElementById"という名前の関数への呼び出しになる
                 return Some(
@@ -197,14 +442,14 @@ impl JsRuntime {
             Node::NumericLiteral(value) => Some(RuntimeValue::Number(*value)),
             Node::VariableDeclaration { declarations } => {
                 for declaration in declarations {
-                    self.eval(&declaration, env.clone());
+                    self.eval(dom, &declaration, env.clone());
                 }
                 None
             }
             Node::VariableDeclarator { id, init } => {
                 if let Some(node) = id {
                     if let Node::Identifier(id) = node.borrow() {
-                        let init = self.eval(&init, env.clone());
+                        let init = self.eval(dom, &init, env.clone());
                         env.borrow_mut().add_variable(id.to_string(), init);
                     }
                 }
@@ -222,13 +467,13 @@ impl JsRuntime {
             Node::BlockStatement { body } => {
                 let mut result: Option<RuntimeValue> = None;
                 for stmt in body {
-                    result = self.eval(&stmt, env.clone());
+                    result = self.eval(dom, &stmt, env.clone());
                 }
                 result
             }
-            Node::ReturnStatement { argument } => self.eval(&argument, env.clone()),
+            Node::ReturnStatement { argument } => self.eval(dom, &argument, env.clone()),
             Node::FunctionDeclaration { id, params, body } => {
-                if let Some(RuntimeValue::StringLiteral(id)) = self.eval(&id, env.clone()) {
+                if let Some(RuntimeValue::StringLiteral(id)) = self.eval(dom, &id, env.clone()) {
                     let cloned_body = match body {
                         Some(b) => Some(b.clone()),
                         None => None,
@@ -242,13 +487,14 @@ impl JsRuntime {
                 // 新しいスコープを作成する
                 let new_env = Rc::new(RefCell::new(Environment::new(Some(env))));
 
-                let callee_value = match self.eval(callee, new_env.clone()) {
+                let callee_value = match self.eval(dom, callee, new_env.clone()) {
                     Some(value) => value,
                     None => return None,
                 };
 
                 // ブラウザAPIの呼び出しを試みる
-                let api_result = self.call_browser_api(&callee_value, &arguments, new_env.clone());
+                let api_result =
+                    self.call_browser_api(dom, &callee_value, &arguments, new_env.clone());
                 if api_result.0 {
                     // もしブラウザAPIを呼び出していたら、ユーザーが定義した関数は実行しない
                     return api_result.1;
@@ -274,17 +520,241 @@ impl JsRuntime {
                 assert!(arguments.len() == function.params.len());
                 for (i, item) in arguments.iter().enumerate() {
                     if let Some(RuntimeValue::StringLiteral(name)) =
-                        self.eval(&function.params[i], new_env.clone())
+                        self.eval(dom, &function.params[i], new_env.clone())
                     {
-                        new_env
-                            .borrow_mut()
-                            .add_variable(name, self.eval(item, new_env.clone()));
+                        // 引数の式を評価している間は、スコープを変更できないので、先に評価する
+                        let value = self.eval(dom, item, new_env.clone());
+                        new_env.borrow_mut().add_variable(name, value);
                     }
                 }
 
                 // 関数の中身を新しいスコープとともに eval メソッドで解釈する
-                self.eval(&function.body.clone(), new_env.clone())
+                self.eval(dom, &function.body.clone(), new_env.clone())
+            }
+            Node::NewExpression { callee, arguments } => {
+                // 本書のブラウザでは、new で作成できるのは MutationObserver のみ
+                let callee_value = self.eval(dom, callee, env.clone())?;
+                if callee_value != RuntimeValue::StringLiteral("MutationObserver".to_string()) {
+                    return None;
+                }
+                let callback = self.eval(dom, arguments.first()?, env.clone())?.to_string();
+                let observer = MutationObserver::new(&callback);
+                self.mutation_observers.push(observer.clone());
+                Some(RuntimeValue::MutationObserver {
+                    observer,
+                    property: None,
+                })
+            }
+            Node::ObjectExpression { properties } => {
+                let mut object = Vec::new();
+                for property in properties {
+                    if let Some(Node::Property { key, value }) = property.as_deref() {
+                        let key = match key.as_deref() {
+                            Some(Node::Identifier(name)) | Some(Node::StringLiteral(name)) => {
+                                name.to_string()
+                            }
+                            _ => continue,
+                        };
+                        if let Some(value) = self.eval(dom, value, env.clone()) {
+                            object.push((key, value));
+                        }
+                    }
+                }
+                Some(RuntimeValue::Object(object))
             }
+            // キーと値の組は、オブジェクトリテラルの中でのみ評価される
+            Node::Property { .. } => None,
+        }
+    }
+
+    /// 代入式の左辺を評価する
+    /// `target.textContent` のようなメンバー式の場合、プロパティの値を読み出さずに、
+    /// 代入先となるノードとプロパティ名の組を HtmlElement として返す
+    fn eval_member_target(
+        &mut self,
+        dom: &mut Dom,
+        node: &Option<Rc<Node>>,
+        env: Rc<RefCell<Environment>>,
+    ) -> Option<RuntimeValue> {
+        if let Some(n) = node {
+            if let Node::MemberExpression { object, property } = n.borrow() {
+                let (object, prefix) = match self.eval(dom, object, env.clone()) {
+                    Some(RuntimeValue::HtmlElement { object, property }) => (object, property),
+                    // document.title = "..." のような代入では、文書ノードが代入先になる
+                    Some(RuntimeValue::StringLiteral(s)) if s == "document" => {
+                        (self.document, None)
+                    }
+                    _ => return self.eval(dom, node, env),
+                };
+                let property = self.eval(dom, property, env).map(|p| match prefix {
+                    // target.dataset.foo = "..." のような代入では、"dataset.foo" が代入先になる
+                    Some(prefix) => format!("{}.{}", prefix, p),
+                    None => p.to_string(),
+                });
+                return Some(RuntimeValue::HtmlElement { object, property });
+            }
+        }
+        self.eval(dom, node, env)
+    }
+
+    /// DOM ノードのプロパティの値を返す
+    /// 値を持たないプロパティ(メソッドなど)の場合は None を返す
+    fn get_dom_property(&self, dom: &Dom, object: NodeId, property: &str) -> Option<RuntimeValue> {
+        let is_document = dom.get(object).kind == DomNodeKind::Document;
+        match property {
+            "innerHTML" => Some(RuntimeValue::StringLiteral(serialize_children(dom, object))),
+            "outerHTML" => Some(RuntimeValue::StringLiteral(serialize_node(dom, object))),
+            "className" | "id" => {
+                let name = if property == "className" {
+                    "class"
+                } else {
+                    "id"
+                };
+                let element = dom.get(object).get_element()?;
+                Some(RuntimeValue::StringLiteral(
+                    element.get_attribute(name).unwrap_or_default(),
+                ))
+            }
+            "classList.length" => {
+                let element = dom.get(object).get_element()?;
+                Some(RuntimeValue::Number(element.class_list().len() as u64))
+            }
+            "title" if is_document => {
+                Some(RuntimeValue::StringLiteral(get_document_title(dom, object)))
+            }
+            "readyState" if is_document => {
+                let window = node_window(dom, object)?;
+                let ready_state = dom.window(window).ready_state();
+                Some(RuntimeValue::StringLiteral(ready_state.to_string()))
+            }
+            "ownerDocument" => Some(match dom.get(object).owner_document() {
+                Some(document) => self.document_value(document),
+                None => RuntimeValue::StringLiteral("null".to_string()),
+            }),
+            // 本書の JavaScript では、ウィンドウは "window" という文字列として扱う
+            "defaultView" if is_document => match node_window(dom, object) {
+                Some(_) => Some(RuntimeValue::StringLiteral("window".to_string())),
+                None => Some(RuntimeValue::StringLiteral("null".to_string())),
+            },
+            "children" | "childNodes" => {
+                let filter = if property == "children" {
+                    CollectionFilter::Children
+                } else {
+                    CollectionFilter::ChildNodes
+                };
+                Some(RuntimeValue::HtmlCollection {
+                    collection: HtmlCollection::new(object, filter),
+                    property: None,
+                })
+            }
+            _ => {
+                // target.dataset.fooBar は data-foo-bar 属性の値になる
+                let key = property.strip_prefix("dataset.")?;
+                let element = dom.get(object).get_element()?;
+                element.get_data(key).map(RuntimeValue::StringLiteral)
+            }
+        }
+    }
+
+    /// ウィンドウのプロパティの値を返す
+    /// 文書がウィンドウに表示されていない場合は None を返す
+    fn get_window_property(&self, dom: &Dom, property: &str) -> Option<RuntimeValue> {
+        let window = node_window(dom, self.document)?;
+        match property {
+            "document" => Some(self.document_value(dom.window(window).document())),
+            "window" | "self" => Some(RuntimeValue::StringLiteral("window".to_string())),
+            _ => None,
+        }
+    }
+
+    /// 文書ノードを JavaScript の値にする
+    /// スクリプトが実行されている文書は "document" という文字列として扱う
+    fn document_value(&self, document: NodeId) -> RuntimeValue {
+        if document == self.document {
+            return RuntimeValue::StringLiteral("document".to_string());
+        }
+        RuntimeValue::HtmlElement {
+            object: document,
+            property: None,
+        }
+    }
+
+    /// DOM ノードを JavaScript の値にする。ノードがない場合は "null" という文字列になる
+    fn node_value(&self, node: Option<NodeId>) -> RuntimeValue {
+        match node {
+            Some(object) if object == self.document => {
+                RuntimeValue::StringLiteral("document".to_string())
+            }
+            Some(object) => RuntimeValue::HtmlElement {
+                object,
+                property: None,
+            },
+            None => RuntimeValue::StringLiteral("null".to_string()),
+        }
+    }
+
+    /// スクリプトが実行されている文書に属する新しいノードを作成する
+    fn create_node(&self, dom: &mut Dom, kind: DomNodeKind) -> NodeId {
+        let node = dom.create_node(kind);
+        dom.get_mut(node).set_owner_document(Some(self.document));
+        node
+    }
+
+    /// イベントのプロパティの値を返す
+    /// 値を持たないプロパティ(メソッドなど)の場合は、メソッド呼び出しのために property にセットする
+    fn get_event_property(event: Rc<RefCell<Event>>, property: String) -> RuntimeValue {
+        let e = RefCell::borrow(&event).clone();
+        // 本書の JavaScript では、ウィンドウは "window" という文字列として扱う
+        let node = |t: Option<EventTarget>| match t {
+            Some(EventTarget::Node(object)) => RuntimeValue::HtmlElement {
+                object,
+                property: None,
+            },
+            Some(EventTarget::Window(_)) => RuntimeValue::StringLiteral("window".to_string()),
+            None => RuntimeValue::StringLiteral("null".to_string()),
+        };
+        match property.as_str() {
+            "type" => RuntimeValue::StringLiteral(e.event_type()),
+            "target" => node(e.target()),
+            "currentTarget" => node(e.current_target()),
+            "eventPhase" => RuntimeValue::Number(e.phase() as u64),
+            "bubbles" => RuntimeValue::StringLiteral(e.bubbles().to_string()),
+            "cancelable" => RuntimeValue::StringLiteral(e.cancelable().to_string()),
+            "defaultPrevented" => RuntimeValue::StringLiteral(e.default_prevented().to_string()),
+            _ => RuntimeValue::Event {
+                event,
+                property: Some(property),
+            },
+        }
+    }
+
+    /// MutationRecord のプロパティの値を返す
+    fn get_record_property(record: &MutationRecord, property: &str) -> RuntimeValue {
+        let node = |n: Option<NodeId>| match n {
+            Some(object) => RuntimeValue::HtmlElement {
+                object,
+                property: None,
+            },
+            None => RuntimeValue::StringLiteral("null".to_string()),
+        };
+        let string =
+            |s: Option<String>| RuntimeValue::StringLiteral(s.unwrap_or("null".to_string()));
+        match property {
+            "type" => RuntimeValue::StringLiteral(record.record_type().to_string()),
+            "target" => node(Some(record.target())),
+            "addedNodes" => RuntimeValue::NodeList {
+                nodes: record.added_nodes(),
+                property: None,
+            },
+            "removedNodes" => RuntimeValue::NodeList {
+                nodes: record.removed_nodes(),
+                property: None,
+            },
+            "previousSibling" => node(record.previous_sibling()),
+            "nextSibling" => node(record.next_sibling()),
+            "attributeName" => string(record.attribute_name()),
+            "oldValue" => string(record.old_value()),
+            _ => RuntimeValue::StringLiteral("undefined".to_string()),
         }
     }
 
@@ -293,16 +763,17 @@ impl JsRuntime {
     /// Option<RuntimeValue>: ブラウザAPIのよびだしによって得られた結果
     fn call_browser_api(
         &mut self,
+        dom: &mut Dom,
         func: &RuntimeValue,
         argments: &[Option<Rc<Node>>],
         env: Rc<RefCell<Environment>>,
     ) -> (bool, Option<RuntimeValue>) {
         if func == &RuntimeValue::StringLiteral("document.getElementById".to_string()) {
-            let arg = match self.eval(&argments[0], env.clone()) {
+            let arg = match self.eval(dom, &argments[0], env.clone()) {
                 Some(a) => a,
                 None => return (true, None),
             };
-            let target = match get_element_by_id(Some(self.dom_root.clone()), &arg.to_string()) {
+            let target = match get_element_by_id(dom, Some(self.document), &arg.to_string()) {
                 Some(n) => n,
                 None => return (true, None),
             };
@@ -315,8 +786,476 @@ impl JsRuntime {
             );
         }
 
+        if func == &RuntimeValue::StringLiteral("document.createElement".to_string()) {
+            let tag = match self.eval(dom, &argments[0], env.clone()) {
+                Some(t) => t.to_string().to_ascii_lowercase(),
+                None => return (true, None),
+            };
+            // 本書のブラウザが知らない要素は作成できない
+            if ElementKind::from_str(&tag).is_err() {
+                return (true, None);
+            }
+            let element = DomNodeKind::Element(Element::new(&tag, Vec::new()));
+            return (
+                true,
+                Some(RuntimeValue::HtmlElement {
+                    object: self.create_node(dom, element),
+                    property: None,
+                }),
+            );
+        }
+
+        if func == &RuntimeValue::StringLiteral("document.createTextNode".to_string()) {
+            let text = match self.eval(dom, &argments[0], env.clone()) {
+                Some(t) => t.to_string(),
+                None => return (true, None),
+            };
+            return (
+                true,
+                Some(RuntimeValue::HtmlElement {
+                    object: self.create_node(dom, DomNodeKind::Text(text)),
+                    property: None,
+                }),
+            );
+        }
+
+        if func == &RuntimeValue::StringLiteral("document.querySelector".to_string())
+            || func == &RuntimeValue::StringLiteral("document.querySelectorAll".to_string())
+        {
+            let root = self.document;
+            let method = func.to_string().replace("document.", "");
+            return (true, self.query_selector(dom, root, &method, argments, env));
+        }
+
+        if func == &RuntimeValue::StringLiteral("document.getElementsByTagName".to_string())
+            || func == &RuntimeValue::StringLiteral("document.getElementsByClassName".to_string())
+        {
+            let root = self.document;
+            let method = func.to_string().replace("document.", "");
+            return (
+                true,
+                self.get_elements_by(dom, root, &method, argments, env),
+            );
+        }
+
+        if func == &RuntimeValue::StringLiteral("document.addEventListener".to_string())
+            || func == &RuntimeValue::StringLiteral("document.removeEventListener".to_string())
+        {
+            let root = EventTarget::Node(self.document);
+            let method = func.to_string().replace("document.", "");
+            self.update_event_listener(dom, root, &method, argments, env);
+            return (true, None);
+        }
+
+        if func == &RuntimeValue::StringLiteral("window.addEventListener".to_string())
+            || func == &RuntimeValue::StringLiteral("window.removeEventListener".to_string())
+        {
+            if let Some(window) = node_window(dom, self.document) {
+                let method = func.to_string().replace("window.", "");
+                self.update_event_listener(
+                    dom,
+                    EventTarget::Window(window),
+                    &method,
+                    argments,
+                    env,
+                );
+            }
+            return (true, None);
+        }
+
+        // 本書のブラウザでは、3番目の引数の filter には対応せず、whatToShow でのみノードを選別する
+        if func == &RuntimeValue::StringLiteral("document.createTreeWalker".to_string())
+            || func == &RuntimeValue::StringLiteral("document.createNodeIterator".to_string())
+        {
+            let root = match self.eval_dom_node(dom, argments.first(), env.clone()) {
+                Some(r) => r,
+                None => return (true, None),
+            };
+            let what_to_show = match argments.get(1) {
+                Some(arg) => match self.eval(dom, arg, env.clone()) {
+                    Some(RuntimeValue::Number(n)) => n as u32,
+                    _ => SHOW_ALL,
+                },
+                None => SHOW_ALL,
+            };
+            if func.to_string() == "document.createTreeWalker" {
+                return (
+                    true,
+                    Some(RuntimeValue::TreeWalker {
+                        walker: Rc::new(RefCell::new(TreeWalker::new(root, what_to_show, None))),
+                        property: None,
+                    }),
+                );
+            }
+            return (
+                true,
+                Some(RuntimeValue::NodeIterator {
+                    iterator: Rc::new(RefCell::new(NodeIterator::new(root, what_to_show, None))),
+                    property: None,
+                }),
+            );
+        }
+
+        // walker.nextNode() のように TreeWalker のメソッドを呼び出す
+        if let RuntimeValue::TreeWalker {
+            walker,
+            property: Some(method),
+        } = func
+        {
+            let mut walker = walker.borrow_mut();
+            let node = match method.as_str() {
+                "parentNode" => walker.parent_node(dom),
+                "firstChild" => walker.first_child(dom),
+                "lastChild" => walker.last_child(dom),
+                "previousSibling" => walker.previous_sibling(dom),
+                "nextSibling" => walker.next_sibling(dom),
+                "previousNode" => walker.previous_node(dom),
+                "nextNode" => walker.next_node(dom),
+                _ => return (false, None),
+            };
+            return (true, Some(self.node_value(node)));
+        }
+
+        // iterator.nextNode() のように NodeIterator のメソッドを呼び出す
+        if let RuntimeValue::NodeIterator {
+            iterator,
+            property: Some(method),
+        } = func
+        {
+            let mut iterator = iterator.borrow_mut();
+            let node = match method.as_str() {
+                "previousNode" => iterator.previous_node(dom),
+                "nextNode" => iterator.next_node(dom),
+                _ => return (false, None),
+            };
+            return (true, Some(self.node_value(node)));
+        }
+
+        // observer.observe(target, options) のように MutationObserver のメソッドを呼び出す
+        if let RuntimeValue::MutationObserver {
+            observer,
+            property: Some(method),
+        } = func
+        {
+            match method.as_str() {
+                "observe" => {
+                    let target = match self.eval_dom_node(dom, argments.first(), env.clone()) {
+                        Some(t) => t,
+                        None => return (true, None),
+                    };
+                    let options = match argments.get(1) {
+                        Some(arg) => self.eval(dom, arg, env.clone()),
+                        None => None,
+                    };
+                    let option = |name: &str| match &options {
+                        Some(RuntimeValue::Object(properties)) => properties
+                            .iter()
+                            .any(|(k, v)| k == name && v.to_string() == "true"),
+                        _ => false,
+                    };
+                    let init = MutationObserverInit {
+                        child_list: option("childList"),
+                        attributes: option("attributes"),
+                        character_data: option("characterData"),
+                        subtree: option("subtree"),
+                        attribute_old_value: option("attributeOldValue"),
+                        character_data_old_value: option("characterDataOldValue"),
+                    };
+                    // 不正な設定が渡された場合、本来は例外を投げるが、本書のブラウザでは何もしない
+                    let _ = observe(dom, observer, target, init);
+                    return (true, None);
+                }
+                "disconnect" => {
+                    disconnect(dom, observer);
+                    return (true, None);
+                }
+                "takeRecords" => {
+                    let records = observer.borrow_mut().take_records();
+                    return (
+                        true,
+                        Some(RuntimeValue::MutationRecords {
+                            records,
+                            property: None,
+                        }),
+                    );
+                }
+                _ => return (false, None),
+            }
+        }
+
+        // event.preventDefault() のようにイベントのメソッドを呼び出す
+        if let RuntimeValue::Event {
+            event,
+            property: Some(method),
+        } = func
+        {
+            let mut event = event.borrow_mut();
+            match method.as_str() {
+                "preventDefault" => event.prevent_default(),
+                "stopPropagation" => event.stop_propagation(),
+                "stopImmediatePropagation" => event.stop_immediate_propagation(),
+                _ => return (false, None),
+            }
+            return (true, None);
+        }
+
+        // list.item(0) のようにノードのリストから要素を取り出す
+        if let RuntimeValue::NodeList {
+            property: Some(method),
+            ..
+        }
+        | RuntimeValue::HtmlCollection {
+            property: Some(method),
+            ..
+        } = func
+        {
+            if method != "item" {
+                return (false, None);
+            }
+            let index = match argments.first() {
+                Some(arg) => match self.eval(dom, arg, env.clone()) {
+                    Some(RuntimeValue::Number(i)) => i as usize,
+                    _ => return (true, None),
+                },
+                None => return (true, None),
+            };
+            return (true, func.list_property(dom, index.to_string()));
+        }
+
+        if let RuntimeValue::HtmlElement {
+            object,
+            property: Some(method),
+        } = func
+        {
+            let object = *object;
+            // DOM ツリーを変更するメソッドは、本来は不正な操作に対して例外を投げるが、
+            // 本書のブラウザでは何もせずに None を返す
+            let result = match method.as_str() {
+                "appendChild" => match self.eval_dom_node(dom, argments.first(), env.clone()) {
+                    Some(node) => append_child(dom, object, node).ok(),
+                    None => None,
+                },
+                "insertBefore" => {
+                    let node = self.eval_dom_node(dom, argments.first(), env.clone());
+                    let child = self.eval_dom_node(dom, argments.get(1), env.clone());
+                    match node {
+                        Some(node) => insert_before(dom, object, node, child).ok(),
+                        None => None,
+                    }
+                }
+                "removeChild" => match self.eval_dom_node(dom, argments.first(), env.clone()) {
+                    Some(child) => remove_child(dom, object, child).ok(),
+                    None => None,
+                },
+                "replaceChild" => {
+                    let node = self.eval_dom_node(dom, argments.first(), env.clone());
+                    let child = self.eval_dom_node(dom, argments.get(1), env.clone());
+                    match (node, child) {
+                        (Some(node), Some(child)) => replace_child(dom, object, node, child).ok(),
+                        _ => None,
+                    }
+                }
+                "cloneNode" => {
+                    let deep = match argments.first() {
+                        Some(arg) => self
+                            .eval(dom, arg, env.clone())
+                            .map_or(false, |d| d.to_string() == "true"),
+                        None => false,
+                    };
+                    Some(clone_node(dom, object, deep))
+                }
+                "getAttribute" | "hasAttribute" | "removeAttribute" | "classList.contains"
+                | "classList.add" | "classList.remove" | "classList.toggle" => {
+                    let name = match self.eval(dom, &argments[0], env.clone()) {
+                        Some(n) => n.to_string(),
+                        None => return (true, None),
+                    };
+                    // 属性を変更するメソッドの場合は、その変更が MutationObserver に記録される
+                    let result = modify_element(dom, object, |element| {
+                        // 本書の JavaScript には真偽値がないので、true と false は文字列として返す
+                        match method.as_str() {
+                            "getAttribute" => element.get_attribute(&name),
+                            "hasAttribute" => Some(element.has_attribute(&name).to_string()),
+                            "classList.contains" => Some(element.contains_class(&name).to_string()),
+                            "classList.toggle" => Some(element.toggle_class(&name).to_string()),
+                            "removeAttribute" => {
+                                element.remove_attribute(&name);
+                                None
+                            }
+                            "classList.add" => {
+                                element.add_class(&name);
+                                None
+                            }
+                            _ => {
+                                element.remove_class(&name);
+                                None
+                            }
+                        }
+                    });
+                    return (true, result.flatten().map(RuntimeValue::StringLiteral));
+                }
+                "setAttribute" => {
+                    if argments.len() < 2 {
+                        return (true, None);
+                    }
+                    let name = match self.eval(dom, &argments[0], env.clone()) {
+                        Some(n) => n.to_string(),
+                        None => return (true, None),
+                    };
+                    let value = match self.eval(dom, &argments[1], env.clone()) {
+                        Some(v) => v.to_string(),
+                        None => return (true, None),
+                    };
+                    set_attribute(dom, object, &name, &value);
+                    None
+                }
+                "querySelector" | "querySelectorAll" => {
+                    return (
+                        true,
+                        self.query_selector(dom, object, method, argments, env),
+                    );
+                }
+                "getElementsByTagName" | "getElementsByClassName" => {
+                    return (
+                        true,
+                        self.get_elements_by(dom, object, method, argments, env),
+                    );
+                }
+                "addEventListener" | "removeEventListener" => {
+                    let target = EventTarget::Node(object);
+                    self.update_event_listener(dom, target, method, argments, env);
+                    None
+                }
+                "insertAdjacentHTML" => {
+                    if argments.len() < 2 {
+                        return (true, None);
+                    }
+                    let position = match self.eval(dom, &argments[0], env.clone()) {
+                        Some(p) => p.to_string(),
+                        None => return (true, None),
+                    };
+                    let html = match self.eval(dom, &argments[1], env.clone()) {
+                        Some(h) => h.to_string(),
+                        None => return (true, None),
+                    };
+                    // 不正な位置が指定された場合、本来は例外を投げるが、本書のブラウザでは何もしない
+                    let _ = insert_adjacent_html(dom, object, &position, html);
+                    None
+                }
+                _ => return (false, None),
+            };
+
+            return (
+                true,
+                result.map(|node| RuntimeValue::HtmlElement {
+                    object: node,
+                    property: None,
+                }),
+            );
+        }
+
         (false, None)
     }
+
+    /// querySelector と querySelectorAll を呼び出す
+    /// 不正なセレクタが渡された場合、本来は例外を投げるが、本書のブラウザでは None を返す
+    fn query_selector(
+        &mut self,
+        dom: &mut Dom,
+        root: NodeId,
+        method: &str,
+        argments: &[Option<Rc<Node>>],
+        env: Rc<RefCell<Environment>>,
+    ) -> Option<RuntimeValue> {
+        let selectors = self.eval(dom, argments.first()?, env)?.to_string();
+        if method == "querySelector" {
+            return query_selector(dom, root, &selectors).ok()?.map(|node| {
+                RuntimeValue::HtmlElement {
+                    object: node,
+                    property: None,
+                }
+            });
+        }
+        Some(RuntimeValue::NodeList {
+            nodes: query_selector_all(dom, root, &selectors).ok()?,
+            property: None,
+        })
+    }
+
+    /// getElementsByTagName と getElementsByClassName を呼び出す
+    /// 返されるコレクションは、その後の DOM ツリーの変更も反映する
+    fn get_elements_by(
+        &mut self,
+        dom: &mut Dom,
+        root: NodeId,
+        method: &str,
+        argments: &[Option<Rc<Node>>],
+        env: Rc<RefCell<Environment>>,
+    ) -> Option<RuntimeValue> {
+        let arg = self.eval(dom, argments.first()?, env)?.to_string();
+        let collection = if method == "getElementsByTagName" {
+            HtmlCollection::new(root, CollectionFilter::TagName(arg))
+        } else {
+            HtmlCollection::by_class_names(root, &arg)
+        };
+        Some(RuntimeValue::HtmlCollection {
+            collection,
+            property: None,
+        })
+    }
+
+    /// addEventListener と removeEventListener を呼び出す
+    /// リスナーには関数名を渡す。3番目の引数が true の場合は、キャプチャフェーズで呼ばれるリスナーになる
+    fn update_event_listener(
+        &mut self,
+        dom: &mut Dom,
+        target: EventTarget,
+        method: &str,
+        argments: &[Option<Rc<Node>>],
+        env: Rc<RefCell<Environment>>,
+    ) {
+        if argments.len() < 2 {
+            return;
+        }
+        let event_type = match self.eval(dom, &argments[0], env.clone()) {
+            Some(t) => t.to_string(),
+            None => return,
+        };
+        let callback = match self.eval(dom, &argments[1], env.clone()) {
+            Some(c) => c.to_string(),
+            None => return,
+        };
+        let capture = match argments.get(2) {
+            Some(arg) => self
+                .eval(dom, arg, env.clone())
+                .map_or(false, |c| c.to_string() == "true"),
+            None => false,
+        };
+
+        let listener = EventListener::new(&event_type, &callback, capture);
+        if method == "addEventListener" {
+            target.add_event_listener(dom, listener);
+        } else {
+            target.remove_event_listener(dom, &listener);
+        }
+    }
+
+    /// DOM ノードを引数にとるブラウザAPIのために、引数を評価して DOM ノードを返す
+    fn eval_dom_node(
+        &mut self,
+        dom: &mut Dom,
+        arg: Option<&Option<Rc<Node>>>,
+        env: Rc<RefCell<Environment>>,
+    ) -> Option<NodeId> {
+        match self.eval(dom, arg?, env) {
+            Some(RuntimeValue::HtmlElement {
+                object,
+                property: None,
+            }) => Some(object),
+            Some(RuntimeValue::StringLiteral(s)) if s == "document" => Some(self.document),
+            _ => None,
+        }
+    }
 }
 
 #[derive(Debug, Clone, PartialEq)]
@@ -324,11 +1263,104 @@ pub enum RuntimeValue {
     Number(u64),
     StringLiteral(String),
     HtmlElement {
-        object: Rc<RefCell<DomNode>>,
+        object: NodeId,
+        property: Option<String>,
+    },
+    /// querySelectorAll などが返す DOM ノードのリスト
+    NodeList {
+        nodes: Vec<NodeId>,
+        property: Option<String>,
+    },
+    /// getElementsByTagName などが返す、DOM ツリーの変更を反映するノードのリスト
+    HtmlCollection {
+        collection: HtmlCollection,
+        property: Option<String>,
+    },
+    /// イベントリスナーに渡されるイベント
+    Event {
+        event: Rc<RefCell<Event>>,
+        property: Option<String>,
+    },
+    /// { childList: true } のようなオブジェクトリテラル
+    Object(Vec<(String, RuntimeValue)>),
+    /// new MutationObserver で作成された MutationObserver
+    MutationObserver {
+        observer: Rc<RefCell<MutationObserver>>,
+        property: Option<String>,
+    },
+    /// MutationObserver のコールバックに渡される変更の記録のリスト
+    MutationRecords {
+        records: Vec<MutationRecord>,
+        property: Option<String>,
+    },
+    MutationRecord(MutationRecord),
+    /// document.createTreeWalker で作成された TreeWalker
+    TreeWalker {
+        walker: Rc<RefCell<TreeWalker>>,
+        property: Option<String>,
+    },
+    /// document.createNodeIterator で作成された NodeIterator
+    NodeIterator {
+        iterator: Rc<RefCell<NodeIterator>>,
         property: Option<String>,
     },
 }
 
+impl RuntimeValue {
+    /// NodeList や HtmlCollection のプロパティを読み出す
+    /// length はノードの数、数字は添字の位置のノードになる。それ以外はメソッド呼び出しのために property にセットする
+    fn list_property(&self, dom: &Dom, name: String) -> Option<RuntimeValue> {
+        if let RuntimeValue::MutationRecords {
+            records,
+            property: _,
+        } = self
+        {
+            if name == "length" {
+                return Some(RuntimeValue::Number(records.len() as u64));
+            }
+            let index = name.parse::<usize>().ok()?;
+            return records
+                .get(index)
+                .cloned()
+                .map(RuntimeValue::MutationRecord);
+        }
+
+        let nodes = match self {
+            RuntimeValue::NodeList { nodes, property: _ } => nodes.clone(),
+            RuntimeValue::HtmlCollection {
+                collection,
+                property: _,
+            } => collection.nodes(dom),
+            _ => return None,
+        };
+
+        if name == "length" {
+            return Some(RuntimeValue::Number(nodes.len() as u64));
+        }
+        if let Ok(index) = name.parse::<usize>() {
+            return nodes.get(index).map(|node| RuntimeValue::HtmlElement {
+                object: *node,
+                property: None,
+            });
+        }
+
+        match self {
+            RuntimeValue::NodeList { nodes, property: _ } => Some(RuntimeValue::NodeList {
+                nodes: nodes.clone(),
+                property: Some(name),
+            }),
+            RuntimeValue::HtmlCollection {
+                collection,
+                property: _,
+            } => Some(RuntimeValue::HtmlCollection {
+                collection: collection.clone(),
+                property: Some(name),
+            }),
+            _ => None,
+        }
+    }
+}
+
 impl Add<RuntimeValue> for RuntimeValue {
     type Output = RuntimeValue;
 
@@ -364,6 +1396,52 @@ impl Display for RuntimeValue {
             } => {
                 format!("HtmlElement: {:#?}", object)
             }
+            RuntimeValue::NodeList { nodes, property: _ } => {
+                format!("NodeList: {:#?}", nodes)
+            }
+            RuntimeValue::HtmlCollection {
+                collection,
+                property: _,
+            } => {
+                format!("HtmlCollection: {:#?}", collection)
+            }
+            RuntimeValue::Event { event, property: _ } => {
+                format!("Event: {}", RefCell::borrow(event).event_type())
+            }
+            RuntimeValue::Object(properties) => {
+                let properties: Vec<String> = properties
+                    .iter()
+                    .map(|(k, v)| format!("{}: {}", k, v))
+                    .collect();
+                format!("{{{}}}", properties.join(", "))
+            }
+            RuntimeValue::MutationObserver {
+                observer,
+                property: _,
+            } => {
+                format!("MutationObserver: {}", RefCell::borrow(observer).callback())
+            }
+            RuntimeValue::MutationRecords {
+                records,
+                property: _,
+            } => {
+                format!("MutationRecords: {}", records.len())
+            }
+            RuntimeValue::MutationRecord(record) => {
+                format!("MutationRecord: {}", record.record_type())
+            }
+            RuntimeValue::TreeWalker {
+                walker,
+                property: _,
+            } => {
+                format!("TreeWalker: {:#?}", RefCell::borrow(walker).root())
+            }
+            RuntimeValue::NodeIterator {
+                iterator,
+                property: _,
+            } => {
+                format!("NodeIterator: {:#?}", RefCell::borrow(iterator).root())
+            }
         };
         write!(f, "{}", s)
     }
@@ -372,23 +1450,26 @@ impl Display for RuntimeValue {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::renderer::html::parser::HtmlParser;
+    use crate::renderer::html::token::HtmlTokenizer;
     use crate::renderer::js::ast::JsParser;
     use crate::renderer::js::token::JsLexer;
     use alloc::string::ToString;
 
     #[test]
     fn test_num() {
-        let dom = Rc::new(RefCell::new(DomNode::new(DomNodeKind::Document)));
+        let mut dom = Dom::new();
+        let document = dom.create_document();
         let input = "42".to_string();
         let lexer = JsLexer::new(input);
         let mut parser = JsParser::new(lexer);
         let ast = parser.parse_ast();
-        let mut runtime = JsRuntime::new(dom);
+        let mut runtime = JsRuntime::new(document);
         let expected = [Some(RuntimeValue::Number(42))];
         let mut i = 0;
 
         for node in ast.body() {
-            let result = runtime.eval(&Some(node.clone()), runtime.env.clone());
+            let result = runtime.eval(&mut dom, &Some(node.clone()), runtime.env.clone());
             assert_eq!(expected[i], result);
             i += 1;
         }
@@ -396,17 +1477,18 @@ mod tests {
 
     #[test]
     fn test_add_nums() {
-        let dom = Rc::new(RefCell::new(DomNode::new(DomNodeKind::Document)));
+        let mut dom = Dom::new();
+        let document = dom.create_document();
         let input = "1 + 2".to_string();
         let lexer = JsLexer::new(input);
         let mut parser = JsParser::new(lexer);
         let ast = parser.parse_ast();
-        let mut runtime = JsRuntime::new(dom);
+        let mut runtime = JsRuntime::new(document);
         let expected = [Some(RuntimeValue::Number(3))];
         let mut i = 0;
 
         for node in ast.body() {
-            let result = runtime.eval(&Some(node.clone()), runtime.env.clone());
+            let result = runtime.eval(&mut dom, &Some(node.clone()), runtime.env.clone());
             assert_eq!(expected[i], result);
             i += 1;
         }
@@ -414,17 +1496,18 @@ mod tests {
 
     #[test]
     fn test_sub_nums() {
-        let dom = Rc::new(RefCell::new(DomNode::new(DomNodeKind::Document)));
+        let mut dom = Dom::new();
+        let document = dom.create_document();
         let input = "2 - 1".to_string();
         let lexer = JsLexer::new(input);
         let mut parser = JsParser::new(lexer);
         let ast = parser.parse_ast();
-        let mut runtime = JsRuntime::new(dom);
+        let mut runtime = JsRuntime::new(document);
         let expected = [Some(RuntimeValue::Number(1))];
         let mut i = 0;
 
         for node in ast.body() {
-            let result = runtime.eval(&Some(node.clone()), runtime.env.clone());
+            let result = runtime.eval(&mut dom, &Some(node.clone()), runtime.env.clone());
             assert_eq!(expected[i], result);
             i += 1;
         }
@@ -432,17 +1515,18 @@ mod tests {
 
     #[test]
     fn test_assign_variable() {
-        let dom = Rc::new(RefCell::new(DomNode::new(DomNodeKind::Document)));
+        let mut dom = Dom::new();
+        let document = dom.create_document();
         let input = "var foo=42;".to_string();
         let lexer = JsLexer::new(input);
         let mut parser = JsParser::new(lexer);
         let ast = parser.parse_ast();
-        let mut runtime = JsRuntime::new(dom);
+        let mut runtime = JsRuntime::new(document);
         let expected = [None];
         let mut i = 0;
 
         for node in ast.body() {
-            let result = runtime.eval(&Some(node.clone()), runtime.env.clone());
+            let result = runtime.eval(&mut dom, &Some(node.clone()), runtime.env.clone());
             assert_eq!(expected[i], result);
             i += 1;
         }
@@ -450,17 +1534,18 @@ mod tests {
 
     #[test]
     fn test_add_variable_and_num() {
-        let dom = Rc::new(RefCell::new(DomNode::new(DomNodeKind::Document)));
+        let mut dom = Dom::new();
+        let document = dom.create_document();
         let input = "var foo=42; foo+1".to_string();
         let lexer = JsLexer::new(input);
         let mut parser = JsParser::new(lexer);
         let ast = parser.parse_ast();
-        let mut runtime = JsRuntime::new(dom);
+        let mut runtime = JsRuntime::new(document);
         let expected = [None, Some(RuntimeValue::Number(43))];
         let mut i = 0;
 
         for node in ast.body() {
-            let result = runtime.eval(&Some(node.clone()), runtime.env.clone());
+            let result = runtime.eval(&mut dom, &Some(node.clone()), runtime.env.clone());
             assert_eq!(expected[i], result);
             i += 1;
         }
@@ -468,17 +1553,18 @@ mod tests {
 
     #[test]
     fn test_reassign_variable() {
-        let dom = Rc::new(RefCell::new(DomNode::new(DomNodeKind::Document)));
+        let mut dom = Dom::new();
+        let document = dom.create_document();
         let input = "var foo=42; foo=1; foo".to_string();
         let lexer = JsLexer::new(input);
         let mut parser = JsParser::new(lexer);
         let ast = parser.parse_ast();
-        let mut runtime = JsRuntime::new(dom);
+        let mut runtime = JsRuntime::new(document);
         let expected = [None, None, Some(RuntimeValue::Number(1))];
         let mut i = 0;
 
         for node in ast.body() {
-            let result = runtime.eval(&Some(node.clone()), runtime.env.clone());
+            let result = runtime.eval(&mut dom, &Some(node.clone()), runtime.env.clone());
             assert_eq!(expected[i], result);
             i += 1;
         }
@@ -486,17 +1572,18 @@ mod tests {
 
     #[test]
     fn test_add_function_and_num() {
-        let dom = Rc::new(RefCell::new(DomNode::new(DomNodeKind::Document)));
+        let mut dom = Dom::new();
+        let document = dom.create_document();
         let input = "function foo() { return 42; } foo()+1".to_string();
         let lexer = JsLexer::new(input);
         let mut parser = JsParser::new(lexer);
         let ast = parser.parse_ast();
-        let mut runtime = JsRuntime::new(dom);
+        let mut runtime = JsRuntime::new(document);
         let expected = [None, Some(RuntimeValue::Number(43))];
         let mut i = 0;
 
         for node in ast.body() {
-            let result = runtime.eval(&Some(node.clone()), runtime.env.clone());
+            let result = runtime.eval(&mut dom, &Some(node.clone()), runtime.env.clone());
             assert_eq!(expected[i], result);
             i += 1;
         }
@@ -504,35 +1591,342 @@ mod tests {
 
     #[test]
     fn test_add_function_and_num_with_args() {
-        let dom = Rc::new(RefCell::new(DomNode::new(DomNodeKind::Document)));
+        let mut dom = Dom::new();
+        let document = dom.create_document();
         let input = "function foo(a, b) { return a + b; } foo(1, 2) + 3".to_string();
         let lexer = JsLexer::new(input);
         let mut parser = JsParser::new(lexer);
         let ast = parser.parse_ast();
-        let mut runtime = JsRuntime::new(dom);
+        let mut runtime = JsRuntime::new(document);
         let expected = [None, Some(RuntimeValue::Number(6))];
         let mut i = 0;
 
         for node in ast.body() {
-            let result = runtime.eval(&Some(node.clone()), runtime.env.clone());
+            let result = runtime.eval(&mut dom, &Some(node.clone()), runtime.env.clone());
             assert_eq!(expected[i], result);
             i += 1;
         }
     }
 
+    #[test]
+    fn test_inner_html() {
+        let html =
+            "<html><head></head><body><p id=\"target\">a<a href=\"x\">b</a></p></body></html>";
+        let mut dom = Dom::new();
+        let document = HtmlParser::new(&mut dom, HtmlTokenizer::new(html.to_string()))
+            .construct_tree(&mut dom);
+        let input =
+            "var target=document.getElementById(\"target\"); target.innerHTML; target.outerHTML"
+                .to_string();
+        let lexer = JsLexer::new(input);
+        let mut parser = JsParser::new(lexer);
+        let ast = parser.parse_ast();
+        let mut runtime = JsRuntime::new(document);
+        let expected = [
+            None,
+            Some(RuntimeValue::StringLiteral(
+                "a<a href=\"x\">b</a>".to_string(),
+            )),
+            Some(RuntimeValue::StringLiteral(
+                "<p id=\"target\">a<a href=\"x\">b</a></p>".to_string(),
+            )),
+        ];
+        for (i, node) in ast.body().iter().enumerate() {
+            let result = runtime.eval(&mut dom, &Some(node.clone()), runtime.env.clone());
+            assert_eq!(expected[i], result);
+        }
+    }
+
+    #[test]
+    fn test_set_inner_html() {
+        let html = "<html><head></head><body><p id=\"target\">old</p></body></html>";
+        let mut dom = Dom::new();
+        let document = HtmlParser::new(&mut dom, HtmlTokenizer::new(html.to_string()))
+            .construct_tree(&mut dom);
+        let input = "var target=document.getElementById(\"target\"); target.innerHTML=\"<a>new</a>\"; target.insertAdjacentHTML(\"beforeend\", \"!\"); target.innerHTML"
+            .to_string();
+        let lexer = JsLexer::new(input);
+        let mut parser = JsParser::new(lexer);
+        let ast = parser.parse_ast();
+        let mut runtime = JsRuntime::new(document);
+        let expected = [
+            None,
+            None,
+            None,
+            Some(RuntimeValue::StringLiteral("<a>new</a>!".to_string())),
+        ];
+        for (i, node) in ast.body().iter().enumerate() {
+            let result = runtime.eval(&mut dom, &Some(node.clone()), runtime.env.clone());
+            assert_eq!(expected[i], result);
+        }
+    }
+
+    #[test]
+    fn test_document_title() {
+        let html = "<html><head><title> old\n title </title></head><body></body></html>";
+        let mut dom = Dom::new();
+        let document = HtmlParser::new(&mut dom, HtmlTokenizer::new(html.to_string()))
+            .construct_tree(&mut dom);
+        let input = "var a=document.title; document.title=\"new\"; document.title".to_string();
+        let lexer = JsLexer::new(input);
+        let mut parser = JsParser::new(lexer);
+        let ast = parser.parse_ast();
+        let mut runtime = JsRuntime::new(document);
+        let expected = [
+            None,
+            None,
+            Some(RuntimeValue::StringLiteral("new".to_string())),
+        ];
+        for (i, node) in ast.body().iter().enumerate() {
+            let result = runtime.eval(&mut dom, &Some(node.clone()), runtime.env.clone());
+            assert_eq!(expected[i], result);
+        }
+        assert_eq!(
+            Some(RuntimeValue::StringLiteral("old title".to_string())),
+            runtime.env.borrow_mut().get_variable("a".to_string())
+        );
+    }
+
+    #[test]
+    fn test_dom_mutation() {
+        let html = "<html><head></head><body><p id=\"a\">a</p><p id=\"b\">b</p></body></html>";
+        let mut dom = Dom::new();
+        let document = HtmlParser::new(&mut dom, HtmlTokenizer::new(html.to_string()))
+            .construct_tree(&mut dom);
+        let input = "var a=document.getElementById(\"a\"); var b=document.getElementById(\"b\"); var h=document.createElement(\"h1\"); h.appendChild(document.createTextNode(\"new\")); a.insertBefore(h, null); a.replaceChild(b.cloneNode(true), h); a.innerHTML".to_string();
+        let lexer = JsLexer::new(input);
+        let mut parser = JsParser::new(lexer);
+        let ast = parser.parse_ast();
+        let mut runtime = JsRuntime::new(document);
+        let last = ast.body().len() - 1;
+        // 最後の式以外を実行して DOM ツリーを変更する
+        for node in ast.body().iter().take(last) {
+            runtime.eval(&mut dom, &Some(node.clone()), runtime.env.clone());
+        }
+        assert_eq!(
+            Some(RuntimeValue::StringLiteral(
+                "a<p id=\"b\">b</p>".to_string()
+            )),
+            runtime.eval(
+                &mut dom,
+                &Some(ast.body()[last].clone()),
+                runtime.env.clone()
+            )
+        );
+    }
+
+    #[test]
+    fn test_attributes() {
+        let html = "<html><head></head><body><p id=\"target\" class=\"a b\" data-user-id=\"1\">x</p></body></html>";
+        let mut dom = Dom::new();
+        let document = HtmlParser::new(&mut dom, HtmlTokenizer::new(html.to_string()))
+            .construct_tree(&mut dom);
+        let input = "var t=document.getElementById(\"target\"); t.setAttribute(\"title\", \"hi\"); t.getAttribute(\"title\"); t.hasAttribute(\"lang\"); t.classList.contains(\"b\"); t.classList.add(\"c\"); t.classList.remove(\"a\"); t.className; t.dataset.userId; t.dataset.userName=\"saba\"; t.outerHTML".to_string();
+        let lexer = JsLexer::new(input);
+        let mut parser = JsParser::new(lexer);
+        let ast = parser.parse_ast();
+        let mut runtime = JsRuntime::new(document);
+        let expected = [
+            None,
+            None,
+            Some(RuntimeValue::StringLiteral("hi".to_string())),
+            Some(RuntimeValue::StringLiteral("false".to_string())),
+            Some(RuntimeValue::StringLiteral("true".to_string())),
+            None,
+            None,
+            Some(RuntimeValue::StringLiteral("b c".to_string())),
+            Some(RuntimeValue::StringLiteral("1".to_string())),
+            None,
+            Some(RuntimeValue::StringLiteral(
+                "<p id=\"target\" class=\"b c\" data-user-id=\"1\" title=\"hi\" data-user-name=\"saba\">x</p>".to_string(),
+            )),
+        ];
+        for (i, node) in ast.body().iter().enumerate() {
+            let result = runtime.eval(&mut dom, &Some(node.clone()), runtime.env.clone());
+            assert_eq!(expected[i], result);
+        }
+    }
+
+    #[test]
+    fn test_query_selector() {
+        let html = "<html><head></head><body><p class=\"note\">a</p><h1>b<a class=\"note\">c</a></h1></body></html>";
+        let mut dom = Dom::new();
+        let document = HtmlParser::new(&mut dom, HtmlTokenizer::new(html.to_string()))
+            .construct_tree(&mut dom);
+        let input = "var h=document.querySelector(\"h1\"); var all=document.querySelectorAll(\".note\"); all.length; var c=all.item(1); c.innerHTML; var inner=h.querySelectorAll(\".note\"); inner.length".to_string();
+        let lexer = JsLexer::new(input);
+        let mut parser = JsParser::new(lexer);
+        let ast = parser.parse_ast();
+        let mut runtime = JsRuntime::new(document);
+        let expected = [
+            None,
+            None,
+            Some(RuntimeValue::Number(2)),
+            None,
+            Some(RuntimeValue::StringLiteral("c".to_string())),
+            None,
+            Some(RuntimeValue::Number(1)),
+        ];
+        for (i, node) in ast.body().iter().enumerate() {
+            let result = runtime.eval(&mut dom, &Some(node.clone()), runtime.env.clone());
+            assert_eq!(expected[i], result);
+        }
+    }
+
+    #[test]
+    fn test_html_collection() {
+        let html = "<html><head></head><body><p class=\"note\">a</p><h1>b<a class=\"note\">c</a></h1></body></html>";
+        let mut dom = Dom::new();
+        let document = HtmlParser::new(&mut dom, HtmlTokenizer::new(html.to_string()))
+            .construct_tree(&mut dom);
+        let input = "var ps=document.getElementsByTagName(\"p\"); var notes=document.getElementsByClassName(\"note\"); var body=document.querySelector(\"body\"); var kids=body.children; ps.length; notes[1].innerHTML; var n=notes.item(0); n.innerHTML; body.childNodes.length; var p=document.createElement(\"p\"); var x=body.appendChild(p); ps.length; kids.length; ps[1].outerHTML".to_string();
+        let lexer = JsLexer::new(input);
+        let mut parser = JsParser::new(lexer);
+        let ast = parser.parse_ast();
+        let mut runtime = JsRuntime::new(document);
+        let expected = [
+            None,
+            None,
+            None,
+            None,
+            Some(RuntimeValue::Number(1)),
+            Some(RuntimeValue::StringLiteral("c".to_string())),
+            None,
+            Some(RuntimeValue::StringLiteral("a".to_string())),
+            Some(RuntimeValue::Number(2)),
+            None,
+            None,
+            // 要素を追加すると、取得済みのコレクションにも反映される
+            Some(RuntimeValue::Number(2)),
+            Some(RuntimeValue::Number(3)),
+            Some(RuntimeValue::StringLiteral("<p></p>".to_string())),
+        ];
+        for (i, node) in ast.body().iter().enumerate() {
+            let result = runtime.eval(&mut dom, &Some(node.clone()), runtime.env.clone());
+            assert_eq!(expected[i], result);
+        }
+    }
+
+    #[test]
+    fn test_event_listener() {
+        let html = "<html><head></head><body><p id=\"p\"><a id=\"a\">link</a></p></body></html>";
+        let mut dom = Dom::new();
+        let document = HtmlParser::new(&mut dom, HtmlTokenizer::new(html.to_string()))
+            .construct_tree(&mut dom);
+        let input = "var log=\"\"; function capture(e) { log=log+\"capture:\"+e.eventPhase+\",\"; } function target(e) { log=log+e.target.id+\",\"; } function stop(e) { log=log+\"stop,\"; e.stopPropagation(); e.preventDefault(); } function never(e) { log=log+\"never,\"; } var p=document.getElementById(\"p\"); var a=document.getElementById(\"a\"); document.addEventListener(\"click\", capture, true); a.addEventListener(\"click\", target); p.addEventListener(\"click\", stop); document.addEventListener(\"click\", never); a.addEventListener(\"click\", never); a.removeEventListener(\"click\", never)".to_string();
+        let lexer = JsLexer::new(input);
+        let mut parser = JsParser::new(lexer);
+        let ast = parser.parse_ast();
+        let mut runtime = JsRuntime::new(document);
+        runtime.execute(&mut dom, &ast);
+
+        let a = get_element_by_id(&dom, Some(document), &"a".to_string()).expect("a should exist");
+        let event = Rc::new(RefCell::new(Event::new("click", true, true)));
+        assert!(!runtime.dispatch_event(&mut dom, EventTarget::Node(a), &event));
+        assert_eq!(
+            Some(RuntimeValue::StringLiteral("capture:1,a,stop,".to_string())),
+            RefCell::borrow(&runtime.env).get_variable("log".to_string())
+        );
+    }
+
+    #[test]
+    fn test_mutation_observer() {
+        let html = "<html><head></head><body><p id=\"p\">a</p></body></html>";
+        let mut dom = Dom::new();
+        let document = HtmlParser::new(&mut dom, HtmlTokenizer::new(html.to_string()))
+            .construct_tree(&mut dom);
+        let input = "var log=\"\"; function children(records, observer) { log=log+records.length+\":\"+records[0].type+\",\"+records[0].addedNodes.length+\",\"+records[2].attributeName+\"=\"+records[2].oldValue+\";\"; } function text(records) { log=log+records[0].type+\"=\"+records[0].oldValue+\";\"; } var p=document.getElementById(\"p\"); var o=new MutationObserver(children); o.observe(p, {childList: true, attributeOldValue: true}); var t=new MutationObserver(text); t.observe(document, {characterData: true, characterDataOldValue: true, subtree: true}); var x=p.appendChild(document.createElement(\"a\")); p.setAttribute(\"class\", \"b\"); p.className=\"c\"; p.childNodes[0].textContent=\"z\"".to_string();
+        let mut parser = JsParser::new(JsLexer::new(input));
+        let mut runtime = JsRuntime::new(document);
+        // 変更の記録は、スクリプトの実行が終わったあとにまとめて届けられる
+        runtime.execute(&mut dom, &parser.parse_ast());
+        assert_eq!(
+            Some(RuntimeValue::StringLiteral(
+                "3:childList,1,class=b;characterData=a;".to_string()
+            )),
+            RefCell::borrow(&runtime.env).get_variable("log".to_string())
+        );
+
+        // 監視をやめた MutationObserver や、取り出された記録は届けられない
+        let input = "o.disconnect(); p.setAttribute(\"id\", \"q\"); p.childNodes[0].textContent=\"y\"; var r=t.takeRecords(); log=log+r[0].oldValue".to_string();
+        let mut parser = JsParser::new(JsLexer::new(input));
+        runtime.execute(&mut dom, &parser.parse_ast());
+        assert_eq!(
+            Some(RuntimeValue::StringLiteral(
+                "3:childList,1,class=b;characterData=a;z".to_string()
+            )),
+            RefCell::borrow(&runtime.env).get_variable("log".to_string())
+        );
+    }
+
+    #[test]
+    fn test_tree_walker_and_node_iterator() {
+        let html = "<html><head></head><body><p id=\"p\">a<a id=\"x\">b</a></p>c<h1 id=\"h\">d</h1></body></html>";
+        let mut dom = Dom::new();
+        let document = HtmlParser::new(&mut dom, HtmlTokenizer::new(html.to_string()))
+            .construct_tree(&mut dom);
+        // テキストノードは whatToShow によって飛ばされる
+        let input = "var w=document.createTreeWalker(document.querySelector(\"body\"), NodeFilter.SHOW_ELEMENT); var n=w.nextNode(); var log=n.id; n=w.nextNode(); log=log+n.id; n=w.nextNode(); log=log+n.id+w.nextNode(); n=w.previousSibling(); log=log+n.id; n=w.firstChild(); log=log+n.id+w.currentNode.id; n=w.parentNode(); log=log+n.id+\",\"".to_string();
+        let mut parser = JsParser::new(JsLexer::new(input));
+        let mut runtime = JsRuntime::new(document);
+        runtime.execute(&mut dom, &parser.parse_ast());
+        assert_eq!(
+            Some(RuntimeValue::StringLiteral("pxhnullpxxp,".to_string())),
+            RefCell::borrow(&runtime.env).get_variable("log".to_string())
+        );
+
+        // whatToShow を省略すると、すべてのノードを返す
+        let input = "var i=document.createNodeIterator(document.getElementById(\"p\"), NodeFilter.SHOW_TEXT); n=i.nextNode(); log=log+n.outerHTML; n=i.nextNode(); log=log+n.outerHTML+i.nextNode(); n=i.previousNode(); log=log+n.outerHTML+i.referenceNode.outerHTML+i.root.id+\",\"; i=document.createNodeIterator(document); log=log+i.nextNode()".to_string();
+        let mut parser = JsParser::new(JsLexer::new(input));
+        runtime.execute(&mut dom, &parser.parse_ast());
+        assert_eq!(
+            Some(RuntimeValue::StringLiteral(
+                "pxhnullpxxp,abnullbbp,document".to_string()
+            )),
+            RefCell::borrow(&runtime.env).get_variable("log".to_string())
+        );
+    }
+
+    #[test]
+    fn test_window() {
+        let html = "<html><head><title>saba</title></head><body><p id=\"p\">a</p></body></html>";
+        let mut dom = Dom::new();
+        let document = HtmlParser::new(&mut dom, HtmlTokenizer::new(html.to_string()))
+            .construct_tree(&mut dom);
+        let input = "var p=document.getElementById(\"p\"); p.ownerDocument.title; document.ownerDocument; document.defaultView; window.document.title; var t=document.createTextNode(\"b\"); t.ownerDocument.title".to_string();
+        let lexer = JsLexer::new(input);
+        let mut parser = JsParser::new(lexer);
+        let ast = parser.parse_ast();
+        let mut runtime = JsRuntime::new(document);
+        let expected = [
+            None,
+            Some(RuntimeValue::StringLiteral("saba".to_string())),
+            Some(RuntimeValue::StringLiteral("null".to_string())),
+            Some(RuntimeValue::StringLiteral("window".to_string())),
+            Some(RuntimeValue::StringLiteral("saba".to_string())),
+            None,
+            Some(RuntimeValue::StringLiteral("saba".to_string())),
+        ];
+        for (i, node) in ast.body().iter().enumerate() {
+            let result = runtime.eval(&mut dom, &Some(node.clone()), runtime.env.clone());
+            assert_eq!(expected[i], result);
+        }
+    }
+
     #[test]
     fn test_local_variable() {
-        let dom = Rc::new(RefCell::new(DomNode::new(DomNodeKind::Document)));
+        let mut dom = Dom::new();
+        let document = dom.create_document();
         let input = "var a=42; function foo() { var a=1; return a; } foo()+a".to_string();
         let lexer = JsLexer::new(input);
         let mut parser = JsParser::new(lexer);
         let ast = parser.parse_ast();
-        let mut runtime = JsRuntime::new(dom);
+        let mut runtime = JsRuntime::new(document);
         let expected = [None, None, Some(RuntimeValue::Number(43))];
         let mut i = 0;
 
         for node in ast.body() {
-            let result = runtime.eval(&Some(node.clone()), runtime.env.clone());
+            let result = runtime.eval(&mut dom, &Some(node.clone()), runtime.env.clone());
             assert_eq!(expected[i], result);
             i += 1;
         }
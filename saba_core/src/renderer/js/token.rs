@@ -2,7 +2,7 @@ use alloc::string::String;
 use alloc::string::ToString;
 use alloc::vec::Vec;
 
-static RESERVED_WORDS: [&str; 3] = ["var", "function", "return"];
+static RESERVED_WORDS: [&str; 4] = ["var", "function", "return", "new"];
 
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub enum Token {
@@ -67,6 +67,9 @@ impl JsLexer {
     }
 
     fn contains(&self, keyword: &str) -> bool {
+        if self.pos + keyword.len() > self.input.len() {
+            return false;
+        }
         for i in 0..keyword.len() {
             if keyword
                 .chars()
@@ -77,7 +80,11 @@ impl JsLexer {
                 return false;
             }
         }
-        true
+        // newNode のように予約語から始まる識別子は、予約語として扱わない
+        match self.input.get(self.pos + keyword.len()) {
+            Some(c) => !(c.is_ascii_alphanumeric() || *c == '_' || *c == '$'),
+            None => true,
+        }
     }
 
     fn check_reserved_word(&self) -> Option<String> {
@@ -97,8 +104,9 @@ impl JsLexer {
                 return result;
             }
 
-            if self.input[self.pos].is_ascii_alphanumeric() || self.input[self.pos] == '$' {
-                result.push(self.input[self.pos]);
+            let c = self.input[self.pos];
+            if c.is_ascii_alphanumeric() || c == '_' || c == '$' {
+                result.push(c);
                 self.pos += 1;
             } else {
                 return result;
@@ -134,7 +142,7 @@ impl Iterator for JsLexer {
         let c = self.input[self.pos];
 
         let token = match c {
-            '+' | '-' | ';' | '=' | '(' | ')' | '{' | '}' | ',' | '.' => {
+            '+' | '-' | ';' | '=' | '(' | ')' | '{' | '}' | ',' | '.' | '[' | ']' | ':' => {
                 let t = Token::Punctuator(c);
                 self.pos += 1;
                 t
@@ -270,4 +278,32 @@ mod tests {
         }
         assert!(lexer.peek().is_none());
     }
+
+    #[test]
+    fn test_new_and_object() {
+        let input = "var newNode = new Foo({a: 1});".to_string();
+        let mut lexer = JsLexer::new(input).peekable();
+        let expected = [
+            Token::Keyword("var".to_string()),
+            Token::Identifier("newNode".to_string()),
+            Token::Punctuator('='),
+            Token::Keyword("new".to_string()),
+            Token::Identifier("Foo".to_string()),
+            Token::Punctuator('('),
+            Token::Punctuator('{'),
+            Token::Identifier("a".to_string()),
+            Token::Punctuator(':'),
+            Token::Number(1),
+            Token::Punctuator('}'),
+            Token::Punctuator(')'),
+            Token::Punctuator(';'),
+        ]
+        .to_vec();
+        let mut i = 0;
+        while lexer.peek().is_some() {
+            assert_eq!(Some(expected[i].clone()), lexer.next());
+            i += 1;
+        }
+        assert!(lexer.peek().is_none());
+    }
 }
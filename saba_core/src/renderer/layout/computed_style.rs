@@ -1,12 +1,8 @@
 use crate::error::Error;
-use crate::renderer::dom::node::ElementKind;
-use crate::renderer::dom::node::Node;
-use crate::renderer::dom::node::NodeKind;
 use alloc::format;
-use alloc::rc::Rc;
 use alloc::string::String;
 use alloc::string::ToString;
-use core::cell::RefCell;
+use core::str::FromStr;
 
 #[derive(Debug, Clone, PartialEq)]
 pub struct ComputedStyle {
@@ -15,6 +11,10 @@ pub struct ComputedStyle {
     display: Option<DisplayType>,
     font_size: Option<FontSize>,
     text_decoration: Option<TextDecoration>,
+    white_space: Option<WhiteSpace>,
+    // 上下のマージン (px)。本書のブラウザでは左右のマージンはサポートしない
+    margin_top: Option<i64>,
+    margin_bottom: Option<i64>,
     height: Option<f64>,
     width: Option<f64>,
 }
@@ -27,6 +27,9 @@ impl ComputedStyle {
             display: None,
             font_size: None,
             text_decoration: None,
+            white_space: None,
+            margin_top: None,
+            margin_bottom: None,
             height: None,
             width: None,
         }
@@ -61,16 +64,51 @@ impl ComputedStyle {
             .expect("failed to access CSS property: display")
     }
 
+    pub fn set_font_size(&mut self, font_size: FontSize) {
+        self.font_size = Some(font_size);
+    }
+
     pub fn font_size(&self) -> FontSize {
         self.font_size
             .expect("failed to access CSS property: font_size")
     }
 
+    pub fn set_text_decoration(&mut self, text_decoration: TextDecoration) {
+        self.text_decoration = Some(text_decoration);
+    }
+
     pub fn text_decoration(&self) -> TextDecoration {
         self.text_decoration
             .expect("failed to access CSS property: text_decoration")
     }
 
+    pub fn set_white_space(&mut self, white_space: WhiteSpace) {
+        self.white_space = Some(white_space);
+    }
+
+    pub fn white_space(&self) -> WhiteSpace {
+        self.white_space
+            .expect("failed to access CSS property: white_space")
+    }
+
+    pub fn set_margin_top(&mut self, margin: i64) {
+        self.margin_top = Some(margin);
+    }
+
+    pub fn margin_top(&self) -> i64 {
+        self.margin_top
+            .expect("failed to access CSS property: margin-top")
+    }
+
+    pub fn set_margin_bottom(&mut self, margin: i64) {
+        self.margin_bottom = Some(margin);
+    }
+
+    pub fn margin_bottom(&self) -> i64 {
+        self.margin_bottom
+            .expect("failed to access CSS property: margin-bottom")
+    }
+
     pub fn set_height(&mut self, height: f64) {
         self.height = Some(height);
     }
@@ -87,7 +125,10 @@ impl ComputedStyle {
         self.width.expect("failed to access CSS property: width")
     }
 
-    pub fn defaulting(&mut self, node: &Rc<RefCell<Node>>, parent_style: Option<ComputedStyle>) {
+    /// 要素ごとのデフォルトの値はユーザーエージェントスタイルシートによって決まるので、
+    /// ここでは継承する値と、プロパティの初期値のみを設定する
+    /// https://www.w3.org/TR/css-cascade-4/#defaulting
+    pub fn defaulting(&mut self, parent_style: Option<ComputedStyle>) {
         // もし親ノードが存在し、親の CSS の値が初期値とは異なる場合、値を継承する
         if let Some(parent_style) = parent_style {
             if self.background_color.is_none() && parent_style.background_color() != Color::white()
@@ -105,6 +146,9 @@ impl ComputedStyle {
             {
                 self.text_decoration = Some(parent_style.text_decoration());
             }
+            if self.white_space.is_none() && parent_style.white_space() != WhiteSpace::Normal {
+                self.white_space = Some(parent_style.white_space());
+            }
         }
 
         // 各プロパティに対して、初期値を設定する
@@ -115,13 +159,22 @@ impl ComputedStyle {
             self.color = Some(Color::black());
         }
         if self.display.is_none() {
-            self.display = Some(DisplayType::default(node));
+            self.display = Some(DisplayType::Inline);
         }
         if self.font_size.is_none() {
-            self.font_size = Some(FontSize::default(node));
+            self.font_size = Some(FontSize::Medium);
         }
         if self.text_decoration.is_none() {
-            self.text_decoration = Some(TextDecoration::default(node));
+            self.text_decoration = Some(TextDecoration::None);
+        }
+        if self.white_space.is_none() {
+            self.white_space = Some(WhiteSpace::Normal);
+        }
+        if self.margin_top.is_none() {
+            self.margin_top = Some(0);
+        }
+        if self.margin_bottom.is_none() {
+            self.margin_bottom = Some(0);
         }
         if self.height.is_none() {
             self.height = Some(0.0);
@@ -240,15 +293,18 @@ pub enum FontSize {
     XXLarge,
 }
 
-impl FontSize {
-    fn default(node: &Rc<RefCell<Node>>) -> Self {
-        match &node.borrow().kind() {
-            NodeKind::Element(element) => match element.kind() {
-                ElementKind::H1 => FontSize::XXLarge,
-                ElementKind::H2 => FontSize::XLarge,
-                _ => FontSize::Medium,
-            },
-            _ => FontSize::Medium,
+impl FromStr for FontSize {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "medium" => Ok(FontSize::Medium),
+            "x-large" => Ok(FontSize::XLarge),
+            "xx-large" => Ok(FontSize::XXLarge),
+            _ => Err(Error::UnexpectedInput(format!(
+                "font-size {:?} is not supported yet",
+                s
+            ))),
         }
     }
 }
@@ -261,20 +317,6 @@ pub enum DisplayType {
 }
 
 impl DisplayType {
-    fn default(node: &Rc<RefCell<Node>>) -> Self {
-        match &node.borrow().kind() {
-            NodeKind::Document => DisplayType::Block,
-            NodeKind::Element(e) => {
-                if e.is_block_element() {
-                    DisplayType::Block
-                } else {
-                    DisplayType::Inline
-                }
-            }
-            NodeKind::Text(_) => DisplayType::Inline,
-        }
-    }
-
     pub fn from_str(s: &str) -> Result<Self, Error> {
         match s {
             "block" => Ok(DisplayType::Block),
@@ -294,14 +336,71 @@ pub enum TextDecoration {
     Underline,
 }
 
-impl TextDecoration {
-    fn default(node: &Rc<RefCell<Node>>) -> Self {
-        match &node.borrow().kind() {
-            NodeKind::Element(element) => match element.kind() {
-                ElementKind::A => TextDecoration::Underline,
-                _ => TextDecoration::None,
-            },
-            _ => TextDecoration::None,
+impl FromStr for TextDecoration {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "none" => Ok(TextDecoration::None),
+            "underline" => Ok(TextDecoration::Underline),
+            _ => Err(Error::UnexpectedInput(format!(
+                "text-decoration {:?} is not supported yet",
+                s
+            ))),
+        }
+    }
+}
+
+/// 空白文字と改行の扱い方
+/// https://www.w3.org/TR/css-text-3/#white-space-property
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum WhiteSpace {
+    /// 連続する空白と改行を1つの空白にまとめ、行の幅に合わせて折り返す
+    Normal,
+    /// 空白と改行をそのまま残し、折り返さない
+    Pre,
+    /// Normal と同じように空白をまとめるが、折り返さない
+    Nowrap,
+    /// 空白と改行をそのまま残し、行の幅に合わせて折り返す
+    PreWrap,
+    /// 連続する空白は1つにまとめるが、改行は残し、行の幅に合わせて折り返す
+    PreLine,
+}
+
+impl FromStr for WhiteSpace {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "normal" => Ok(WhiteSpace::Normal),
+            "pre" => Ok(WhiteSpace::Pre),
+            "nowrap" => Ok(WhiteSpace::Nowrap),
+            "pre-wrap" => Ok(WhiteSpace::PreWrap),
+            "pre-line" => Ok(WhiteSpace::PreLine),
+            _ => Err(Error::UnexpectedInput(format!(
+                "white-space {:?} is not supported yet",
+                s
+            ))),
         }
     }
 }
+
+impl WhiteSpace {
+    /// 連続する空白をまとめるかどうか
+    pub fn collapses_spaces(&self) -> bool {
+        matches!(
+            self,
+            WhiteSpace::Normal | WhiteSpace::Nowrap | WhiteSpace::PreLine
+        )
+    }
+
+    /// 改行をそのまま残すかどうか
+    pub fn preserves_line_feeds(&self) -> bool {
+        !matches!(self, WhiteSpace::Normal | WhiteSpace::Nowrap)
+    }
+
+    /// 行の幅に合わせて折り返すかどうか
+    pub fn wraps(&self) -> bool {
+        !matches!(self, WhiteSpace::Pre | WhiteSpace::Nowrap)
+    }
+}
@@ -3,29 +3,47 @@ use crate::constants::CHAR_HEIGHT_WITH_PADDING;
 use crate::constants::CHAR_WIDTH;
 use crate::constants::CONTENT_AREA_WIDTH;
 use crate::display_item::DisplayItem;
+use crate::renderer::css::cascade::cascade;
+use crate::renderer::css::cascade::CascadedValues;
 use crate::renderer::css::cssom::ComponentValue;
-use crate::renderer::css::cssom::Declaration;
-use crate::renderer::css::cssom::Selector;
 use crate::renderer::css::cssom::StyleSheet;
-use crate::renderer::dom::node::Node;
+use crate::renderer::dom::node::Dom;
+use crate::renderer::dom::node::NodeId;
 use crate::renderer::dom::node::NodeKind;
 use crate::renderer::layout::computed_style::Color;
 use crate::renderer::layout::computed_style::ComputedStyle;
 use crate::renderer::layout::computed_style::DisplayType;
 use crate::renderer::layout::computed_style::FontSize;
-use alloc::rc::Rc;
-use alloc::rc::Weak;
+use crate::renderer::layout::computed_style::TextDecoration;
+use crate::renderer::layout::computed_style::WhiteSpace;
 use alloc::vec;
 use alloc::vec::Vec;
-use core::cell::RefCell;
+use core::str::FromStr;
+
+/// レイアウトツリーのノードを指す ID
+/// LayoutView が持つ配列 (アリーナ) の添字で、レイアウトツリーが作り直されるまで変わらない
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct LayoutObjectId(usize);
+
+impl LayoutObjectId {
+    pub fn new(index: usize) -> Self {
+        Self(index)
+    }
+
+    pub fn index(&self) -> usize {
+        self.0
+    }
+}
 
 #[derive(Debug, Clone)]
 pub struct LayoutObject {
     kind: LayoutObjectKind,
-    node: Rc<RefCell<Node>>,
-    first_child: Option<Rc<RefCell<LayoutObject>>>,
-    next_sibling: Option<Rc<RefCell<LayoutObject>>>,
-    parent: Weak<RefCell<LayoutObject>>,
+    node: NodeId,
+    // レイアウトツリーを作成した時点でのノードの種類
+    node_kind: NodeKind,
+    first_child: Option<LayoutObjectId>,
+    next_sibling: Option<LayoutObjectId>,
+    parent: Option<LayoutObjectId>,
     style: ComputedStyle,
     point: LayoutPoint,
     size: LayoutSize,
@@ -95,14 +113,11 @@ pub enum LayoutObjectKind {
 }
 
 impl LayoutObject {
-    pub fn new(node: Rc<RefCell<Node>>, parent_obj: &Option<Rc<RefCell<LayoutObject>>>) -> Self {
-        let parent = match parent_obj {
-            Some(p) => Rc::downgrade(p),
-            None => Weak::new(),
-        };
+    pub fn new(node: NodeId, node_kind: NodeKind, parent: Option<LayoutObjectId>) -> Self {
         Self {
             kind: LayoutObjectKind::Block,
-            node: node.clone(),
+            node,
+            node_kind,
             first_child: None,
             next_sibling: None,
             parent,
@@ -116,28 +131,32 @@ impl LayoutObject {
         self.kind
     }
 
+    pub fn node(&self) -> NodeId {
+        self.node
+    }
+
     pub fn node_kind(&self) -> NodeKind {
-        self.node.borrow().kind().clone()
+        self.node_kind.clone()
     }
 
-    pub fn set_first_child(&mut self, first_child: Option<Rc<RefCell<LayoutObject>>>) {
+    pub fn set_first_child(&mut self, first_child: Option<LayoutObjectId>) {
         self.first_child = first_child;
     }
 
-    pub fn first_child(&self) -> Option<Rc<RefCell<LayoutObject>>> {
-        self.first_child.as_ref().cloned()
+    pub fn first_child(&self) -> Option<LayoutObjectId> {
+        self.first_child
     }
 
-    pub fn set_next_sibling(&mut self, next_sibling: Option<Rc<RefCell<LayoutObject>>>) {
+    pub fn set_next_sibling(&mut self, next_sibling: Option<LayoutObjectId>) {
         self.next_sibling = next_sibling;
     }
 
-    pub fn next_sibling(&self) -> Option<Rc<RefCell<LayoutObject>>> {
-        self.next_sibling.as_ref().cloned()
+    pub fn next_sibling(&self) -> Option<LayoutObjectId> {
+        self.next_sibling
     }
 
-    pub fn parent(&self) -> Weak<RefCell<Self>> {
-        self.parent.clone()
+    pub fn parent(&self) -> Option<LayoutObjectId> {
+        self.parent
     }
 
     pub fn style(&self) -> ComputedStyle {
@@ -152,51 +171,21 @@ impl LayoutObject {
         self.size
     }
 
-    pub fn is_node_selected(&self, selector: &Selector) -> bool {
-        match &self.node_kind() {
-            NodeKind::Element(e) => match selector {
-                Selector::TypeSelector(type_name) => {
-                    if e.kind().to_string() == *type_name {
-                        return true;
-                    }
-                    false
-                }
-                Selector::ClassSelector(class_name) => {
-                    for attr in &e.attributes() {
-                        if attr.name() == "class" && attr.value() == *class_name {
-                            return true;
-                        }
-                    }
-                    false
-                }
-                Selector::IdSelector(id_name) => {
-                    for attr in &e.attributes() {
-                        if attr.name() == "id" && attr.value() == *id_name {
-                            return true;
-                        }
-                    }
-                    false
-                }
-                Selector::UnknownSelector => false,
-            },
-            _ => false,
-        }
-    }
-
-    pub fn cascading_style(&mut self, declarations: Vec<Declaration>) {
-        for declaration in declarations {
-            match declaration.property.as_str() {
+    /// カスケードで決まった値から、スタイルを計算する
+    pub fn cascading_style(&mut self, values: &CascadedValues) {
+        for (property, value) in values {
+            match property.as_str() {
                 "background-color" => {
-                    if let ComponentValue::Ident(value) = &declaration.value {
-                        let color = match Color::from_name(&value) {
+                    if let ComponentValue::Ident(value) = value {
+                        let color = match Color::from_name(value) {
                             Ok(color) => color,
                             Err(_) => Color::white(),
                         };
                         self.style.set_background_color(color);
                         continue;
                     }
-                    if let ComponentValue::HashToken(color_code) = &declaration.value {
-                        let color = match Color::from_code(&color_code) {
+                    if let ComponentValue::HashToken(color_code) = value {
+                        let color = match Color::from_code(color_code) {
                             Ok(color) => color,
                             Err(_) => Color::white(),
                         };
@@ -205,16 +194,16 @@ impl LayoutObject {
                     }
                 }
                 "color" => {
-                    if let ComponentValue::Ident(value) = &declaration.value {
-                        let color = match Color::from_name(&value) {
+                    if let ComponentValue::Ident(value) = value {
+                        let color = match Color::from_name(value) {
                             Ok(color) => color,
                             Err(_) => Color::black(),
                         };
                         self.style.set_color(color);
                     }
 
-                    if let ComponentValue::HashToken(color_code) = &declaration.value {
-                        let color = match Color::from_code(&color_code) {
+                    if let ComponentValue::HashToken(color_code) = value {
+                        let color = match Color::from_code(color_code) {
                             Ok(color) => color,
                             Err(_) => Color::black(),
                         };
@@ -222,26 +211,61 @@ impl LayoutObject {
                     }
                 }
                 "display" => {
-                    if let ComponentValue::Ident(value) = declaration.value {
-                        let display_type = match DisplayType::from_str(&value) {
+                    if let ComponentValue::Ident(value) = value {
+                        let display_type = match DisplayType::from_str(value) {
                             Ok(display_type) => display_type,
                             Err(_) => DisplayType::DisplayNone,
                         };
                         self.style.set_display(display_type)
                     }
                 }
+                "white-space" => {
+                    if let ComponentValue::Ident(value) = value {
+                        if let Ok(white_space) = WhiteSpace::from_str(value) {
+                            self.style.set_white_space(white_space);
+                        }
+                    }
+                }
+                "font-size" => {
+                    if let ComponentValue::Ident(value) = value {
+                        if let Ok(font_size) = FontSize::from_str(value) {
+                            self.style.set_font_size(font_size);
+                        }
+                    }
+                }
+                "text-decoration" => {
+                    if let ComponentValue::Ident(value) = value {
+                        if let Ok(text_decoration) = TextDecoration::from_str(value) {
+                            self.style.set_text_decoration(text_decoration);
+                        }
+                    }
+                }
+                "margin-top" => {
+                    if let Some(margin) = length_in_px(value) {
+                        self.style.set_margin_top(margin);
+                    }
+                }
+                "margin-bottom" => {
+                    if let Some(margin) = length_in_px(value) {
+                        self.style.set_margin_bottom(margin);
+                    }
+                }
 
                 _ => {}
             }
         }
     }
 
-    pub fn defaulting_style(
-        &mut self,
-        node: &Rc<RefCell<Node>>,
-        parent_style: Option<ComputedStyle>,
-    ) {
-        self.style.defaulting(node, parent_style);
+    pub fn defaulting_style(&mut self, parent_style: Option<ComputedStyle>) {
+        self.style.defaulting(parent_style);
+    }
+
+    /// 上下のマージン。インライン要素とテキストには上下のマージンはない
+    pub fn vertical_margin(&self) -> (i64, i64) {
+        match self.kind {
+            LayoutObjectKind::Block => (self.style.margin_top(), self.style.margin_bottom()),
+            _ => (0, 0),
+        }
     }
 
     pub fn update_kind(&mut self) {
@@ -263,7 +287,14 @@ impl LayoutObject {
 
     /// 1つのノードのサイズを計算する
     /// ユーザーによって CSS でwidth や height が司令されている場合はその値を使用するが、今回は CSS で横幅と高さは指定できないので関係ない。
-    pub fn compute_size(&mut self, parent_size: LayoutSize) {
+    ///
+    /// * `parent_size` - 親ノードのサイズ
+    /// * `children` - 子ノードの種類、サイズ、上下のマージン。子ノードの順に並ぶ
+    pub fn compute_size(
+        &mut self,
+        parent_size: LayoutSize,
+        children: &[(LayoutObjectKind, LayoutSize, (i64, i64))],
+    ) {
         let mut size = LayoutSize::new(0, 0);
 
         match self.kind() {
@@ -272,68 +303,51 @@ impl LayoutObject {
                 size.set_width(parent_size.width());
 
                 let mut height = 0;
-                let mut child = self.first_child();
-
                 let mut previous_child_kind = LayoutObjectKind::Block;
-                while child.is_some() {
-                    let c = match child {
-                        Some(c) => c,
-                        None => panic!("first child should exist"),
-                    };
-
+                // 直前の子ノードの下マージン
+                let mut margin = 0;
+                for (kind, child_size, (margin_top, margin_bottom)) in children {
                     if previous_child_kind == LayoutObjectKind::Block
-                        || c.borrow().kind() == LayoutObjectKind::Block
+                        || *kind == LayoutObjectKind::Block
                     {
-                        height += c.borrow().size.height();
+                        // 隣り合う上下のマージンは、大きいほうの1つにまとめる (マージンの相殺)
+                        height += margin.max(*margin_top) + child_size.height();
+                        margin = *margin_bottom;
                     }
 
-                    previous_child_kind = c.borrow().kind();
-                    child = c.borrow().next_sibling();
+                    previous_child_kind = *kind;
                 }
-                // ブロック要素の高さはすべての子ノードの高さの合計になる、インライン要素が横に並んでいる場合は、高さが増えることはない
-                size.set_height(height);
+                // ブロック要素の高さはすべての子ノードの高さとマージンの合計になる、インライン要素が横に並んでいる場合は、高さが増えることはない
+                size.set_height(height + margin);
             }
             LayoutObjectKind::Inline => {
                 // ノードがインライン要素の場合、高さも横幅も子要素のサイズを足し合わせたものになる。本実装ではインライン要素の子ノードは常にテキストノードである想定
                 let mut width = 0;
                 let mut height = 0;
-                let mut child = self.first_child();
-                while child.is_some() {
-                    let c = match child {
-                        Some(c) => c,
-                        None => panic!("first child should exist"),
-                    };
-
-                    width += c.borrow().size.width();
-                    height += c.borrow().size.height();
-
-                    child = c.borrow().next_sibling();
+                for (_, child_size, _) in children {
+                    width += child_size.width();
+                    height += child_size.height();
                 }
 
                 size.set_width(width);
                 size.set_height(height);
             }
             LayoutObjectKind::Text => {
-                if let NodeKind::Text(t) = self.node_kind() {
-                    let ratio = match self.style.font_size() {
-                        FontSize::Medium => 1,
-                        FontSize::XLarge => 2,
-                        FontSize::XXLarge => 3,
-                    };
-                    let width = CHAR_WIDTH * ratio * t.len() as i64;
-                    if width > CONTENT_AREA_WIDTH {
-                        size.set_width(CONTENT_AREA_WIDTH);
-                        let line_num = if width.wrapping_rem(CONTENT_AREA_WIDTH) == 0 {
-                            width.wrapping_div(CONTENT_AREA_WIDTH)
-                        } else {
-                            width.wrapping_div(CONTENT_AREA_WIDTH) + 1
-                        };
-                        size.set_height(CHAR_HEIGHT_WITH_PADDING * ratio * line_num);
-                    } else {
-                        size.set_width(width);
-                        size.set_height(CHAR_HEIGHT_WITH_PADDING * ratio);
-                    }
+                let ratio = self.font_ratio();
+                let lines = self.text_lines();
+                let max_len = lines
+                    .iter()
+                    .map(|line| line.chars().count())
+                    .max()
+                    .unwrap_or(0);
+
+                let width = CHAR_WIDTH * ratio * max_len as i64;
+                if self.style.white_space().wraps() && width > CONTENT_AREA_WIDTH {
+                    size.set_width(CONTENT_AREA_WIDTH);
+                } else {
+                    size.set_width(width);
                 }
+                size.set_height(CHAR_HEIGHT_WITH_PADDING * ratio * lines.len() as i64);
             }
         }
 
@@ -347,12 +361,14 @@ impl LayoutObject {
     /// * `parent_point` - 親ノードの位置
     /// * `previous_sibling_kind` - 自分より前の兄弟ノードの種類
     /// * `previous_sibling_point` - 自分より前の兄弟ノードの位置
+    /// * `previous_sibling_margin_bottom` - 自分より前の兄弟ノードの下マージン
     pub fn compute_position(
         &mut self,
         parent_point: LayoutPoint,
         previous_sibling_kind: LayoutObjectKind,
         previous_sibling_point: Option<LayoutPoint>,
         previous_sibling_size: Option<LayoutSize>,
+        previous_sibling_margin_bottom: i64,
     ) {
         let mut point = LayoutPoint::new(0, 0);
 
@@ -360,12 +376,14 @@ impl LayoutObject {
         match (self.kind(), previous_sibling_kind) {
             // もしブロック要素が兄弟ノードの場合、Y 軸方向に進む
             (LayoutObjectKind::Block, _) | (_, LayoutObjectKind::Block) => {
+                // 兄弟ノードとの間のマージンは、大きいほうの1つにまとめる (マージンの相殺)
+                let margin = previous_sibling_margin_bottom.max(self.vertical_margin().0);
                 if let (Some(size), Some(pos)) = (previous_sibling_size, previous_sibling_point) {
-                    // もし兄弟ノードが存在する場合、兄弟ノードの Y 位置と高さを足し合わせたものが次の位置になる
-                    point.set_y(pos.y() + size.height());
+                    // もし兄弟ノードが存在する場合、兄弟ノードの Y 位置と高さにマージンを足し合わせたものが次の位置になる
+                    point.set_y(pos.y() + size.height() + margin);
                 } else {
-                    // もし兄弟ノードが存在しない場合、親ノードの Y 位置が次の位置になる
-                    point.set_y(parent_point.y());
+                    // もし兄弟ノードが存在しない場合、親ノードの Y 位置にマージンを足したものが次の位置になる
+                    point.set_y(parent_point.y() + margin);
                 }
                 // 新しい行から始まるため、X 軸方向は常に親ノードの X 位置になる
                 point.set_x(parent_point.x());
@@ -394,7 +412,51 @@ impl LayoutObject {
         self.point = point;
     }
 
-    pub fn paint(&mut self) -> Vec<DisplayItem> {
+    fn font_ratio(&self) -> i64 {
+        match self.style.font_size() {
+            FontSize::Medium => 1,
+            FontSize::XLarge => 2,
+            FontSize::XXLarge => 3,
+        }
+    }
+
+    /// テキストノードを、white-space プロパティに従って空白を処理し、描画する行に分割する
+    fn text_lines(&self) -> Vec<String> {
+        let t = match self.node_kind() {
+            NodeKind::Text(t) => t,
+            _ => return Vec::new(),
+        };
+        let white_space = self.style.white_space();
+
+        let segments = if white_space.preserves_line_feeds() {
+            let mut segments: Vec<&str> = t.split('\n').collect();
+            // 最後の改行は新しい行を作らない
+            if segments.len() > 1 && segments.last() == Some(&"") {
+                segments.pop();
+            }
+            segments
+        } else {
+            vec![t.as_str()]
+        };
+
+        let mut lines = Vec::new();
+        for segment in segments {
+            let line = if white_space.collapses_spaces() {
+                collapse_spaces(segment)
+            } else {
+                expand_tabs(segment)
+            };
+
+            if white_space.wraps() {
+                lines.extend(split_text(line, CHAR_WIDTH * self.font_ratio()));
+            } else {
+                lines.push(line);
+            }
+        }
+        lines
+    }
+
+    pub fn paint(&self) -> Vec<DisplayItem> {
         if self.style.display() == DisplayType::DisplayNone {
             return vec![];
         }
@@ -414,38 +476,21 @@ impl LayoutObject {
                 // <img> タグなどをサポートした場合はこのアームの中で処理をする
             }
             LayoutObjectKind::Text => {
-                if let NodeKind::Text(t) = self.node_kind() {
-                    let mut v = vec![];
-
-                    let ratio = match self.style.font_size() {
-                        FontSize::Medium => 1,
-                        FontSize::XLarge => 2,
-                        FontSize::XXLarge => 3,
+                let ratio = self.font_ratio();
+                let mut v = vec![];
+                for (i, line) in self.text_lines().into_iter().enumerate() {
+                    let item = DisplayItem::Text {
+                        text: line,
+                        style: self.style(),
+                        layout_point: LayoutPoint::new(
+                            self.point().x(),
+                            self.point().y() + CHAR_HEIGHT_WITH_PADDING * ratio * i as i64,
+                        ),
                     };
-
-                    let plain_text = t
-                        .replace("\n", " ")
-                        .split(' ')
-                        .filter(|s| !s.is_empty())
-                        .collect::<Vec<_>>()
-                        .join(" ");
-                    let lines = split_text(plain_text, CHAR_WIDTH * ratio);
-                    let mut i = 0;
-                    for line in lines {
-                        let item = DisplayItem::Text {
-                            text: line,
-                            style: self.style(),
-                            layout_point: LayoutPoint::new(
-                                self.point().x(),
-                                self.point().y() + i * CHAR_HEIGHT_WITH_PADDING * i,
-                            ),
-                        };
-                        v.push(item);
-                        i += 1;
-                    }
-
-                    return v;
+                    v.push(item);
                 }
+
+                return v;
             }
         }
 
@@ -453,43 +498,46 @@ impl LayoutObject {
     }
 }
 
+/// 長さを表す値を px に変換する。単位は px のみサポートし、単位のない数値は 0 のみ受け付ける
+/// https://www.w3.org/TR/css-values-4/#lengths
+fn length_in_px(value: &ComponentValue) -> Option<i64> {
+    match value {
+        ComponentValue::Dimension(n, unit) if unit.eq_ignore_ascii_case("px") => Some(*n as i64),
+        ComponentValue::Number(n) if *n == 0.0 => Some(0),
+        _ => None,
+    }
+}
+
+/// DOM ノードに対応する LayoutObject を作成する
+/// `parent` は親ノードの ID とスタイルの組で、ルートの場合は None になる
 pub fn create_layout_object(
-    node: &Option<Rc<RefCell<Node>>>,
-    parent_obj: &Option<Rc<RefCell<LayoutObject>>>,
-    cssom: &StyleSheet,
-) -> Option<Rc<RefCell<LayoutObject>>> {
-    if let Some(n) = node {
-        // LayoutObject を作成する
-        let layout_object = Rc::new(RefCell::new(LayoutObject::new(n.clone(), parent_obj)));
-
-        // CSS のルールをセレクタで選択されたノードに適用する
-        for rule in &cssom.rules {
-            if layout_object.borrow().is_node_selected(&rule.selector) {
-                layout_object
-                    .borrow_mut()
-                    .cascading_style(rule.declarations.clone());
-            }
-        }
+    dom: &Dom,
+    node: NodeId,
+    parent: Option<(LayoutObjectId, ComputedStyle)>,
+    sheets: &[&StyleSheet],
+) -> Option<LayoutObject> {
+    let (parent_id, parent_style) = match parent {
+        Some((id, style)) => (Some(id), Some(style)),
+        None => (None, None),
+    };
 
-        // CSS でスタイルが指定されていない場合、デフォルトの値または親のノードから継承した値を使用する
-        let parent_style = if let Some(parent) = parent_obj {
-            Some(parent.borrow().style())
-        } else {
-            None
-        };
+    // LayoutObject を作成する
+    let mut layout_object = LayoutObject::new(node, dom.get(node).kind(), parent_id);
 
-        layout_object.borrow_mut().defaulting_style(n, parent_style);
+    // ノードに適用される CSS の宣言を優先順位に従って1つの値に決め、スタイルに反映する
+    layout_object.cascading_style(&cascade(dom, node, sheets));
 
-        // display プロパティが none の場合、ノードを作成しない
-        if layout_object.borrow().style().display() == DisplayType::DisplayNone {
-            return None;
-        }
+    // CSS でスタイルが指定されていない場合、デフォルトの値または親のノードから継承した値を使用する
+    layout_object.defaulting_style(parent_style);
 
-        // display プロパティの最終的な値を使用してノードの種類を決定する
-        layout_object.borrow_mut().update_kind();
-        return Some(layout_object);
+    // display プロパティが none の場合、ノードを作成しない
+    if layout_object.style().display() == DisplayType::DisplayNone {
+        return None;
     }
-    None
+
+    // display プロパティの最終的な値を使用してノードの種類を決定する
+    layout_object.update_kind();
+    Some(layout_object)
 }
 
 impl PartialEq for LayoutObject {
@@ -502,6 +550,25 @@ use crate::constants::WINDOW_PADDING;
 use crate::constants::WINDOW_WIDTH;
 use alloc::string::String;
 
+/// 連続する空白文字を1つの空白にまとめ、前後の空白を取り除く
+fn collapse_spaces(s: &str) -> String {
+    s.split_ascii_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+/// タブ文字を、次のタブ位置 (8文字ごと) までの空白に置き換える
+fn expand_tabs(s: &str) -> String {
+    let mut result = String::new();
+    for c in s.chars() {
+        if c == '\t' {
+            let count = 8 - result.chars().count() % 8;
+            result.push_str(&" ".repeat(count));
+        } else {
+            result.push(c);
+        }
+    }
+    result
+}
+
 fn find_index_for_line_break(line: String, max_index: usize) -> usize {
     for i in (0..max_index).rev() {
         if line.chars().collect::<Vec<char>>()[i] == ' ' {
@@ -1,289 +1,304 @@
 use crate::constants::CONTENT_AREA_WIDTH;
 use crate::display_item::DisplayItem;
 use crate::renderer::css::cssom::StyleSheet;
+use crate::renderer::css::media::MediaEnvironment;
 use crate::renderer::dom::api::get_target_element_node;
+use crate::renderer::dom::node::Dom;
 use crate::renderer::dom::node::ElementKind;
-use crate::renderer::dom::node::Node;
+use crate::renderer::dom::node::NodeId;
 use crate::renderer::layout::layout_object::create_layout_object;
 use crate::renderer::layout::layout_object::LayoutObject;
+use crate::renderer::layout::layout_object::LayoutObjectId;
 use crate::renderer::layout::layout_object::LayoutObjectKind;
 use crate::renderer::layout::layout_object::LayoutPoint;
 use crate::renderer::layout::layout_object::LayoutSize;
-use alloc::rc::Rc;
+use alloc::vec;
 use alloc::vec::Vec;
-use core::cell::RefCell;
 
+/// レイアウトツリー
+/// すべての LayoutObject は `objects` (アリーナ) が所有し、ノード同士は LayoutObjectId で参照しあう
 #[derive(Debug, Clone)]
 pub struct LayoutView {
-    root: Option<Rc<RefCell<LayoutObject>>>,
+    objects: Vec<LayoutObject>,
+    root: Option<LayoutObjectId>,
 }
 
 impl LayoutView {
-    pub fn new(root: Rc<RefCell<Node>>, cssom: &StyleSheet) -> Self {
-        // レイアウトツリーは描画される要素だけを持つツリーなので、<body>タグを取得し、
-        // その子要素以下をレイアウトツリーのノードに変換する。
-        let body_root = get_target_element_node(Some(root), ElementKind::Body);
-
+    /// `root` は `dom` の文書ノード
+    /// `user_agent` は要素のデフォルトのスタイルを決めるユーザーエージェントスタイルシート、
+    /// `cssom` は文書のスタイルシート
+    /// `media` は @media の条件を評価する環境で、条件を満たすルールだけがカスケードに使われる
+    pub fn new(
+        dom: &Dom,
+        root: NodeId,
+        user_agent: &StyleSheet,
+        cssom: &StyleSheet,
+        media: &MediaEnvironment,
+    ) -> Self {
         let mut tree = Self {
-            root: build_layout_tree(&body_root, &None, cssom),
+            objects: Vec::new(),
+            root: None,
         };
 
+        let user_agent = user_agent.matching_media(media);
+        let author = cssom.matching_media(media);
+        let sheets = [&user_agent, &author];
+
+        // レイアウトツリーは描画される要素だけを持つツリーなので、<body>タグを取得し、
+        // その子要素以下をレイアウトツリーのノードに変換する。
+        if let Some(body) = get_target_element_node(dom, Some(root), ElementKind::Body) {
+            tree.root = tree.build_layout_tree(dom, body, &sheets);
+        }
+
         tree.update_layout();
 
         tree
     }
 
-    pub fn root(&self) -> Option<Rc<RefCell<LayoutObject>>> {
-        self.root.clone()
+    pub fn root(&self) -> Option<LayoutObjectId> {
+        self.root
     }
 
-    fn update_layout(&mut self) {
-        Self::calculate_node_size(&self.root, LayoutSize::new(CONTENT_AREA_WIDTH, 0));
-
-        Self::calculate_node_position(
-            &self.root,
-            LayoutPoint::new(0, 0),
-            LayoutObjectKind::Block,
-            None,
-            None,
-        );
+    /// `id` が指す LayoutObject を返す
+    pub fn get(&self, id: LayoutObjectId) -> &LayoutObject {
+        &self.objects[id.index()]
     }
 
-    /// レイアウトツリーの各ノードのサイズを計算する
-    ///
-    /// * `node` - 計算対象のノード
-    /// * `parent_size` - 親ノードのサイズ
-    ///
-    fn calculate_node_size(node: &Option<Rc<RefCell<LayoutObject>>>, parent_size: LayoutSize) {
-        if let Some(n) = node {
-            // ノードがブロック要素の場合、子ノードのレイアウトを計算する前に現在のノードの横幅を決める
-            if n.borrow().kind() == LayoutObjectKind::Block {
-                n.borrow_mut().compute_size(parent_size);
-            }
-
-            // 子ノードと兄弟ノードのサイズを再帰的に計算する
-            let first_child = n.borrow().first_child();
-            // １回目の calculate_node_size の呼び出しでは、親のノードサイズによって横幅を決定する。ブロック要素は親の横幅いっぱいまで広がるため、親ノードの横幅と同等になる
-            Self::calculate_node_size(&first_child, n.borrow().size());
-
-            let next_sibling = n.borrow().next_sibling();
-            // 2回目の calculate_node_size の呼び出しでは、子要素のサイズが決定したあとなので、子要素のサイズをもとに高さを決定する。インライン要素の場合は、高さも横幅も子ノードのサイズに依存する。
-            Self::calculate_node_size(&next_sibling, parent_size);
+    fn get_mut(&mut self, id: LayoutObjectId) -> &mut LayoutObject {
+        &mut self.objects[id.index()]
+    }
 
-            // 子ノードのサイズが決まったあとにサイズを計算する
-            // ブロック要素の時、高さは子ノードの高さに依存する
-            // インライン要素のとき、高さも横幅も子ノードに依存する
-            n.borrow_mut().compute_size(parent_size);
+    /// `id` の子ノードを順に返す
+    pub fn children(&self, id: LayoutObjectId) -> Vec<LayoutObjectId> {
+        let mut children = Vec::new();
+        let mut child = self.get(id).first_child();
+        while let Some(c) = child {
+            children.push(c);
+            child = self.get(c).next_sibling();
         }
+        children
     }
 
-    /// レイアウトツリーの各ノードの位置を計算する.
-    /// compute_position メソッドを呼んで現在のノードの位置を計算したあとに、calculate_node_position 関数を子ノードと兄弟ノードに対して再帰的に呼び出すことでほかのノードの位置を計算する.
-    ///
-    /// * `node` - 計算対象のノード
-    /// * `parent_point` - 親ノードの位置
-    /// * `previous_sibling_kind` - 自分より前の兄弟ノードの種類
-    /// * `previous_sibling_point` - 自分より前の兄弟ノードの位置
-    /// * `previous_sibling_size` - 自分より前の兄弟ノードのサイズ
-    fn calculate_node_position(
-        node: &Option<Rc<RefCell<LayoutObject>>>,
-        parent_point: LayoutPoint,
-        previous_sibling_kind: LayoutObjectKind,
-        previous_sibling_point: Option<LayoutPoint>,
-        previous_sibling_size: Option<LayoutSize>,
-    ) {
-        if let Some(n) = node {
-            n.borrow_mut().compute_position(
-                parent_point,
-                previous_sibling_kind,
-                previous_sibling_point,
-                previous_sibling_size,
-            );
-
-            // ノードの子ノードの位置を計算する
-            let first_child = n.borrow().first_child();
-            Self::calculate_node_position(
-                &first_child,
-                n.borrow().point(),
-                LayoutObjectKind::Block,
-                None,
-                None,
-            );
-
-            // ノードの兄弟ノードの位置を計算する
-            let next_sibling = n.borrow().next_sibling();
-            Self::calculate_node_position(
-                &next_sibling,
-                parent_point,
-                n.borrow().kind(),
-                Some(n.borrow().point()),
-                Some(n.borrow().size()),
-            );
+    /// レイアウトツリーのノードを行きがけ順 (文書順) に返す
+    /// 再帰を使わずに、明示的なスタックで走査する
+    pub fn preorder(&self) -> Vec<LayoutObjectId> {
+        let mut result = Vec::new();
+        let mut stack: Vec<LayoutObjectId> = self.root.into_iter().collect();
+        while let Some(id) = stack.pop() {
+            result.push(id);
+            // 最初の子ノードが先に取り出されるように、逆順にスタックに積む
+            stack.extend(self.children(id).into_iter().rev());
         }
+        result
     }
 
-    fn paint_node(node: &Option<Rc<RefCell<LayoutObject>>>, display_items: &mut Vec<DisplayItem>) {
-        match node {
-            Some(n) => {
-                display_items.extend(n.borrow_mut().paint());
-
-                let first_child = n.borrow().first_child();
-                Self::paint_node(&first_child, display_items);
+    /// DOM ノード `root` とその子孫を LayoutObject に変換し、アリーナに追加する
+    /// 描画されないノード (display: none など) は、その子孫も含めて追加しない
+    fn build_layout_tree(
+        &mut self,
+        dom: &Dom,
+        root: NodeId,
+        sheets: &[&StyleSheet],
+    ) -> Option<LayoutObjectId> {
+        // 各 LayoutObject の最後の子ノード。子ノードを兄弟ノードのリストの末尾に追加するために使う
+        let mut last_children: Vec<Option<LayoutObjectId>> = Vec::new();
+        let mut root_id = None;
+
+        // DOM ノードとその親の LayoutObject の組を、文書順に取り出せるように積む
+        let mut stack = vec![(root, None)];
+        while let Some((node, parent)) = stack.pop() {
+            let parent_style = parent.map(|p| (p, self.get(p).style()));
+            let layout_object = match create_layout_object(dom, node, parent_style, sheets) {
+                Some(obj) => obj,
+                None => continue,
+            };
+
+            let id = LayoutObjectId::new(self.objects.len());
+            self.objects.push(layout_object);
+            last_children.push(None);
+
+            match parent {
+                Some(p) => {
+                    match last_children[p.index()] {
+                        Some(last) => self.get_mut(last).set_next_sibling(Some(id)),
+                        None => self.get_mut(p).set_first_child(Some(id)),
+                    }
+                    last_children[p.index()] = Some(id);
+                }
+                None => root_id = Some(id),
+            }
 
-                let next_sibling = n.borrow().next_sibling();
-                Self::paint_node(&next_sibling, display_items);
+            let mut children = Vec::new();
+            let mut child = dom.get(node).first_child();
+            while let Some(c) = child {
+                child = dom.get(c).next_sibling();
+                children.push((c, Some(id)));
             }
-            None => {}
+            stack.extend(children.into_iter().rev());
         }
-    }
 
-    /// paint_node 関数を再帰的に呼び出して、レイアウトツリーを走査する。
-    pub fn paint(&self) -> Vec<DisplayItem> {
-        let mut display_items = Vec::new();
-
-        Self::paint_node(&self.root, &mut display_items);
-
-        display_items
+        root_id
     }
 
-    pub fn find_node_by_position(&self, position: (i64, i64)) -> Option<Rc<RefCell<LayoutObject>>> {
-        Self::find_node_by_position_internal(&self.root, position)
+    fn update_layout(&mut self) {
+        let order = self.preorder();
+        self.calculate_node_size(&order);
+        self.calculate_node_position(&order);
     }
 
-    /// 再帰的に呼ばれる。
-    fn find_node_by_position_internal(
-        node: &Option<Rc<RefCell<LayoutObject>>>,
-        position: (i64, i64),
-    ) -> Option<Rc<RefCell<LayoutObject>>> {
-        match node {
-            Some(n) => {
-                let first_child = n.borrow().first_child();
-                let result1 = Self::find_node_by_position_internal(&first_child, position);
-                if result1.is_some() {
-                    return result1;
-                }
-
-                let next_sibling = n.borrow().next_sibling();
-                let result2 = Self::find_node_by_position_internal(&next_sibling, position);
-                if result2.is_some() {
-                    return result2;
-                }
-
-                if n.borrow().point().x() <= position.0
-                    && position.0 <= (n.borrow().point().x() + n.borrow().size().width())
-                    && n.borrow().point().y() <= position.1
-                    && position.1 <= (n.borrow().point().y() + n.borrow().size().height())
-                {
-                    return Some(n.clone());
-                }
+    /// 子ノードの種類、サイズ、上下のマージンを、子ノードの順に返す
+    fn children_sizes(
+        &self,
+        id: LayoutObjectId,
+    ) -> Vec<(LayoutObjectKind, LayoutSize, (i64, i64))> {
+        self.children(id)
+            .into_iter()
+            .map(|c| {
+                let object = self.get(c);
+                (object.kind(), object.size(), object.vertical_margin())
+            })
+            .collect()
+    }
 
-                None
+    /// レイアウトツリーの各ノードのサイズを計算する
+    ///
+    /// * `order` - レイアウトツリーのノードの行きがけ順
+    ///
+    fn calculate_node_size(&mut self, order: &[LayoutObjectId]) {
+        // 各ノードの親ノードのサイズ。ルートの親のサイズはコンテンツエリアの横幅になる
+        let mut parent_sizes = vec![LayoutSize::new(CONTENT_AREA_WIDTH, 0); self.objects.len()];
+
+        // 親ノードから順に、子ノードのレイアウトを計算する前に横幅を決める
+        // ブロック要素は親の横幅いっぱいまで広がるため、子ノードの横幅は現在のノードの横幅によって決まる
+        for &id in order {
+            if self.get(id).kind() == LayoutObjectKind::Block {
+                let children = self.children_sizes(id);
+                self.get_mut(id)
+                    .compute_size(parent_sizes[id.index()], &children);
+            }
+            let size = self.get(id).size();
+            for child in self.children(id) {
+                parent_sizes[child.index()] = size;
             }
-            None => None,
         }
-    }
-}
 
-fn build_layout_tree(
-    node: &Option<Rc<RefCell<Node>>>,
-    parent_obj: &Option<Rc<RefCell<LayoutObject>>>,
-    cssom: &StyleSheet,
-) -> Option<Rc<RefCell<LayoutObject>>> {
-    let mut target_node = node.clone();
-    let mut layout_object = create_layout_object(node, parent_obj, cssom);
-
-    while layout_object.is_none() {
-        if let Some(n) = target_node {
-            target_node = n.borrow().next_sibling().clone();
-            layout_object = create_layout_object(&target_node, parent_obj, cssom);
-        } else {
-            return layout_object;
+        // 子ノードのサイズが決まったあとにサイズを計算する。行きがけ順の逆順では、子ノードは常に親より先になる
+        // ブロック要素の時、高さは子ノードの高さに依存する
+        // インライン要素のとき、高さも横幅も子ノードに依存する
+        for &id in order.iter().rev() {
+            let children = self.children_sizes(id);
+            self.get_mut(id)
+                .compute_size(parent_sizes[id.index()], &children);
         }
     }
 
-    if let Some(n) = target_node {
-        let original_first_child = n.borrow().first_child();
-        let original_next_sibling = n.borrow().next_sibling();
-        let mut first_child = build_layout_tree(&original_first_child, &layout_object, cssom);
-        let mut next_sibling = build_layout_tree(&original_next_sibling, &None, cssom);
-
-        if first_child.is_none() && original_first_child.is_some() {
-            let mut original_dom_node = original_first_child
-                .expect("first child should exist")
-                .borrow()
-                .next_sibling();
-
-            loop {
-                first_child = build_layout_tree(&original_dom_node, &layout_object, cssom);
-
-                if first_child.is_none() && original_dom_node.is_some() {
-                    original_dom_node = original_dom_node
-                        .expect("next sibling should exist")
-                        .borrow()
-                        .next_sibling();
-                    continue;
-                }
+    /// レイアウトツリーの各ノードの位置を計算する.
+    /// ノードの位置は、親ノードの位置と自分より前の兄弟ノードによって決まるので、行きがけ順に計算する
+    ///
+    /// * `order` - レイアウトツリーのノードの行きがけ順
+    fn calculate_node_position(&mut self, order: &[LayoutObjectId]) {
+        // 各ノードの親ノードの位置と、直前の兄弟ノード
+        // 兄弟ノードの位置は、行きがけ順でそのノードより先に計算されるので、計算するときに読み出す
+        let mut args: Vec<(LayoutPoint, Option<LayoutObjectId>)> =
+            vec![(LayoutPoint::new(0, 0), None); self.objects.len()];
+
+        for &id in order {
+            let (parent_point, previous_sibling) = args[id.index()];
+            let (
+                previous_sibling_kind,
+                previous_sibling_point,
+                previous_sibling_size,
+                previous_sibling_margin_bottom,
+            ) = match previous_sibling {
+                Some(p) => (
+                    self.get(p).kind(),
+                    Some(self.get(p).point()),
+                    Some(self.get(p).size()),
+                    self.get(p).vertical_margin().1,
+                ),
+                None => (LayoutObjectKind::Block, None, None, 0),
+            };
+            self.get_mut(id).compute_position(
+                parent_point,
+                previous_sibling_kind,
+                previous_sibling_point,
+                previous_sibling_size,
+                previous_sibling_margin_bottom,
+            );
 
-                break;
+            let point = self.get(id).point();
+            let mut previous: Option<LayoutObjectId> = None;
+            for child in self.children(id) {
+                args[child.index()] = (point, previous);
+                previous = Some(child);
             }
         }
+    }
 
-        if next_sibling.is_none() && n.borrow().next_sibling().is_some() {
-            let mut original_dom_node = original_next_sibling
-                .expect("first child should exist")
-                .borrow()
-                .next_sibling();
-
-            loop {
-                next_sibling = build_layout_tree(&original_dom_node, &None, cssom);
-
-                if next_sibling.is_none() && original_dom_node.is_some() {
-                    original_dom_node = original_dom_node
-                        .expect("next sibling should exist")
-                        .borrow()
-                        .next_sibling();
-                    continue;
-                }
+    /// レイアウトツリーを文書順に走査して、描画するアイテムを集める
+    pub fn paint(&self) -> Vec<DisplayItem> {
+        let mut display_items = Vec::new();
 
-                break;
-            }
+        for id in self.preorder() {
+            display_items.extend(self.get(id).paint());
         }
 
-        let obj = match layout_object {
-            Some(ref obj) => obj,
-            None => panic!("render object should exist here"),
-        };
-
-        obj.borrow_mut().set_first_child(first_child);
-        obj.borrow_mut().set_next_sibling(next_sibling);
+        display_items
     }
 
-    layout_object
+    /// `position` の位置にあるノードのうち、最も深いノードを返す
+    /// 後ろにある兄弟ノードと子孫が優先されるように、行きがけ順の逆順に調べる
+    pub fn find_node_by_position(&self, position: (i64, i64)) -> Option<LayoutObjectId> {
+        self.preorder().into_iter().rev().find(|&id| {
+            let n = self.get(id);
+            n.point().x() <= position.0
+                && position.0 <= (n.point().x() + n.size().width())
+                && n.point().y() <= position.1
+                && position.1 <= (n.point().y() + n.size().height())
+        })
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
     use crate::alloc::string::ToString;
+    use crate::constants::CHAR_HEIGHT_WITH_PADDING;
+    use crate::display_item::DisplayItem;
     use crate::renderer::css::cssom::CssParser;
     use crate::renderer::css::token::CssTokenizer;
-    use crate::renderer::dom::api::get_style_content;
+    use crate::renderer::css::user_agent::user_agent_stylesheet;
+    use crate::renderer::dom::api::get_style_contents;
     use crate::renderer::dom::node::Element;
     use crate::renderer::dom::node::NodeKind;
     use crate::renderer::html::parser::HtmlParser;
     use crate::renderer::html::token::HtmlTokenizer;
+    use crate::renderer::layout::computed_style::FontSize;
+    use crate::renderer::layout::computed_style::TextDecoration;
+    use alloc::format;
     use alloc::string::String;
+    use alloc::vec;
     use alloc::vec::Vec;
 
     /// ユニットテストで毎回レイアウトツリーを作成するときのヘルパー関数
     fn create_layout_view(html: String) -> LayoutView {
         let t = HtmlTokenizer::new(html);
-        let window = HtmlParser::new(t).construct_tree();
-        let dom = window.borrow().document();
-        let style = get_style_content(dom.clone());
-        let css_tokenizer = CssTokenizer::new(style);
-        let cssom = CssParser::new(css_tokenizer).parse_stylesheet();
-        LayoutView::new(dom, &cssom)
+        let mut dom = Dom::new();
+        let document = HtmlParser::new(&mut dom, t).construct_tree(&mut dom);
+        let mut cssom = StyleSheet::new();
+        for style in get_style_contents(&dom, document) {
+            let css_tokenizer = CssTokenizer::new(style);
+            cssom
+                .rules
+                .extend(CssParser::new(css_tokenizer).parse_stylesheet().rules);
+        }
+        LayoutView::new(
+            &dom,
+            document,
+            &user_agent_stylesheet(),
+            &cssom,
+            &MediaEnvironment::default(),
+        )
     }
 
     #[test]
@@ -299,16 +314,11 @@ mod tests {
 
         let root = layout_view.root();
         assert!(root.is_some());
-        assert_eq!(
-            LayoutObjectKind::Block,
-            root.clone().expect("root should exist").borrow().kind()
-        );
+        let root = layout_view.get(root.expect("root should exist"));
+        assert_eq!(LayoutObjectKind::Block, root.kind());
         assert_eq!(
             NodeKind::Element(Element::new("body", Vec::new())),
-            root.clone()
-                .expect("root should exist")
-                .borrow()
-                .node_kind()
+            root.node_kind()
         );
     }
 
@@ -319,31 +329,22 @@ mod tests {
 
         let root = layout_view.root();
         assert!(root.is_some());
-        assert_eq!(
-            LayoutObjectKind::Block,
-            root.clone().expect("root should exist").borrow().kind()
-        );
+        let root_id = root.expect("root should exist");
+        let root = layout_view.get(root_id);
+        assert_eq!(LayoutObjectKind::Block, root.kind());
         assert_eq!(
             NodeKind::Element(Element::new("body", Vec::new())),
-            root.clone()
-                .expect("root should exist")
-                .borrow()
-                .node_kind()
+            root.node_kind()
         );
 
-        let text = root.expect("root should exist").borrow().first_child();
+        let text = root.first_child();
         assert!(text.is_some());
-        assert_eq!(
-            LayoutObjectKind::Text,
-            text.clone().expect("text should exist").borrow().kind()
-        );
-        assert_eq!(
-            NodeKind::Text("text".to_string()),
-            text.clone()
-                .expect("text should exist")
-                .borrow()
-                .node_kind()
-        );
+        let text_id = text.expect("text should exist");
+        let text = layout_view.get(text_id);
+        assert_eq!(LayoutObjectKind::Text, text.kind());
+        assert_eq!(NodeKind::Text("text".to_string()), text.node_kind());
+        assert_eq!(Some(root_id), text.parent());
+        assert_eq!(vec![text_id], layout_view.children(root_id));
     }
 
     #[test]
@@ -376,38 +377,217 @@ mod tests {
 
         let root = layout_view.root();
         assert!(root.is_some());
-        assert_eq!(
-            LayoutObjectKind::Block,
-            root.clone().expect("root should exist").borrow().kind()
-        );
+        let root = layout_view.get(root.expect("root should exist"));
+        assert_eq!(LayoutObjectKind::Block, root.kind());
         assert_eq!(
             NodeKind::Element(Element::new("body", Vec::new())),
-            root.clone()
-                .expect("root should exist")
-                .borrow()
-                .node_kind()
+            root.node_kind()
         );
 
-        let p = root.expect("root should exist").borrow().first_child();
+        let p = root.first_child();
         assert!(p.is_some());
+        let p = layout_view.get(p.expect("p should exist"));
+        assert_eq!(LayoutObjectKind::Block, p.kind());
         assert_eq!(
-            LayoutObjectKind::Block,
-            p.clone().expect("p should exist").borrow().kind()
+            NodeKind::Element(Element::new("p", Vec::new())),
+            p.node_kind()
         );
+        assert!(p.first_child().is_none());
+        assert!(p.next_sibling().is_none());
+    }
+
+    #[test]
+    fn test_cascade_order() {
+        let html = r#"<html><head><style>
+            p#a, p.b { display: none; }
+            p { display: block; }
+            .c { display: inline !important; }
+        </style></head><body>
+            <p id="a">a</p>
+            <p class="b">b</p>
+            <p>c</p>
+            <p style="display: none">d</p>
+            <p class="c" style="display: none">e</p>
+        </body></html>"#;
+        let layout_view = create_layout_view(html.to_string());
+
+        // 詳細度の高いルールと style 属性が、後に書かれたルールより優先される
+        let texts: Vec<(LayoutObjectKind, String)> = layout_view
+            .preorder()
+            .into_iter()
+            .filter_map(|id| {
+                let object = layout_view.get(id);
+                let child = layout_view.get(object.first_child()?);
+                match child.node_kind() {
+                    NodeKind::Text(text) => Some((object.kind(), text)),
+                    _ => None,
+                }
+            })
+            .collect();
         assert_eq!(
-            NodeKind::Element(Element::new("p", Vec::new())),
-            p.clone().expect("p should exist").borrow().node_kind()
+            vec![
+                (LayoutObjectKind::Block, "c".to_string()),
+                (LayoutObjectKind::Inline, "e".to_string())
+            ],
+            texts
+        );
+    }
+
+    #[test]
+    fn test_find_node_by_position() {
+        let html = "<html><head></head><body><p>a</p><p>b</p><p>c</p></body></html>".to_string();
+        let layout_view = create_layout_view(html);
+        // ユーザーエージェントスタイルシートによる <p> の上下のマージン。隣り合うマージンは相殺される
+        let margin = 16;
+
+        let id = layout_view
+            .find_node_by_position((1, margin * 2 + CHAR_HEIGHT_WITH_PADDING + 1))
+            .expect("node should exist");
+        assert_eq!(
+            NodeKind::Text("b".to_string()),
+            layout_view.get(id).node_kind()
+        );
+        // 前の兄弟ノードの位置が計算されたあとに、次の兄弟ノードの位置が決まる
+        let id = layout_view
+            .find_node_by_position((1, margin * 3 + CHAR_HEIGHT_WITH_PADDING * 2 + 1))
+            .expect("node should exist");
+        assert_eq!(
+            NodeKind::Text("c".to_string()),
+            layout_view.get(id).node_kind()
+        );
+        assert_eq!(None, layout_view.find_node_by_position((1, 1000)));
+    }
+
+    #[test]
+    fn test_user_agent_stylesheet() {
+        let html = r#"<html><head><style>h2 { display: none; }</style></head><body>
+            <h1>a</h1><h2>b</h2><p>c</p><a href="/">d</a>
+        </body></html>"#;
+        let layout_view = create_layout_view(html.to_string());
+        let objects: Vec<&LayoutObject> = layout_view
+            .preorder()
+            .into_iter()
+            .map(|id| layout_view.get(id))
+            .filter(|object| matches!(object.node_kind(), NodeKind::Element(_)))
+            .collect();
+
+        // 作者のスタイルシートは、ユーザーエージェントスタイルシートより優先される
+        let kinds: Vec<ElementKind> = objects
+            .iter()
+            .filter_map(|object| match object.node_kind() {
+                NodeKind::Element(e) => Some(e.kind()),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(
+            vec![
+                ElementKind::Body,
+                ElementKind::H1,
+                ElementKind::P,
+                ElementKind::A
+            ],
+            kinds
+        );
+
+        let (h1, p, a) = (objects[1], objects[2], objects[3]);
+        assert_eq!(FontSize::XXLarge, h1.style().font_size());
+        assert_eq!(LayoutObjectKind::Block, p.kind());
+        assert_eq!(TextDecoration::Underline, a.style().text_decoration());
+
+        // <h1> の下マージン (21px) と <p> の上マージン (16px) は、大きいほうにまとめられる
+        assert_eq!(21, h1.point().y());
+        assert_eq!(h1.point().y() + h1.size().height() + 21, p.point().y());
+    }
+
+    #[test]
+    fn test_many_siblings() {
+        // 兄弟ノードが非常に多くても、スタックを使い果たさずにレイアウトと描画ができる
+        let html = format!(
+            "<html><head></head><body>{}</body></html>",
+            "<p>a</p>".repeat(20_000)
+        );
+        let layout_view = create_layout_view(html);
+        assert_eq!(20_000, texts(&layout_view).len());
+    }
+
+    #[test]
+    fn test_multiple_classes() {
+        let html = r#"<html><head><style>.hidden { display: none; }</style></head><body>
+            <p class="note hidden">hidden</p>
+            <p class="hidden-not">shown</p>
+        </body></html>"#;
+        let layout_view = create_layout_view(html.to_string());
+        assert_eq!(vec!["shown".to_string()], texts(&layout_view));
+    }
+
+    fn texts(layout_view: &LayoutView) -> Vec<String> {
+        layout_view
+            .paint()
+            .into_iter()
+            .filter_map(|item| match item {
+                DisplayItem::Text { text, .. } => Some(text),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_pre() {
+        let html = "<html><head></head><body><pre>\nfn main() {\n    a  b\n}\n</pre></body></html>";
+        let layout_view = create_layout_view(html.to_string());
+        assert_eq!(
+            vec![
+                "fn main() {".to_string(),
+                "    a  b".to_string(),
+                "}".to_string()
+            ],
+            texts(&layout_view)
+        );
+
+        // <pre> の上マージンの下から、行ごとに下にずれて描画される
+        let margin = 16;
+        let points: Vec<i64> = layout_view
+            .paint()
+            .into_iter()
+            .filter_map(|item| match item {
+                DisplayItem::Text { layout_point, .. } => Some(layout_point.y()),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(
+            vec![
+                margin,
+                margin + CHAR_HEIGHT_WITH_PADDING,
+                margin + CHAR_HEIGHT_WITH_PADDING * 2
+            ],
+            points
+        );
+    }
+
+    #[test]
+    fn test_white_space() {
+        let html = r#"<html><head><style>
+            .normal { white-space: normal; }
+            .line { white-space: pre-line; }
+            .wrap { white-space: pre-wrap; }
+        </style></head><body>
+            <p class="normal">a   b
+c</p>
+            <p class="line">a   b
+c</p>
+            <p class="wrap">a   b
+c</p>
+        </body></html>"#;
+        let layout_view = create_layout_view(html.to_string());
+        assert_eq!(
+            vec![
+                "a b c".to_string(),
+                "a b".to_string(),
+                "c".to_string(),
+                "a   b".to_string(),
+                "c".to_string()
+            ],
+            texts(&layout_view)
         );
-        assert!(p
-            .clone()
-            .expect("p node should exist")
-            .borrow()
-            .first_child()
-            .is_none());
-        assert!(p
-            .expect("p node should exist")
-            .borrow()
-            .next_sibling()
-            .is_none());
     }
 }
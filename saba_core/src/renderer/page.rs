@@ -1,32 +1,78 @@
 use crate::browser::Browser;
 use crate::display_item::DisplayItem;
 use crate::http::HttpResponse;
+use crate::renderer::css::cssom::CssDiagnostic;
 use crate::renderer::css::cssom::CssParser;
 use crate::renderer::css::cssom::StyleSheet;
+use crate::renderer::css::media::ColorScheme;
+use crate::renderer::css::media::MediaEnvironment;
+use crate::renderer::css::media::MediaQueryList;
 use crate::renderer::css::token::CssTokenizer;
-use crate::renderer::dom::api::get_js_content;
-use crate::renderer::dom::api::get_style_content;
+use crate::renderer::css::user_agent::user_agent_stylesheet;
+use crate::renderer::dom::api::get_document_title;
+use crate::renderer::dom::api::get_style_sources;
+use crate::renderer::dom::api::get_text_content;
+use crate::renderer::dom::api::node_window;
+use crate::renderer::dom::api::StyleSource;
+use crate::renderer::dom::event::dispatch_event;
+use crate::renderer::dom::event::Event;
+use crate::renderer::dom::event::EventTarget;
+use crate::renderer::dom::mutation::disconnect;
+use crate::renderer::dom::mutation::observe;
+use crate::renderer::dom::mutation::MutationObserver;
+use crate::renderer::dom::mutation::MutationObserverInit;
+use crate::renderer::dom::node::DocumentReadyState;
+use crate::renderer::dom::node::Dom;
 use crate::renderer::dom::node::ElementKind;
+use crate::renderer::dom::node::ElementState;
+use crate::renderer::dom::node::NodeId;
 use crate::renderer::dom::node::NodeKind;
-use crate::renderer::dom::node::Window;
+use crate::renderer::dom::node::WindowId;
 use crate::renderer::html::parser::HtmlParser;
+use crate::renderer::html::parser::ParserStatus;
 use crate::renderer::html::token::HtmlTokenizer;
 use crate::renderer::js::ast::JsParser;
 use crate::renderer::js::runtime::JsRuntime;
 use crate::renderer::js::token::JsLexer;
 use crate::renderer::layout::layout_view::LayoutView;
+use crate::url::Url;
+use alloc::collections::BTreeMap;
 use alloc::rc::Rc;
 use alloc::rc::Weak;
 use alloc::string::String;
+use alloc::string::ToString;
+use alloc::vec;
 use alloc::vec::Vec;
 use core::cell::RefCell;
 
 #[derive(Debug, Clone)]
 pub struct Page {
     browser: Weak<RefCell<Browser>>,
-    frame: Option<Rc<RefCell<Window>>>,
+    // このページ自身への参照。ウィンドウからページをたどれるようにするために使う
+    this: Weak<RefCell<Page>>,
+    // 表示している文書の DOM ツリーとウィンドウ。新しい文書を読み込むたびに作りなおす
+    dom: Dom,
+    frame: Option<WindowId>,
+    // 表示している文書の URL。文書の中の相対 URL は、この URL を基準にする
+    url: String,
+    // 読み込み中の文書のパーサー。読み込みが終わると None になる
+    parser: Option<HtmlParser>,
+    // 文書内のすべてのスクリプトで共有される JavaScript の実行環境
+    runtime: Option<JsRuntime>,
+    // 読み込みが終わったあとの DOM ツリーの変更を監視し、描画しなおす必要があるかを判断する
+    dom_observer: Rc<RefCell<MutationObserver>>,
+    // 要素のデフォルトのスタイルを決めるユーザーエージェントスタイルシート
+    // レイアウトのたびに解析しなおさないように、ページの作成時に一度だけ解析する
+    user_agent_style: StyleSheet,
     style: Option<StyleSheet>,
+    // 取得した外部のスタイルシート。取得できなかった場合は None になる
+    // スタイルを計算しなおすたびに取得しなおさないように、文書を読み込んでいる間は使いまわす
+    stylesheets: BTreeMap<String, Option<String>>,
+    // @media の条件を評価する、表示領域の大きさとユーザーの設定
+    media: MediaEnvironment,
     layout_view: Option<LayoutView>,
+    // receive_data で受け取った HTML が、まだ描画に反映されていないかどうか
+    needs_rendering: bool,
     display_items: Vec<DisplayItem>,
 }
 
@@ -34,84 +80,417 @@ impl Page {
     pub fn new() -> Self {
         Self {
             browser: Weak::new(),
+            this: Weak::new(),
+            dom: Dom::new(),
             frame: None,
+            url: String::new(),
+            parser: None,
+            runtime: None,
+            dom_observer: MutationObserver::new(""),
+            user_agent_style: user_agent_stylesheet(),
             style: None,
+            stylesheets: BTreeMap::new(),
+            media: MediaEnvironment::default(),
             layout_view: None,
+            needs_rendering: false,
             display_items: Vec::new(),
         }
     }
 
-    pub fn clicked(&self, position: (i64, i64)) -> Option<String> {
-        let view = match &self.layout_view {
-            Some(v) => v,
+    /// 表示領域の大きさが変わったときに呼ばれる。描画しなおした場合は true を返す
+    pub fn set_viewport_size(&mut self, width: i64, height: i64) -> bool {
+        let color_scheme = self.media.color_scheme();
+        self.set_media_environment(MediaEnvironment::new(width, height, color_scheme))
+    }
+
+    /// ユーザーが好む配色が変わったときに呼ばれる。描画しなおした場合は true を返す
+    pub fn set_preferred_color_scheme(&mut self, color_scheme: ColorScheme) -> bool {
+        let (width, height) = (self.media.width(), self.media.height());
+        self.set_media_environment(MediaEnvironment::new(width, height, color_scheme))
+    }
+
+    /// @media の条件を評価する環境が変わった場合、適用されるルールを選びなおして描画し、true を返す
+    fn set_media_environment(&mut self, media: MediaEnvironment) -> bool {
+        if self.media == media {
+            return false;
+        }
+        self.media = media;
+        if self.layout_view.is_none() {
+            return false;
+        }
+        self.set_layout_view();
+        self.paint_tree();
+        true
+    }
+
+    /// 表示している文書
+    fn document(&self) -> Option<NodeId> {
+        self.frame.map(|frame| self.dom.window(frame).document())
+    }
+
+    /// `position` の位置にある要素を返す
+    /// テキストの位置の場合は、そのテキストを含む要素になる
+    fn element_at(&self, position: (i64, i64)) -> Option<NodeId> {
+        let node = match &self.layout_view {
+            Some(view) => view.get(view.find_node_by_position(position)?).node(),
             None => return None,
         };
 
-        if let Some(n) = view.find_node_by_position(position) {
-            if let Some(parent) = n.borrow().parent().upgrade() {
-                if let NodeKind::Element(e) = parent.borrow().node_kind() {
-                    if e.kind() == ElementKind::A {
-                        return e.get_attribute("href");
-                    }
-                }
-            }
+        let element = match self.dom.get(node).kind {
+            NodeKind::Text(_) => self.dom.get(node).parent()?,
+            _ => node,
+        };
+        Some(element)
+    }
+
+    /// `element` を `state` の状態にする。状態が変わった場合は、動的な擬似クラスを反映するために
+    /// スタイルを計算しなおして描画し、true を返す
+    fn set_element_state(&mut self, state: ElementState, element: Option<NodeId>) -> bool {
+        let frame = match self.frame {
+            Some(frame) => frame,
+            None => return false,
+        };
+
+        if self.dom.window(frame).element_with_state(state) == element {
+            return false;
+        }
+
+        self.dom
+            .window_mut(frame)
+            .set_element_with_state(state, element);
+        self.set_layout_view();
+        self.paint_tree();
+        true
+    }
+
+    /// マウスカーソルが `position` に移動したときに呼ばれる。描画しなおした場合は true を返す
+    pub fn mouse_moved(&mut self, position: (i64, i64)) -> bool {
+        let element = self.element_at(position);
+        self.set_element_state(ElementState::Hover, element)
+    }
+
+    /// `position` の位置でマウスのボタンが押されたときに呼ばれる
+    /// 押された要素を :active にし、フォーカスできる要素であればフォーカスを移す
+    /// 描画しなおした場合は true を返す
+    pub fn mouse_pressed(&mut self, position: (i64, i64)) -> bool {
+        let element = self.element_at(position);
+        let focus = element.and_then(|e| focusable_ancestor(&self.dom, e));
+        let active = self.set_element_state(ElementState::Active, element);
+        let focused = self.set_element_state(ElementState::Focus, focus);
+        active || focused
+    }
+
+    /// マウスのボタンが離されたときに呼ばれる。描画しなおした場合は true を返す
+    pub fn mouse_released(&mut self) -> bool {
+        self.set_element_state(ElementState::Active, None)
+    }
+
+    /// `position` の位置がクリックされたときに呼ばれる。クリックされた要素に click イベントを送り、
+    /// スクリプトによって既定の動作が取り消されなかった場合は、リンク先の URL を返す
+    pub fn clicked(&mut self, position: (i64, i64)) -> Option<String> {
+        let target = self.element_at(position)?;
+
+        let not_canceled =
+            self.dispatch_event(EventTarget::Node(target), Event::new("click", true, true));
+
+        // イベントリスナーが DOM ツリーを変更した場合のみ、描画しなおす
+        if !self.dom_observer.borrow_mut().take_records().is_empty() {
+            self.set_style();
+            self.set_layout_view();
+            self.paint_tree();
+        }
+
+        if !not_canceled {
+            return None;
+        }
+        click_default_action(&self.dom, target)
+    }
+
+    /// `target` にイベントを送り、スクリプトのイベントリスナーを呼び出す
+    /// 既定の動作が取り消されなかった場合は true を返す
+    fn dispatch_event(&mut self, target: EventTarget, event: Event) -> bool {
+        let event = Rc::new(RefCell::new(event));
+        match self.runtime {
+            Some(ref mut runtime) => runtime.dispatch_event(&mut self.dom, target, &event),
+            None => dispatch_event(&mut self.dom, target, &event, &mut |_, _, _| {}),
         }
-        None
+    }
+
+    /// 文書の読み込みの状態を変更し、readystatechange イベントを送る
+    /// https://html.spec.whatwg.org/multipage/dom.html#update-the-current-document-readiness
+    fn set_ready_state(&mut self, ready_state: DocumentReadyState) {
+        let frame = match self.frame {
+            Some(frame) => frame,
+            None => return,
+        };
+        self.dom.window_mut(frame).set_ready_state(ready_state);
+        let document = self.dom.window(frame).document();
+        self.dispatch_event(
+            EventTarget::Node(document),
+            Event::new("readystatechange", false, false),
+        );
     }
 
     pub fn set_browser(&mut self, browser: Weak<RefCell<Browser>>) {
         self.browser = browser;
     }
 
-    pub fn receive_response(&mut self, response: HttpResponse) {
-        self.create_frame(response.body());
+    /// ページを Rc<RefCell<Page>> に格納したあとに、そのページ自身を指す参照を設定する
+    pub fn set_this(&mut self, this: Weak<RefCell<Page>>) {
+        self.this = this;
+        if let Some(frame) = self.frame {
+            self.dom.window_mut(frame).set_page(self.this.clone());
+        }
+    }
+
+    /// `url` から受け取ったレスポンスを、新しい文書として読み込む
+    pub fn receive_response(&mut self, url: String, response: HttpResponse) {
+        self.set_url(url);
+        self.start_loading();
+        self.receive_data(&response.body());
+        self.finish_loading();
+    }
 
-        self.execute_js();
+    /// 読み込む文書の URL を設定する
+    pub fn set_url(&mut self, url: String) {
+        self.url = url;
+    }
+
+    /// 新しい文書の読み込みを開始する
+    /// 以降、receive_data で受け取った HTML を少しずつ解釈し、finish_loading で読み込みを終える
+    pub fn start_loading(&mut self) {
+        // 前の文書の監視をやめてから、DOM ツリーを作りなおす
+        // アリーナはノードを解放しないので、前の文書のノードはここでまとめて破棄する
+        disconnect(&mut self.dom, &self.dom_observer);
+        self.dom = Dom::new();
+        let parser = HtmlParser::new(&mut self.dom, HtmlTokenizer::new_streaming());
+        let document = parser.document();
+        let window = node_window(&self.dom, document);
+        if let Some(window) = window {
+            self.dom.window_mut(window).set_page(self.this.clone());
+            if let Some(browser) = self.browser.upgrade() {
+                self.dom
+                    .window_mut(window)
+                    .set_visited_urls(browser.borrow().history());
+            }
+        }
+        self.runtime = Some(JsRuntime::new(document));
+        self.frame = window;
+        self.parser = Some(parser);
+        self.style = None;
+        self.stylesheets = BTreeMap::new();
+        self.layout_view = None;
+        self.needs_rendering = false;
+        self.display_items = Vec::new();
+    }
+
+    /// 受け取った HTML の一部を解釈する
+    /// 描画はしないので、届いたデータをまとめて解釈したあとに update_rendering を呼ぶ
+    pub fn receive_data(&mut self, chunk: &str) {
+        match self.parser {
+            Some(ref mut parser) => parser.push_str(chunk),
+            None => return,
+        }
+        self.parse();
+        self.needs_rendering = true;
+    }
 
+    /// 前回の描画のあとに受け取った HTML があれば、その時点までの内容でスタイルとレイアウトを計算しなおして描画する
+    /// 描画しなおした場合は true を返す
+    pub fn update_rendering(&mut self) -> bool {
+        if !self.needs_rendering {
+            return false;
+        }
+        self.needs_rendering = false;
+        self.set_style();
         self.set_layout_view();
         self.paint_tree();
+        true
     }
 
-    fn execute_js(&mut self) {
-        let dom = match &self.frame {
-            Some(frame) => frame.borrow().document(),
+    /// すべての HTML を受け取ったあとに呼ばれる。残りの入力を解釈し、スクリプトを実行して描画する
+    pub fn finish_loading(&mut self) {
+        match self.parser {
+            Some(ref mut parser) => parser.close(),
+            None => return,
+        }
+        self.parse();
+        self.parser = None;
+
+        // https://html.spec.whatwg.org/multipage/parsing.html#the-end
+        self.set_ready_state(DocumentReadyState::Interactive);
+        if let Some(frame) = self.frame {
+            let document = self.dom.window(frame).document();
+            self.dispatch_event(
+                EventTarget::Node(document),
+                Event::new("DOMContentLoaded", true, false),
+            );
+            self.set_ready_state(DocumentReadyState::Complete);
+            self.dispatch_event(EventTarget::Window(frame), Event::new("load", false, false));
+
+            let init = MutationObserverInit {
+                child_list: true,
+                attributes: true,
+                character_data: true,
+                subtree: true,
+                ..Default::default()
+            };
+            // 監視する変更の種類を指定しているので、失敗することはない
+            let _ = observe(&mut self.dom, &self.dom_observer, document, init);
+        }
+
+        self.set_style();
+
+        self.set_layout_view();
+        self.paint_tree();
+        self.needs_rendering = false;
+    }
+
+    /// 受け取っている入力を解釈できるところまで解釈する
+    /// <script> 要素が閉じられるたびに、その位置でスクリプトを実行する
+    fn parse(&mut self) {
+        loop {
+            let status = match self.parser {
+                Some(ref mut parser) => parser.parse(&mut self.dom),
+                None => return,
+            };
+
+            match status {
+                ParserStatus::PausedOnScript(script) => self.execute_script(script),
+                ParserStatus::NeedMoreInput | ParserStatus::Finished => return,
+            }
+        }
+    }
+
+    fn execute_script(&mut self, script: NodeId) {
+        let runtime = match self.runtime {
+            Some(ref mut runtime) => runtime,
             None => return,
         };
-        let js = get_js_content(dom.clone());
+
+        let js = get_text_content(&self.dom, script);
         let lexer = JsLexer::new(js);
 
         let mut parser = JsParser::new(lexer);
         let ast = parser.parse_ast();
 
-        let mut runtime = JsRuntime::new(dom);
-        runtime.execute(&ast);
+        runtime.execute(&mut self.dom, &ast);
     }
 
-    pub fn create_frame(&mut self, html: String) {
-        let html_tokenizer = HtmlTokenizer::new(html);
-        let frame = HtmlParser::new(html_tokenizer).construct_tree();
-        let dom = frame.borrow().document();
-
-        let style = get_style_content(dom);
-        let css_tokenizer = CssTokenizer::new(style);
-        let cssom = CssParser::new(css_tokenizer).parse_stylesheet();
+    /// <style> 要素と <link rel="stylesheet"> 要素のスタイルシートを文書順に解釈し、1つのスタイルシートにまとめる
+    /// 後から現れたルールほど優先されるように、ルールは文書順に並ぶ
+    fn set_style(&mut self) {
+        let document = match self.document() {
+            Some(document) => document,
+            None => return,
+        };
 
-        self.frame = Some(frame);
+        let mut cssom = StyleSheet::new();
+        for source in get_style_sources(&self.dom, document) {
+            match source {
+                StyleSource::Inline(css) => {
+                    let url = self.url.clone();
+                    self.add_stylesheet(&mut cssom, css, &url, &mut Vec::new(), &[]);
+                }
+                StyleSource::External(href) => {
+                    let url = Url::new(self.url.clone()).resolve(&href);
+                    match self.fetch_stylesheet(&url) {
+                        Some(css) => {
+                            let mut importing = vec![url.clone()];
+                            self.add_stylesheet(&mut cssom, css, &url, &mut importing, &[]);
+                        }
+                        None => cssom.diagnostics.push(CssDiagnostic::FetchFailed(url)),
+                    }
+                }
+            }
+        }
         self.style = Some(cssom);
     }
 
+    /// `css` を解釈し、`cssom` に追加する。@import で読み込むスタイルシートのルールは、`css` のルールより前に並ぶ
+    /// * `url` - `css` の URL。@import の相対 URL の基準になる
+    /// * `importing` - 読み込み中のスタイルシートの URL。循環する @import を防ぐために使う
+    /// * `media` - @import で指定された、ルールを適用する条件
+    fn add_stylesheet(
+        &mut self,
+        cssom: &mut StyleSheet,
+        css: String,
+        url: &str,
+        importing: &mut Vec<String>,
+        media: &[MediaQueryList],
+    ) {
+        let sheet = CssParser::new(CssTokenizer::new(css)).parse_stylesheet();
+        cssom.diagnostics.extend(sheet.diagnostics);
+
+        for import in sheet.imports {
+            let import_url = Url::new(url.to_string()).resolve(&import.url);
+            if importing.contains(&import_url) {
+                cssom
+                    .diagnostics
+                    .push(CssDiagnostic::CircularImport(import_url));
+                continue;
+            }
+            let css = match self.fetch_stylesheet(&import_url) {
+                Some(css) => css,
+                None => {
+                    cssom
+                        .diagnostics
+                        .push(CssDiagnostic::FetchFailed(import_url));
+                    continue;
+                }
+            };
+
+            let mut import_media = media.to_vec();
+            import_media.push(import.media);
+            importing.push(import_url.clone());
+            self.add_stylesheet(cssom, css, &import_url, importing, &import_media);
+            importing.pop();
+        }
+
+        for mut rule in sheet.rules {
+            // @import の条件は、ルールを囲む @media の条件より外側にある
+            rule.media.splice(0..0, media.iter().cloned());
+            cssom.rules.push(rule);
+        }
+    }
+
+    /// ネットワークから `url` のスタイルシートを取得する。取得できなかった場合は None を返す
+    fn fetch_stylesheet(&mut self, url: &str) -> Option<String> {
+        if let Some(css) = self.stylesheets.get(url) {
+            return css.clone();
+        }
+
+        let css = match self.browser.upgrade() {
+            Some(browser) => match browser.borrow().fetch(url.to_string()) {
+                Ok(response) if (200..300).contains(&response.status_code()) => {
+                    Some(response.body())
+                }
+                _ => None,
+            },
+            None => None,
+        };
+        self.stylesheets.insert(url.to_string(), css.clone());
+        css
+    }
+
     fn set_layout_view(&mut self) {
-        let dom = match &self.frame {
-            Some(frame) => frame.borrow().document(),
+        let document = match self.document() {
+            Some(document) => document,
             None => return,
         };
 
-        let style = match self.style.clone() {
+        let style = match &self.style {
             Some(style) => style,
             None => return,
         };
 
-        let layout_view = LayoutView::new(dom, &style);
+        let layout_view = LayoutView::new(
+            &self.dom,
+            document,
+            &self.user_agent_style,
+            style,
+            &self.media,
+        );
 
         self.layout_view = Some(layout_view);
     }
@@ -122,6 +501,14 @@ impl Page {
         }
     }
 
+    /// 文書のタイトル (document.title) を返す。スクリプトによって変更された場合は変更後の値になる
+    pub fn title(&self) -> String {
+        match self.document() {
+            Some(document) => get_document_title(&self.dom, document),
+            None => String::new(),
+        }
+    }
+
     pub fn display_items(&self) -> Vec<DisplayItem> {
         self.display_items.clone()
     }
@@ -130,3 +517,404 @@ impl Page {
         self.display_items = Vec::new();
     }
 }
+
+/// `element` を含む要素のうち、マウスのクリックでフォーカスできる最も近い要素を返す
+/// https://html.spec.whatwg.org/multipage/interaction.html#focusable-area
+fn focusable_ancestor(dom: &Dom, element: NodeId) -> Option<NodeId> {
+    let mut node = Some(element);
+    while let Some(n) = node {
+        if let Some(e) = dom.get(n).get_element() {
+            let focusable = match e.kind() {
+                ElementKind::A => e.has_attribute("href"),
+                ElementKind::Input | ElementKind::Textarea => true,
+                _ => false,
+            };
+            if focusable {
+                return Some(n);
+            }
+        }
+        node = dom.get(n).parent();
+    }
+    None
+}
+
+/// click イベントの既定の動作。ターゲットを含む <a> 要素のリンク先を返す
+/// https://html.spec.whatwg.org/multipage/links.html#links-created-by-a-and-area-elements
+fn click_default_action(dom: &Dom, target: NodeId) -> Option<String> {
+    let mut node = Some(target);
+    while let Some(n) = node {
+        if let Some(e) = dom.get(n).get_element() {
+            if e.kind() == ElementKind::A && e.has_attribute("href") {
+                return e.get_attribute("href");
+            }
+        }
+        node = dom.get(n).parent();
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::Error;
+    use crate::renderer::dom::api::get_target_element_node;
+    use crate::renderer::dom::api::node_page;
+    use crate::renderer::layout::computed_style::Color;
+    use alloc::format;
+    use alloc::string::ToString;
+    use alloc::vec;
+
+    fn texts(page: &Page) -> Vec<String> {
+        page.display_items()
+            .into_iter()
+            .filter_map(|item| match item {
+                DisplayItem::Text { text, .. } => Some(text),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_receive_data_in_chunks() {
+        let mut page = Page::new();
+        page.start_loading();
+
+        page.receive_data("<html><head></head><body><p>hel");
+        // 描画は update_rendering を呼ぶまで行われない
+        assert!(texts(&page).is_empty());
+        // 読み込みの途中でも、それまでに受け取った内容が描画される
+        assert!(page.update_rendering());
+        assert_eq!(vec!["hel".to_string()], texts(&page));
+        // 新しいデータを受け取るまでは、描画しなおさない
+        assert!(!page.update_rendering());
+
+        page.receive_data("lo</p><p>wor");
+        page.receive_data("ld</p></body></html>");
+        assert!(page.update_rendering());
+        assert_eq!(vec!["hello".to_string(), "world".to_string()], texts(&page));
+        page.finish_loading();
+        assert_eq!(vec!["hello".to_string(), "world".to_string()], texts(&page));
+    }
+
+    #[test]
+    fn test_multiple_styles_and_scripts() {
+        let html = r#"<html><head>
+            <style>.a { display: none; }</style>
+            <script>function f() { return "second"; }</script>
+            <style>.b { display: none; }</style>
+        </head><body>
+            <p class="a">hidden1</p>
+            <p class="b">hidden2</p>
+            <p id="target">original</p>
+            <script>var t=document.getElementById("target"); t.textContent="first";</script>
+            <p id="later">original</p>
+            <script>var u=document.getElementById("later"); u.textContent=f();</script>
+        </body></html>"#;
+
+        let mut page = Page::new();
+        page.start_loading();
+        page.receive_data(html);
+        page.finish_loading();
+
+        assert_eq!(
+            vec!["first".to_string(), "second".to_string()],
+            texts(&page)
+        );
+    }
+
+    #[test]
+    fn test_title() {
+        let mut page = Page::new();
+        assert_eq!("", page.title());
+
+        page.start_loading();
+        page.receive_data("<html><head><title>Hello,\n  saba</title></head><body>");
+        assert_eq!("Hello, saba", page.title());
+
+        page.receive_data("<script>document.title=\"changed\";</script></body></html>");
+        page.finish_loading();
+        assert_eq!("changed", page.title());
+    }
+
+    #[test]
+    fn test_dom_is_reset_on_navigation() {
+        let html = r#"<html><head></head><body><p id="a">x</p>
+            <script>
+                var a=document.getElementById("a");
+                a.innerHTML="<b>1</b><b>2</b>";
+                a.innerHTML="<b>3</b><b>4</b>";
+            </script>
+        </body></html>"#;
+
+        let mut page = Page::new();
+        page.start_loading();
+        page.receive_data(html);
+        page.finish_loading();
+        let count = page.dom.node_count();
+
+        // 同じ文書を読み込みなおしても、取り除かれたノードはアリーナに残り続けない
+        for _ in 0..3 {
+            page.start_loading();
+            page.receive_data(html);
+            page.finish_loading();
+            assert_eq!(count, page.dom.node_count());
+        }
+
+        page.start_loading();
+        page.receive_data("<html><head></head><body></body></html>");
+        page.finish_loading();
+        assert!(page.dom.node_count() < count);
+    }
+
+    #[test]
+    fn test_window_and_page() {
+        let browser = Browser::new();
+        let page = browser.borrow().current_page();
+        page.borrow_mut().start_loading();
+        page.borrow_mut()
+            .receive_data("<html><head></head><body><p>a</p></body></html>");
+        page.borrow_mut().finish_loading();
+
+        let page_ref = page.borrow();
+        let dom = &page_ref.dom;
+        let document = page_ref.document().expect("document should exist");
+        let p = get_target_element_node(dom, Some(document), ElementKind::P).unwrap();
+        // 文書内の任意のノードから、ウィンドウとページにたどり着ける
+        let window = node_window(dom, p).expect("window should exist");
+        assert_eq!(page_ref.frame, Some(window));
+        assert!(Rc::ptr_eq(
+            &page,
+            &node_page(dom, p).expect("page should exist")
+        ));
+    }
+
+    #[test]
+    fn test_ready_state() {
+        let html = r#"<html><head></head><body>
+            <p id="out">start</p>
+            <script>
+                function log(s) { var t=document.getElementById("out"); t.innerHTML=t.innerHTML+","+s; }
+                function state() { log(document.readyState); }
+                function loaded(e) { log(e.type); }
+                document.addEventListener("readystatechange", state);
+                document.addEventListener("DOMContentLoaded", loaded);
+                window.addEventListener("DOMContentLoaded", state);
+                window.addEventListener("load", loaded);
+                document.addEventListener("load", loaded);
+                state();
+            </script>
+        </body></html>"#;
+
+        let mut page = Page::new();
+        page.start_loading();
+        page.receive_data(html);
+        page.finish_loading();
+
+        // DOMContentLoaded はウィンドウまで伝播するが、load はウィンドウにのみ送られる
+        assert_eq!(
+            vec![
+                "start,loading,interactive,DOMContentLoaded,interactive,complete,load".to_string()
+            ],
+            texts(&page)
+        );
+    }
+
+    /// `text` を描画している位置を返す
+    fn position_of(page: &Page, text: &str) -> (i64, i64) {
+        page.display_items()
+            .into_iter()
+            .find_map(|item| match item {
+                DisplayItem::Text {
+                    text: t,
+                    layout_point,
+                    ..
+                } if t == text => Some((layout_point.x() + 1, layout_point.y() + 1)),
+                _ => None,
+            })
+            .expect("text should be painted")
+    }
+
+    #[test]
+    fn test_click() {
+        let html = r#"<html><head></head><body>
+            <p><a href="/next">next</a></p>
+            <p><a id="cancel" href="/canceled">cancel</a></p>
+            <p id="out">none</p>
+            <script>
+                function cancel(e) { e.preventDefault(); }
+                function mark(e) { var t=document.getElementById("out"); t.textContent=e.type; }
+                var a=document.getElementById("cancel");
+                a.addEventListener("click", cancel);
+                document.addEventListener("click", mark);
+            </script>
+        </body></html>"#;
+
+        let mut page = Page::new();
+        page.start_loading();
+        page.receive_data(html);
+        page.finish_loading();
+
+        // リンクのクリックの既定の動作は、リンク先への移動になる
+        let next = position_of(&page, "next");
+        let cancel = position_of(&page, "cancel");
+        assert_eq!(Some("/next".to_string()), page.clicked(next));
+
+        // リスナーの変更が描画に反映される
+        assert_eq!(
+            vec![
+                "next".to_string(),
+                "cancel".to_string(),
+                "click".to_string()
+            ],
+            texts(&page)
+        );
+
+        // preventDefault によってリンク先への移動が取り消される
+        assert_eq!(None, page.clicked(cancel));
+    }
+
+    /// `text` を描画している文字の色が `name` の色かどうか
+    fn has_color(page: &Rc<RefCell<Page>>, text: &str, name: &str) -> bool {
+        let color = page
+            .borrow()
+            .display_items()
+            .into_iter()
+            .find_map(|item| match item {
+                DisplayItem::Text { text: t, style, .. } if t == text => Some(style.color()),
+                _ => None,
+            })
+            .expect("text should be painted");
+        color.code_u32() == Color::from_name(name).unwrap().code_u32()
+    }
+
+    #[test]
+    fn test_dynamic_pseudo_classes() {
+        let html = r#"<html><head><style>
+            a:link { color: navy; }
+            a:visited { color: green; }
+            a:hover { color: red; }
+            p:active a { color: orange; }
+            a:focus { color: purple; }
+        </style></head><body>
+            <p><a href="/visited">visited</a></p>
+            <p><a href="/next">next</a></p>
+            <p>text</p>
+        </body></html>"#;
+
+        let browser = Browser::new();
+        browser.borrow_mut().push_history("/visited".to_string());
+        let page = browser.borrow().current_page();
+        page.borrow_mut().start_loading();
+        page.borrow_mut().receive_data(html);
+        page.borrow_mut().finish_loading();
+
+        // 履歴にある URL へのリンクは :visited になる
+        assert!(has_color(&page, "visited", "green"));
+        assert!(has_color(&page, "next", "navy"));
+
+        let next = position_of(&page.borrow(), "next");
+        let text = position_of(&page.borrow(), "text");
+
+        assert!(page.borrow_mut().mouse_moved(next));
+        assert!(has_color(&page, "next", "red"));
+        // カーソルの下の要素が変わらなければ、描画しなおさない
+        assert!(!page.borrow_mut().mouse_moved(next));
+
+        // ボタンが押されている間は、リンクとその祖先が :active になり、リンクにフォーカスが移る
+        // 詳細度の高い p:active a が、後に書かれた a:focus より優先される
+        assert!(page.borrow_mut().mouse_pressed(next));
+        assert!(has_color(&page, "next", "orange"));
+        assert!(page.borrow_mut().mouse_released());
+        assert!(has_color(&page, "next", "purple"));
+
+        // フォーカスできない要素を押すと、フォーカスが外れる
+        assert!(page.borrow_mut().mouse_moved(text));
+        assert!(has_color(&page, "next", "purple"));
+        assert!(page.borrow_mut().mouse_pressed(text));
+        assert!(has_color(&page, "next", "navy"));
+        assert!(has_color(&page, "visited", "green"));
+    }
+
+    #[test]
+    fn test_media_queries() {
+        let html = r#"<html><head><style>
+            p { color: black; }
+            @media screen and (max-width: 400px) {
+                p { color: red; }
+            }
+            @media (prefers-color-scheme: dark) {
+                @media (min-width: 300px) { p { color: navy; } }
+            }
+            @media print { p { color: green; } }
+        </style></head><body><p>text</p></body></html>"#;
+
+        let browser = Browser::new();
+        let page = browser.borrow().current_page();
+        page.borrow_mut().start_loading();
+        page.borrow_mut().receive_data(html);
+        page.borrow_mut().finish_loading();
+        assert!(has_color(&page, "text", "black"));
+
+        // 表示領域の大きさが変わると、@media の条件を評価しなおす
+        assert!(page.borrow_mut().set_viewport_size(400, 300));
+        assert!(has_color(&page, "text", "red"));
+        assert!(!page.borrow_mut().set_viewport_size(400, 300));
+
+        // 入れ子になった @media は、すべての条件を満たす場合にのみ適用される
+        assert!(page
+            .borrow_mut()
+            .set_preferred_color_scheme(ColorScheme::Dark));
+        assert!(has_color(&page, "text", "navy"));
+        assert!(page.borrow_mut().set_viewport_size(200, 300));
+        assert!(has_color(&page, "text", "red"));
+    }
+
+    /// テスト用のネットワーク。URL ごとに決まった CSS を返す
+    fn fetch_css(url: String) -> Result<HttpResponse, Error> {
+        let css = match url.as_str() {
+            "http://example.com/css/main.css" => {
+                "@import url(base.css); @import \"/print.css\" print; @import \"main.css\"; \
+                 @import \"missing.css\"; p { color: red; }"
+            }
+            "http://example.com/css/base.css" => "p { color: green; } h1 { color: green; }",
+            "http://example.com/print.css" => "h1 { color: navy; }",
+            _ => return HttpResponse::new("HTTP/1.1 404 Not Found\n\n".to_string()),
+        };
+        HttpResponse::new(format!(
+            "HTTP/1.1 200 OK\nContent-Type: text/css\n\n{}",
+            css
+        ))
+    }
+
+    #[test]
+    fn test_external_stylesheets() {
+        let html = r#"<html><head>
+            <style>h2 { color: red; } p { color: navy; }</style>
+            <link rel="StyleSheet" href="css/main.css">
+            <link rel="icon" href="favicon.css">
+            <style>h2 { color: purple; }</style>
+        </head><body><h1>title</h1><h2>subtitle</h2><p>text</p></body></html>"#;
+
+        let browser = Browser::new();
+        browser.borrow_mut().set_fetch_handler(fetch_css);
+        let page = browser.borrow().current_page();
+        page.borrow_mut().receive_response(
+            "http://example.com/index.html".to_string(),
+            HttpResponse::new(format!("HTTP/1.1 200 OK\n\n{}", html)).unwrap(),
+        );
+
+        // <link> のスタイルシートは文書順に、@import のスタイルシートはそれを読み込んだスタイルシートの前に並ぶ
+        assert!(has_color(&page, "text", "red"));
+        assert!(has_color(&page, "title", "green"));
+        assert!(has_color(&page, "subtitle", "purple"));
+
+        let diagnostics = page.borrow().style.clone().unwrap().diagnostics;
+        assert_eq!(
+            vec![
+                CssDiagnostic::CircularImport("http://example.com/css/main.css".to_string()),
+                CssDiagnostic::FetchFailed("http://example.com/css/missing.css".to_string()),
+            ],
+            diagnostics
+        );
+    }
+}
@@ -1,3 +1,4 @@
+use alloc::format;
 use alloc::string::{String, ToString};
 use alloc::vec::Vec;
 #[derive(Debug, Clone, PartialEq)]
@@ -117,6 +118,95 @@ impl Url {
     pub fn searchpart(&self) -> String {
         self.searchpart.clone()
     }
+
+    /// この URL を基準にして、相対 URL `reference` を絶対 URL にする
+    /// この URL が絶対 URL でない場合は、`reference` をそのまま返す
+    /// https://url.spec.whatwg.org/#concept-basic-url-parser
+    pub fn resolve(&self, reference: &str) -> String {
+        // スキームを持つ URL は、すでに絶対 URL になっている
+        if has_scheme(reference) {
+            return reference.to_string();
+        }
+        let (scheme, rest) = match self.url.split_once("://") {
+            Some(parts) => parts,
+            None => return reference.to_string(),
+        };
+        if let Some(reference) = reference.strip_prefix("//") {
+            return format!("{}://{}", scheme, reference);
+        }
+
+        // ホストとポート、パスとクエリに分ける。フラグメントは使わない
+        let rest = rest.split('#').next().unwrap_or_default();
+        let (authority, path_and_query) = match rest.find('/') {
+            Some(i) => (&rest[..i], &rest[i..]),
+            None => (rest, "/"),
+        };
+        let path = path_and_query.split('?').next().unwrap_or_default();
+        let origin = format!("{}://{}", scheme, authority);
+
+        if reference.is_empty() || reference.starts_with('#') {
+            return format!("{}{}{}", origin, path_and_query, reference);
+        }
+        if reference.starts_with('?') {
+            return format!("{}{}{}", origin, path, reference);
+        }
+        if reference.starts_with('/') {
+            return format!("{}{}", origin, remove_dot_segments(reference));
+        }
+        // 相対パスは、基準の URL のパスの最後の / の後ろを置き換える
+        let directory = &path[..path.rfind('/').map_or(0, |i| i + 1)];
+        format!(
+            "{}{}",
+            origin,
+            remove_dot_segments(&format!("{}{}", directory, reference))
+        )
+    }
+}
+
+/// `url` が "http:" のようなスキームから始まるかどうか
+/// スキームは英字で始まり、英数字と + - . が続いて : で終わる。/ ? # より後ろの : はスキームではない
+/// https://url.spec.whatwg.org/#scheme-start-state
+fn has_scheme(url: &str) -> bool {
+    let scheme = match url.find([':', '/', '?', '#']) {
+        Some(i) if url[i..].starts_with(':') => &url[..i],
+        _ => return false,
+    };
+    let mut chars = scheme.chars();
+    match chars.next() {
+        Some(c) if c.is_ascii_alphabetic() => {}
+        _ => return false,
+    }
+    chars.all(|c| c.is_ascii_alphanumeric() || matches!(c, '+' | '-' | '.'))
+}
+
+/// / から始まるパスの . と .. を取り除く。クエリとフラグメントはそのまま残す
+/// https://www.rfc-editor.org/rfc/rfc3986#section-5.2.4
+fn remove_dot_segments(path: &str) -> String {
+    let (path, suffix) = match path.find(['?', '#']) {
+        Some(i) => path.split_at(i),
+        None => (path, ""),
+    };
+
+    let parts: Vec<&str> = path.split('/').skip(1).collect();
+    let mut segments: Vec<&str> = Vec::new();
+    for (i, part) in parts.iter().enumerate() {
+        let last = i + 1 == parts.len();
+        match *part {
+            "." => {}
+            ".." => {
+                segments.pop();
+            }
+            part => {
+                segments.push(part);
+                continue;
+            }
+        }
+        // a/. や a/.. のように . で終わるパスは、ディレクトリを表す
+        if last {
+            segments.push("");
+        }
+    }
+    format!("/{}{}", segments.join("/"), suffix)
 }
 
 
@@ -215,4 +305,58 @@ mod tests {
         let expected = Err("Only HTTP schema is supported.".to_string());
         assert_eq!(expected, Url::new(url).parse());
     }
+
+    #[test]
+    fn test_resolve() {
+        let base = Url::new("http://example.com:8888/a/b/index.html?x=1#top".to_string());
+        assert_eq!(
+            "http://example.com:8888/a/b/style.css",
+            base.resolve("style.css")
+        );
+        assert_eq!("http://example.com:8888/a/c.css", base.resolve("../c.css"));
+        assert_eq!(
+            "http://example.com:8888/a/b/d/e.css",
+            base.resolve("./d/./e.css")
+        );
+        assert_eq!(
+            "http://example.com:8888/z.css",
+            base.resolve("../../../z.css")
+        );
+        assert_eq!("http://example.com:8888/a/", base.resolve(".."));
+        assert_eq!(
+            "http://example.com:8888/root.css?v=2",
+            base.resolve("/root.css?v=2")
+        );
+        assert_eq!(
+            "http://cdn.example.com/x.css",
+            base.resolve("//cdn.example.com/x.css")
+        );
+        assert_eq!(
+            "http://other.com/y.css",
+            base.resolve("http://other.com/y.css")
+        );
+        assert_eq!(
+            "http://example.com:8888/a/b/index.html?y=2",
+            base.resolve("?y=2")
+        );
+        assert_eq!(
+            "http://example.com/a.css",
+            Url::new("http://example.com".to_string()).resolve("a.css")
+        );
+        // クエリやパスに含まれる :// はスキームではない
+        assert_eq!(
+            "http://example.com:8888/a/b/next.html?to=http://other.com/",
+            base.resolve("next.html?to=http://other.com/")
+        );
+        assert_eq!(
+            "http://example.com:8888/a/b/c/http://d",
+            base.resolve("c/http://d")
+        );
+        assert_eq!(
+            "mailto:saba@example.com",
+            base.resolve("mailto:saba@example.com")
+        );
+        // 基準の URL が絶対 URL でない場合は、そのままになる
+        assert_eq!("a.css", Url::new("".to_string()).resolve("a.css"));
+    }
 }
@@ -1,28 +1,29 @@
-use crate::renderer::dom::node::Node;
+use crate::renderer::dom::node::Dom;
+use crate::renderer::dom::node::NodeId;
+use crate::renderer::dom::traversal::preorder;
 use alloc::format;
-use alloc::rc::Rc;
 use alloc::string::String;
-use core::cell::RefCell;
+use alloc::vec::Vec;
 
-pub fn convert_dom_to_string(root: &Option<Rc<RefCell<Node>>>) -> String {
+pub fn convert_dom_to_string(dom: &Dom, root: &Option<NodeId>) -> String {
     let mut result = String::from("\n");
-    convert_dom_to_string_internal(root, 0, &mut result);
-    result
-}
+    let root = match root {
+        Some(r) => *r,
+        None => return result,
+    };
 
-fn convert_dom_to_string_internal(
-    node: &Option<Rc<RefCell<Node>>>,
-    depth: usize,
-    result: &mut String,
-) {
-    match node {
-        Some(n) => {
-            result.push_str(&"  ".repeat(depth));
-            result.push_str(&format!("{:?}\n", n.borrow().kind()));
-            result.push('\n');
-            convert_dom_to_string_internal(&n.borrow().first_child(), depth + 1, result);
-            convert_dom_to_string_internal(&n.borrow().next_sibling(), depth, result);
+    // 文書順に並んだノードの祖先を保持し、その数をノードの深さとする
+    let mut ancestors: Vec<NodeId> = Vec::new();
+    for node in preorder(dom, root) {
+        let parent = dom.get(node).parent();
+        while !ancestors.is_empty() && ancestors.last().copied() != parent {
+            ancestors.pop();
         }
-        None => {}
+
+        result.push_str(&"  ".repeat(ancestors.len()));
+        result.push_str(&format!("{:?}\n", dom.get(node).kind()));
+        result.push('\n');
+        ancestors.push(node);
     }
+    result
 }
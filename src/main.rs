@@ -17,6 +17,12 @@ use saba_core::url::Url;
 use ui_wasabi::app::WasabiUI;
 
 fn handle_url(url: String) -> Result<HttpResponse, Error> {
+    handle_url_streaming(url, &mut |_| {})
+}
+
+/// `url` のリソースを取得し、届いたボディをその都度 `on_body` に渡す
+/// リダイレクトした場合は、転送先のレスポンスのボディのみを渡す
+fn handle_url_streaming(url: String, on_body: &mut dyn FnMut(&str)) -> Result<HttpResponse, Error> {
     // URL を解釈する
     let parsed_url = match Url::new(url.to_string()).parse() {
         Ok(url) => url,
@@ -30,13 +36,18 @@ fn handle_url(url: String) -> Result<HttpResponse, Error> {
 
     // HTTP リクエストを送信する
     let client = HttpClient::new();
-    let response = match client.get(
+    let response = match client.get_streaming(
         parsed_url.host(),
         parsed_url.port().parse::<u16>().expect(&format!(
             "port number should be u16 but got {}",
             parsed_url.port(),
         )),
         parsed_url.path(),
+        &mut |head, body| {
+            if head.status_code() != 302 {
+                on_body(body);
+            }
+        },
     ) {
         Ok(res) => {
             // HTTP レスポンスのステータスコードが302のとき、転送する(リダイレクト)
@@ -47,13 +58,14 @@ fn handle_url(url: String) -> Result<HttpResponse, Error> {
                 };
                 let redirect_parsed_url = Url::new(location);
 
-                let redirect_res = match client.get(
+                let redirect_res = match client.get_streaming(
                     redirect_parsed_url.host(),
                     redirect_parsed_url.port().parse::<u16>().expect(&format!(
                         "port number should be u16 but got {}",
                         parsed_url.port(),
                     )),
                     redirect_parsed_url.path(),
+                    &mut |_, body| on_body(body),
                 ) {
                     Ok(res) => res,
                     Err(e) => return Err(Error::Network(format!("{:?}", e))),
@@ -82,7 +94,7 @@ fn main() -> u64 {
     let ui = Rc::new(RefCell::new(WasabiUI::new(browser)));
 
     // アプリの実行を開始
-    match ui.borrow_mut().start(handle_url) {
+    match ui.borrow_mut().start(handle_url, handle_url_streaming) {
         Ok(_) => {}
         Err(e) => {
             println!("browser fails to start {:?}", e);
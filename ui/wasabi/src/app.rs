@@ -22,6 +22,7 @@ use saba_core::constants::*;
 use saba_core::display_item::DisplayItem;
 use saba_core::error::Error;
 use saba_core::http::HttpResponse;
+use saba_core::renderer::css::media::ColorScheme;
 use saba_core::renderer::layout::computed_style::FontSize;
 use saba_core::renderer::layout::computed_style::TextDecoration;
 
@@ -32,13 +33,36 @@ fn convert_font_size(size: FontSize) -> StringSize {
         FontSize::XXLarge => StringSize::XLarge,
     }
 }
+
+/// URL のリソースを取得し、届いたボディをその都度コールバックに渡す関数
+type StreamingUrlHandler = fn(String, &mut dyn FnMut(&str)) -> Result<HttpResponse, Error>;
+
+// ページにタイトルがない場合にウィンドウに表示するタイトル
+static DEFAULT_TITLE: &str = "saba";
+
+fn create_window(title: &str) -> Window {
+    Window::new(
+        title.to_string(),
+        WHITE,
+        WINDOW_INIT_X_POS,
+        WINDOW_INIT_Y_POS,
+        WINDOW_WIDTH,
+        WINDOW_HEIGHT,
+    )
+    .unwrap()
+}
+
 #[derive(Debug)]
 pub struct WasabiUI {
     browser: Rc<RefCell<Browser>>,
     input_url: String,
     window: Window,
+    // 現在表示しているページのタイトル
+    title: String,
     input_mode: InputMode,
     cursor: Cursor,
+    // 前回の入力の時点でマウスのボタンが押されていたかどうか
+    button_pressed: bool,
 }
 
 impl WasabiUI {
@@ -47,16 +71,10 @@ impl WasabiUI {
             browser,
             input_url: String::new(),
             input_mode: InputMode::Normal,
-            window: Window::new(
-                "saba".to_string(),
-                WHITE,
-                WINDOW_INIT_X_POS,
-                WINDOW_INIT_Y_POS,
-                WINDOW_WIDTH,
-                WINDOW_HEIGHT,
-            )
-            .unwrap(),
+            window: create_window(DEFAULT_TITLE),
+            title: String::new(),
             cursor: Cursor::new(),
+            button_pressed: false,
         }
     }
 
@@ -116,89 +134,113 @@ impl WasabiUI {
         Ok(())
     }
 
+    /// `handle_url` はページが外部のスタイルシートを取得するときに、
+    /// `handle_url_streaming` はページを移動するときに使う
     pub fn start(
         &mut self,
         handle_url: fn(String) -> Result<HttpResponse, Error>,
+        handle_url_streaming: StreamingUrlHandler,
     ) -> Result<(), Error> {
         self.setup()?;
+        self.update_media_environment()?;
+
+        // ページが外部のスタイルシートを取得するときも、同じ関数でネットワークにアクセスする
+        self.browser.borrow_mut().set_fetch_handler(handle_url);
 
-        self.run_app(handle_url)?;
+        self.run_app(handle_url_streaming)?;
 
         Ok(())
     }
 
-    fn run_app(
-        &mut self,
-        handle_url: fn(String) -> Result<HttpResponse, Error>,
-    ) -> Result<(), Error> {
+    fn run_app(&mut self, handle_url: StreamingUrlHandler) -> Result<(), Error> {
         loop {
             self.handle_key_input(handle_url)?;
             self.handle_mouse_input(handle_url)?;
         }
     }
 
-    fn handle_mouse_input(
-        &mut self,
-        handle_url: fn(String) -> Result<HttpResponse, Error>,
-    ) -> Result<(), Error> {
+    fn handle_mouse_input(&mut self, handle_url: StreamingUrlHandler) -> Result<(), Error> {
         if let Some(MouseEvent { button, position }) = Api::get_mouse_cursor_info() {
             self.window.flush_area(self.cursor.rect());
             self.cursor.set_position(position.x, position.y);
             self.window.flush_area(self.cursor.rect());
             self.cursor.flush();
 
-            if button.l() || button.c() || button.r() {
-                // 相対位置を計算する
-                let relative_pos = (
-                    position.x - WINDOW_INIT_X_POS,
-                    position.y - WINDOW_INIT_Y_POS,
-                );
-
-                // ウィンドウの外をクリックされたときは何もしない
-                if relative_pos.0 < 0
-                    || relative_pos.0 >= WINDOW_WIDTH
-                    || relative_pos.1 < 0
-                    || relative_pos.1 >= WINDOW_HEIGHT
-                {
+            let pressed = button.l() || button.c() || button.r();
+            let was_pressed = core::mem::replace(&mut self.button_pressed, pressed);
+
+            // 相対位置を計算する
+            let relative_pos = (
+                position.x - WINDOW_INIT_X_POS,
+                position.y - WINDOW_INIT_Y_POS,
+            );
+
+            // ウィンドウの外では何もしない
+            if relative_pos.0 < 0
+                || relative_pos.0 >= WINDOW_WIDTH
+                || relative_pos.1 < 0
+                || relative_pos.1 >= WINDOW_HEIGHT
+            {
+                if pressed {
                     println!("button clicked OUTSIDE window: {button:?} {position:?}");
-
-                    return Ok(());
                 }
+                return Ok(());
+            }
 
-                // ツールバーの範囲をクリックされたとき、InputMode を Editing に切り替える
-                if relative_pos.1 < TOOLBAR_HEIGHT + TITLE_BAR_HEIGHT
-                    && relative_pos.1 >= TOOLBAR_HEIGHT
-                {
-                    self.clear_address_bar()?;
-                    self.input_url = String::new();
-                    self.input_mode = InputMode::Editing;
-                    println!("button clicked in toolbar: {button:?} {position:?}");
-                    return Ok(());
-                }
-                self.input_mode = InputMode::Normal;
-
-                let position_in_content_area = (
-                    relative_pos.0,
-                    relative_pos.1 - TITLE_BAR_HEIGHT - TOOLBAR_HEIGHT,
-                );
-                let page = self.browser.borrow().current_page();
-                let next_destination = page.borrow_mut().clicked(position_in_content_area);
-
-                if let Some(url) = next_destination {
-                    self.input_url = url.clone();
-                    self.update_address_bar()?;
-                    self.start_navigation(handle_url, url)?;
+            let position_in_content_area = (
+                relative_pos.0,
+                relative_pos.1 - TITLE_BAR_HEIGHT - TOOLBAR_HEIGHT,
+            );
+            let page = self.browser.borrow().current_page();
+
+            // カーソルの下の要素やボタンの状態が変わると、:hover や :active などのスタイルが変わる
+            let mut restyled = page.borrow_mut().mouse_moved(position_in_content_area);
+            if pressed && !was_pressed {
+                restyled |= page.borrow_mut().mouse_pressed(position_in_content_area);
+            } else if !pressed && was_pressed {
+                restyled |= page.borrow_mut().mouse_released();
+            }
+
+            // ボタンが押された瞬間のみクリックとして扱う
+            if !pressed || was_pressed {
+                if restyled {
+                    self.update_title()?;
+                    self.clear_content_area()?;
+                    self.update_ui()?;
                 }
+                return Ok(());
+            }
+
+            // ツールバーの範囲をクリックされたとき、InputMode を Editing に切り替える
+            if relative_pos.1 < TOOLBAR_HEIGHT + TITLE_BAR_HEIGHT
+                && relative_pos.1 >= TOOLBAR_HEIGHT
+            {
+                self.clear_address_bar()?;
+                self.input_url = String::new();
+                self.input_mode = InputMode::Editing;
+                println!("button clicked in toolbar: {button:?} {position:?}");
+                return Ok(());
+            }
+            self.input_mode = InputMode::Normal;
+
+            let next_destination = page.borrow_mut().clicked(position_in_content_area);
+
+            if let Some(url) = next_destination {
+                self.input_url = url.clone();
+                self.update_address_bar()?;
+                self.start_navigation(handle_url, url)?;
+            } else {
+                // イベントリスナーによってページが変更されている可能性があるので、描画しなおす
+                self.update_title()?;
+                self.clear_content_area()?;
+                self.update_ui()?;
             }
         }
 
         Ok(())
     }
 
-    fn handle_key_input(
-        &mut self,
-        handle_url: fn(String) -> Result<HttpResponse, Error>,
-    ) -> Result<(), Error> {
+    fn handle_key_input(&mut self, handle_url: StreamingUrlHandler) -> Result<(), Error> {
         match self.input_mode {
             InputMode::Normal => {
                 // InputMode が Normal の場合はキー入力を無視する
@@ -299,26 +341,62 @@ impl WasabiUI {
 
     fn start_navigation(
         &mut self,
-        handle_url: fn(String) -> Result<HttpResponse, Error>,
+        handle_url: StreamingUrlHandler,
         destination: String,
     ) -> Result<(), Error> {
         self.clear_content_area()?;
 
-        match handle_url(destination) {
-            Ok(response) => {
-                let page = self.browser.borrow().current_page();
-                page.borrow_mut().receive_response(response);
+        let page = self.browser.borrow().current_page();
+        let mut loading = false;
+        let mut painted: Result<(), Error> = Ok(());
+        let result = handle_url(destination.clone(), &mut |chunk| {
+            if !loading {
+                self.start_loading(&destination);
+                loading = true;
             }
-            Err(error) => {
-                return Err(error);
+            page.borrow_mut().receive_data(chunk);
+
+            // 届いた分までを描画し、読み込みの途中でもページの内容が表示されるようにする
+            // 一度に届いたデータごとに1回だけ、スタイルとレイアウトを計算しなおす
+            if page.borrow_mut().update_rendering() && painted.is_ok() {
+                painted = self.clear_content_area().and_then(|_| self.update_ui());
+            }
+        });
+
+        if let Err(error) = result {
+            // 途中まで読み込んだ文書は、受け取った分だけで読み込みを終える
+            if loading {
+                page.borrow_mut().finish_loading();
             }
+            return Err(error);
         }
+        painted?;
 
+        // ボディが空のレスポンスでも、空の文書として読み込む
+        if !loading {
+            self.start_loading(&destination);
+        }
+        page.borrow_mut().finish_loading();
+
+        // 読み込み中はウィンドウを作り直さないように、スクリプトが変更したタイトルは読み込みが終わってから表示する
+        self.update_title()?;
+        self.clear_content_area()?;
         self.update_ui()?;
 
         Ok(())
     }
 
+    /// `destination` の文書の読み込みを開始する
+    fn start_loading(&mut self, destination: &str) {
+        // 履歴に追加した URL へのリンクは、新しいページで :visited になる
+        self.browser
+            .borrow_mut()
+            .push_history(destination.to_string());
+        let page = self.browser.borrow().current_page();
+        page.borrow_mut().set_url(destination.to_string());
+        page.borrow_mut().start_loading();
+    }
+
     fn clear_content_area(&mut self) -> Result<(), Error> {
         // コンテンツエリアを白く塗りつぶす
         if self
@@ -342,6 +420,52 @@ impl WasabiUI {
         Ok(())
     }
 
+    /// ページのタイトル (document.title) をウィンドウのタイトルバーに表示する
+    /// 読み込みが終わったあとと、イベントによってページが変更されたあとに呼ぶ
+    fn update_title(&mut self) -> Result<(), Error> {
+        let title = self.browser.borrow().current_page().borrow().title();
+        if title == self.title {
+            return Ok(());
+        }
+        self.title = title;
+        self.recreate_window()
+    }
+
+    /// 現在のタイトルでウィンドウを作り直す
+    /// ウィンドウのタイトルは作成時にしか指定できないので、タイトルを変えるにはウィンドウごと作り直す必要がある
+    /// 作り直したウィンドウは何も描画されていないので、ツールバーとアドレスバーを描画しなおす
+    fn recreate_window(&mut self) -> Result<(), Error> {
+        if self.title.is_empty() {
+            self.window = create_window(DEFAULT_TITLE);
+        } else {
+            self.window = create_window(&self.title);
+        }
+        self.setup()?;
+        self.update_media_environment()?;
+        // 作り直したウィンドウのアドレスバーは空なので、入力中の URL を描画しなおす
+        self.update_address_bar()
+    }
+
+    /// ウィンドウのコンテンツエリアの大きさと配色をページに伝え、@media の条件を評価しなおす
+    /// ウィンドウを作成したときと、大きさが変わったときに呼ぶ
+    fn update_media_environment(&mut self) -> Result<(), Error> {
+        let page = self.browser.borrow().current_page();
+        let resized = page
+            .borrow_mut()
+            .set_viewport_size(CONTENT_AREA_WIDTH, CONTENT_AREA_HEIGHT);
+        // ウィンドウの背景は白なので、明るい配色を好むものとする
+        let recolored = page
+            .borrow_mut()
+            .set_preferred_color_scheme(ColorScheme::Light);
+
+        // 適用されるルールが変わって描画しなおされた場合は、画面にも反映する
+        if resized || recolored {
+            self.clear_content_area()?;
+            self.update_ui()?;
+        }
+        Ok(())
+    }
+
     fn update_ui(&mut self) -> Result<(), Error> {
         let display_items = self
             .browser